@@ -1,4 +1,12 @@
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+mod sink;
+
+use log_output::checksum;
+use log_output::sink::OutputSink;
+use sink::{FilePerKeySink, StdoutSink};
+use std::collections::HashSet;
+use std::io;
+use std::time::Instant;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
 use tokio_stream::StreamExt;
 use tokio_util::io::StreamReader;
 
@@ -6,48 +14,265 @@ const LOG_FILE_URL: &str =
     "https://raw.githubusercontent.com/logpai/loghub/refs/heads/master/Apache/Apache_2k.log";
 const OUTPUT_DIR_PATH: &str = "output";
 const KEYWORD_REGEX: &str = r"^\[.*?\]\s*\[([^\]]+)\]";
+const DEFAULT_FLUSH_INTERVAL: usize = 100;
+
+#[derive(Debug)]
+struct Options {
+    /// Number of written lines after which all open output files are flushed
+    flush_interval: usize,
+    /// Stop after this many bytes of input have been consumed
+    limit_bytes: Option<u64>,
+    /// Keep at most this many output files open, closing the least recently used one first
+    max_open_files: Option<usize>,
+    /// Truncate each output file the first time it is opened in this run instead of appending
+    fresh: bool,
+    /// Write every line to stdout prefixed with its keyword instead of writing the output files
+    stdout: bool,
+    /// Skip the effective-config line and run summary printed to stderr
+    quiet: bool,
+    /// Write a `.sha256` sidecar next to each output file once it is complete
+    with_checksum: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            limit_bytes: None,
+            max_open_files: None,
+            fresh: false,
+            stdout: false,
+            quiet: false,
+            with_checksum: false,
+        }
+    }
+}
+
+fn parse_args() -> Result<Options, Box<dyn std::error::Error>> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--flush-every" => {
+                let value = args.next().ok_or("--flush-every requires a value")?;
+                options.flush_interval = value.parse()?;
+                if options.flush_interval == 0 {
+                    return Err("--flush-every must be at least 1".into());
+                }
+            }
+            "--limit-bytes" => {
+                let value = args.next().ok_or("--limit-bytes requires a value")?;
+                options.limit_bytes = Some(value.parse()?);
+            }
+            "--max-open-files" => {
+                let value = args.next().ok_or("--max-open-files requires a value")?;
+                let max_open_files: usize = value.parse()?;
+                if max_open_files == 0 {
+                    return Err("--max-open-files must be at least 1".into());
+                }
+                options.max_open_files = Some(max_open_files);
+            }
+            "--fresh" => options.fresh = true,
+            "--stdout" | "-" => options.stdout = true,
+            "--quiet" => options.quiet = true,
+            "--with-checksum" => options.with_checksum = true,
+            other => return Err(format!("unknown argument: {}", other).into()),
+        }
+    }
+
+    Ok(options)
+}
+
+/// Counters reported in the summary at the end of a run.
+#[derive(Debug, Default)]
+struct RunStats {
+    lines_read: u64,
+    bytes_read: u64,
+    lines_written: u64,
+    keywords: HashSet<String>,
+}
+
+/// Routes every line with a keyword to `sink` under that keyword, flushing the sink every
+/// `flush_interval` lines so a killed run loses at most that many lines of output.
+async fn split_by_keyword<R, S>(
+    reader: &mut R,
+    sink: &mut S,
+    options: &Options,
+) -> io::Result<RunStats>
+where
+    R: AsyncBufRead + Unpin,
+    S: OutputSink,
+{
+    let keyword_regex = regex::Regex::new(KEYWORD_REGEX).unwrap();
+    let mut unflushed_lines = 0_usize;
+    let mut stats = RunStats::default();
+
+    let mut line = String::new();
+    loop {
+        let n = reader.read_line(&mut line).await?;
+        if n == 0 {
+            break;
+        }
+
+        // only lines that fit entirely within the byte limit are processed
+        if options
+            .limit_bytes
+            .is_some_and(|limit| stats.bytes_read + n as u64 > limit)
+        {
+            break;
+        }
+        stats.bytes_read += n as u64;
+        stats.lines_read += 1;
+
+        // extract the keyword from the second column of the log line using regex
+        // e.g.: [text1 text2 text3] [keyword] [text]
+        if let Some(keyword) = keyword_regex.captures(&line).and_then(|cap| cap.get(1)) {
+            sink.write_entry(keyword.as_str(), &line).await?;
+            stats.lines_written += 1;
+            if !stats.keywords.contains(keyword.as_str()) {
+                stats.keywords.insert(keyword.as_str().to_string());
+            }
+
+            unflushed_lines += 1;
+            if unflushed_lines >= options.flush_interval {
+                sink.flush().await?;
+                unflushed_lines = 0;
+            }
+        }
+
+        line.clear();
+    }
+
+    sink.flush().await?;
+    Ok(stats)
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = parse_args()?;
+    let started = Instant::now();
+    if !options.quiet {
+        let output = if options.stdout {
+            "stdout"
+        } else {
+            OUTPUT_DIR_PATH
+        };
+        eprintln!(
+            "Effective config: source={} output={} {:?}",
+            LOG_FILE_URL, output, options
+        );
+    }
+
     let response = reqwest::get(LOG_FILE_URL).await?.error_for_status()?;
 
     let byte_stream = response
         .bytes_stream()
-        .map(|result| result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)));
+        .map(|result| result.map_err(std::io::Error::other));
     let stream_reader = StreamReader::new(byte_stream);
     let mut reader = BufReader::new(stream_reader);
 
-    let pwd = std::env::current_dir()?;
-    let output_dir_path = pwd.join(OUTPUT_DIR_PATH);
+    let stats = if options.stdout {
+        let mut sink = StdoutSink::new();
+        split_by_keyword(&mut reader, &mut sink, &options).await?
+    } else {
+        let pwd = std::env::current_dir()?;
+        let mut sink = FilePerKeySink::new(
+            pwd.join(OUTPUT_DIR_PATH),
+            options.max_open_files,
+            options.fresh,
+        );
+        let stats = split_by_keyword(&mut reader, &mut sink, &options).await?;
 
-    let mut line = String::new();
-    while reader.read_line(&mut line).await? > 0 {
-        // extract the keyword from the second column of the log line using regex
-        // e.g.: [text1 text2 text3] [keyword] [text]
-        match regex::Regex::new(KEYWORD_REGEX)
-            .unwrap()
-            .captures(&line)
-            .map(|cap| cap.get(1).unwrap().as_str())
-            .into_iter()
-            .next()
-            .map(String::from)
-        {
-            Some(keyword) => {
-                let output_file_path = output_dir_path.join(format!("Apache_2k-[{}].txt", keyword));
-                // create the file if not existing, otherwise append the line to the file
-                let mut file = tokio::fs::OpenOptions::new()
-                    .create(true)
-                    .append(true)
-                    .open(output_file_path)
-                    .await?;
-
-                file.write_all(line.as_bytes()).await?;
+        if options.with_checksum {
+            for path in sink.output_paths() {
+                checksum::write_sidecar(&path).await?;
             }
-            None => {}
         }
 
-        line.clear();
+        if let Some(max_open_files) = options.max_open_files {
+            println!(
+                "Reopened output files {} time(s) with at most {} open",
+                sink.reopens(),
+                max_open_files
+            );
+        }
+        stats
+    };
+
+    if !options.quiet {
+        eprintln!(
+            "Read {} lines ({} bytes), wrote {} matching lines for {} keyword(s) in {:.2?}",
+            stats.lines_read,
+            stats.bytes_read,
+            stats.lines_written,
+            stats.keywords.len(),
+            started.elapsed()
+        );
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log_output::sink::MemorySink;
+
+    const LOG: &str = "\
+[Sun Dec 04 04:47:44 2005] [notice] workerEnv.init() ok /etc/httpd/conf/workers2.properties
+[Sun Dec 04 04:47:44 2005] [error] mod_jk child workerEnv in error state 6
+no keyword on this line
+[Sun Dec 04 04:51:08 2005] [notice] jk2_init() Found child 6725 in scoreboard slot 10
+";
+
+    async fn split(log: &str, options: &Options) -> (MemorySink, RunStats) {
+        let mut sink = MemorySink::default();
+        let stats = split_by_keyword(&mut log.as_bytes(), &mut sink, options)
+            .await
+            .unwrap();
+        (sink, stats)
+    }
+
+    #[tokio::test]
+    async fn lines_are_grouped_by_keyword() {
+        let (sink, stats) = split(LOG, &Options::default()).await;
+
+        let notice = sink.lines("notice");
+        assert_eq!(notice.len(), 2);
+        assert!(notice[0].contains("workerEnv.init() ok"));
+        assert!(notice[1].contains("jk2_init()"));
+        assert_eq!(sink.lines("error").len(), 1);
+        assert_eq!(stats.keywords.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn lines_without_a_keyword_are_read_but_not_written() {
+        let (sink, stats) = split(LOG, &Options::default()).await;
+
+        assert_eq!(stats.lines_read, 4);
+        assert_eq!(stats.bytes_read, LOG.len() as u64);
+        assert_eq!(stats.lines_written, 3);
+        assert_eq!(sink.entries().len(), 3);
+        assert!(sink.entries().iter().all(|(_, line)| line.ends_with('\n')));
+    }
+
+    #[tokio::test]
+    async fn limit_bytes_stops_before_a_line_that_does_not_fit() {
+        let first_two = LOG
+            .lines()
+            .take(2)
+            .map(|line| line.len() + 1)
+            .sum::<usize>();
+        let options = Options {
+            limit_bytes: Some(first_two as u64 + 5),
+            ..Options::default()
+        };
+        let (sink, stats) = split(LOG, &options).await;
+
+        assert_eq!(stats.lines_read, 2);
+        assert_eq!(stats.bytes_read, first_two as u64);
+        assert_eq!(sink.lines("notice").len(), 1);
+        assert_eq!(sink.lines("error").len(), 1);
+    }
+}
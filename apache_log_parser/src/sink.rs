@@ -0,0 +1,133 @@
+use log_output::sink::OutputSink;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+/// Writes each key to its own `Apache_2k-[<key>].txt` file inside `dir`.
+pub struct FilePerKeySink {
+    dir: PathBuf,
+    max_open_files: Option<usize>,
+    fresh: bool,
+    // each writer is stamped with the write it was last used on, so that with
+    // `max_open_files` the stalest one can be closed to make room for a new key
+    writers: HashMap<String, (BufWriter<tokio::fs::File>, u64)>,
+    opened_keys: HashSet<String>,
+    use_counter: u64,
+    reopens: u64,
+}
+
+impl FilePerKeySink {
+    pub fn new(dir: PathBuf, max_open_files: Option<usize>, fresh: bool) -> Self {
+        Self {
+            dir,
+            max_open_files,
+            fresh,
+            writers: HashMap::new(),
+            opened_keys: HashSet::new(),
+            use_counter: 0,
+            reopens: 0,
+        }
+    }
+
+    /// Number of times a file closed by the LRU limit had to be opened again
+    pub fn reopens(&self) -> u64 {
+        self.reopens
+    }
+
+    /// Every file written to in this run, in no particular order
+    pub fn output_paths(&self) -> Vec<PathBuf> {
+        self.opened_keys
+            .iter()
+            .map(|key| Self::output_path(&self.dir, key))
+            .collect()
+    }
+
+    fn output_path(dir: &Path, key: &str) -> PathBuf {
+        dir.join(format!("Apache_2k-[{}].txt", key))
+    }
+
+    async fn evict_lru(&mut self) -> io::Result<()> {
+        let lru_key = self
+            .writers
+            .iter()
+            .min_by_key(|(_, (_, last_used))| *last_used)
+            .map(|(key, _)| key.clone());
+        if let Some((mut evicted, _)) = lru_key.and_then(|k| self.writers.remove(&k)) {
+            evicted.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+impl OutputSink for FilePerKeySink {
+    async fn write_entry(&mut self, key: &str, line: &str) -> io::Result<()> {
+        if !self.writers.contains_key(key)
+            && self
+                .max_open_files
+                .is_some_and(|max| self.writers.len() >= max)
+        {
+            self.evict_lru().await?;
+        }
+
+        self.use_counter += 1;
+        let (writer, last_used) = match self.writers.entry(key.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let first_open = self.opened_keys.insert(entry.key().clone());
+                if !first_open {
+                    self.reopens += 1;
+                }
+                let output_file_path = Self::output_path(&self.dir, entry.key());
+                // create the file if not existing, otherwise append the lines to the file;
+                // with `fresh` the previous run's content is dropped on first open, while
+                // LRU reopens within this run still append
+                let mut open_options = tokio::fs::OpenOptions::new();
+                open_options.create(true);
+                if self.fresh && first_open {
+                    open_options.write(true).truncate(true);
+                } else {
+                    open_options.append(true);
+                }
+                let file = open_options.open(output_file_path).await?;
+                entry.insert((BufWriter::new(file), self.use_counter))
+            }
+        };
+        *last_used = self.use_counter;
+
+        writer.write_all(line.as_bytes()).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        for (writer, _) in self.writers.values_mut() {
+            writer.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes every line to stdout prefixed with its key, e.g. `[error] <line>`, for piping.
+pub struct StdoutSink {
+    writer: BufWriter<tokio::io::Stdout>,
+}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self {
+            writer: BufWriter::new(tokio::io::stdout()),
+        }
+    }
+}
+
+impl OutputSink for StdoutSink {
+    async fn write_entry(&mut self, key: &str, line: &str) -> io::Result<()> {
+        self.writer
+            .write_all(format!("[{}] {}", key, line).as_bytes())
+            .await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush().await
+    }
+}
@@ -1,12 +1,31 @@
+mod progress;
+mod sink;
+
+use log_output::checksum;
+use log_output::sink::OutputSink;
+use progress::Progress;
+use regex::{Captures, Regex};
+use sink::{SingleFileSink, StdoutSink};
 use std::collections::BTreeMap;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use std::io;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio_stream::StreamExt;
 use tokio_util::io::StreamReader;
 
 const LOG_FILE_URL: &str =
     "https://raw.githubusercontent.com/logpai/loghub/refs/heads/master/Linux/Linux_2k.log";
 const OUTPUT_FILE_PATH: &str = "Linux2k_IP_stat.txt";
+const DEFAULT_FLUSH_INTERVAL: usize = 100;
+const SUMMARY_TOP_N: usize = 5;
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(5);
 const IPV4_REGEX: &str = r"(25[0-5]|2[0-4]\d|[01]?\d?\d)[\.-](25[0-5]|2[0-4]\d|[01]?\d?\d)[\.-](25[0-5]|2[0-4]\d|[01]?\d?\d)[\.-](25[0-5]|2[0-4]\d|[01]?\d?\d)";
+/// Same as `IPV4_REGEX` but only accepts `.` between octets, used with `--strict-dots`
+const IPV4_STRICT_REGEX: &str = r"(25[0-5]|2[0-4]\d|[01]?\d?\d)\.(25[0-5]|2[0-4]\d|[01]?\d?\d)\.(25[0-5]|2[0-4]\d|[01]?\d?\d)\.(25[0-5]|2[0-4]\d|[01]?\d?\d)";
+/// `IPV4_REGEX` and `IPV4_STRICT_REGEX`, compiled once on first use
+static IPV4: LazyLock<Regex> = LazyLock::new(|| Regex::new(IPV4_REGEX).unwrap());
+static IPV4_STRICT: LazyLock<Regex> = LazyLock::new(|| Regex::new(IPV4_STRICT_REGEX).unwrap());
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 struct IPv4Address {
@@ -19,13 +38,17 @@ struct IPv4Address {
 impl IPv4Address {
     pub fn try_parse(ip_str: &str) -> Option<Self> {
         // use the regex to extract the octets
-        let regex = regex::Regex::new(IPV4_REGEX).unwrap();
-        let captures = regex.captures(ip_str)?;
+        Self::from_captures(&IPV4.captures(ip_str)?)
+    }
 
-        let first_octet = captures[1].parse::<u8>().ok()?;
-        let second_octet = captures[2].parse::<u8>().ok()?;
-        let third_octet = captures[3].parse::<u8>().ok()?;
-        let fourth_octet = captures[4].parse::<u8>().ok()?;
+    /// Builds the address from capture groups 1 to 4.
+    fn from_captures(captures: &Captures) -> Option<Self> {
+        // a missing or unparsable group means the line is skipped, never a panic
+        let octet = |group: usize| captures.get(group)?.as_str().parse::<u8>().ok();
+        let first_octet = octet(1)?;
+        let second_octet = octet(2)?;
+        let third_octet = octet(3)?;
+        let fourth_octet = octet(4)?;
 
         Some(Self {
             first_octet,
@@ -46,49 +69,363 @@ impl std::fmt::Display for IPv4Address {
     }
 }
 
+#[derive(Debug)]
+struct Options {
+    /// Number of written lines after which the output file is flushed
+    flush_interval: usize,
+    /// Stop after this many bytes of input have been consumed
+    limit_bytes: Option<u64>,
+    /// Truncate the output file instead of appending to the previous run's content
+    fresh: bool,
+    /// Print totals and the most frequent IPs to stdout after processing
+    summary: bool,
+    /// Stream the IP/count lines to stdout instead of writing the output file
+    stdout: bool,
+    /// Stop tracking new addresses once this many are in the table, counting them as overflow
+    max_distinct: Option<usize>,
+    /// Skip the effective-config line and run summary printed to stderr
+    quiet: bool,
+    /// Write a `.sha256` sidecar next to the output file once it is complete
+    with_checksum: bool,
+    /// Only accept `.` between octets, so dash-separated numbers are not counted as IPs
+    strict_dots: bool,
+    /// Print lines read, distinct IPs and approximate table size to stderr every few seconds
+    progress: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            flush_interval: DEFAULT_FLUSH_INTERVAL,
+            limit_bytes: None,
+            fresh: false,
+            summary: false,
+            stdout: false,
+            max_distinct: None,
+            quiet: false,
+            with_checksum: false,
+            strict_dots: false,
+            progress: false,
+        }
+    }
+}
+
+fn parse_args() -> Result<Options, Box<dyn std::error::Error>> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--flush-every" => {
+                let value = args.next().ok_or("--flush-every requires a value")?;
+                options.flush_interval = value.parse()?;
+                if options.flush_interval == 0 {
+                    return Err("--flush-every must be at least 1".into());
+                }
+            }
+            "--limit-bytes" => {
+                let value = args.next().ok_or("--limit-bytes requires a value")?;
+                options.limit_bytes = Some(value.parse()?);
+            }
+            "--fresh" => options.fresh = true,
+            "--summary" => options.summary = true,
+            "--stdout" | "-" => options.stdout = true,
+            "--max-distinct" => {
+                let value = args.next().ok_or("--max-distinct requires a value")?;
+                let max_distinct: usize = value.parse()?;
+                if max_distinct == 0 {
+                    return Err("--max-distinct must be at least 1".into());
+                }
+                options.max_distinct = Some(max_distinct);
+            }
+            "--quiet" => options.quiet = true,
+            "--with-checksum" => options.with_checksum = true,
+            "--strict-dots" => options.strict_dots = true,
+            "--progress" => options.progress = true,
+            other => return Err(format!("unknown argument: {}", other).into()),
+        }
+    }
+
+    Ok(options)
+}
+
+fn print_summary(total_lines: u64, ip_table: &BTreeMap<IPv4Address, u32>) {
+    let total_occurrences: u64 = ip_table.values().map(|&count| count as u64).sum();
+    println!("Lines processed:  {}", total_lines);
+    println!("IP occurrences:   {}", total_occurrences);
+    println!("Distinct IPs:     {}", ip_table.len());
+
+    // partially sort so only the top N need a full ordering; ties keep address order
+    let mut by_count = ip_table.iter().collect::<Vec<_>>();
+    let by_count_desc = |a: &(&IPv4Address, &u32), b: &(&IPv4Address, &u32)| {
+        b.1.cmp(a.1).then_with(|| a.0.cmp(b.0))
+    };
+    if by_count.len() > SUMMARY_TOP_N {
+        by_count.select_nth_unstable_by(SUMMARY_TOP_N - 1, by_count_desc);
+        by_count.truncate(SUMMARY_TOP_N);
+    }
+    by_count.sort_unstable_by(by_count_desc);
+
+    let by_count = by_count
+        .into_iter()
+        .map(|(ip, count)| (ip.to_string(), count))
+        .collect::<Vec<_>>();
+    let width = by_count.iter().map(|(ip, _)| ip.len()).max().unwrap_or(0);
+    println!("Top {} IPs:", SUMMARY_TOP_N);
+    for (ip, count) in by_count {
+        println!("  {:<width$} {}", ip, count);
+    }
+}
+
+/// Dumps the IP address and count pairs to `sink`, keyed by address, flushing every
+/// `flush_interval` lines so a killed run loses at most that many.
+async fn write_ip_table<S: OutputSink>(
+    ip_table: BTreeMap<IPv4Address, u32>,
+    sink: &mut S,
+    options: &Options,
+) -> io::Result<()> {
+    // render the addresses once, so the column can be sized to the widest one
+    let rows = ip_table
+        .into_iter()
+        .map(|(ip, count)| (ip.to_string(), count))
+        .collect::<Vec<_>>();
+    let width = rows.iter().map(|(ip, _)| ip.len()).max().unwrap_or(0);
+
+    for (written, (ip, count)) in rows.iter().enumerate() {
+        sink.write_entry(ip, &format!("{:<width$} {}\n", ip, count))
+            .await?;
+
+        if (written + 1) % options.flush_interval == 0 {
+            sink.flush().await?;
+        }
+    }
+    sink.flush().await
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let options = parse_args()?;
+    let started = Instant::now();
+    if !options.quiet {
+        let output = if options.stdout {
+            "stdout".to_string()
+        } else {
+            format!("output/{}", OUTPUT_FILE_PATH)
+        };
+        eprintln!(
+            "Effective config: source={} output={} {:?}",
+            LOG_FILE_URL, output, options
+        );
+    }
+
     let response = reqwest::get(LOG_FILE_URL).await?.error_for_status()?;
 
     let byte_stream = response
         .bytes_stream()
-        .map(|result| result.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)));
+        .map(|result| result.map_err(std::io::Error::other));
     let stream_reader = StreamReader::new(byte_stream);
     let mut reader = BufReader::new(stream_reader);
 
-    let pwd = std::env::current_dir()?;
-    let output_file_path = pwd.join("output").join(OUTPUT_FILE_PATH);
-    let mut file = tokio::fs::OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(output_file_path)
-        .await?;
-    let ip_regex = regex::Regex::new(IPV4_REGEX).unwrap();
+    let ip_regex = if options.strict_dots {
+        &*IPV4_STRICT
+    } else {
+        &*IPV4
+    };
     let mut ip_table = BTreeMap::<IPv4Address, u32>::new();
 
+    let mut bytes_read = 0_u64;
+    let mut total_lines = 0_u64;
+    let mut ip_matches = 0_u64;
+    let mut overflow = 0_u64;
     let mut line = String::new();
-    while reader.read_line(&mut line).await? > 0 {
+    let progress = Arc::new(Progress::default());
+    let reporter = options
+        .progress
+        .then(|| progress.spawn_reporter(PROGRESS_INTERVAL));
+    loop {
+        let n = reader.read_line(&mut line).await?;
+        if n == 0 {
+            break;
+        }
+
+        // only lines that fit entirely within the byte limit are processed
+        if options
+            .limit_bytes
+            .is_some_and(|limit| bytes_read + n as u64 > limit)
+        {
+            break;
+        }
+        bytes_read += n as u64;
+        total_lines += 1;
+
         // extract all the IP addresses from the log line using regex and count the occurrences of each IP address
         let ip_addresses = ip_regex
             .find_iter(&line)
             .map(|m| m.as_str())
-            .map(IPv4Address::try_parse)
-            .flatten()
+            .filter_map(IPv4Address::try_parse)
             .collect::<Vec<_>>();
-        ip_addresses.iter().for_each(|ip| {
-            let count = ip_table.entry(ip.clone()).or_insert(0);
-            *count += 1;
-        });
+        ip_matches += ip_addresses.len() as u64;
+        for ip in ip_addresses {
+            if let Some(count) = ip_table.get_mut(&ip) {
+                *count += 1;
+            } else if options
+                .max_distinct
+                .is_some_and(|max| ip_table.len() >= max)
+            {
+                // the table is full: already-tracked addresses stay exact, new ones are only counted
+                overflow += 1;
+            } else {
+                ip_table.insert(ip, 1);
+            }
+        }
 
+        if reporter.is_some() {
+            progress.update(total_lines, bytes_read, ip_table.len());
+        }
         line.clear();
     }
+    if let Some(reporter) = reporter {
+        reporter.abort();
+    }
 
-    // dump the IP address and count pairs to the output file
-    for (ip, count) in ip_table {
-        // use a fixed-width field width of 10 to align the output
-        file.write_all(format!("{:<15} {}\n", ip, count).as_bytes())
-            .await?;
+    if options.summary {
+        print_summary(total_lines, &ip_table);
+    }
+
+    let lines_written = ip_table.len();
+    if options.stdout {
+        let mut sink = StdoutSink::new();
+        write_ip_table(ip_table, &mut sink, &options).await?;
+    } else {
+        let pwd = std::env::current_dir()?;
+        let output_file_path = pwd.join("output").join(OUTPUT_FILE_PATH);
+        let mut sink = SingleFileSink::open(&output_file_path, options.fresh).await?;
+        write_ip_table(ip_table, &mut sink, &options).await?;
+
+        if options.with_checksum {
+            checksum::write_sidecar(&output_file_path).await?;
+        }
+    }
+
+    if overflow > 0 {
+        eprintln!(
+            "Address table full at {} entries: {} occurrence(s) of untracked addresses were not counted",
+            lines_written, overflow
+        );
+    }
+
+    if !options.quiet {
+        eprintln!(
+            "Read {} lines ({} bytes), found {} IP matches, wrote {} lines in {:.2?}",
+            total_lines,
+            bytes_read,
+            ip_matches,
+            lines_written,
+            started.elapsed()
+        );
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log_output::sink::MemorySink;
+
+    fn ip(a: u8, b: u8, c: u8, d: u8) -> IPv4Address {
+        IPv4Address {
+            first_octet: a,
+            second_octet: b,
+            third_octet: c,
+            fourth_octet: d,
+        }
+    }
+
+    #[test]
+    fn parses_valid_addresses() {
+        assert_eq!(
+            IPv4Address::try_parse("192.168.0.1"),
+            Some(ip(192, 168, 0, 1))
+        );
+        assert_eq!(IPv4Address::try_parse("0.0.0.0"), Some(ip(0, 0, 0, 0)));
+        assert_eq!(
+            IPv4Address::try_parse("255.255.255.255"),
+            Some(ip(255, 255, 255, 255))
+        );
+    }
+
+    #[test]
+    fn accepts_dashes_between_octets() {
+        assert_eq!(IPv4Address::try_parse("10-0-0-7"), Some(ip(10, 0, 0, 7)));
+    }
+
+    #[test]
+    fn finds_the_address_inside_a_log_line() {
+        assert_eq!(
+            IPv4Address::try_parse("sshd[1234]: connection from 218.188.2.4 port 22"),
+            Some(ip(218, 188, 2, 4))
+        );
+    }
+
+    #[test]
+    fn rejects_text_without_an_address() {
+        assert_eq!(IPv4Address::try_parse(""), None);
+        assert_eq!(IPv4Address::try_parse("no address here"), None);
+        assert_eq!(IPv4Address::try_parse("1.2.3"), None);
+        assert_eq!(IPv4Address::try_parse("a.b.c.d"), None);
+    }
+
+    #[test]
+    fn out_of_range_octets_match_only_their_valid_prefix() {
+        // 256 is not an octet, so the match ends at "25" rather than failing to parse
+        assert_eq!(IPv4Address::try_parse("1.2.3.256"), Some(ip(1, 2, 3, 25)));
+        assert_eq!(IPv4Address::try_parse("300.1.1.1"), Some(ip(0, 1, 1, 1)));
+    }
+
+    #[test]
+    fn strict_regex_refuses_dashes() {
+        assert!(IPV4_STRICT.find("10-0-0-7").is_none());
+        assert_eq!(
+            IPV4_STRICT.find("ip 10.0.0.7").unwrap().as_str(),
+            "10.0.0.7"
+        );
+    }
+
+    #[test]
+    fn missing_capture_group_is_skipped_without_panicking() {
+        // a pattern whose fourth group is optional can match without it
+        let regex = Regex::new(r"(\d+)\.(\d+)\.(\d+)(?:\.(\d+))?").unwrap();
+        let captures = regex.captures("10.0.0").unwrap();
+        assert!(captures.get(4).is_none());
+        assert_eq!(IPv4Address::from_captures(&captures), None);
+
+        let captures = regex.captures("10.0.0.1").unwrap();
+        assert_eq!(IPv4Address::from_captures(&captures), Some(ip(10, 0, 0, 1)));
+    }
+
+    #[test]
+    fn unparsable_capture_group_is_skipped() {
+        let regex = Regex::new(r"(\d+)\.(\d+)\.(\d+)\.(\d+)").unwrap();
+        let captures = regex.captures("10.0.0.999").unwrap();
+        assert_eq!(IPv4Address::from_captures(&captures), None);
+    }
+
+    #[tokio::test]
+    async fn ip_table_is_written_in_address_order_with_aligned_counts() {
+        let table = BTreeMap::from([
+            (ip(10, 0, 0, 7), 3),
+            (ip(192, 168, 100, 200), 12),
+            (ip(9, 1, 1, 1), 1),
+        ]);
+        let mut sink = MemorySink::default();
+        write_ip_table(table, &mut sink, &Options::default())
+            .await
+            .unwrap();
+
+        let keys: Vec<&str> = sink.entries().iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["9.1.1.1", "10.0.0.7", "192.168.100.200"]);
+        assert_eq!(sink.lines("9.1.1.1"), ["9.1.1.1         1\n"]);
+        assert_eq!(sink.lines("192.168.100.200"), ["192.168.100.200 12\n"]);
+    }
+}
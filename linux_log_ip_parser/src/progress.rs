@@ -0,0 +1,48 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+
+/// Rough bytes one address table entry costs: the 8 bytes of address and count, doubled
+/// because BTreeMap nodes are often only half full.
+const APPROX_BYTES_PER_ENTRY: u64 = 16;
+
+/// Counters the parse loop publishes for the `--progress` reporter.
+#[derive(Debug, Default)]
+pub struct Progress {
+    lines: AtomicU64,
+    bytes: AtomicU64,
+    distinct: AtomicU64,
+}
+
+impl Progress {
+    /// Records the totals so far; called once per line, so only relaxed stores are used
+    pub fn update(&self, lines: u64, bytes: u64, distinct: usize) {
+        self.lines.store(lines, Ordering::Relaxed);
+        self.bytes.store(bytes, Ordering::Relaxed);
+        self.distinct.store(distinct as u64, Ordering::Relaxed);
+    }
+
+    /// Prints a progress line to stderr every `interval` until the returned task is aborted
+    pub fn spawn_reporter(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let progress = Arc::clone(self);
+        let started = Instant::now();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // the first tick completes immediately, before anything has been read
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let distinct = progress.distinct.load(Ordering::Relaxed);
+                eprintln!(
+                    "[{:.0?}] {} lines ({} bytes) read, {} distinct IPs, ~{} KiB table",
+                    started.elapsed(),
+                    progress.lines.load(Ordering::Relaxed),
+                    progress.bytes.load(Ordering::Relaxed),
+                    distinct,
+                    distinct * APPROX_BYTES_PER_ENTRY / 1024
+                );
+            }
+        })
+    }
+}
@@ -0,0 +1,59 @@
+use log_output::sink::OutputSink;
+use std::io;
+use std::path::Path;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+/// Writes every line to one file regardless of its key.
+pub struct SingleFileSink {
+    writer: BufWriter<tokio::fs::File>,
+}
+
+impl SingleFileSink {
+    /// Appends to `path` by default, so repeated runs accumulate; `fresh` starts from an empty file
+    pub async fn open(path: &Path, fresh: bool) -> io::Result<Self> {
+        let mut open_options = tokio::fs::OpenOptions::new();
+        open_options.create(true);
+        if fresh {
+            open_options.write(true).truncate(true);
+        } else {
+            open_options.append(true);
+        }
+        let file = open_options.open(path).await?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl OutputSink for SingleFileSink {
+    async fn write_entry(&mut self, _key: &str, line: &str) -> io::Result<()> {
+        self.writer.write_all(line.as_bytes()).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush().await
+    }
+}
+
+/// Writes every line to stdout regardless of its key, for piping into other commands.
+pub struct StdoutSink {
+    writer: BufWriter<tokio::io::Stdout>,
+}
+
+impl StdoutSink {
+    pub fn new() -> Self {
+        Self {
+            writer: BufWriter::new(tokio::io::stdout()),
+        }
+    }
+}
+
+impl OutputSink for StdoutSink {
+    async fn write_entry(&mut self, _key: &str, line: &str) -> io::Result<()> {
+        self.writer.write_all(line.as_bytes()).await
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush().await
+    }
+}
@@ -0,0 +1,65 @@
+use sha2::{Digest, Sha256};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncReadExt;
+
+/// Writes `<path>.sha256` next to a finished output file, in the `sha256sum` format
+/// (`<hex digest>  <file name>`) so it can be checked with `sha256sum -c`.
+pub async fn write_sidecar(path: &Path) -> io::Result<()> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    let digest = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut sidecar_path = path.as_os_str().to_os_string();
+    sidecar_path.push(".sha256");
+    tokio::fs::write(
+        PathBuf::from(sidecar_path),
+        format!("{}  {}\n", digest, file_name),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sidecar_matches_sha256sum_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.txt");
+        tokio::fs::write(&path, "abc").await.unwrap();
+
+        write_sidecar(&path).await.unwrap();
+
+        let sidecar = tokio::fs::read_to_string(dir.path().join("out.txt.sha256"))
+            .await
+            .unwrap();
+        assert_eq!(
+            sidecar,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  out.txt\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn sidecar_of_a_missing_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(
+            write_sidecar(&dir.path().join("missing.txt"))
+                .await
+                .is_err()
+        );
+    }
+}
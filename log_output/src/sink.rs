@@ -0,0 +1,90 @@
+use std::io;
+
+/// Destination a parser writes its output lines to, grouped by key.
+// the parsers await their sink on the task that owns it, so the futures need no `Send` bound
+#[allow(async_fn_in_trait)]
+pub trait OutputSink {
+    /// Writes `line` (including its trailing newline) under `key`
+    async fn write_entry(&mut self, key: &str, line: &str) -> io::Result<()>;
+
+    /// Pushes any buffered output to its destination
+    async fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Keeps every `(key, line)` entry in memory, in the order written, instead of writing it
+/// anywhere, so a parser can be tested without touching the filesystem.
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    entries: Vec<(String, String)>,
+}
+
+impl MemorySink {
+    pub fn entries(&self) -> &[(String, String)] {
+        &self.entries
+    }
+
+    /// The lines written under `key`, in order
+    pub fn lines(&self, key: &str) -> Vec<&str> {
+        self.entries
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, line)| line.as_str())
+            .collect()
+    }
+}
+
+impl OutputSink for MemorySink {
+    async fn write_entry(&mut self, key: &str, line: &str) -> io::Result<()> {
+        self.entries.push((key.to_string(), line.to_string()));
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn memory_sink_keeps_entries_in_write_order() {
+        let mut sink = MemorySink::default();
+        sink.write_entry("error", "first\n").await.unwrap();
+        sink.write_entry("notice", "second\n").await.unwrap();
+        sink.write_entry("error", "third\n").await.unwrap();
+        sink.flush().await.unwrap();
+
+        let entries: Vec<(&str, &str)> = sink
+            .entries()
+            .iter()
+            .map(|(key, line)| (key.as_str(), line.as_str()))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("error", "first\n"),
+                ("notice", "second\n"),
+                ("error", "third\n")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn memory_sink_groups_lines_by_key() {
+        let mut sink = MemorySink::default();
+        sink.write_entry("error", "first\n").await.unwrap();
+        sink.write_entry("notice", "second\n").await.unwrap();
+        sink.write_entry("error", "third\n").await.unwrap();
+
+        assert_eq!(sink.lines("error"), ["first\n", "third\n"]);
+        assert_eq!(sink.lines("notice"), ["second\n"]);
+        assert!(sink.lines("warn").is_empty());
+    }
+
+    #[test]
+    fn memory_sink_starts_empty() {
+        assert!(MemorySink::default().entries().is_empty());
+    }
+}
@@ -1,18 +1,63 @@
+mod metrics;
+mod profile;
+mod sink;
+
 use crossbeam::scope;
 use crossbeam_channel::{Receiver, Sender, bounded};
-use rand::Rng;
+use metrics::{ChannelGauges, Throughput};
+use profile::{Activity, StageProfile};
+use rand::rngs::StdRng;
+use rand::{RngExt, SeedableRng};
 use serde::Deserialize;
+use sink::{RobotSink, SinkConfig, open_sink};
 use std::collections::VecDeque;
 use std::fs::File;
+use std::iter::StepBy;
+use std::ops::RangeFrom;
 use std::path::Path;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::thread;
 use std::time::Duration;
 
+const SKELETON_SEED_OFFSET: u64 = 0;
+const MOTOR_SEED_OFFSET: u64 = 1;
+// one seed offset per producer kind, so thread i of each kind gets `kind + i * stride`
+const SEED_STRIDE: u64 = 2;
+const MAX_HARDNESS: u8 = 100;
+
 #[derive(Debug, Deserialize)]
 struct Config {
     assembling_rates: AssemblingRates,
+    #[serde(default)]
+    run: RunConfig,
+    #[serde(default)]
+    quality_control: QcConfig,
+    /// Where the consumer persists robots; prints them to stdout when omitted
+    #[serde(default = "default_sinks")]
+    sinks: Vec<SinkConfig>,
+}
+
+fn default_sinks() -> Vec<SinkConfig> {
+    vec![SinkConfig::Stdout]
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RunConfig {
+    /// Seeds every producer's RNG deterministically when set; entropy-seeded otherwise
+    seed: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+struct QcConfig {
+    /// Skeletons softer than this are rejected before assembly; 0 (the default) accepts all
+    min_hardness: u8,
+}
+
+impl QcConfig {
+    fn accepts(&self, skeleton: &Skeleton) -> bool {
+        skeleton.hardness >= self.min_hardness
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -22,10 +67,48 @@ struct AssemblingRates {
     robot_producer: AssemblingRate,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Copy, Deserialize)]
 struct AssemblingRate {
     delay: u64,
     capacity: u64,
+    /// Producer threads feeding the stage's channel; only parts producers may use several
+    #[serde(default = "default_threads")]
+    threads: u64,
+}
+
+fn default_threads() -> u64 {
+    1
+}
+
+impl Config {
+    fn validate(&self) -> anyhow::Result<()> {
+        let rates = &self.assembling_rates;
+        if rates.skeleton_producer.threads == 0 || rates.motor_producer.threads == 0 {
+            anyhow::bail!("producer threads must be at least 1");
+        }
+        if self.quality_control.min_hardness > MAX_HARDNESS {
+            anyhow::bail!(
+                "quality_control.min_hardness above {} would reject every skeleton",
+                MAX_HARDNESS
+            );
+        }
+        if rates.robot_producer.threads != 1 {
+            anyhow::bail!("the robot producer zips parts in order and must run on 1 thread");
+        }
+        // several producers emit ids out of order, which a checkpoint cannot track
+        let parallel = rates.skeleton_producer.threads > 1 || rates.motor_producer.threads > 1;
+        if parallel
+            && self
+                .sinks
+                .iter()
+                .any(|s| matches!(s, SinkConfig::File { .. }))
+        {
+            anyhow::bail!(
+                "file sinks need ids in order and cannot be used with several producer threads"
+            );
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -65,18 +148,41 @@ enum State {
 }
 
 fn main() -> anyhow::Result<()> {
+    let mut profiling = false;
+    let mut lockstep = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--profile" => profiling = true,
+            "--lockstep" => lockstep = true,
+            other => anyhow::bail!("unknown argument: {}", other),
+        }
+    }
+    if profiling && lockstep {
+        anyhow::bail!(
+            "--profile measures the threaded pipeline and cannot be combined with --lockstep"
+        );
+    }
+
+    // logs go to stderr so stdout keeps only the robots and the final reports
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
     let config = load_config("config/config.yaml")?;
+    config.validate()?;
+    eprintln!(
+        "Effective config: profiling={} lockstep={} {:?}",
+        profiling, lockstep, config
+    );
     let state = Arc::new(AtomicUsize::new(State::Running as usize));
 
-    // producer channels
-    let (skeleton_tx, skeleton_rx) =
-        bounded::<Skeleton>(config.assembling_rates.skeleton_producer.capacity as usize);
-    let (motor_tx, motor_rx) =
-        bounded::<Motor>(config.assembling_rates.motor_producer.capacity as usize);
-
-    // consumer channels
-    let (robot_tx, robot_rx) =
-        bounded::<Robot>(config.assembling_rates.robot_producer.capacity as usize);
+    let mut sinks = config
+        .sinks
+        .iter()
+        .map(open_sink)
+        .collect::<anyhow::Result<Vec<Box<dyn RobotSink>>>>()?;
+    // resume ids from the sink furthest behind; sinks ahead of it skip what they already hold
+    let start_id = sinks.iter().filter_map(|s| s.next_id()).min().unwrap_or(0);
 
     {
         let state_for_signal = Arc::clone(&state);
@@ -88,43 +194,169 @@ fn main() -> anyhow::Result<()> {
         })?;
     }
 
+    let throughput = Throughput::start();
+    if lockstep {
+        run_lockstep(&config, start_id, &state, &throughput, |robot| {
+            deliver(&mut sinks, robot)
+        });
+        println!("Shutdown completed");
+        println!("{}", throughput.summary());
+        return Ok(());
+    }
+
+    let profiles = run_pipeline(&config, start_id, &state, profiling, &throughput, |robot| {
+        deliver(&mut sinks, robot)
+    });
+
+    println!("Shutdown completed");
+    println!("{}", throughput.summary());
+
+    if profiling {
+        for profile in &profiles {
+            println!("{}", profile.report());
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the threaded pipeline until shutdown, handing every assembled robot to `inspect` on
+/// the calling thread. The binary passes a closure that delivers to the configured sinks;
+/// a harness can instead collect robots or check invariants. An error from `inspect` shuts
+/// the pipeline down like a failed sink write. Producers count what they hand on, and the
+/// quality-control stage what it rejects, in `throughput`, which a reporter thread logs
+/// every second along with the channel fill levels. Returns the per-stage profiles, which
+/// only hold timings when `profiling` is set.
+fn run_pipeline(
+    config: &Config,
+    start_id: u64,
+    state: &Arc<AtomicUsize>,
+    profiling: bool,
+    throughput: &Throughput,
+    mut inspect: impl FnMut(&Robot) -> anyhow::Result<()>,
+) -> [StageProfile; 5] {
+    // producer channels
+    let (skeleton_tx, skeleton_rx) =
+        bounded::<Skeleton>(config.assembling_rates.skeleton_producer.capacity as usize);
+    // skeletons that passed quality control, buffered like the raw ones
+    let (inspected_tx, inspected_rx) =
+        bounded::<Skeleton>(config.assembling_rates.skeleton_producer.capacity as usize);
+    let (motor_tx, motor_rx) =
+        bounded::<Motor>(config.assembling_rates.motor_producer.capacity as usize);
+
+    // consumer channels
+    let (robot_tx, robot_rx) =
+        bounded::<Robot>(config.assembling_rates.robot_producer.capacity as usize);
+
+    let skeleton_profile = StageProfile::new("skeleton producer", profiling);
+    let motor_profile = StageProfile::new("motor producer", profiling);
+    let qc_profile = StageProfile::new("quality control", profiling);
+    let robot_profile = StageProfile::new("robot producer", profiling);
+    let consumer_profile = StageProfile::new("consumer", profiling);
+
+    // the reporter's receiver clones only live until the consumer is done, see below
+    let gauges = ChannelGauges {
+        skeletons: skeleton_rx.clone(),
+        inspected: inspected_rx.clone(),
+        motors: motor_rx.clone(),
+        robots: robot_rx.clone(),
+    };
+    let (stop_reporter, reporter_stop) = bounded::<()>(0);
+
     scope(|s| {
+        s.spawn(move |_| throughput.report_until(gauges, reporter_stop));
+
         {
-            let state = Arc::clone(&state);
-            let tx = skeleton_tx;
+            // moved in and dropped after the loop, so only the producers hold a sender
+            let skeleton_tx = skeleton_tx;
             let asm_rate = config.assembling_rates.skeleton_producer;
-            s.spawn(|_| launch_skeleton_producer(state, tx, asm_rate));
+            for index in 0..asm_rate.threads {
+                let state = Arc::clone(state);
+                let tx = skeleton_tx.clone();
+                let ids = producer_ids(start_id, index, asm_rate.threads);
+                let rng = producer_rng(config.run.seed, SKELETON_SEED_OFFSET + SEED_STRIDE * index);
+                let profile = &skeleton_profile;
+                let made = &throughput.skeletons;
+                s.spawn(move |_| {
+                    profile.run(|| {
+                        launch_skeleton_producer(state, tx, asm_rate, rng, ids, profile, made)
+                    })
+                });
+            }
         }
 
         {
-            let state = Arc::clone(&state);
-            let tx = motor_tx;
+            let motor_tx = motor_tx;
             let asm_rate = config.assembling_rates.motor_producer;
-            s.spawn(|_| launch_motor_producer(state, tx, asm_rate));
+            for index in 0..asm_rate.threads {
+                let state = Arc::clone(state);
+                let tx = motor_tx.clone();
+                let ids = producer_ids(start_id, index, asm_rate.threads);
+                let rng = producer_rng(config.run.seed, MOTOR_SEED_OFFSET + SEED_STRIDE * index);
+                let profile = &motor_profile;
+                let made = &throughput.motors;
+                s.spawn(move |_| {
+                    profile
+                        .run(|| launch_motor_producer(state, tx, asm_rate, rng, ids, profile, made))
+                });
+            }
         }
 
         {
-            let state = Arc::clone(&state);
-            let s_rx = skeleton_rx.clone();
-            let m_rx = motor_rx.clone();
-            let tx = robot_tx.clone();
-            let asm_rate = config.assembling_rates.robot_producer;
-            s.spawn(|_| launch_robot_producer(state, s_rx, m_rx, tx, asm_rate));
+            // move (not clone) the channel ends, so each channel disconnects as soon as
+            // the stage on either side of it stops; main must not keep a handle alive
+            let rx = skeleton_rx;
+            let tx = inspected_tx;
+            let qc = config.quality_control;
+            let profile = &qc_profile;
+            let rejected = &throughput.rejected;
+            s.spawn(move |_| profile.run(|| launch_quality_control(rx, tx, qc, profile, rejected)));
         }
 
-        for robot in robot_rx.iter() {
-            println!("Assembled robot: {}", robot);
+        {
+            let state = Arc::clone(state);
+            let s_rx = inspected_rx;
+            let m_rx = motor_rx;
+            let tx = robot_tx;
+            let asm_rate = config.assembling_rates.robot_producer;
+            let profile = &robot_profile;
+            let made = &throughput.robots;
+            s.spawn(move |_| {
+                profile
+                    .run(|| launch_robot_producer(state, s_rx, m_rx, tx, asm_rate, profile, made))
+            });
+        }
 
-            if state.load(Ordering::Relaxed) == State::Terminated as usize {
-                break;
+        let robot_rx = robot_rx;
+        consumer_profile.run(|| {
+            // ends once the robot producer has finished and every buffered robot was consumed;
+            // waiting for the disconnect rather than polling the state also works with
+            // `capacity: 0` rendezvous channels, which never hold a backlog to wake us up
+            while let Ok(robot) = consumer_profile.time(Activity::BlockedOnRecv, || robot_rx.recv())
+            {
+                if let Err(e) = consumer_profile.time(Activity::Writing, || inspect(&robot)) {
+                    // stop without acknowledging, so the robot is retried on restart
+                    eprintln!("Consumer failed on robot {}: {:#}", robot.id, e);
+                    state.store(State::ShuttingDown as usize, Ordering::SeqCst);
+                    break;
+                }
             }
-        }
+        });
+        // stop the reporter first: its receiver clones would otherwise keep a producer that
+        // is blocked on a full channel from seeing the disconnect
+        drop(stop_reporter);
+        // unblock the robot producer if we stopped early
+        drop(robot_rx);
     })
     .expect("Failed to launch scoped threads for producers");
 
-    println!("Shutdown completed");
-
-    Ok(())
+    [
+        skeleton_profile,
+        motor_profile,
+        qc_profile,
+        robot_profile,
+        consumer_profile,
+    ]
 }
 
 fn load_config<P: AsRef<Path>>(path: P) -> anyhow::Result<Config> {
@@ -133,47 +365,234 @@ fn load_config<P: AsRef<Path>>(path: P) -> anyhow::Result<Config> {
     Ok(cfg)
 }
 
+/// Ids for producer thread `index` of `threads`: the id space is strided by thread, so
+/// producers of the same kind never hand out the same id.
+fn producer_ids(start_id: u64, index: u64, threads: u64) -> StepBy<RangeFrom<u64>> {
+    (start_id + index..).step_by(threads as usize)
+}
+
+// offset the seed per producer so they don't generate identical sequences
+fn producer_rng(seed: Option<u64>, offset: u64) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(offset)),
+        None => StdRng::from_rng(&mut rand::rng()),
+    }
+}
+
+fn build_skeleton(id: u64, rng: &mut StdRng) -> Skeleton {
+    Skeleton {
+        id,
+        hardness: rng.random_range(0..=MAX_HARDNESS),
+    }
+}
+
+fn build_motor(id: u64, rng: &mut StdRng) -> Motor {
+    Motor {
+        id,
+        rpm: rng.random_range(0..=1000),
+    }
+}
+
+fn assemble_robot(skeleton: Skeleton, motor: Motor) -> Robot {
+    Robot {
+        id: skeleton.id, // assuming skeleton and motor ids are synchronized
+        skeleton,
+        motor,
+    }
+}
+
+/// Writes `robot` to every sink that does not already hold it, stopping at the first failure.
+fn deliver(sinks: &mut [Box<dyn RobotSink>], robot: &Robot) -> anyhow::Result<()> {
+    for sink in sinks.iter_mut() {
+        if sink.next_id().is_some_and(|next| robot.id < next) {
+            continue;
+        }
+        sink.write(robot)?;
+    }
+    Ok(())
+}
+
+/// "queued/capacity" for a channel, as printed by the lockstep log
+fn channel_state<T>(tx: &Sender<T>) -> String {
+    format!("{}/{}", tx.len(), tx.capacity().unwrap_or(0))
+}
+
+/// Runs the pipeline on the calling thread, moving one robot at a time through every stage
+/// and logging each channel send/receive, so the MPSC flow can be followed step by step.
+/// Uses the same stages, channels and consumer hook as the threaded pipeline, minus the
+/// concurrency. A step whose skeleton fails quality control ends without a robot.
+fn run_lockstep(
+    config: &Config,
+    start_id: u64,
+    state: &AtomicUsize,
+    throughput: &Throughput,
+    mut inspect: impl FnMut(&Robot) -> anyhow::Result<()>,
+) {
+    let rates = &config.assembling_rates;
+    // a lone thread would block forever on a `capacity: 0` rendezvous, so keep room for one
+    let channel = |rate: &AssemblingRate| rate.capacity.max(1) as usize;
+    let (skeleton_tx, skeleton_rx) = bounded::<Skeleton>(channel(&rates.skeleton_producer));
+    let (inspected_tx, inspected_rx) = bounded::<Skeleton>(channel(&rates.skeleton_producer));
+    let (motor_tx, motor_rx) = bounded::<Motor>(channel(&rates.motor_producer));
+    let (robot_tx, robot_rx) = bounded::<Robot>(channel(&rates.robot_producer));
+    let mut skeleton_rng = producer_rng(config.run.seed, SKELETON_SEED_OFFSET);
+    let mut motor_rng = producer_rng(config.run.seed, MOTOR_SEED_OFFSET);
+    let qc = config.quality_control;
+
+    // every step drains what it sent, so the channels can never be full or empty below;
+    // rejected skeletons use up their id, so skeleton and motor ids can drift apart
+    let (mut skeleton_id, mut motor_id) = (start_id, start_id);
+    let mut step = 1;
+    while state.load(Ordering::Relaxed) == State::Running as usize {
+        println!("--- step {} ---", step);
+        step += 1;
+
+        thread::sleep(Duration::from_millis(rates.skeleton_producer.delay));
+        let skeleton = build_skeleton(skeleton_id, &mut skeleton_rng);
+        skeleton_id += 1;
+        println!("skeleton producer: send {:?}", skeleton);
+        skeleton_tx
+            .try_send(skeleton)
+            .expect("lockstep channel has room");
+        throughput.skeletons.fetch_add(1, Ordering::Relaxed);
+        println!("  skeleton channel: {} queued", channel_state(&skeleton_tx));
+
+        let skeleton = skeleton_rx.try_recv().expect("skeleton was just sent");
+        println!(
+            "quality control: recv skeleton {} (skeleton channel: {} queued)",
+            skeleton.id,
+            channel_state(&skeleton_tx)
+        );
+        if !qc.accepts(&skeleton) {
+            println!(
+                "quality control: reject skeleton {} (hardness {} < {})",
+                skeleton.id, skeleton.hardness, qc.min_hardness
+            );
+            throughput.rejected.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+        println!("quality control: send skeleton {}", skeleton.id);
+        inspected_tx
+            .try_send(skeleton)
+            .expect("lockstep channel has room");
+        println!(
+            "  inspected channel: {} queued",
+            channel_state(&inspected_tx)
+        );
+
+        thread::sleep(Duration::from_millis(rates.motor_producer.delay));
+        let motor = build_motor(motor_id, &mut motor_rng);
+        motor_id += 1;
+        println!("motor producer: send {:?}", motor);
+        motor_tx.try_send(motor).expect("lockstep channel has room");
+        throughput.motors.fetch_add(1, Ordering::Relaxed);
+        println!("  motor channel: {} queued", channel_state(&motor_tx));
+
+        let skeleton = inspected_rx.try_recv().expect("skeleton was just sent");
+        println!(
+            "robot producer: recv skeleton {} (inspected channel: {} queued)",
+            skeleton.id,
+            channel_state(&inspected_tx)
+        );
+        let motor = motor_rx.try_recv().expect("motor was just sent");
+        println!(
+            "robot producer: recv motor {} (motor channel: {} queued)",
+            motor.id,
+            channel_state(&motor_tx)
+        );
+        thread::sleep(Duration::from_millis(rates.robot_producer.delay));
+        let robot = assemble_robot(skeleton, motor);
+        println!("robot producer: send robot {}", robot.id);
+        robot_tx.try_send(robot).expect("lockstep channel has room");
+        throughput.robots.fetch_add(1, Ordering::Relaxed);
+        println!("  robot channel: {} queued", channel_state(&robot_tx));
+
+        let robot = robot_rx.try_recv().expect("robot was just sent");
+        println!(
+            "consumer: recv robot {} (robot channel: {} queued)",
+            robot.id,
+            channel_state(&robot_tx)
+        );
+        if let Err(e) = inspect(&robot) {
+            eprintln!("Consumer failed on robot {}: {:#}", robot.id, e);
+            break;
+        }
+    }
+}
+
 fn launch_skeleton_producer(
     state: Arc<AtomicUsize>,
     tx: Sender<Skeleton>,
     asm_rate: AssemblingRate,
+    mut rng: StdRng,
+    ids: StepBy<RangeFrom<u64>>,
+    profile: &StageProfile,
+    made: &AtomicU64,
 ) {
-    let mut id = 0_u64;
-    let mut rng = rand::rng();
-
-    while state.load(Ordering::Relaxed) == State::Running as usize {
-        let skeleton = Skeleton {
-            id,
-            hardness: rng.random_range(0..=100),
-        };
+    for id in ids.take_while(|_| state.load(Ordering::Relaxed) == State::Running as usize) {
+        let skeleton = build_skeleton(id, &mut rng);
 
-        thread::sleep(Duration::from_millis(asm_rate.delay));
+        profile.time(Activity::Sleeping, || {
+            thread::sleep(Duration::from_millis(asm_rate.delay))
+        });
 
-        if tx.send(skeleton).is_err() {
+        if profile
+            .time(Activity::BlockedOnSend, || tx.send(skeleton))
+            .is_err()
+        {
             break;
         }
-
-        id += 1;
+        made.fetch_add(1, Ordering::Relaxed);
     }
 }
 
-fn launch_motor_producer(state: Arc<AtomicUsize>, tx: Sender<Motor>, asm_rate: AssemblingRate) {
-    let mut id = 0_u64;
-    let mut rng = rand::rng();
-
-    while state.load(Ordering::Relaxed) == State::Running as usize {
-        let motor = Motor {
-            id,
-            rpm: rng.random_range(0..=1000),
-        };
+fn launch_motor_producer(
+    state: Arc<AtomicUsize>,
+    tx: Sender<Motor>,
+    asm_rate: AssemblingRate,
+    mut rng: StdRng,
+    ids: StepBy<RangeFrom<u64>>,
+    profile: &StageProfile,
+    made: &AtomicU64,
+) {
+    for id in ids.take_while(|_| state.load(Ordering::Relaxed) == State::Running as usize) {
+        let motor = build_motor(id, &mut rng);
 
-        thread::sleep(Duration::from_millis(asm_rate.delay));
+        profile.time(Activity::Sleeping, || {
+            thread::sleep(Duration::from_millis(asm_rate.delay))
+        });
 
-        if tx.send(motor).is_err() {
+        if profile
+            .time(Activity::BlockedOnSend, || tx.send(motor))
+            .is_err()
+        {
             break;
         }
+        made.fetch_add(1, Ordering::Relaxed);
+    }
+}
 
-        id += 1;
+/// Passes on the skeletons `qc` accepts and counts the others in `rejected`. Needs no state
+/// check: it stops when the skeleton producers have all stopped or the robot producer has.
+fn launch_quality_control(
+    rx: Receiver<Skeleton>,
+    tx: Sender<Skeleton>,
+    qc: QcConfig,
+    profile: &StageProfile,
+    rejected: &AtomicU64,
+) {
+    while let Ok(skeleton) = profile.time(Activity::BlockedOnRecv, || rx.recv()) {
+        if !qc.accepts(&skeleton) {
+            rejected.fetch_add(1, Ordering::Relaxed);
+            continue;
+        }
+
+        if profile
+            .time(Activity::BlockedOnSend, || tx.send(skeleton))
+            .is_err()
+        {
+            break;
+        }
     }
 }
 
@@ -184,6 +603,8 @@ fn launch_robot_producer(
     m_rx: Receiver<Motor>,
     tx: Sender<Robot>,
     asm_rate: AssemblingRate,
+    profile: &StageProfile,
+    made: &AtomicU64,
 ) {
     // we don't need bounded buffers here since the channels already provide buffering
     let mut skeleton_buf = VecDeque::<Skeleton>::new();
@@ -193,14 +614,14 @@ fn launch_robot_producer(
 
     while s_open || m_open || (!skeleton_buf.is_empty() && !motor_buf.is_empty()) {
         if s_open {
-            match s_rx.recv() {
+            match profile.time(Activity::BlockedOnRecv, || s_rx.recv()) {
                 Ok(skeleton) => skeleton_buf.push_back(skeleton),
                 Err(_) => s_open = false,
             }
         }
 
         if m_open {
-            match m_rx.recv() {
+            match profile.time(Activity::BlockedOnRecv, || m_rx.recv()) {
                 Ok(motor) => motor_buf.push_back(motor),
                 Err(_) => m_open = false,
             }
@@ -208,21 +629,162 @@ fn launch_robot_producer(
 
         while let (Some(skeleton), Some(motor)) = (skeleton_buf.pop_front(), motor_buf.pop_front())
         {
-            let robot = Robot {
-                id: skeleton.id, // assuming skeleton and motor ids are synchronized
-                skeleton,
-                motor,
-            };
+            let robot = assemble_robot(skeleton, motor);
 
-            thread::sleep(Duration::from_millis(asm_rate.delay));
+            profile.time(Activity::Sleeping, || {
+                thread::sleep(Duration::from_millis(asm_rate.delay))
+            });
 
-            if tx.send(robot).is_err() {
+            if profile
+                .time(Activity::BlockedOnSend, || tx.send(robot))
+                .is_err()
+            {
                 (s_open, m_open) = (false, false);
                 break;
             }
+            made.fetch_add(1, Ordering::Relaxed);
         }
     }
 
     // drain the remaining buffers
     state.store(State::Terminated as usize, Ordering::SeqCst);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    /// Parts of an assembled robot, as seen by the consumer hook
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+    struct Assembled {
+        skeleton: u64,
+        motor: u64,
+        hardness: u8,
+    }
+
+    fn config(capacity: u64, threads: u64, min_hardness: u8) -> Config {
+        let rate = |threads| AssemblingRate {
+            delay: 0,
+            capacity,
+            threads,
+        };
+        Config {
+            assembling_rates: AssemblingRates {
+                skeleton_producer: rate(threads),
+                motor_producer: rate(threads),
+                robot_producer: rate(1),
+            },
+            run: RunConfig { seed: Some(7) },
+            quality_control: QcConfig { min_hardness },
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Runs the threaded pipeline until `robots` robots were consumed, then shuts it down the
+    /// way Ctrl+C does. Fails instead of hanging when the pipeline does not terminate.
+    fn run_until(config: Config, robots: usize) -> (Vec<Assembled>, Throughput, usize) {
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || {
+            let state = Arc::new(AtomicUsize::new(State::Running as usize));
+            let throughput = Throughput::start();
+            let mut assembled = Vec::new();
+            run_pipeline(&config, 0, &state, false, &throughput, |robot| {
+                assembled.push(Assembled {
+                    skeleton: robot.skeleton.id,
+                    motor: robot.motor.id,
+                    hardness: robot.skeleton.hardness,
+                });
+                if assembled.len() == robots {
+                    state.store(State::ShuttingDown as usize, Ordering::SeqCst);
+                }
+                Ok(())
+            });
+            let _ = done_tx.send((assembled, throughput, state.load(Ordering::SeqCst)));
+        });
+        done_rx
+            .recv_timeout(Duration::from_secs(30))
+            .expect("pipeline did not terminate")
+    }
+
+    #[test]
+    fn zero_capacity_pipeline_terminates_and_drains() {
+        let (assembled, throughput, state) = run_until(config(0, 1, 0), 20);
+
+        assert_eq!(state, State::Terminated as usize);
+        assert!(assembled.len() >= 20);
+        // every robot the assembler handed on reached the consumer
+        assert_eq!(
+            throughput.robots.load(Ordering::Relaxed),
+            assembled.len() as u64
+        );
+    }
+
+    #[test]
+    fn producer_ids_never_overlap() {
+        let mut ids: Vec<u64> = (0..3)
+            .flat_map(|index| producer_ids(5, index, 3).take(4))
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (5..17).collect::<Vec<_>>());
+    }
+
+    /// The ids of one producer thread's parts, in the order the consumer got them
+    fn from_producer(ids: impl Iterator<Item = u64>, index: u64, threads: u64) -> Vec<u64> {
+        ids.filter(|id| id % threads == index).collect()
+    }
+
+    #[test]
+    fn several_producers_feed_one_consumer_without_loss_or_duplicates() {
+        let threads = 3;
+        let (assembled, throughput, _) = run_until(config(2, threads, 0), 60);
+
+        assert_eq!(
+            throughput.robots.load(Ordering::Relaxed),
+            assembled.len() as u64
+        );
+        // each producer's parts arrive in order and without gaps, so none were lost or
+        // duplicated in between
+        for index in 0..threads {
+            let skeletons = from_producer(assembled.iter().map(|a| a.skeleton), index, threads);
+            let motors = from_producer(assembled.iter().map(|a| a.motor), index, threads);
+            assert!(!skeletons.is_empty() && !motors.is_empty());
+            for ids in [skeletons, motors] {
+                let expected: Vec<u64> = producer_ids(0, index, threads).take(ids.len()).collect();
+                assert_eq!(ids, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn qc_accepts_skeletons_at_or_above_the_threshold() {
+        let qc = QcConfig { min_hardness: 50 };
+        let skeleton = |hardness| Skeleton { id: 0, hardness };
+
+        assert!(!qc.accepts(&skeleton(0)));
+        assert!(!qc.accepts(&skeleton(49)));
+        assert!(qc.accepts(&skeleton(50)));
+        assert!(qc.accepts(&skeleton(MAX_HARDNESS)));
+        assert!(QcConfig::default().accepts(&skeleton(0)));
+    }
+
+    #[test]
+    fn qc_stage_rejects_soft_skeletons_and_counts_them() {
+        let (assembled, throughput, _) = run_until(config(2, 1, 50), 30);
+
+        assert!(assembled.iter().all(|a| a.hardness >= 50), "{assembled:?}");
+        let rejected = throughput.rejected.load(Ordering::Relaxed);
+        assert!(rejected > 0);
+        // every skeleton handed on was either rejected or assembled, bar a few still in flight
+        let skeletons = throughput.skeletons.load(Ordering::Relaxed);
+        assert!(skeletons >= rejected + assembled.len() as u64);
+        // rejected skeletons leave gaps, but no accepted one is assembled twice or out of order
+        assert!(assembled.windows(2).all(|w| w[0].skeleton < w[1].skeleton));
+    }
+
+    #[test]
+    fn min_hardness_above_the_maximum_is_a_config_error() {
+        let config = config(2, 1, MAX_HARDNESS + 1);
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,79 @@
+use crate::{Motor, Robot, Skeleton};
+use crossbeam_channel::{Receiver, RecvTimeoutError};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Parts and robots each producer has handed on, and skeletons quality control rejected,
+/// counted from the start of the run.
+#[derive(Debug)]
+pub struct Throughput {
+    pub skeletons: AtomicU64,
+    pub rejected: AtomicU64,
+    pub motors: AtomicU64,
+    pub robots: AtomicU64,
+    started: Instant,
+}
+
+/// Receiving ends of the pipeline's channels, only used to read how full each one is.
+pub struct ChannelGauges {
+    pub skeletons: Receiver<Skeleton>,
+    /// Skeletons that passed quality control
+    pub inspected: Receiver<Skeleton>,
+    pub motors: Receiver<Motor>,
+    pub robots: Receiver<Robot>,
+}
+
+impl Throughput {
+    /// Starts the clock the average assembly rate is measured against.
+    pub fn start() -> Self {
+        Self {
+            skeletons: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            motors: AtomicU64::new(0),
+            robots: AtomicU64::new(0),
+            started: Instant::now(),
+        }
+    }
+
+    /// Logs the counters and channel occupancy every second until `stop` disconnects.
+    /// The gauges hold receiver clones, so `stop` must disconnect once the consumer is done,
+    /// before anything waits on a channel to close from the receiving side.
+    pub fn report_until(&self, gauges: ChannelGauges, stop: Receiver<()>) {
+        while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(REPORT_INTERVAL) {
+            tracing::info!(
+                skeletons = self.skeletons.load(Ordering::Relaxed),
+                rejected = self.rejected.load(Ordering::Relaxed),
+                motors = self.motors.load(Ordering::Relaxed),
+                robots = self.robots.load(Ordering::Relaxed),
+                skeleton_channel = %occupancy(&gauges.skeletons),
+                inspected_channel = %occupancy(&gauges.inspected),
+                motor_channel = %occupancy(&gauges.motors),
+                robot_channel = %occupancy(&gauges.robots),
+                "throughput"
+            );
+        }
+    }
+
+    /// Totals and average assembly rate over the whole run, e.g.
+    /// "Totals: 24 skeletons (3 rejected), 12 motors, 6 robots in 12.5s (0.48 robots/s)".
+    pub fn summary(&self) -> String {
+        let elapsed = self.started.elapsed();
+        let robots = self.robots.load(Ordering::Relaxed);
+        format!(
+            "Totals: {} skeletons ({} rejected), {} motors, {} robots in {:.1?} ({:.2} robots/s)",
+            self.skeletons.load(Ordering::Relaxed),
+            self.rejected.load(Ordering::Relaxed),
+            self.motors.load(Ordering::Relaxed),
+            robots,
+            elapsed,
+            robots as f64 / elapsed.as_secs_f64()
+        )
+    }
+}
+
+/// "queued/capacity", matching the lockstep log
+fn occupancy<T>(rx: &Receiver<T>) -> String {
+    format!("{}/{}", rx.len(), rx.capacity().unwrap_or(0))
+}
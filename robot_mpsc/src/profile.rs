@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Activity {
+    Sleeping,
+    BlockedOnSend,
+    BlockedOnRecv,
+    Writing,
+}
+
+impl Activity {
+    const ALL: [Activity; 4] = [
+        Activity::Sleeping,
+        Activity::BlockedOnSend,
+        Activity::BlockedOnRecv,
+        Activity::Writing,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            Activity::Sleeping => "sleeping",
+            Activity::BlockedOnSend => "blocked-on-send",
+            Activity::BlockedOnRecv => "blocked-on-recv",
+            Activity::Writing => "writing",
+        }
+    }
+}
+
+/// Time a pipeline stage spent on each activity, accumulated only when profiling is enabled.
+#[derive(Debug)]
+pub struct StageProfile {
+    name: &'static str,
+    enabled: bool,
+    activity_nanos: [AtomicU64; Activity::ALL.len()],
+    lifetime_nanos: AtomicU64,
+}
+
+impl StageProfile {
+    pub fn new(name: &'static str, enabled: bool) -> Self {
+        Self {
+            name,
+            enabled,
+            activity_nanos: Default::default(),
+            lifetime_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs `f`, charging its duration to `activity`.
+    pub fn time<T>(&self, activity: Activity, f: impl FnOnce() -> T) -> T {
+        if !self.enabled {
+            return f();
+        }
+
+        let start = Instant::now();
+        let result = f();
+        self.activity_nanos[activity as usize]
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        result
+    }
+
+    /// Runs the whole stage, recording its lifetime so untracked time shows up as "other".
+    /// A stage running on several threads adds up their lifetimes, like their activities.
+    pub fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.lifetime_nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        result
+    }
+
+    /// One-line breakdown, e.g. "skeleton producer: 80.0% sleeping, 15.0% blocked-on-send, 5.0% other".
+    pub fn report(&self) -> String {
+        let lifetime = self.lifetime_nanos.load(Ordering::Relaxed);
+        if lifetime == 0 {
+            return format!("{}: no time recorded", self.name);
+        }
+
+        let mut parts = Vec::new();
+        let mut tracked = 0_u64;
+        for activity in Activity::ALL {
+            let nanos = self.activity_nanos[activity as usize].load(Ordering::Relaxed);
+            if nanos > 0 {
+                tracked += nanos;
+                parts.push(format!(
+                    "{:.1}% {}",
+                    percent(nanos, lifetime),
+                    activity.label()
+                ));
+            }
+        }
+        let other = lifetime.saturating_sub(tracked);
+        parts.push(format!("{:.1}% other", percent(other, lifetime)));
+
+        format!(
+            "{}: {} (over {:.1?})",
+            self.name,
+            parts.join(", "),
+            Duration::from_nanos(lifetime)
+        )
+    }
+}
+
+fn percent(part: u64, whole: u64) -> f64 {
+    part as f64 / whole as f64 * 100.0
+}
@@ -0,0 +1,127 @@
+use crate::Robot;
+use serde::Deserialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum SinkConfig {
+    Stdout,
+    File { path: PathBuf, checkpoint: PathBuf },
+}
+
+/// Destination the consumer persists assembled robots to.
+pub trait RobotSink {
+    /// Id of the first robot this sink still needs, or `None` if it keeps no checkpoint
+    fn next_id(&self) -> Option<u64>;
+
+    /// Persists the robot and acknowledges it; once this returns the robot is never rewritten
+    fn write(&mut self, robot: &Robot) -> anyhow::Result<()>;
+}
+
+pub fn open_sink(config: &SinkConfig) -> anyhow::Result<Box<dyn RobotSink>> {
+    match config {
+        SinkConfig::Stdout => Ok(Box::new(StdoutSink)),
+        SinkConfig::File { path, checkpoint } => Ok(Box::new(FileSink::open(path, checkpoint)?)),
+    }
+}
+
+/// Prints robots as they arrive; nothing survives a restart, so it has no checkpoint.
+pub struct StdoutSink;
+
+impl RobotSink for StdoutSink {
+    fn next_id(&self) -> Option<u64> {
+        None
+    }
+
+    fn write(&mut self, robot: &Robot) -> anyhow::Result<()> {
+        println!("Assembled robot: {}", robot);
+        Ok(())
+    }
+}
+
+/// Appends one line per robot to a file, recording the last acknowledged robot id and the
+/// file length after it in a checkpoint once the line is on disk.
+pub struct FileSink {
+    file: File,
+    checkpoint: PathBuf,
+    next_id: u64,
+}
+
+impl FileSink {
+    /// Opens the sink and recovers from the checkpoint: anything written past the last
+    /// acknowledged robot (a torn line or an unacknowledged one) is truncated away, so those
+    /// robots are written exactly once when they are produced again.
+    pub fn open(path: &Path, checkpoint: &Path) -> anyhow::Result<Self> {
+        let (next_id, len) = match fs::read_to_string(checkpoint) {
+            Ok(contents) => parse_checkpoint(&contents)
+                .ok_or_else(|| anyhow::anyhow!("malformed checkpoint {}", checkpoint.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (0, 0),
+            Err(e) => return Err(e.into()),
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        file.set_len(len)?;
+        file.seek(SeekFrom::End(0))?;
+
+        if next_id > 0 {
+            eprintln!(
+                "Recovered sink {}: resuming after robot {}",
+                path.display(),
+                next_id - 1
+            );
+        }
+
+        Ok(Self {
+            file,
+            checkpoint: checkpoint.to_path_buf(),
+            next_id,
+        })
+    }
+
+    // write-then-rename so a crash never leaves a half-written checkpoint behind
+    fn save_checkpoint(&self, last_id: u64, len: u64) -> anyhow::Result<()> {
+        let mut tmp_name = self.checkpoint.as_os_str().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp = PathBuf::from(tmp_name);
+
+        let mut f = File::create(&tmp)?;
+        writeln!(f, "{} {}", last_id, len)?;
+        f.sync_all()?;
+        fs::rename(&tmp, &self.checkpoint)?;
+        Ok(())
+    }
+}
+
+impl RobotSink for FileSink {
+    fn next_id(&self) -> Option<u64> {
+        Some(self.next_id)
+    }
+
+    fn write(&mut self, robot: &Robot) -> anyhow::Result<()> {
+        writeln!(self.file, "{}", robot)?;
+        self.file.sync_data()?;
+
+        let len = self.file.stream_position()?;
+        self.save_checkpoint(robot.id, len)?;
+        self.next_id = robot.id + 1;
+        Ok(())
+    }
+}
+
+// checkpoint format: "<last_acked_id> <file_len>"
+fn parse_checkpoint(contents: &str) -> Option<(u64, u64)> {
+    let mut fields = contents.split_whitespace();
+    let last_id = fields.next()?.parse::<u64>().ok()?;
+    let len = fields.next()?.parse::<u64>().ok()?;
+    Some((last_id + 1, len))
+}
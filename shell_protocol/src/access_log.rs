@@ -0,0 +1,64 @@
+use crate::Response;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Appends one line per request to the file given with `--access-log`, e.g.
+/// `ts=1700000000.123 client=127.0.0.1:50000 request=Upload outcome=Ok`. A failed write is
+/// reported on stderr and otherwise ignored, so a full disk never costs a client its reply.
+#[derive(Debug)]
+pub struct AccessLogger {
+    file: Mutex<File>,
+    /// Set while writes fail, so a broken log is reported once rather than per request
+    failing: AtomicBool,
+}
+
+impl AccessLogger {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("cannot open access log {}: {}", path.display(), e))?;
+        Ok(AccessLogger {
+            file: Mutex::new(file),
+            failing: AtomicBool::new(false),
+        })
+    }
+
+    /// Logs a request named `request` from `client`, answered with `resp`.
+    pub fn record(&self, client: SocketAddr, request: &str, resp: &Response) {
+        let outcome = match resp {
+            Response::Error(_) => "Error",
+            _ => "Ok",
+        };
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // formatted up front so each line reaches the file in a single write
+        let line = format!(
+            "ts={}.{:03} client={} request={} outcome={}\n",
+            ts.as_secs(),
+            ts.subsec_millis(),
+            client,
+            request,
+            outcome
+        );
+
+        // a panic mid-write leaves the file usable, so keep using it
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        match file.write_all(line.as_bytes()) {
+            Ok(()) => self.failing.store(false, Ordering::Relaxed),
+            Err(e) => {
+                if !self.failing.swap(true, Ordering::Relaxed) {
+                    eprintln!("Access log write failed: {}", e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use crate::hash::to_hex;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Accounts a server accepts, loaded from a credentials file with one `user:salt:hash` line
+/// per account, where `hash` is the hex SHA-256 of the salt followed by the password. Blank
+/// lines and lines starting with `#` are ignored.
+#[derive(Debug, Default)]
+pub struct Credentials {
+    users: HashMap<String, (String, String)>,
+}
+
+impl Credentials {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read credentials {}: {}", path.display(), e))?;
+        let mut users = HashMap::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(3, ':');
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(user), Some(salt), Some(hash)) if !user.is_empty() && hash.len() == 64 => {
+                    users.insert(user.to_string(), (salt.to_string(), hash.to_lowercase()));
+                }
+                _ => {
+                    return Err(format!(
+                        "{}:{}: expected user:salt:sha256-hex",
+                        path.display(),
+                        i + 1
+                    ));
+                }
+            }
+        }
+        Ok(Credentials { users })
+    }
+
+    /// Checks a password against the stored hash. An unknown user is hashed all the same, so
+    /// a failed login takes as long whether or not the account exists.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        let (salt, expected) = match self.users.get(user) {
+            Some((salt, hash)) => (salt.as_str(), Some(hash)),
+            None => ("", None),
+        };
+        let digest = to_hex(
+            &Sha256::new()
+                .chain_update(salt)
+                .chain_update(password)
+                .finalize(),
+        );
+        expected.is_some_and(|expected| constant_time_eq(expected.as_bytes(), digest.as_bytes()))
+    }
+}
+
+/// Compares without stopping at the first difference, so timing does not reveal how much of
+/// a guessed hash was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
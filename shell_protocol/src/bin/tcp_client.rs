@@ -1,132 +1,703 @@
-use bincode::config::standard;
-use bincode::serde::{decode_from_std_read, encode_into_std_write};
-use shell_protocol::{DirEntry, Request, Response};
+use rustls::ClientConfig;
+use shell_protocol::cli::{
+    DIR_SORT_KEYS, apply_cd, check_feature, parse_touch_args, print_df_table, print_stat, prompt,
+    sort_dir_list,
+};
+use shell_protocol::client::{
+    ShellClient, UploadOptions, download_request, host_of, partial_path, upload_request,
+};
+use shell_protocol::hash::{HashAlgo, hash_file};
+use shell_protocol::tls::{Transport, client_config};
+use shell_protocol::{DirEntry, Request, Response, ShellError};
 use std::fs::File;
 use std::io::{self, BufRead, Read, Write};
-use std::net::TcpStream;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
 
-fn send_request(stream: &mut TcpStream, req: &Request) -> io::Result<Response> {
-    encode_into_std_write(req, stream, standard())
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("encode error: {e}")))?;
-    let resp: Response = decode_from_std_read(stream, standard())
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("decode error: {e}")))?;
-    Ok(resp)
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+const CONNECT_RETRY_DELAY_MS: u64 = 1000;
+
+/// The message for a failed request: the server's own error when it sent one.
+fn reason(e: &io::Error) -> String {
+    match e
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<ShellError>())
+    {
+        Some(shell_error) => shell_error.to_string(),
+        None => e.to_string(),
+    }
 }
 
-fn do_upload(stream: &mut TcpStream, local_path: &str, remote_folder: &str) -> io::Result<()> {
-    let mut f = File::open(local_path)?;
-    let metadata = f.metadata()?;
-    let size = metadata.len();
-    let filename = std::path::Path::new(local_path)
-        .file_name()
-        .and_then(|os| os.to_str())
-        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid filename"))?
-        .to_string();
+fn do_upload(
+    client: &mut ShellClient,
+    local_path: &str,
+    remote_folder: &str,
+    options: UploadOptions,
+) -> io::Result<()> {
+    let path = Path::new(local_path);
+    let req = upload_request(path, remote_folder, options)?;
+    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+    send_upload(client, &req, local_path, &filename, options.compressed)
+}
 
+/// Uploads everything left on `input`, up to its end, as the remote file `remote_path`.
+/// The size is not known up front, so the data goes out in chunks and, unlike a file
+/// upload, cannot be checksummed or resumed.
+fn do_upload_stdin(
+    client: &mut ShellClient,
+    input: &mut impl Read,
+    remote_path: &str,
+    options: UploadOptions,
+) -> io::Result<()> {
+    let (remote_folder, filename) = remote_path.rsplit_once('/').unwrap_or((".", remote_path));
     let req = Request::Upload {
         dst_path: remote_folder.to_string(),
-        file_name: filename.clone(),
-        size,
+        file_name: filename.to_string(),
+        size: None,
+        atomic: options.atomic,
+        overwrite: options.overwrite,
+        append: options.append,
+        sha256: None,
+        compressed: false,
     };
+    client.start_upload(&req).inspect_err(|e| {
+        eprintln!("Upload error: {}", reason(e));
+    })?;
+
+    match client.stream_upload_chunked(input) {
+        Ok((bytes_written, Response::UploadComplete { .. })) => {
+            println!("Uploaded {} ({} bytes)", filename, bytes_written);
+            Ok(())
+        }
+        Ok((_, Response::Error(msg))) => {
+            eprintln!("Upload error: {}", msg);
+            Err(io::Error::other(msg))
+        }
+        Ok((_, other)) => {
+            eprintln!("Unexpected response to upload: {:?}", other);
+            Err(io::Error::other("Unexpected response"))
+        }
+        Err(e) => {
+            eprintln!("Upload interrupted: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Throws away the rest of stdin after a failed `upload -`, so the data meant for the upload
+/// is not taken for commands.
+fn discard_input(input: &mut impl Read) {
+    let _ = io::copy(input, &mut io::sink());
+}
 
-    // Send the upload request
-    encode_into_std_write(&req, stream, standard())
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("encode error: {e}")))?;
+/// Continues an interrupted upload on this connection: the server reports how much of the
+/// file it already holds and only the rest of `local_path` is sent.
+fn do_resume_upload(
+    client: &mut ShellClient,
+    id: u64,
+    local_path: &str,
+    compressed: bool,
+) -> io::Result<()> {
+    let filename = Path::new(local_path)
+        .file_name()
+        .map(|os| os.to_string_lossy().to_string())
+        .unwrap_or_else(|| local_path.to_string());
+    let req = Request::UploadResume {
+        id,
+        size: std::fs::metadata(local_path)?.len(),
+        compressed,
+    };
+    send_upload(client, &req, local_path, &filename, compressed)
+}
 
-    // Wait for server acknowledgment
-    let resp: Response = decode_from_std_read(stream, standard())
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("decode error: {e}")))?;
+/// Sends an `Upload` or `UploadResume` request, streams the file from the offset the
+/// server replies with, and waits for the server to confirm it stored and checked the data.
+/// If the connection drops on the way, prints how to resume the upload later.
+fn send_upload(
+    client: &mut ShellClient,
+    req: &Request,
+    local_path: &str,
+    filename: &str,
+    compressed: bool,
+) -> io::Result<()> {
+    let mut f = File::open(local_path)?;
+    let session = client.start_upload(req).inspect_err(|e| {
+        eprintln!("Upload error: {}", reason(e));
+    })?;
+    if session.received > 0 {
+        println!("Server already holds {} bytes, resuming", session.received);
+    }
 
-    match resp {
-        Response::Ok => {
-            // Server is ready, now send the file data
-            let bytes_written = io::copy(&mut f, stream)?;
-            stream.flush()?;
+    match client.stream_upload(&mut f, session.received, compressed) {
+        Ok((bytes_written, Response::UploadComplete { .. })) => {
             println!("Uploaded {} ({} bytes)", filename, bytes_written);
+            Ok(())
         }
-        Response::Error(msg) => {
+        Ok((_, Response::Error(msg))) => {
             eprintln!("Upload error: {}", msg);
-            return Err(io::Error::new(io::ErrorKind::Other, msg));
+            Err(io::Error::other(msg))
+        }
+        Ok((_, other)) => {
+            eprintln!("Unexpected response to upload: {:?}", other);
+            Err(io::Error::other("Unexpected response"))
+        }
+        Err(e) => {
+            eprintln!(
+                "Upload interrupted: {}. Reconnect and run `resume-upload {:016x} {}` to continue",
+                e, session.id, local_path
+            );
+            Err(e)
+        }
+    }
+}
+
+fn do_download(
+    client: &mut ShellClient,
+    remote_path: &str,
+    local_folder: &str,
+    keep_partial: bool,
+    compressed: bool,
+) -> io::Result<()> {
+    let req = download_request(remote_path, Path::new(local_folder), compressed);
+    fetch_file(client, &req, local_folder, keep_partial)
+}
+
+/// Fetches a file into `local_folder`, printing progress as it arrives.
+fn fetch_file(
+    client: &mut ShellClient,
+    req: &Request,
+    local_folder: &str,
+    keep_partial: bool,
+) -> io::Result<()> {
+    if let Request::Download { offset, .. } = req
+        && *offset > 0
+    {
+        println!("Resuming at byte {}", offset);
+    }
+    let result = client.fetch(req, Path::new(local_folder), keep_partial, print_progress);
+    match result {
+        Ok(local_path) => {
+            println!(
+                "Downloaded {} ({} bytes) → {}",
+                local_path.file_name().unwrap_or_default().to_string_lossy(),
+                std::fs::metadata(&local_path)?.len(),
+                local_path.display()
+            );
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Download failed: {}", reason(&e));
+            Err(e)
+        }
+    }
+}
+
+/// Writes the file `req` fetches to stdout, so it can be piped into another program.
+/// Progress and the outcome go to stderr to keep the data clean.
+fn do_download_stdout(client: &mut ShellClient, req: &Request) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    match client.fetch_to(req, &mut out, |name, received, size| {
+        eprintln!("{}", progress_line(name, received, size))
+    }) {
+        Ok((name, size)) => {
+            eprintln!("Downloaded {} ({} bytes) → stdout", name, size);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("Download failed: {}", reason(&e));
+            Err(e)
+        }
+    }
+}
+
+fn print_progress(name: &str, received: u64, size: Option<u64>) {
+    println!("{}", progress_line(name, received, size));
+}
+
+/// A streamed file has no size to measure progress against, so only its bytes are counted.
+fn progress_line(name: &str, received: u64, size: Option<u64>) -> String {
+    match size {
+        Some(size) => format!(
+            "Downloading {}: {}/{} bytes ({:.2}%)",
+            name,
+            received,
+            size,
+            (received as f64 / size as f64) * 100.0
+        ),
+        None => format!("Downloading {}: {} bytes", name, received),
+    }
+}
+
+/// Downloads several files in one request. By default their bytes are concatenated, in the
+/// order given, into the single file `local_target`; with `separate` each is saved under its
+/// own name inside the folder `local_target`.
+fn do_getmany(
+    client: &mut ShellClient,
+    remote_paths: &[&str],
+    local_target: &str,
+    separate: bool,
+    keep_partial: bool,
+) -> io::Result<()> {
+    let req = Request::DownloadMany {
+        paths: remote_paths.iter().map(|path| path.to_string()).collect(),
+    };
+    let files = match client.request(&req)? {
+        Response::BatchMetadata { files } => files,
+        Response::Error(msg) => {
+            eprintln!("Download error: {}", msg);
+            return Err(io::Error::other(msg));
+        }
+        other => {
+            eprintln!("Unexpected response: {:?}", other);
+            return Err(io::Error::other("Unexpected response"));
+        }
+    };
+    let total: u64 = files.iter().map(|file| file.size).sum();
+    println!("Downloading {} files ({} bytes)", files.len(), total);
+
+    if separate {
+        std::fs::create_dir_all(local_target)?;
+        for file in &files {
+            let local_path = Path::new(local_target).join(&file.name);
+            let mut f = File::create(&local_path)?;
+            let result = client.receive_into(&mut f, file.size, |received| {
+                print_progress(&file.name, received, Some(file.size))
+            });
+            drop(f);
+            if let Err(e) = result {
+                eprintln!("Download failed: {}", e);
+                discard_partial(&local_path, keep_partial);
+                return Err(e);
+            }
         }
-        _ => {
-            eprintln!("Unexpected response to upload: {:?}", resp);
-            return Err(io::Error::new(io::ErrorKind::Other, "Unexpected response"));
+        println!("Saved {} files into {}", files.len(), local_target);
+    } else {
+        let local_path = Path::new(local_target);
+        let mut f = File::create(local_path)?;
+        let result = files.iter().try_for_each(|file| {
+            client.receive_into(&mut f, file.size, |received| {
+                print_progress(&file.name, received, Some(file.size))
+            })
+        });
+        drop(f);
+        if let Err(e) = result {
+            eprintln!("Download failed: {}", e);
+            discard_partial(local_path, keep_partial);
+            return Err(e);
         }
+        println!("Concatenated {} files into {}", files.len(), local_target);
     }
     Ok(())
 }
 
-fn do_download(stream: &mut TcpStream, remote_path: &str, local_folder: &str) -> io::Result<()> {
-    let req = Request::Download {
-        src_path: remote_path.to_string(),
+/// Deals with the local file of a failed batch download: removed by default so a truncated
+/// file never looks complete, or with `keep_partial` renamed to `<name>.partial` for inspection.
+fn discard_partial(local_path: &Path, keep_partial: bool) {
+    let result = if keep_partial {
+        let partial = partial_path(local_path);
+        std::fs::rename(local_path, &partial)
+            .map(|_| eprintln!("Kept partial download as {}", partial.display()))
+    } else {
+        std::fs::remove_file(local_path)
     };
+    if let Err(e) = result {
+        eprintln!("Cannot clean up {}: {}", local_path.display(), e);
+    }
+}
 
-    // Send download request
-    encode_into_std_write(&req, stream, standard())
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("encode error: {e}")))?;
+/// Subscribes to changes under a remote directory and prints them until the connection ends.
+/// Returns an error only if the server refused the watch, in which case the connection is still usable.
+fn do_watch(client: &mut ShellClient, remote_path: &str) -> io::Result<()> {
+    let req = Request::Watch {
+        path: remote_path.to_string(),
+    };
+
+    match client.request(&req)? {
+        Response::Ok => println!("Watching {} (Ctrl-C to stop)", remote_path),
+        Response::Error(msg) => {
+            eprintln!("Watch error: {}", msg);
+            return Err(io::Error::other(msg));
+        }
+        other => {
+            eprintln!("Unexpected response: {:?}", other);
+            return Err(io::Error::other("Unexpected response"));
+        }
+    }
 
-    // Wait for server response with metadata
-    let resp: Response = decode_from_std_read(stream, standard())
-        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("decode error: {e}")))?;
+    // the server only stops streaming by closing the connection
+    while let Ok(event) = client.receive() {
+        match event {
+            Response::FsEvent { kind, path } => println!("[{}] {}", kind, path),
+            other => println!("Unexpected event: {:?}", other),
+        }
+    }
+    Ok(())
+}
 
-    println!("Response: {:?}", resp);
+/// Requests a checksum manifest of a remote directory and writes it to `manifest.txt` in
+/// `local_folder`, one `<relative_path>\t<size>\t<sha256>` line per file.
+fn do_manifest(client: &mut ShellClient, remote_path: &str, local_folder: &str) -> io::Result<()> {
+    if let Err(e) = std::fs::create_dir_all(local_folder) {
+        eprintln!("Cannot create local folder {}: {}", local_folder, e);
+        return Err(e);
+    }
+    let local_path = std::path::Path::new(local_folder).join("manifest.txt");
 
-    match resp {
-        Response::FileMetadata { name, size } => {
-            let local_path = std::path::Path::new(local_folder).join(&name);
-            println!("Downloading {} to {}", name, local_path.display());
+    let req = Request::Manifest {
+        path: remote_path.to_string(),
+    };
+    client.send(&req)?;
 
-            // create parent dir if needed
-            if let Some(parent) = local_path.parent() {
-                let _ = std::fs::create_dir_all(parent);
+    // entries are written as they arrive so large trees never sit in memory
+    let mut out = io::BufWriter::new(File::create(&local_path)?);
+    loop {
+        let resp = client.receive()?;
+        match resp {
+            Response::ManifestEntry { path, size, sha256 } => {
+                writeln!(out, "{}\t{}\t{}", path, size, sha256)?;
             }
-            let mut f = File::create(&local_path)?;
-
-            let mut remaining = size;
-            let mut buf = [0u8; 8192];
-            let mut total_read = 0u64;
-
-            while remaining > 0 {
-                let to_read = std::cmp::min(buf.len() as u64, remaining) as usize;
-                let n = stream.read(&mut buf[..to_read])?;
-                println!("Read {} bytes", n);
-                if n == 0 {
-                    return Err(io::Error::new(
-                        io::ErrorKind::UnexpectedEof,
-                        format!("Connection closed after {} of {} bytes", total_read, size),
-                    ));
-                }
-                f.write_all(&buf[..n])?;
-                remaining -= n as u64;
-                total_read += n as u64;
+            Response::ManifestEnd { files } => {
+                out.flush()?;
                 println!(
-                    "Downloading {}: {}/{} bytes ({:.2}%)",
-                    name,
-                    total_read,
-                    size,
-                    (total_read as f64 / size as f64) * 100.0
+                    "Wrote manifest of {} ({} files) → {}",
+                    remote_path,
+                    files,
+                    local_path.display()
                 );
+                return Ok(());
+            }
+            Response::Error(msg) => {
+                drop(out);
+                let _ = std::fs::remove_file(&local_path);
+                eprintln!("Manifest error: {}", msg);
+                return Err(io::Error::other(msg));
+            }
+            other => {
+                eprintln!("Unexpected response: {:?}", other);
+                return Err(io::Error::other("Unexpected response"));
+            }
+        }
+    }
+}
+
+/// Recursively downloads `remote_path` into `local_folder`, recreating its subdirectories.
+/// The file list comes from a manifest; files whose local copy already has the same size
+/// and SHA-256 are skipped, so re-running only fetches what changed.
+fn do_mirror(
+    client: &mut ShellClient,
+    remote_path: &str,
+    local_folder: &str,
+    keep_partial: bool,
+    dry_run: bool,
+) -> io::Result<()> {
+    let req = Request::Manifest {
+        path: remote_path.to_string(),
+    };
+    client.send(&req)?;
+
+    // the whole manifest is read first, since downloads can't start mid-stream
+    let mut entries = Vec::new();
+    loop {
+        let resp = client.receive()?;
+        match resp {
+            Response::ManifestEntry { path, size, sha256 } => entries.push((path, size, sha256)),
+            Response::ManifestEnd { .. } => break,
+            Response::Error(msg) => {
+                eprintln!("Mirror error: {}", msg);
+                return Err(io::Error::other(msg));
             }
-            f.flush()?;
+            other => {
+                eprintln!("Unexpected response: {:?}", other);
+                return Err(io::Error::other("Unexpected response"));
+            }
+        }
+    }
+
+    let (mut downloaded, mut skipped, mut bytes) = (0u64, 0u64, 0u64);
+    for (path, size, sha256) in entries {
+        let rel = std::path::Path::new(&path);
+        // never let a manifest path write outside the local folder
+        if !rel
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+        {
+            eprintln!("Skipping unsafe path {}", path);
+            continue;
+        }
+        let local_path = std::path::Path::new(local_folder).join(rel);
+
+        let up_to_date = std::fs::metadata(&local_path).is_ok_and(|m| m.len() == size)
+            && hash_file(&local_path, HashAlgo::Sha256).is_ok_and(|(_, digest)| digest == sha256);
+        if up_to_date {
+            skipped += 1;
+            continue;
+        }
+
+        let local_dir = local_path
+            .parent()
+            .unwrap_or(std::path::Path::new(local_folder));
+        let remote_file = format!("{}/{}", remote_path.trim_end_matches('/'), path);
+        if dry_run {
             println!(
-                "Downloaded {} ({} bytes) → {}",
-                name,
+                "[dry-run] would download {} ({} bytes) into {}",
+                remote_file,
                 size,
-                local_path.display()
+                local_dir.display()
             );
+            downloaded += 1;
+            bytes += size;
+            continue;
+        }
+        do_download(
+            client,
+            &remote_file,
+            &local_dir.to_string_lossy(),
+            keep_partial,
+            false,
+        )?;
+        downloaded += 1;
+        bytes += size;
+    }
+
+    if dry_run {
+        println!(
+            "[dry-run] mirror {} → {}: {} to download, {} up to date, {} bytes",
+            remote_path, local_folder, downloaded, skipped, bytes
+        );
+        return Ok(());
+    }
+    println!(
+        "Mirrored {} → {}: {} downloaded, {} skipped, {} bytes",
+        remote_path, local_folder, downloaded, skipped, bytes
+    );
+    Ok(())
+}
+
+/// Searches a remote directory and prints each hit as it arrives, as `path` or, with a
+/// content filter, `path:line_number: line`.
+fn do_search(
+    client: &mut ShellClient,
+    remote_path: &str,
+    name_glob: Option<String>,
+    content_regex: Option<String>,
+) -> io::Result<()> {
+    let req = Request::Search {
+        path: remote_path.to_string(),
+        name_glob,
+        content_regex,
+    };
+    client.send(&req)?;
+
+    loop {
+        let resp = client.receive()?;
+        match resp {
+            Response::SearchHit {
+                path,
+                line_number: Some(line_number),
+                line: Some(line),
+            } => println!("{}:{}: {}", path, line_number, line),
+            Response::SearchHit { path, .. } => println!("{}", path),
+            Response::SearchEnd { hits, truncated } => {
+                if truncated {
+                    println!("{} hits (limit reached, more files match)", hits);
+                } else {
+                    println!("{} hits", hits);
+                }
+                return Ok(());
+            }
+            Response::Error(msg) => {
+                eprintln!("Search error: {}", msg);
+                return Err(io::Error::other(msg));
+            }
+            other => {
+                eprintln!("Unexpected response: {:?}", other);
+                return Err(io::Error::other("Unexpected response"));
+            }
         }
+    }
+}
+
+/// Prints lines appended to a remote file as they arrive, until the connection ends.
+/// Returns an error only if the server refused the follow, in which case the connection is still usable.
+fn do_follow(client: &mut ShellClient, remote_path: &str) -> io::Result<()> {
+    let req = Request::Follow {
+        path: remote_path.to_string(),
+    };
+
+    match client.request(&req)? {
+        Response::Ok => println!("Following {} (Ctrl-C to stop)", remote_path),
         Response::Error(msg) => {
-            eprintln!("Download error: {}", msg);
-            return Err(io::Error::new(io::ErrorKind::Other, msg));
+            eprintln!("Follow error: {}", msg);
+            return Err(io::Error::other(msg));
+        }
+        other => {
+            eprintln!("Unexpected response: {:?}", other);
+            return Err(io::Error::other("Unexpected response"));
+        }
+    }
+
+    while let Ok(frame) = client.receive() {
+        match frame {
+            Response::Line { text } => println!("{}", text),
+            other => println!("Unexpected frame: {:?}", other),
+        }
+    }
+    Ok(())
+}
+
+/// Hashes `local_path` and asks the server for the digest of `remote_path` with the same
+/// algorithm, printing MATCH or MISMATCH.
+fn do_verify(
+    client: &mut ShellClient,
+    remote_path: &str,
+    local_path: &str,
+    algo: HashAlgo,
+) -> io::Result<()> {
+    let (_, local_digest) = hash_file(std::path::Path::new(local_path), algo)?;
+
+    let req = Request::Hash {
+        path: remote_path.to_string(),
+        algo,
+    };
+    match client.request(&req)? {
+        Response::Hash { digest } if digest == local_digest => {
+            println!("MATCH ({} {})", algo.name(), digest);
+        }
+        Response::Hash { digest } => {
+            println!("MISMATCH ({})", algo.name());
+            println!("  remote {}  {}", digest, remote_path);
+            println!("  local  {}  {}", local_digest, local_path);
         }
-        _ => {
-            eprintln!("Unexpected response: {:?}", resp);
-            return Err(io::Error::new(io::ErrorKind::Other, "Unexpected response"));
+        Response::Error(msg) => return Err(io::Error::other(msg)),
+        other => {
+            return Err(io::Error::other(format!(
+                "Unexpected response: {:?}",
+                other
+            )));
         }
     }
     Ok(())
 }
 
+/// Connects to `addr`, giving each resolved address `timeout` to answer, and retries the
+/// whole attempt up to `retries` more times before giving up. With `tls`, the connection is
+/// encrypted and the server's certificate must be valid for the host part of `addr`.
+/// `framed` selects the length-prefixed messages of the async server.
+fn connect(
+    addr: &str,
+    timeout: Duration,
+    retries: u64,
+    tls: Option<&Arc<ClientConfig>>,
+    framed: bool,
+) -> io::Result<ShellClient> {
+    let socket_addrs = addr.to_socket_addrs()?.collect::<Vec<_>>();
+    if socket_addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} did not resolve to any address", addr),
+        ));
+    }
+
+    let mut attempt = 0;
+    loop {
+        let mut last_err = None;
+        for socket_addr in &socket_addrs {
+            match TcpStream::connect_timeout(socket_addr, timeout) {
+                Ok(stream) => {
+                    let client = ShellClient::new(Transport::connect(stream, host_of(addr), tls)?);
+                    return Ok(if framed { client.framed() } else { client });
+                }
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::TimedOut {
+                        eprintln!(
+                            "Connecting to {} timed out after {}s",
+                            socket_addr,
+                            timeout.as_secs()
+                        );
+                    } else {
+                        eprintln!("Connecting to {} failed: {}", socket_addr, e);
+                    }
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        if attempt >= retries {
+            // `socket_addrs` is non-empty, so at least one attempt recorded an error
+            return Err(last_err.unwrap());
+        }
+        attempt += 1;
+        eprintln!("Retrying ({}/{})", attempt, retries);
+        thread::sleep(Duration::from_millis(CONNECT_RETRY_DELAY_MS));
+    }
+}
+
+/// What a command that changes files would do, for `--dry-run`; `None` for commands that
+/// only read, which still run so listings and enumeration keep working.
+fn dry_run_description(cmd: &str, args: &[&str]) -> Option<String> {
+    let positional: Vec<&str> = args
+        .iter()
+        .copied()
+        .filter(|a| !a.starts_with("--"))
+        .collect();
+    let desc = match (cmd, positional.as_slice()) {
+        ("mkdir", [name]) => format!("create directory {}", name),
+        ("mktemp", _) => "create an empty temporary file".to_string(),
+        ("touch", [.., path]) => format!("set the modification time of {}", path),
+        ("copy", [src, dst]) => format!("copy {} to {}", src, dst),
+        ("ln", [target, link]) => format!("hard-link {} to {}", link, target),
+        ("swap", [a, b]) => format!("swap {} and {}", a, b),
+        ("del" | "rm", [path]) => format!("delete {}", path),
+        ("move" | "mv", [src, dst]) => format!("move {} to {}", src, dst),
+        ("resume-upload", [id, local, ..]) => format!("resume upload {} from {}", id, local),
+        ("upload", ["-", remote, ..]) => format!("upload stdin to {}", remote),
+        ("upload", [local, remote, ..]) => match std::fs::metadata(local) {
+            Ok(meta) => format!("upload {} ({} bytes) to {}", local, meta.len(), remote),
+            Err(e) => format!("upload {} to {} (cannot read it: {})", local, remote, e),
+        },
+        ("download" | "download-tar", [remote, "-", ..]) => {
+            format!("download {} to stdout", remote)
+        }
+        ("download" | "download-zip" | "download-tar", [remote, local, ..]) => {
+            format!("download {} into {}", remote, local)
+        }
+        ("download" | "download-zip" | "download-tar", [remote]) => {
+            format!("download {} into .", remote)
+        }
+        ("getmany", [local, remotes @ ..]) => {
+            format!("download {} into {}", remotes.join(", "), local)
+        }
+        (
+            "mkdir" | "touch" | "copy" | "ln" | "swap" | "del" | "rm" | "move" | "mv" | "upload"
+            | "download" | "download-zip" | "download-tar" | "getmany",
+            _,
+        ) => format!("run `{}`", [&[cmd], args].concat().join(" ")),
+        _ => return None,
+    };
+    Some(desc)
+}
+
+/// Prompts for a user name and password and logs in with them. Called when the server
+/// advertises `auth`, since it answers nothing else until a login has succeeded.
+fn log_in(
+    client: &mut ShellClient,
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+) -> io::Result<()> {
+    let mut ask = |prompt: &str| -> io::Result<String> {
+        eprint!("{}", prompt);
+        match lines.next() {
+            Some(line) => Ok(line?.trim().to_string()),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "No input")),
+        }
+    };
+    let user = ask("Username: ")?;
+    let password = ask("Password: ")?;
+    client.log_in(&user, &password)
+}
+
 fn print_dir_list(list: Vec<DirEntry>) {
     for e in list {
         println!("{}{}", e.name, if e.is_dir { "/" } else { "" });
@@ -134,50 +705,231 @@ fn print_dir_list(list: Vec<DirEntry>) {
 }
 
 fn main() -> io::Result<()> {
+    let mut connect_timeout = Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS);
+    let mut retries = 0;
+    let mut keep_partial = false;
+    let mut dry_run = false;
+    let mut framed = false;
+    let mut ca = None;
+    let mut insecure = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--ca" {
+            ca = args.next().map(PathBuf::from);
+            continue;
+        }
+        if arg == "--insecure" {
+            insecure = true;
+            continue;
+        }
+        if arg == "--keep-partial" {
+            keep_partial = true;
+            continue;
+        }
+        if arg == "--dry-run" {
+            dry_run = true;
+            continue;
+        }
+        if arg == "--framed" {
+            framed = true;
+            continue;
+        }
+        match (
+            arg.as_str(),
+            args.next().and_then(|v| v.parse::<u64>().ok()),
+        ) {
+            ("--connect-timeout", Some(secs)) if secs > 0 => {
+                connect_timeout = Duration::from_secs(secs)
+            }
+            ("--retries", Some(n)) => retries = n,
+            _ => {
+                eprintln!(
+                    "Usage: tcp_client [--connect-timeout <secs>] [--retries <n>] [--keep-partial] [--dry-run] [--framed] [--ca <pem> | --insecure]"
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // TLS is used when the server's CA is given, or when verification is explicitly skipped
+    let tls = if ca.is_some() || insecure {
+        match client_config(ca.as_deref(), insecure) {
+            Ok(config) => Some(config),
+            Err(msg) => {
+                eprintln!("{}", msg);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // prompts and connection status go to stderr, leaving stdout to what commands print,
+    // so `download <remote> -` can be piped
     let mut input = String::new();
-    print!("Server address (host:port): ");
-    io::stdout().flush()?;
+    eprint!("Server address (host:port): ");
     io::stdin().read_line(&mut input)?;
     let addr = input.trim();
 
-    let mut stream = TcpStream::connect(addr)?;
-    println!("Connected to {}", addr);
+    let mut client = match connect(addr, connect_timeout, retries, tls.as_ref(), framed) {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Could not connect to {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    };
+    eprintln!("Connected to {}", addr);
+
+    // an older server cannot decode the query and drops the connection, so reconnect and
+    // carry on without capability information
+    let features = match client.capabilities() {
+        Ok((proto_version, features)) => {
+            eprintln!(
+                "Server protocol v{}, features: {}",
+                proto_version,
+                features.join(", ")
+            );
+            Some(features)
+        }
+        Err(_) => {
+            client = match connect(addr, connect_timeout, retries, tls.as_ref(), framed) {
+                Ok(client) => client,
+                Err(e) => {
+                    eprintln!("Could not reconnect to {}: {}", addr, e);
+                    std::process::exit(1);
+                }
+            };
+            eprintln!("Server does not report capabilities");
+            None
+        }
+    };
+
+    // commands are read off the same lock `upload -` takes its data from
+    let mut input = io::stdin().lock();
+    if features
+        .as_deref()
+        .is_some_and(|f| f.iter().any(|f| f == "auth"))
+    {
+        if let Err(e) = log_in(&mut client, &mut (&mut input).lines()) {
+            eprintln!("Login failed: {}", e);
+            std::process::exit(1);
+        }
+        eprintln!("Logged in");
+    }
+
+    // the server does not report its cwd, so the client tracks it from successful `cd`s
+    let mut remote_dirs = Vec::new();
+    loop {
+        eprint!("{}", prompt(&remote_dirs));
 
-    let stdin = io::stdin();
-    print!("> ");
-    io::stdout().flush()?;
-    for line in stdin.lock().lines() {
-        let line = line?;
+        let line = match (&mut input).lines().next() {
+            Some(Ok(l)) => l,
+            Some(Err(e)) => {
+                eprintln!("Read error: {}", e);
+                break;
+            }
+            // Ctrl-D: end the prompt line before saying goodbye
+            None => {
+                eprintln!();
+                break;
+            }
+        };
         let line = line.trim();
         if line.is_empty() {
-            print!("> ");
-            io::stdout().flush()?;
             continue;
         }
 
         let mut parts = line.split_whitespace();
-        match parts.next() {
+        let cmd = parts.next();
+        let feature = match cmd {
+            Some(
+                cmd @ ("watch" | "follow" | "manifest" | "search" | "find" | "du" | "df" | "stat"
+                | "mktemp" | "swap" | "getmany" | "resume-upload"),
+            ) => Some(cmd),
+            Some("del" | "rm") => Some("delete"),
+            Some("move" | "mv") => Some("move"),
+            Some("verify") => Some("hash"),
+            Some("touch") => Some("settime"),
+            Some("ln") => Some("link"),
+            Some("mirror") => Some("manifest"),
+            Some("download-zip") => Some("zip"),
+            Some("download-tar") => Some("tar"),
+            _ => None,
+        };
+        if feature.is_some_and(|f| !check_feature(features.as_deref(), f)) {
+            continue;
+        }
+        if dry_run
+            && let Some(cmd) = cmd
+            && let Some(desc) = dry_run_description(cmd, &parts.clone().collect::<Vec<_>>())
+        {
+            println!("[dry-run] would {}", desc);
+            // the data of `upload -` must not be read as commands either
+            if cmd == "upload" && parts.clone().find(|p| !p.starts_with("--")) == Some("-") {
+                discard_input(&mut input);
+            }
+            continue;
+        }
+
+        match cmd {
             Some("dir") => {
-                if let Ok(resp) = send_request(&mut stream, &Request::Dir) {
-                    if let Response::DirList(list) = resp {
+                let sort_key = match (parts.next(), parts.next()) {
+                    (None, _) => None,
+                    (Some("--sort"), Some(key)) if DIR_SORT_KEYS.contains(&key) => Some(key),
+                    _ => {
+                        println!("Usage: dir [--sort name|type|size|mtime]");
+                        continue;
+                    }
+                };
+                match client.dir() {
+                    Ok(mut list) => {
+                        if let Some(key) = sort_key {
+                            sort_dir_list(&mut list, key);
+                        }
                         print_dir_list(list);
-                    } else {
-                        println!("Response: {:?}", resp);
                     }
+                    Err(e) => eprintln!("dir failed: {}", reason(&e)),
+                }
+            }
+
+            Some("df") => match client.request(&Request::Df) {
+                Ok(Response::Df { entries }) => print_df_table(&entries),
+                Ok(resp) => println!("Response: {:?}", resp),
+                Err(e) => eprintln!("Request failed: {}", e),
+            },
+
+            Some("stat") => {
+                if let Some(path) = parts.next() {
+                    let req = Request::Stat {
+                        path: path.to_string(),
+                    };
+                    match client.request(&req) {
+                        Ok(Response::StatResult {
+                            name,
+                            size,
+                            is_dir,
+                            modified_unix,
+                            readonly,
+                        }) => print_stat(&name, size, is_dir, modified_unix, readonly),
+                        Ok(Response::Error(e)) => eprintln!("stat failed: {}", e),
+                        Ok(resp) => println!("Response: {:?}", resp),
+                        Err(e) => eprintln!("Request failed: {}", e),
+                    }
+                } else {
+                    println!("Usage: stat <remote_path>");
                 }
             }
 
             Some("cd") => {
                 if let Some(arg) = parts.next() {
-                    let req = if arg == ".." {
-                        Request::CdUp
+                    let result = if arg == ".." {
+                        client.cd_up()
                     } else {
-                        Request::Cd {
-                            path: arg.to_string(),
-                        }
+                        client.cd(arg)
                     };
-                    if let Ok(resp) = send_request(&mut stream, &req) {
-                        println!("{:?}", resp);
+                    match result {
+                        Ok(()) => apply_cd(&mut remote_dirs, arg),
+                        Err(e) => eprintln!("cd failed: {}", reason(&e)),
                     }
                 } else {
                     println!("Usage: cd <path> or cd ..");
@@ -186,28 +938,46 @@ fn main() -> io::Result<()> {
 
             Some("mkdir") => {
                 if let Some(name) = parts.next() {
-                    if let Ok(resp) = send_request(
-                        &mut stream,
-                        &Request::Mkdir {
-                            name: name.to_string(),
-                        },
-                    ) {
-                        println!("{:?}", resp);
+                    match client.mkdir(name) {
+                        Ok(()) => println!("Created {}", name),
+                        Err(e) => eprintln!("mkdir failed: {}", reason(&e)),
                     }
                 } else {
                     println!("Usage: mkdir <folder>");
                 }
             }
 
+            Some("mktemp") => {
+                let req = Request::MkTemp {
+                    prefix: parts.next().unwrap_or("tmp-").to_string(),
+                    suffix: parts.next().unwrap_or("").to_string(),
+                };
+                match client.request(&req) {
+                    Ok(Response::Path(name)) => println!("{}", name),
+                    Ok(resp) => println!("Response: {:?}", resp),
+                    Err(e) => eprintln!("Request failed: {}", e),
+                }
+            }
+
+            Some("touch") => {
+                let args = parts.collect::<Vec<_>>();
+                if let Some((path, mtime_secs)) = parse_touch_args(&args) {
+                    let req = Request::SetTime { path, mtime_secs };
+                    match client.request(&req) {
+                        Ok(resp) => println!("{:?}", resp),
+                        Err(e) => eprintln!("Request failed: {}", e),
+                    }
+                } else {
+                    println!("Usage: touch [-t <unix_secs>] <remote_file>");
+                }
+            }
+
             Some("copy") => {
                 if let (Some(src), Some(dst)) = (parts.next(), parts.next()) {
-                    if let Ok(resp) = send_request(
-                        &mut stream,
-                        &Request::Copy {
-                            src: src.to_string(),
-                            dst: dst.to_string(),
-                        },
-                    ) {
+                    if let Ok(resp) = client.request(&Request::Copy {
+                        src: src.to_string(),
+                        dst: dst.to_string(),
+                    }) {
                         println!("{:?}", resp);
                     }
                 } else {
@@ -215,37 +985,342 @@ fn main() -> io::Result<()> {
                 }
             }
 
-            Some("upload") => {
-                if let (Some(local), Some(remote_folder)) = (parts.next(), parts.next()) {
-                    let _ = do_upload(&mut stream, local, remote_folder);
+            Some("ln") => {
+                if let (Some(target), Some(link)) = (parts.next(), parts.next()) {
+                    let req = Request::Link {
+                        target: target.to_string(),
+                        link: link.to_string(),
+                    };
+                    match client.request(&req) {
+                        Ok(resp) => println!("{:?}", resp),
+                        Err(e) => eprintln!("Request failed: {}", e),
+                    }
+                } else {
+                    println!("Usage: ln <target> <link>");
+                }
+            }
+
+            Some("swap") => {
+                if let (Some(a), Some(b)) = (parts.next(), parts.next()) {
+                    let req = Request::Swap {
+                        a: a.to_string(),
+                        b: b.to_string(),
+                    };
+                    match client.request(&req) {
+                        Ok(resp) => println!("{:?}", resp),
+                        Err(e) => eprintln!("Request failed: {}", e),
+                    }
+                } else {
+                    println!("Usage: swap <a> <b>");
+                }
+            }
+
+            Some("del" | "rm") => {
+                if let Some(path) = parts.next() {
+                    let req = Request::Delete {
+                        path: path.to_string(),
+                    };
+                    match client.request(&req) {
+                        Ok(resp) => println!("{:?}", resp),
+                        Err(e) => eprintln!("Request failed: {}", e),
+                    }
                 } else {
-                    println!("Usage: upload <local_path> <remote_folder_on_server>");
+                    println!("Usage: del <file>");
+                }
+            }
+
+            Some("move" | "mv") => {
+                if let (Some(src), Some(dst)) = (parts.next(), parts.next()) {
+                    let req = Request::Move {
+                        src: src.to_string(),
+                        dst: dst.to_string(),
+                    };
+                    match client.request(&req) {
+                        Ok(resp) => println!("{:?}", resp),
+                        Err(e) => eprintln!("Request failed: {}", e),
+                    }
+                } else {
+                    println!("Usage: move <src> <dst>");
+                }
+            }
+
+            Some("upload") => {
+                let (flags, args): (Vec<&str>, Vec<&str>) =
+                    parts.partition(|p| p.starts_with("--"));
+                let options = UploadOptions {
+                    atomic: flags.contains(&"--atomic"),
+                    overwrite: flags.contains(&"--force"),
+                    append: flags.contains(&"--append"),
+                    compressed: flags.contains(&"--compress"),
+                };
+                if options.compressed && !check_feature(features.as_deref(), "compress") {
+                    continue;
+                }
+                match args.as_slice() {
+                    ["-", remote_path] if !options.compressed => {
+                        if do_upload_stdin(&mut client, &mut input, remote_path, options).is_err() {
+                            discard_input(&mut input);
+                        }
+                    }
+                    ["-", ..] => {
+                        println!(
+                            "Usage: upload - <remote_path_on_server> [--atomic] [--force] [--append] (no --compress)"
+                        );
+                        discard_input(&mut input);
+                    }
+                    [local, remote_folder] => {
+                        let _ = do_upload(&mut client, local, remote_folder, options);
+                    }
+                    _ => println!(
+                        "Usage: upload <local_path> <remote_folder_on_server> [--atomic] [--force] [--append] [--compress]\n       upload - <remote_path_on_server> [--atomic] [--force] [--append]"
+                    ),
+                }
+            }
+
+            Some("resume-upload") => {
+                let (flags, args): (Vec<&str>, Vec<&str>) =
+                    parts.partition(|p| p.starts_with("--"));
+                let compressed = flags.contains(&"--compress");
+                if compressed && !check_feature(features.as_deref(), "compress") {
+                    continue;
+                }
+                match args.as_slice() {
+                    [id, local] => match u64::from_str_radix(id, 16) {
+                        Ok(id) => {
+                            let _ = do_resume_upload(&mut client, id, local, compressed);
+                        }
+                        Err(_) => println!("Upload ids are hexadecimal, e.g. 3f9c0a1b2d4e5f60"),
+                    },
+                    _ => println!("Usage: resume-upload <id> <local_path> [--compress]"),
                 }
             }
 
             Some("download") => {
+                let (flags, args): (Vec<&str>, Vec<&str>) =
+                    parts.partition(|p| p.starts_with("--"));
+                let compressed = flags.contains(&"--compress");
+                if compressed && !check_feature(features.as_deref(), "compress") {
+                    continue;
+                }
+                if let [remote_path, "-"] = args.as_slice() {
+                    let req = Request::Download {
+                        src_path: remote_path.to_string(),
+                        offset: 0,
+                        compressed,
+                    };
+                    let _ = do_download_stdout(&mut client, &req);
+                } else if let [remote_path, local_folder] = args.as_slice() {
+                    let _ = do_download(
+                        &mut client,
+                        remote_path,
+                        local_folder,
+                        keep_partial,
+                        compressed,
+                    );
+                } else {
+                    println!(
+                        "Usage: download <remote_path_on_server> <local_folder | -> [--compress]"
+                    );
+                }
+            }
+
+            Some("verify") => {
+                let args = parts.collect::<Vec<_>>();
+                let parsed = match args.as_slice() {
+                    [remote_path, local_path] => Some((remote_path, local_path, HashAlgo::Sha256)),
+                    [remote_path, local_path, "--algo", algo] => {
+                        HashAlgo::from_name(algo).map(|algo| (remote_path, local_path, algo))
+                    }
+                    _ => None,
+                };
+                if let Some((remote_path, local_path, algo)) = parsed {
+                    if let Err(e) = do_verify(&mut client, remote_path, local_path, algo) {
+                        eprintln!("Verify failed: {}", e);
+                    }
+                } else {
+                    println!("Usage: verify <remote_path> <local_path> [--algo sha256|sha512]");
+                }
+            }
+
+            Some("follow") => {
+                if let Some(remote_path) = parts.next() {
+                    // a started follow owns the connection until the server closes it
+                    if do_follow(&mut client, remote_path).is_ok() {
+                        println!("Follow ended, connection closed.");
+                        break;
+                    }
+                } else {
+                    println!("Usage: follow <remote_file>");
+                }
+            }
+
+            Some("manifest") => {
                 if let (Some(remote_path), Some(local_folder)) = (parts.next(), parts.next()) {
-                    let _ = do_download(&mut stream, remote_path, local_folder);
+                    let _ = do_manifest(&mut client, remote_path, local_folder);
                 } else {
-                    println!("Usage: download <remote_path_on_server> <local_folder>");
+                    println!("Usage: manifest <remote_dir> <local_folder>");
                 }
             }
 
-            Some("exit") | Some("quit") => {
-                println!("Exiting.");
-                break;
+            Some("download-zip") => {
+                if let Some(remote_path) = parts.next() {
+                    let local_folder = parts.next().unwrap_or(".");
+                    let req = Request::DownloadZip {
+                        src_path: remote_path.to_string(),
+                    };
+                    let _ = fetch_file(&mut client, &req, local_folder, keep_partial);
+                } else {
+                    println!("Usage: download-zip <remote_dir> [local_folder]");
+                }
             }
 
+            Some("download-tar") => {
+                if let Some(remote_path) = parts.next() {
+                    let req = Request::DownloadArchive {
+                        path: remote_path.to_string(),
+                    };
+                    match parts.next().unwrap_or(".") {
+                        "-" => {
+                            let _ = do_download_stdout(&mut client, &req);
+                        }
+                        local_folder => {
+                            let _ = fetch_file(&mut client, &req, local_folder, keep_partial);
+                        }
+                    }
+                } else {
+                    println!("Usage: download-tar <remote_dir> [local_folder | -]");
+                }
+            }
+
+            Some("getmany") => {
+                let mut args = parts.collect::<Vec<_>>();
+                let separate = args.first() == Some(&"--separate");
+                if separate {
+                    args.remove(0);
+                }
+                if let [local_target, remote_paths @ ..] = args.as_slice()
+                    && !remote_paths.is_empty()
+                {
+                    let _ = do_getmany(
+                        &mut client,
+                        remote_paths,
+                        local_target,
+                        separate,
+                        keep_partial,
+                    );
+                } else {
+                    println!("Usage: getmany [--separate] <local_file_or_folder> <remote>...");
+                }
+            }
+
+            Some("mirror") => {
+                if let (Some(remote_path), Some(local_folder)) = (parts.next(), parts.next()) {
+                    if let Err(e) = do_mirror(
+                        &mut client,
+                        remote_path,
+                        local_folder,
+                        keep_partial,
+                        dry_run,
+                    ) {
+                        eprintln!("Mirror failed: {}", e);
+                    }
+                } else {
+                    println!("Usage: mirror <remote_dir> <local_dir>");
+                }
+            }
+
+            Some("find") => {
+                let args = parts.collect::<Vec<_>>();
+                let (pattern, max_depth) = match args.as_slice() {
+                    [pattern] => (pattern, None),
+                    [pattern, "--max-depth", depth] => match depth.parse::<u32>() {
+                        Ok(depth) => (pattern, Some(depth)),
+                        Err(_) => {
+                            println!("--max-depth requires a number");
+                            continue;
+                        }
+                    },
+                    _ => {
+                        println!("Usage: find <pattern> [--max-depth <n>]");
+                        continue;
+                    }
+                };
+                let req = Request::Find {
+                    pattern: pattern.to_string(),
+                    max_depth,
+                };
+                match client.request(&req) {
+                    Ok(Response::FindResult(paths)) => {
+                        for path in &paths {
+                            println!("{}", path);
+                        }
+                        println!("{} matches", paths.len());
+                    }
+                    Ok(resp) => println!("{:?}", resp),
+                    Err(e) => eprintln!("Request failed: {}", e),
+                }
+            }
+
+            Some("du") => {
+                let req = Request::DiskUsage {
+                    path: parts.next().unwrap_or(".").to_string(),
+                };
+                match client.request(&req) {
+                    Ok(Response::DiskUsageResult {
+                        total_bytes,
+                        file_count,
+                    }) => println!("{} bytes in {} files", total_bytes, file_count),
+                    Ok(Response::Error(e)) => eprintln!("du failed: {}", e),
+                    Ok(resp) => println!("Response: {:?}", resp),
+                    Err(e) => eprintln!("Request failed: {}", e),
+                }
+            }
+
+            Some("search") => {
+                let mut remote_path = None;
+                let mut name_glob = None;
+                let mut content_regex = None;
+                let mut valid = true;
+                while let Some(arg) = parts.next() {
+                    match arg {
+                        "--name" => name_glob = parts.next().map(str::to_string),
+                        "--content" => content_regex = parts.next().map(str::to_string),
+                        path if remote_path.is_none() => remote_path = Some(path),
+                        _ => valid = false,
+                    }
+                }
+                match remote_path {
+                    Some(remote_path) if valid => {
+                        let _ = do_search(&mut client, remote_path, name_glob, content_regex);
+                    }
+                    _ => println!("Usage: search <remote_dir> [--name <glob>] [--content <regex>]"),
+                }
+            }
+
+            Some("watch") => {
+                if let Some(remote_path) = parts.next() {
+                    // a started watch owns the connection until the server closes it
+                    if do_watch(&mut client, remote_path).is_ok() {
+                        println!("Watch ended, connection closed.");
+                        break;
+                    }
+                } else {
+                    println!("Usage: watch <remote_dir>");
+                }
+            }
+
+            Some("exit") | Some("quit") => break,
+
             Some(cmd) => {
                 println!("Unknown command: {}", cmd);
             }
 
             None => {}
         }
-
-        print!("> ");
-        io::stdout().flush()?;
     }
 
+    // let the server see the disconnect right away instead of on its next read
+    let _ = client.shutdown();
+    eprintln!("Goodbye.");
     Ok(())
 }
@@ -0,0 +1,32 @@
+use shell_protocol::tokio_server::run_tokio_server;
+use shell_protocol::validate_root;
+use std::io;
+use std::path::Path;
+use tokio::net::TcpListener;
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let [addr, root] = args.as_slice() else {
+        eprintln!("Usage: tokio_server <addr:port> <root_dir>");
+        std::process::exit(1);
+    };
+    if let Err(msg) = validate_root(Path::new(root)) {
+        eprintln!("{}", msg);
+        std::process::exit(1);
+    }
+    // every path a client names is canonicalized and compared against this canonical root
+    let root = std::fs::canonicalize(root)?;
+
+    let listener = TcpListener::bind(addr).await?;
+    println!(
+        "Async server listening on {} (length-prefixed frames)",
+        listener.local_addr()?
+    );
+
+    run_tokio_server(listener, root, async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+    .await;
+    Ok(())
+}
@@ -1,35 +1,424 @@
-use bincode::config::standard;
 use bincode::{decode_from_slice, encode_to_vec};
-use shell_protocol::{Request, Response};
+use shell_protocol::cli::{
+    DIR_SORT_KEYS, apply_cd, check_feature, parse_touch_args, print_df_table, print_stat, prompt,
+    sort_dir_list,
+};
+use shell_protocol::client::UploadOptions;
+use shell_protocol::compress::{COMPRESSION_SLACK, compress_chunk, decompress_chunk};
+use shell_protocol::hash::{HashAlgo, hash_file, sha256_file, to_hex};
+use shell_protocol::{CHUNK_OVERHEAD, DirEntry, Request, Response, wire_config};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
-use std::io::{self, BufRead, Read, Write};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
 use std::net::UdpSocket;
-use std::time::Duration;
+use std::thread;
+use std::time::{Duration, Instant};
 
 const MAX_PACKET_SIZE: usize = 65507;
 const TIMEOUT_SECS: u64 = 5;
 const CHUNK_SIZE: usize = 8192;
+/// Upload chunks kept in flight unacknowledged, unless `--window` says otherwise
+const DEFAULT_UPLOAD_WINDOW: usize = 8;
+/// Largest `--window`, so the server's buffer of chunks that arrive ahead of a gap
+/// (256 per upload) never overflows
+const MAX_UPLOAD_WINDOW: usize = 256;
+/// Acks for later chunks after which an unacknowledged one is resent without waiting
+const FAST_RESEND_ACKS: u32 = 3;
+/// Times one chunk may fail its CRC-32 before the transfer is given up
+const MAX_CORRUPT_CHUNKS: u32 = 5;
+/// Received download chunks are written to disk once this many are buffered
+const REASSEMBLY_FLUSH_CHUNKS: usize = 64;
+const LOSS_PROBE_INTERVAL_MS: u64 = 200;
 
 fn send_request(socket: &UdpSocket, req: &Request) -> io::Result<Response> {
     // Encode request
-    let data = encode_to_vec(req, standard())
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("encode error: {e}")))?;
+    let data = encode_to_vec(req, wire_config())
+        .map_err(|e| io::Error::other(format!("encode error: {e}")))?;
 
     // Send request
     socket.send(&data)?;
 
-    // Receive response
+    recv_response(socket)
+}
+
+/// Waits for the next response datagram without sending anything.
+fn recv_response(socket: &UdpSocket) -> io::Result<Response> {
     let mut buf = vec![0u8; MAX_PACKET_SIZE];
     let size = socket.recv(&mut buf)?;
 
     // Decode response
-    let (resp, _): (Response, _) = decode_from_slice(&buf[..size], standard())
+    let (resp, _): (Response, _) = decode_from_slice(&buf[..size], wire_config())
         .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("decode error: {e}")))?;
 
     Ok(resp)
 }
 
-fn print_dir_list(entries: &[shell_protocol::DirEntry]) {
+/// How a datagram whose reply does not arrive in time is resent.
+#[derive(Debug, Clone, Copy)]
+struct RetryPolicy {
+    /// Resends after the first attempt before giving up
+    retries: u32,
+    /// Pause before the first resend, doubled for each one after it
+    backoff: Duration,
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// Sends `req` and waits for a reply that `accept` takes as its answer, resending the same
+/// datagram on a timeout as `retry` allows. Other replies, such as a duplicate ack for a
+/// chunk that was already confirmed, are skipped while waiting.
+fn send_reliable(
+    socket: &UdpSocket,
+    req: &Request,
+    retry: RetryPolicy,
+    mut accept: impl FnMut(&Response) -> bool,
+) -> io::Result<Response> {
+    let data = encode_to_vec(req, wire_config())
+        .map_err(|e| io::Error::other(format!("encode error: {e}")))?;
+
+    let mut attempt = 0;
+    loop {
+        socket.send(&data)?;
+        let result = loop {
+            match recv_response(socket) {
+                Ok(resp) if !accept(&resp) => continue,
+                result => break result,
+            }
+        };
+        match result {
+            Err(e) if is_timeout(&e) && attempt < retry.retries => {
+                let pause = retry.backoff.saturating_mul(1 << attempt.min(16));
+                attempt += 1;
+                eprintln!("No response, retrying ({}/{})", attempt, retry.retries);
+                thread::sleep(pause);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Sends a request that changes server state, tagged with `request_id` so it can be resent
+/// after a timeout: the server replays its first response to a repeated id instead of
+/// executing the request again.
+fn send_mutation(
+    socket: &UdpSocket,
+    req: Request,
+    request_id: u64,
+    retry: RetryPolicy,
+) -> io::Result<Response> {
+    let tagged = Request::Idempotent {
+        request_id,
+        request: Box::new(req),
+    };
+    send_reliable(socket, &tagged, retry, |_| true)
+}
+
+/// Datagram sizes tried by `--discover-mtu`, smallest first.
+const MTU_PROBE_SIZES: [usize; 6] = [1200, 1400, 1472, 2048, 4096, CHUNK_SIZE + CHUNK_OVERHEAD];
+const MTU_PROBE_TIMEOUT_MS: u64 = 500;
+const MTU_PROBE_ATTEMPTS: u32 = 2;
+/// Chunk size used when the path cannot be probed.
+const FALLBACK_CHUNK_SIZE: usize = 1400;
+
+/// Asks the kernel not to fragment outgoing datagrams, so oversized probes fail
+/// instead of silently being split.
+#[cfg(target_os = "linux")]
+fn set_dont_fragment(socket: &UdpSocket) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let value: libc::c_int = libc::IP_PMTUDISC_DO;
+    // SAFETY: the fd is owned by `socket` and `value` outlives the call
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            &value as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_dont_fragment(_socket: &UdpSocket) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "don't-fragment is not supported on this platform",
+    ))
+}
+
+/// Whether a send failed because the datagram exceeds the known path MTU.
+#[cfg(target_os = "linux")]
+fn is_too_large(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(libc::EMSGSIZE)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_too_large(_e: &io::Error) -> bool {
+    false
+}
+
+/// Sends an `Echo` datagram of up to `datagram_size` bytes and waits for it to come back,
+/// ignoring late replies to earlier, smaller probes.
+fn echo_round_trips(socket: &UdpSocket, datagram_size: usize) -> io::Result<bool> {
+    // shrink the payload until the encoded request hits the target size
+    let mut payload_len = datagram_size;
+    let data = loop {
+        let data = encode_to_vec(
+            Request::Echo {
+                payload: vec![0u8; payload_len],
+            },
+            wire_config(),
+        )
+        .map_err(|e| io::Error::other(format!("encode error: {e}")))?;
+        if data.len() <= datagram_size {
+            break data;
+        }
+        payload_len -= data.len() - datagram_size;
+    };
+
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+    for _ in 0..MTU_PROBE_ATTEMPTS {
+        match socket.send(&data) {
+            Ok(_) => {}
+            // the kernel already knows this exceeds the path MTU
+            Err(e) if is_too_large(&e) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+
+        loop {
+            match socket.recv(&mut buf) {
+                Ok(size) => {
+                    if let Ok((Response::Echo { payload }, _)) =
+                        decode_from_slice::<Response, _>(&buf[..size], wire_config())
+                        && payload.len() == payload_len
+                    {
+                        return Ok(true);
+                    }
+                }
+                Err(e) if is_timeout(&e) => {
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Probes increasingly large `Echo` datagrams with fragmentation disabled and returns the
+/// upload chunk size that fits in the largest one that round-tripped.
+fn discover_chunk_size(socket: &UdpSocket) -> io::Result<usize> {
+    if let Err(e) = set_dont_fragment(socket) {
+        eprintln!(
+            "MTU probing unavailable ({}), using {} byte chunks",
+            e, FALLBACK_CHUNK_SIZE
+        );
+        return Ok(FALLBACK_CHUNK_SIZE);
+    }
+
+    socket.set_read_timeout(Some(Duration::from_millis(MTU_PROBE_TIMEOUT_MS)))?;
+    let mut largest = None;
+    for size in MTU_PROBE_SIZES {
+        if !echo_round_trips(socket, size)? {
+            break;
+        }
+        largest = Some(size);
+    }
+    socket.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+
+    match largest {
+        Some(size) => {
+            let chunk_size = size - CHUNK_OVERHEAD;
+            println!(
+                "Largest unfragmented datagram: {} bytes, using {} byte chunks",
+                size, chunk_size
+            );
+            Ok(chunk_size)
+        }
+        None => {
+            eprintln!(
+                "No probe came back, using {} byte chunks",
+                FALLBACK_CHUNK_SIZE
+            );
+            Ok(FALLBACK_CHUNK_SIZE)
+        }
+    }
+}
+
+/// Asks the server for `preferred` byte chunks and returns the size it settled on, which may
+/// be smaller; `None` if it did not answer.
+fn negotiate_chunk_size(socket: &UdpSocket, preferred: usize) -> Option<usize> {
+    let req = Request::Hello {
+        preferred_chunk_size: preferred.min(u32::MAX as usize) as u32,
+    };
+    match send_request(socket, &req) {
+        Ok(Response::Hello { chunk_size }) => {
+            let chunk_size = chunk_size as usize;
+            if chunk_size < preferred {
+                println!(
+                    "Server limits chunks to {} bytes (asked for {})",
+                    chunk_size, preferred
+                );
+            }
+            Some(chunk_size)
+        }
+        Ok(other) => {
+            eprintln!("Unexpected response to Hello: {:?}", other);
+            None
+        }
+        Err(e) => {
+            eprintln!("Chunk size negotiation failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Sends `count` sequence-numbered `Echo` requests, like a mini ping, and reports how many
+/// came back within the read timeout along with min/avg/max round-trip times.
+fn measure_loss(socket: &UdpSocket, count: u32) -> io::Result<()> {
+    let timeout = socket
+        .read_timeout()?
+        .unwrap_or(Duration::from_secs(TIMEOUT_SECS));
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+    let mut rtts = Vec::new();
+
+    for seq in 0..count {
+        let payload = seq.to_le_bytes().to_vec();
+        let data = encode_to_vec(
+            Request::Echo {
+                payload: payload.clone(),
+            },
+            wire_config(),
+        )
+        .map_err(|e| io::Error::other(format!("encode error: {e}")))?;
+
+        let start = Instant::now();
+        socket.send(&data)?;
+
+        // late replies to earlier probes carry another sequence number and are skipped
+        let rtt = loop {
+            match socket.recv(&mut buf) {
+                Ok(size) => {
+                    if let Ok((Response::Echo { payload: echoed }, _)) =
+                        decode_from_slice::<Response, _>(&buf[..size], wire_config())
+                        && echoed == payload
+                    {
+                        break Some(start.elapsed());
+                    }
+                    if start.elapsed() >= timeout {
+                        break None;
+                    }
+                }
+                Err(e) if is_timeout(&e) => {
+                    break None;
+                }
+                Err(e) => return Err(e),
+            }
+        };
+
+        match rtt {
+            Some(rtt) => {
+                println!("seq={} time={:.2} ms", seq, rtt.as_secs_f64() * 1000.0);
+                rtts.push(rtt);
+            }
+            None => println!("seq={} timed out", seq),
+        }
+
+        if seq + 1 < count {
+            thread::sleep(Duration::from_millis(LOSS_PROBE_INTERVAL_MS));
+        }
+    }
+
+    let received = rtts.len() as u32;
+    println!(
+        "{} sent, {} received, {:.1}% loss",
+        count,
+        received,
+        (count - received) as f64 / count as f64 * 100.0
+    );
+    if let (Some(min), Some(max)) = (rtts.iter().min(), rtts.iter().max()) {
+        let avg = rtts.iter().sum::<Duration>() / received;
+        println!(
+            "rtt min/avg/max = {:.2}/{:.2}/{:.2} ms",
+            min.as_secs_f64() * 1000.0,
+            avg.as_secs_f64() * 1000.0,
+            max.as_secs_f64() * 1000.0
+        );
+    }
+    Ok(())
+}
+
+/// What a command that changes files would do, for `--dry-run`; `None` for commands that
+/// only read, which still run so listings and enumeration keep working.
+fn dry_run_description(cmd: &str, args: &[&str]) -> Option<String> {
+    let positional: Vec<&str> = args
+        .iter()
+        .copied()
+        .filter(|a| !a.starts_with("--"))
+        .collect();
+    let desc = match (cmd, positional.as_slice()) {
+        ("mkdir", [name]) => format!("create directory {}", name),
+        ("mktemp", _) => "create an empty temporary file".to_string(),
+        ("touch", [.., path]) => format!("set the modification time of {}", path),
+        ("copy", [src, dst]) => format!("copy {} to {}", src, dst),
+        ("ln", [target, link]) => format!("hard-link {} to {}", link, target),
+        ("swap", [a, b]) => format!("swap {} and {}", a, b),
+        ("del" | "rm", [path]) => format!("delete {}", path),
+        ("move" | "mv", [src, dst]) => format!("move {} to {}", src, dst),
+        ("upload", [local, remote, ..]) => match std::fs::metadata(local) {
+            Ok(meta) => format!("upload {} ({} bytes) to {}", local, meta.len(), remote),
+            Err(e) => format!("upload {} to {} (cannot read it: {})", local, remote, e),
+        },
+        ("download", [remote, local, ..]) => format!("download {} into {}", remote, local),
+        ("download", [remote]) => format!("download {} into .", remote),
+        (
+            "mkdir" | "touch" | "copy" | "ln" | "swap" | "del" | "rm" | "move" | "mv" | "upload"
+            | "download",
+            _,
+        ) => format!("run `{}`", [&[cmd], args].concat().join(" ")),
+        _ => return None,
+    };
+    Some(desc)
+}
+
+/// Prompts for a user name and password and logs in with them. Called when the server
+/// advertises `auth`, since it answers nothing else until a login has succeeded.
+fn log_in(
+    socket: &UdpSocket,
+    lines: &mut impl Iterator<Item = io::Result<String>>,
+) -> io::Result<()> {
+    let mut ask = |prompt: &str| -> io::Result<String> {
+        print!("{}", prompt);
+        io::stdout().flush()?;
+        match lines.next() {
+            Some(line) => Ok(line?.trim().to_string()),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "No input")),
+        }
+    };
+    let user = ask("Username: ")?;
+    let password = ask("Password: ")?;
+    match send_request(socket, &Request::Auth { user, password })? {
+        Response::Ok => Ok(()),
+        Response::Error(e) => Err(io::Error::other(e)),
+        other => Err(io::Error::other(format!(
+            "Unexpected response: {:?}",
+            other
+        ))),
+    }
+}
+
+fn print_dir_list(entries: &[DirEntry]) {
     for entry in entries {
         if entry.is_dir {
             println!("{}/", entry.name);
@@ -39,7 +428,16 @@ fn print_dir_list(entries: &[shell_protocol::DirEntry]) {
     }
 }
 
-fn do_upload(socket: &UdpSocket, local_path: &str, remote_folder: &str) -> io::Result<()> {
+fn do_upload(
+    socket: &UdpSocket,
+    local_path: &str,
+    remote_folder: &str,
+    chunk_size: usize,
+    retry: RetryPolicy,
+    options: UploadOptions,
+    window: usize,
+) -> io::Result<()> {
+    let compressed = options.compressed;
     let mut f = File::open(local_path)?;
     let metadata = f.metadata()?;
     let size = metadata.len();
@@ -55,178 +453,559 @@ fn do_upload(socket: &UdpSocket, local_path: &str, remote_folder: &str) -> io::R
     let req = Request::Upload {
         dst_path: remote_folder.to_string(),
         file_name: filename.clone(),
-        size,
+        size: Some(size),
+        atomic: options.atomic,
+        overwrite: options.overwrite,
+        append: options.append,
+        sha256: Some(sha256_file(std::path::Path::new(local_path))?),
+        compressed,
     };
 
-    let resp = send_request(socket, &req)?;
+    // a resent Upload whose Ok was lost is answered with Ok again, as long as no chunk is in
+    let resp = send_reliable(socket, &req, retry, |resp| {
+        matches!(resp, Response::Ok | Response::Error(_))
+    })?;
     match resp {
         Response::Ok => {
             println!("Server ready to receive file");
         }
         Response::Error(msg) => {
             eprintln!("Upload error: {}", msg);
-            return Err(io::Error::new(io::ErrorKind::Other, msg));
+            return Err(io::Error::other(msg));
         }
         _ => {
             eprintln!("Unexpected response: {:?}", resp);
-            return Err(io::Error::new(io::ErrorKind::Other, "Unexpected response"));
+            return Err(io::Error::other("Unexpected response"));
         }
     }
 
-    // Send file in chunks
-    let mut chunk_id = 0u32;
-    let mut total_sent = 0u64;
-    let mut buf = vec![0u8; CHUNK_SIZE];
+    // deflate can grow incompressible data slightly, so leave room for that in the datagram
+    let read_size = if compressed {
+        chunk_size.saturating_sub(COMPRESSION_SLACK).max(1)
+    } else {
+        chunk_size
+    };
+    let result = send_chunks(socket, &mut f, size, read_size, compressed, window, retry);
+    // the window shortens the read timeout while chunks are in flight
+    socket.set_read_timeout(Some(Duration::from_secs(TIMEOUT_SECS)))?;
+    let total_bytes = result?;
+    println!();
+    if total_bytes != size {
+        eprintln!(
+            "Upload size mismatch: declared {}, server stored {}",
+            size, total_bytes
+        );
+        return Err(io::Error::other("Upload size mismatch"));
+    }
+    println!("Upload complete: {} ({} bytes)", filename, total_bytes);
+    Ok(())
+}
 
-    loop {
-        let n = f.read(&mut buf)?;
-        if n == 0 {
-            break;
-        }
+/// An upload chunk sent but not yet acknowledged.
+struct InFlightChunk {
+    /// The encoded `UploadChunk`, kept for resending
+    datagram: Vec<u8>,
+    /// File bytes the chunk carries
+    len: u64,
+    sent_at: Instant,
+    /// Resends after a timeout so far, which lengthen the wait before the next one
+    attempts: u32,
+    /// Times the server reported the chunk corrupted
+    nacks: u32,
+    /// Acks for later chunks since this one was first sent; once they reach
+    /// `FAST_RESEND_ACKS` it is resent, but only that once without a timeout
+    later_acks: u32,
+}
 
-        let is_last = n < CHUNK_SIZE;
-        let chunk_data = buf[..n].to_vec();
+/// Streams `f` as numbered `UploadChunk`s with up to `window` of them unacknowledged, and
+/// returns the byte count the server reports once it has stored the whole file. The server
+/// writes chunks in id order whatever order they arrive in, acks duplicates without writing
+/// them twice, and answers the chunk that completes the file with `UploadComplete`.
+///
+/// A chunk is resent once its ack is overdue, and also at once, a single time, when three
+/// later chunks have been acked ahead of it. Either kind of loss halves the window, which then grows back by one
+/// chunk for every window's worth of acks, so a burst that overflows the server's socket
+/// buffer is not simply repeated. `retry` bounds the timeouts in a row without any ack.
+fn send_chunks(
+    socket: &UdpSocket,
+    f: &mut File,
+    size: u64,
+    read_size: usize,
+    compressed: bool,
+    window: usize,
+    retry: RetryPolicy,
+) -> io::Result<u64> {
+    let timeout = Duration::from_secs(TIMEOUT_SECS);
+    // a resend waits out the timeout plus the backoff pause `send_reliable` would take
+    let resend_after =
+        |attempts: u32| timeout + retry.backoff.saturating_mul(1 << attempts.min(16));
 
-        let chunk_req = Request::UploadChunk {
-            chunk_id,
-            data: chunk_data,
-            is_last,
-        };
+    let mut in_flight: BTreeMap<u32, InFlightChunk> = BTreeMap::new();
+    let mut next_id = 0u32;
+    let mut all_sent = false;
+    let mut total_acked = 0u64;
+    let mut buf = vec![0u8; read_size];
+    // the window in use, at most `window`, and the acks towards growing it
+    let mut limit = window;
+    let mut acks_toward_growth = 0;
+    let mut silent_timeouts = 0;
 
-        let chunk_resp = send_request(socket, &chunk_req)?;
-        match chunk_resp {
-            Response::ChunkAck { chunk_id: ack_id } => {
-                if ack_id != chunk_id {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Chunk ID mismatch: expected {}, got {}", chunk_id, ack_id),
-                    ));
-                }
-                total_sent += n as u64;
-                print!(
-                    "\rUploading: {}/{} bytes ({:.1}%)",
-                    total_sent,
-                    size,
-                    (total_sent as f64 / size as f64) * 100.0
-                );
-                io::stdout().flush()?;
+    loop {
+        // the window spans ids from the oldest unacknowledged chunk, so chunks acked past a
+        // lost one cannot push the server's reorder buffer beyond it
+        let oldest = in_flight.keys().next().copied().unwrap_or(next_id);
+        while !all_sent && ((next_id - oldest) as usize) < limit {
+            // a short read marks the last chunk; when the size is a multiple of the chunk size
+            // (or zero) that is an empty chunk, so the server always sees an `is_last`
+            let n = f.read(&mut buf)?;
+            let is_last = n < read_size;
+            let data = if compressed {
+                compress_chunk(&buf[..n])?
+            } else {
+                buf[..n].to_vec()
+            };
+            let req = Request::UploadChunk {
+                chunk_id: next_id,
+                crc32: crc32fast::hash(&data),
+                data,
+                is_last,
+            };
+            let datagram = encode_to_vec(&req, wire_config())
+                .map_err(|e| io::Error::other(format!("encode error: {e}")))?;
+            socket.send(&datagram)?;
+            in_flight.insert(
+                next_id,
+                InFlightChunk {
+                    datagram,
+                    len: n as u64,
+                    sent_at: Instant::now(),
+                    attempts: 0,
+                    nacks: 0,
+                    later_acks: 0,
+                },
+            );
+            next_id += 1;
+            all_sent = is_last;
+        }
 
-                if is_last {
-                    println!();
-                    println!("Upload complete: {} ({} bytes)", filename, total_sent);
-                    break;
+        // only chunks inside the window are resent; the rest wait until it reaches them
+        let span = oldest..oldest.saturating_add(limit as u32);
+        let due = in_flight
+            .range(span.clone())
+            .map(|(_, chunk)| chunk.sent_at + resend_after(chunk.attempts))
+            .min()
+            .unwrap_or_else(|| Instant::now() + timeout);
+        let wait = due.saturating_duration_since(Instant::now());
+        socket.set_read_timeout(Some(wait.max(Duration::from_millis(1))))?;
+        match recv_response(socket) {
+            // acks for chunks already confirmed, resent or not, are skipped
+            Ok(Response::ChunkAck { chunk_id }) => {
+                if let Some(chunk) = in_flight.remove(&chunk_id) {
+                    silent_timeouts = 0;
+                    for (_, earlier) in in_flight.range_mut(..chunk_id) {
+                        earlier.later_acks = (earlier.later_acks + 1).min(FAST_RESEND_ACKS + 1);
+                    }
+                    if limit < window {
+                        acks_toward_growth += 1;
+                        if acks_toward_growth >= limit {
+                            limit += 1;
+                            acks_toward_growth = 0;
+                        }
+                    }
+                    total_acked += chunk.len;
+                    print!(
+                        "\rUploading: {}/{} bytes ({:.1}%)",
+                        total_acked,
+                        size,
+                        (total_acked as f64 / size as f64) * 100.0
+                    );
+                    io::stdout().flush()?;
                 }
             }
-            Response::Error(msg) => {
+            // damaged on the way; it is sent again at once, which says nothing about congestion
+            Ok(Response::ChunkNack { chunk_id }) => {
+                if let Some(chunk) = in_flight.get_mut(&chunk_id) {
+                    silent_timeouts = 0;
+                    chunk.nacks += 1;
+                    if chunk.nacks > MAX_CORRUPT_CHUNKS {
+                        eprintln!("\nChunk {} keeps arriving corrupted", chunk_id);
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("chunk {} keeps arriving corrupted", chunk_id),
+                        ));
+                    }
+                    eprintln!("\nChunk {} arrived corrupted, resending", chunk_id);
+                    socket.send(&chunk.datagram)?;
+                    chunk.sent_at = Instant::now();
+                }
+            }
+            Ok(Response::UploadComplete { total_bytes }) if all_sent => return Ok(total_bytes),
+            Ok(Response::Error(msg)) => {
                 eprintln!("\nUpload error: {}", msg);
-                return Err(io::Error::new(io::ErrorKind::Other, msg));
+                return Err(io::Error::other(msg));
             }
-            _ => {
-                eprintln!("\nUnexpected response: {:?}", chunk_resp);
-                return Err(io::Error::new(io::ErrorKind::Other, "Unexpected response"));
+            Ok(other) => {
+                eprintln!("\nUnexpected response: {:?}", other);
+                return Err(io::Error::other("Unexpected response"));
+            }
+            Err(e) if is_timeout(&e) => {
+                silent_timeouts += 1;
+                if silent_timeouts > retry.retries {
+                    eprintln!("\nNo ack for chunk {}", oldest);
+                    return Err(e);
+                }
             }
+            Err(e) => return Err(e),
         }
 
-        chunk_id += 1;
+        let now = Instant::now();
+        let mut lost = false;
+        for (chunk_id, chunk) in in_flight.range_mut(span) {
+            let overdue = now >= chunk.sent_at + resend_after(chunk.attempts);
+            if !overdue && chunk.later_acks != FAST_RESEND_ACKS {
+                continue;
+            }
+            if overdue {
+                chunk.attempts += 1;
+                eprintln!("\nNo ack for chunk {}, resending", chunk_id);
+            }
+            socket.send(&chunk.datagram)?;
+            chunk.sent_at = now;
+            // past the threshold, later acks no longer trigger a resend
+            chunk.later_acks = FAST_RESEND_ACKS + 1;
+            lost = true;
+        }
+        if lost {
+            limit = (limit / 2).max(1);
+            acks_toward_growth = 0;
+        }
     }
-
-    Ok(())
 }
 
-fn do_download(socket: &UdpSocket, remote_path: &str, local_folder: &str) -> io::Result<()> {
+/// Downloads `remote_path` chunk by chunk, tolerating chunks that arrive out of order or
+/// need to be re-requested, then checks the result against the server's SHA-256. A failed
+/// download's local file is cleaned up by `discard_partial`.
+fn do_download(
+    socket: &UdpSocket,
+    remote_path: &str,
+    local_folder: &str,
+    chunk_size: usize,
+    retries: u32,
+    keep_partial: bool,
+    compressed: bool,
+) -> io::Result<()> {
+    // create the local folder (and any intermediate dirs) before asking for the file,
+    // so a bad target fails before the server starts sending data
+    if let Err(e) = std::fs::create_dir_all(local_folder) {
+        eprintln!("Cannot create local folder {}: {}", local_folder, e);
+        return Err(e);
+    }
+
     // Send download request
     let req = Request::Download {
         src_path: remote_path.to_string(),
+        offset: 0,
+        compressed,
     };
 
     let resp = send_request(socket, &req)?;
 
-    let (file_name, file_size) = match resp {
-        Response::FileMetadata { name, size } => {
+    let (file_name, file_size, expected_sha256) = match resp {
+        Response::FileMetadata {
+            name,
+            size: Some(size),
+            sha256,
+            ..
+        } => {
             println!("Downloading {} ({} bytes)", name, size);
-            (name, size)
+            (name, size, sha256)
         }
         Response::Error(msg) => {
             eprintln!("Download error: {}", msg);
-            return Err(io::Error::new(io::ErrorKind::Other, msg));
+            return Err(io::Error::other(msg));
         }
         _ => {
             eprintln!("Unexpected response: {:?}", resp);
-            return Err(io::Error::new(io::ErrorKind::Other, "Unexpected response"));
+            return Err(io::Error::other("Unexpected response"));
         }
     };
 
     // Create local file
     let local_path = std::path::Path::new(local_folder).join(&file_name);
-    if let Some(parent) = local_path.parent() {
-        let _ = std::fs::create_dir_all(parent);
-    }
     let mut f = File::create(&local_path)?;
 
-    // Download file in chunks
-    let mut chunk_id = 0u32;
-    let mut total_received = 0u64;
+    let result = receive_chunks(socket, &mut f, file_size, chunk_size, retries, compressed)
+        .and_then(|total_received| {
+            if total_received != file_size {
+                return Err(io::Error::other(format!(
+                    "Size mismatch: expected {} bytes, received {}",
+                    file_size, total_received
+                )));
+            }
+            if let Some(expected) = expected_sha256 {
+                let actual = sha256_file(&local_path)?;
+                if actual != expected {
+                    return Err(io::Error::other(format!(
+                        "Checksum mismatch: local sha256 {}, remote {}",
+                        to_hex(&actual),
+                        to_hex(&expected)
+                    )));
+                }
+            }
+            Ok(total_received)
+        });
+    drop(f);
+    let total_received = match result {
+        Ok(total_received) => total_received,
+        Err(e) => {
+            discard_partial(&local_path, keep_partial);
+            return Err(e);
+        }
+    };
 
-    loop {
-        let chunk_req = Request::DownloadChunk { chunk_id };
-        let chunk_resp = send_request(socket, &chunk_req)?;
+    println!(
+        "Download complete: {} ({} bytes) → {}",
+        file_name,
+        total_received,
+        local_path.display()
+    );
+    Ok(())
+}
 
-        match chunk_resp {
-            Response::FileChunk {
-                chunk_id: resp_chunk_id,
-                data,
-                is_last,
-            } => {
-                if resp_chunk_id != chunk_id {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!(
-                            "Chunk ID mismatch: expected {}, got {}",
-                            chunk_id, resp_chunk_id
-                        ),
-                    ));
-                }
+/// Fetches every chunk of the active download into `f`, returning the bytes received.
+/// `max_chunk_size` is the negotiated chunk size, or the largest a server may use.
+fn receive_chunks(
+    socket: &UdpSocket,
+    f: &mut File,
+    file_size: u64,
+    max_chunk_size: usize,
+    retries: u32,
+    compressed: bool,
+) -> io::Result<u64> {
+    // servers that did not negotiate may be configured with smaller chunks; the real size is
+    // learned from chunk 0, which is always asked for first. An empty file is still sent as
+    // one (empty) chunk
+    let mut chunk_size = max_chunk_size as u64;
+    let mut chunk_count = file_size.div_ceil(chunk_size).max(1) as u32;
+    let mut outstanding: BTreeSet<u32> = (0..chunk_count).collect();
+    // chunks received but not yet written, keyed by id so they can arrive in any order
+    let mut reassembly: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    let mut total_received = 0u64;
 
-                f.write_all(&data)?;
-                total_received += data.len() as u64;
+    while let Some(&wanted) = outstanding.first() {
+        let chunk_req = encode_to_vec(Request::DownloadChunk { chunk_id: wanted }, wire_config())
+            .map_err(|e| io::Error::other(format!("encode error: {e}")))?;
+        socket.send(&chunk_req)?;
 
-                print!(
-                    "\rDownloading: {}/{} bytes ({:.1}%)",
-                    total_received,
-                    file_size,
-                    (total_received as f64 / file_size as f64) * 100.0
-                );
-                io::stdout().flush()?;
-
-                if is_last {
-                    println!();
-                    f.flush()?;
-                    println!(
-                        "Download complete: {} ({} bytes) → {}",
-                        file_name,
-                        total_received,
-                        local_path.display()
+        let mut attempt = 0;
+        let mut corrupt = 0;
+        // a late reply to an earlier, retried request can arrive here; keep any chunk still
+        // outstanding and keep listening until the one just requested shows up
+        loop {
+            let chunk_resp = match recv_response(socket) {
+                Ok(resp) => resp,
+                Err(e) if is_timeout(&e) && attempt < retries => {
+                    attempt += 1;
+                    eprintln!(
+                        "\nNo response for chunk {}, retrying ({}/{})",
+                        wanted, attempt, retries
                     );
-                    break;
+                    socket.send(&chunk_req)?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            match chunk_resp {
+                // a damaged chunk is dropped; the one just requested is asked for again, while
+                // an earlier one stays outstanding and is requested in its turn
+                Response::FileChunk {
+                    chunk_id,
+                    data,
+                    crc32,
+                    ..
+                } if crc32fast::hash(&data) != crc32 => {
+                    if chunk_id == wanted {
+                        corrupt += 1;
+                        if corrupt > MAX_CORRUPT_CHUNKS {
+                            return Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("chunk {} keeps arriving corrupted", chunk_id),
+                            ));
+                        }
+                        eprintln!(
+                            "\nChunk {} arrived corrupted, requesting it again",
+                            chunk_id
+                        );
+                        socket.send(&chunk_req)?;
+                    }
+                }
+                Response::FileChunk { chunk_id, data, .. } => {
+                    if chunk_id >= chunk_count {
+                        return Err(io::Error::other(format!(
+                            "Chunk {} is past the end of a {} chunk file",
+                            chunk_id, chunk_count
+                        )));
+                    }
+                    if outstanding.remove(&chunk_id) {
+                        let data = if compressed {
+                            decompress_chunk(&data, max_chunk_size)?
+                        } else {
+                            data
+                        };
+                        // every chunk but the last is full, so a first chunk shorter than
+                        // expected that is not the whole file gives the server's chunk size
+                        let len = data.len() as u64;
+                        if chunk_id == 0 && len > 0 && len < chunk_size && len < file_size {
+                            chunk_size = len;
+                            chunk_count = file_size.div_ceil(chunk_size) as u32;
+                            outstanding = (1..chunk_count).collect();
+                        }
+                        total_received += len;
+                        reassembly.insert(chunk_id, data);
+                    }
+                    if chunk_id == wanted {
+                        break;
+                    }
+                }
+                Response::Error(msg) => {
+                    eprintln!("\nDownload error: {}", msg);
+                    return Err(io::Error::other(msg));
+                }
+                _ => {
+                    eprintln!("\nUnexpected response: {:?}", chunk_resp);
+                    return Err(io::Error::other("Unexpected response"));
                 }
             }
-            Response::Error(msg) => {
-                eprintln!("\nDownload error: {}", msg);
-                return Err(io::Error::new(io::ErrorKind::Other, msg));
-            }
-            _ => {
-                eprintln!("\nUnexpected response: {:?}", chunk_resp);
-                return Err(io::Error::new(io::ErrorKind::Other, "Unexpected response"));
+        }
+
+        if reassembly.len() >= REASSEMBLY_FLUSH_CHUNKS || outstanding.is_empty() {
+            for (chunk_id, data) in std::mem::take(&mut reassembly) {
+                f.seek(SeekFrom::Start(chunk_id as u64 * chunk_size))?;
+                f.write_all(&data)?;
             }
         }
 
-        chunk_id += 1;
+        print!(
+            "\rDownloading: {}/{} bytes ({:.1}%)",
+            total_received,
+            file_size,
+            (total_received as f64 / file_size as f64) * 100.0
+        );
+        io::stdout().flush()?;
+    }
+    println!();
+    f.flush()?;
+    Ok(total_received)
+}
+
+/// Deals with the local file of a failed download: removed by default so a truncated file
+/// never looks complete, or with `keep_partial` renamed to `<name>.partial` for inspection.
+fn discard_partial(local_path: &std::path::Path, keep_partial: bool) {
+    let result = if keep_partial {
+        let mut partial = local_path.as_os_str().to_os_string();
+        partial.push(".partial");
+        std::fs::rename(local_path, &partial)
+            .map(|_| eprintln!("Kept partial download as {}", partial.to_string_lossy()))
+    } else {
+        std::fs::remove_file(local_path)
+    };
+    if let Err(e) = result {
+        eprintln!("Cannot clean up {}: {}", local_path.display(), e);
     }
+}
+
+/// Hashes `local_path` and asks the server for the digest of `remote_path` with the same
+/// algorithm, printing MATCH or MISMATCH.
+fn do_verify(
+    socket: &UdpSocket,
+    remote_path: &str,
+    local_path: &str,
+    algo: HashAlgo,
+) -> io::Result<()> {
+    let (_, local_digest) = hash_file(std::path::Path::new(local_path), algo)?;
 
+    let req = Request::Hash {
+        path: remote_path.to_string(),
+        algo,
+    };
+    match send_request(socket, &req)? {
+        Response::Hash { digest } if digest == local_digest => {
+            println!("MATCH ({} {})", algo.name(), digest);
+        }
+        Response::Hash { digest } => {
+            println!("MISMATCH ({})", algo.name());
+            println!("  remote {}  {}", digest, remote_path);
+            println!("  local  {}  {}", local_digest, local_path);
+        }
+        Response::Error(msg) => return Err(io::Error::other(msg)),
+        other => {
+            return Err(io::Error::other(format!(
+                "Unexpected response: {:?}",
+                other
+            )));
+        }
+    }
     Ok(())
 }
 
 fn main() -> io::Result<()> {
+    let mut discover_mtu = false;
+    let mut preferred_chunk_size = CHUNK_SIZE;
+    let mut retries = 0;
+    let mut retry_backoff = Duration::ZERO;
+    let mut keep_partial = false;
+    let mut dry_run = false;
+    let mut window = DEFAULT_UPLOAD_WINDOW;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--discover-mtu" => discover_mtu = true,
+            "--keep-partial" => keep_partial = true,
+            "--dry-run" => dry_run = true,
+            "--retries" => match args.next().and_then(|v| v.parse::<u32>().ok()) {
+                Some(n) => retries = n,
+                None => {
+                    eprintln!("--retries requires a number");
+                    std::process::exit(1);
+                }
+            },
+            "--retry-backoff" => match args.next().and_then(|v| v.parse::<u64>().ok()) {
+                Some(ms) => retry_backoff = Duration::from_millis(ms),
+                None => {
+                    eprintln!("--retry-backoff requires a number of milliseconds");
+                    std::process::exit(1);
+                }
+            },
+            "--chunk-size" => match args.next().and_then(|v| v.parse::<u32>().ok()) {
+                Some(bytes) if bytes > 0 => preferred_chunk_size = bytes as usize,
+                _ => {
+                    eprintln!("--chunk-size requires a positive byte count");
+                    std::process::exit(1);
+                }
+            },
+            "--window" => match args.next().and_then(|v| v.parse::<usize>().ok()) {
+                Some(n) if (1..=MAX_UPLOAD_WINDOW).contains(&n) => window = n,
+                _ => {
+                    eprintln!("--window requires a number from 1 to {}", MAX_UPLOAD_WINDOW);
+                    std::process::exit(1);
+                }
+            },
+            other => {
+                eprintln!("Unknown argument: {}", other);
+                eprintln!(
+                    "Usage: udp_client [--discover-mtu] [--chunk-size <bytes>] [--retries <n>] [--retry-backoff <ms>] [--window <chunks>] [--keep-partial] [--dry-run]"
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let retry = RetryPolicy {
+        retries,
+        backoff: retry_backoff,
+    };
+
     let stdin = io::stdin();
     let mut lines = stdin.lock().lines();
 
@@ -244,8 +1023,58 @@ fn main() -> io::Result<()> {
 
     println!("Connected to {}", server_addr);
 
+    let features = match send_request(&socket, &Request::Capabilities) {
+        Ok(Response::Capabilities {
+            proto_version,
+            features,
+        }) => {
+            println!(
+                "Server protocol v{}, features: {}",
+                proto_version,
+                features.join(", ")
+            );
+            Some(features)
+        }
+        _ => {
+            println!("Server does not report capabilities");
+            None
+        }
+    };
+
+    if features
+        .as_deref()
+        .is_some_and(|f| f.iter().any(|f| f == "auth"))
+    {
+        if let Err(e) = log_in(&socket, &mut lines) {
+            eprintln!("Login failed: {}", e);
+            std::process::exit(1);
+        }
+        println!("Logged in");
+    }
+
+    if discover_mtu {
+        preferred_chunk_size = discover_chunk_size(&socket)?;
+    }
+    // a server that negotiates settles the chunk size for both directions; otherwise uploads
+    // use the preferred size and downloads learn the server's from their first chunk
+    let negotiated = if features
+        .as_deref()
+        .is_some_and(|f| f.iter().any(|f| f == "hello"))
+    {
+        negotiate_chunk_size(&socket, preferred_chunk_size)
+    } else {
+        None
+    };
+    let chunk_size = negotiated.unwrap_or(preferred_chunk_size);
+    let download_chunk_size = negotiated.unwrap_or(CHUNK_SIZE);
+
+    // the server does not report its cwd, so the client tracks it from successful `cd`s
+    let mut remote_dirs = Vec::new();
+    // ids for requests sent through `send_mutation`; unique within this client's session
+    let mut next_request_id = 0_u64;
+
     loop {
-        print!("> ");
+        print!("{}", prompt(&remote_dirs));
         io::stdout().flush()?;
 
         let line = match lines.next() {
@@ -254,25 +1083,54 @@ fn main() -> io::Result<()> {
                 eprintln!("Read error: {}", e);
                 break;
             }
-            None => break,
+            // Ctrl-D: end the prompt line before saying goodbye
+            None => {
+                println!();
+                break;
+            }
         };
 
-        let parts: Vec<&str> = line.trim().split_whitespace().collect();
+        let parts: Vec<&str> = line.split_whitespace().collect();
         if parts.is_empty() {
             continue;
         }
 
         let cmd = parts[0];
+        let feature = match cmd {
+            "df" | "stat" | "mktemp" | "swap" => Some(cmd),
+            "verify" => Some("hash"),
+            "touch" => Some("settime"),
+            "ln" => Some("link"),
+            "del" | "rm" => Some("delete"),
+            "move" | "mv" => Some("move"),
+            "loss" => Some("echo"),
+            _ => None,
+        };
+        if feature.is_some_and(|f| !check_feature(features.as_deref(), f)) {
+            continue;
+        }
+        if dry_run && let Some(desc) = dry_run_description(cmd, &parts[1..]) {
+            println!("[dry-run] would {}", desc);
+            continue;
+        }
 
         match cmd {
-            "exit" | "quit" => {
-                println!("Exiting.");
-                break;
-            }
+            "exit" | "quit" => break,
             "dir" | "ls" => {
+                let sort_key = match &parts[1..] {
+                    [] => None,
+                    ["--sort", key] if DIR_SORT_KEYS.contains(key) => Some(*key),
+                    _ => {
+                        eprintln!("Usage: dir [--sort name|type|size|mtime]");
+                        continue;
+                    }
+                };
                 let req = Request::Dir;
                 match send_request(&socket, &req) {
-                    Ok(Response::DirList(entries)) => {
+                    Ok(Response::DirList(mut entries)) => {
+                        if let Some(key) = sort_key {
+                            sort_dir_list(&mut entries, key);
+                        }
                         print_dir_list(&entries);
                     }
                     Ok(Response::Error(msg)) => {
@@ -286,6 +1144,33 @@ fn main() -> io::Result<()> {
                     }
                 }
             }
+            "df" => match send_request(&socket, &Request::Df) {
+                Ok(Response::Df { entries }) => print_df_table(&entries),
+                Ok(Response::Error(msg)) => eprintln!("Error: {}", msg),
+                Ok(other) => eprintln!("Unexpected response: {:?}", other),
+                Err(e) => eprintln!("Request failed: {}", e),
+            },
+            "stat" => {
+                if parts.len() < 2 {
+                    eprintln!("Usage: stat <path>");
+                    continue;
+                }
+                let req = Request::Stat {
+                    path: parts[1].to_string(),
+                };
+                match send_request(&socket, &req) {
+                    Ok(Response::StatResult {
+                        name,
+                        size,
+                        is_dir,
+                        modified_unix,
+                        readonly,
+                    }) => print_stat(&name, size, is_dir, modified_unix, readonly),
+                    Ok(Response::Error(msg)) => eprintln!("Error: {}", msg),
+                    Ok(other) => eprintln!("Unexpected response: {:?}", other),
+                    Err(e) => eprintln!("Request failed: {}", e),
+                }
+            }
             "cd" => {
                 if parts.len() < 2 {
                     eprintln!("Usage: cd <path>");
@@ -293,8 +1178,10 @@ fn main() -> io::Result<()> {
                 }
                 let path = parts[1].to_string();
                 let req = Request::Cd { path };
-                match send_request(&socket, &req) {
+                next_request_id += 1;
+                match send_mutation(&socket, req, next_request_id, retry) {
                     Ok(Response::Ok) => {
+                        apply_cd(&mut remote_dirs, parts[1]);
                         println!("Ok");
                     }
                     Ok(Response::Error(msg)) => {
@@ -309,9 +1196,10 @@ fn main() -> io::Result<()> {
                 }
             }
             "cd.." | "cdup" => {
-                let req = Request::CdUp;
-                match send_request(&socket, &req) {
+                next_request_id += 1;
+                match send_mutation(&socket, Request::CdUp, next_request_id, retry) {
                     Ok(Response::Ok) => {
+                        remote_dirs.pop();
                         println!("Ok");
                     }
                     Ok(Response::Error(msg)) => {
@@ -332,7 +1220,8 @@ fn main() -> io::Result<()> {
                 }
                 let name = parts[1].to_string();
                 let req = Request::Mkdir { name };
-                match send_request(&socket, &req) {
+                next_request_id += 1;
+                match send_mutation(&socket, req, next_request_id, retry) {
                     Ok(Response::Ok) => {
                         println!("Ok");
                     }
@@ -347,6 +1236,33 @@ fn main() -> io::Result<()> {
                     }
                 }
             }
+            "mktemp" => {
+                let req = Request::MkTemp {
+                    prefix: parts.get(1).copied().unwrap_or("tmp-").to_string(),
+                    suffix: parts.get(2).copied().unwrap_or("").to_string(),
+                };
+                next_request_id += 1;
+                match send_mutation(&socket, req, next_request_id, retry) {
+                    Ok(Response::Path(name)) => println!("{}", name),
+                    Ok(Response::Error(msg)) => eprintln!("Error: {}", msg),
+                    Ok(other) => eprintln!("Unexpected response: {:?}", other),
+                    Err(e) => eprintln!("Request failed: {}", e),
+                }
+            }
+            "touch" => {
+                let Some((path, mtime_secs)) = parse_touch_args(&parts[1..]) else {
+                    eprintln!("Usage: touch [-t <unix_secs>] <remote_file>");
+                    continue;
+                };
+                let req = Request::SetTime { path, mtime_secs };
+                next_request_id += 1;
+                match send_mutation(&socket, req, next_request_id, retry) {
+                    Ok(Response::Ok) => println!("Ok"),
+                    Ok(Response::Error(msg)) => eprintln!("Error: {}", msg),
+                    Ok(other) => eprintln!("Unexpected response: {:?}", other),
+                    Err(e) => eprintln!("Request failed: {}", e),
+                }
+            }
             "copy" => {
                 if parts.len() < 3 {
                     eprintln!("Usage: copy <src> <dst>");
@@ -355,7 +1271,8 @@ fn main() -> io::Result<()> {
                 let src = parts[1].to_string();
                 let dst = parts[2].to_string();
                 let req = Request::Copy { src, dst };
-                match send_request(&socket, &req) {
+                next_request_id += 1;
+                match send_mutation(&socket, req, next_request_id, retry) {
                     Ok(Response::CopyResult { bytes_copied }) => {
                         println!("Copied {} bytes", bytes_copied);
                     }
@@ -370,41 +1287,191 @@ fn main() -> io::Result<()> {
                     }
                 }
             }
+            "ln" => {
+                let [target, link] = parts[1..] else {
+                    eprintln!("Usage: ln <target> <link>");
+                    continue;
+                };
+                let req = Request::Link {
+                    target: target.to_string(),
+                    link: link.to_string(),
+                };
+                next_request_id += 1;
+                match send_mutation(&socket, req, next_request_id, retry) {
+                    Ok(Response::Ok) => println!("Ok"),
+                    Ok(Response::Error(msg)) => eprintln!("Error: {}", msg),
+                    Ok(other) => eprintln!("Unexpected response: {:?}", other),
+                    Err(e) => eprintln!("Request failed: {}", e),
+                }
+            }
+            "swap" => {
+                let [a, b] = parts[1..] else {
+                    eprintln!("Usage: swap <a> <b>");
+                    continue;
+                };
+                let req = Request::Swap {
+                    a: a.to_string(),
+                    b: b.to_string(),
+                };
+                next_request_id += 1;
+                match send_mutation(&socket, req, next_request_id, retry) {
+                    Ok(Response::Ok) => println!("Ok"),
+                    Ok(Response::Error(msg)) => eprintln!("Error: {}", msg),
+                    Ok(other) => eprintln!("Unexpected response: {:?}", other),
+                    Err(e) => eprintln!("Request failed: {}", e),
+                }
+            }
+            "del" | "rm" => {
+                let [path] = parts[1..] else {
+                    eprintln!("Usage: del <file>");
+                    continue;
+                };
+                let req = Request::Delete {
+                    path: path.to_string(),
+                };
+                next_request_id += 1;
+                match send_mutation(&socket, req, next_request_id, retry) {
+                    Ok(Response::Ok) => println!("Ok"),
+                    Ok(Response::Error(msg)) => eprintln!("Error: {}", msg),
+                    Ok(other) => eprintln!("Unexpected response: {:?}", other),
+                    Err(e) => eprintln!("Request failed: {}", e),
+                }
+            }
+            "move" | "mv" => {
+                let [src, dst] = parts[1..] else {
+                    eprintln!("Usage: move <src> <dst>");
+                    continue;
+                };
+                let req = Request::Move {
+                    src: src.to_string(),
+                    dst: dst.to_string(),
+                };
+                next_request_id += 1;
+                match send_mutation(&socket, req, next_request_id, retry) {
+                    Ok(Response::Ok) => println!("Ok"),
+                    Ok(Response::Error(msg)) => eprintln!("Error: {}", msg),
+                    Ok(other) => eprintln!("Unexpected response: {:?}", other),
+                    Err(e) => eprintln!("Request failed: {}", e),
+                }
+            }
             "upload" => {
-                if parts.len() < 2 {
-                    eprintln!("Usage: upload <local_file> [remote_folder]");
+                let (flags, args): (Vec<&str>, Vec<&str>) =
+                    parts[1..].iter().partition(|p| p.starts_with("--"));
+                if args.is_empty() {
+                    eprintln!(
+                        "Usage: upload <local_file> [remote_folder] [--atomic] [--force] [--append] [--compress]"
+                    );
+                    continue;
+                }
+                let local_file = args[0];
+                let remote_folder = if args.len() >= 2 { args[1] } else { "." };
+                let options = UploadOptions {
+                    atomic: flags.contains(&"--atomic"),
+                    overwrite: flags.contains(&"--force"),
+                    append: flags.contains(&"--append"),
+                    compressed: flags.contains(&"--compress"),
+                };
+                if options.compressed && !check_feature(features.as_deref(), "compress") {
                     continue;
                 }
-                let local_file = parts[1];
-                let remote_folder = if parts.len() >= 3 { parts[2] } else { "." };
 
-                match do_upload(&socket, local_file, remote_folder) {
+                match do_upload(
+                    &socket,
+                    local_file,
+                    remote_folder,
+                    chunk_size,
+                    retry,
+                    options,
+                    window,
+                ) {
                     Ok(_) => {}
                     Err(e) => eprintln!("Upload failed: {}", e),
                 }
             }
             "download" => {
-                if parts.len() < 2 {
-                    eprintln!("Usage: download <remote_file> [local_folder]");
+                let (flags, args): (Vec<&str>, Vec<&str>) =
+                    parts[1..].iter().partition(|p| p.starts_with("--"));
+                if args.is_empty() {
+                    eprintln!("Usage: download <remote_file> [local_folder] [--compress]");
+                    continue;
+                }
+                let remote_file = args[0];
+                let local_folder = if args.len() >= 2 { args[1] } else { "." };
+                let compressed = flags.contains(&"--compress");
+                if compressed && !check_feature(features.as_deref(), "compress") {
                     continue;
                 }
-                let remote_file = parts[1];
-                let local_folder = if parts.len() >= 3 { parts[2] } else { "." };
 
-                match do_download(&socket, remote_file, local_folder) {
+                match do_download(
+                    &socket,
+                    remote_file,
+                    local_folder,
+                    download_chunk_size,
+                    retries,
+                    keep_partial,
+                    compressed,
+                ) {
                     Ok(_) => {}
                     Err(e) => eprintln!("Download failed: {}", e),
                 }
             }
+            "verify" => {
+                let parsed = match &parts[1..] {
+                    [remote_path, local_path] => Some((remote_path, local_path, HashAlgo::Sha256)),
+                    [remote_path, local_path, "--algo", algo] => {
+                        HashAlgo::from_name(algo).map(|algo| (remote_path, local_path, algo))
+                    }
+                    _ => None,
+                };
+                let Some((remote_path, local_path, algo)) = parsed else {
+                    eprintln!("Usage: verify <remote_file> <local_file> [--algo sha256|sha512]");
+                    continue;
+                };
+
+                if let Err(e) = do_verify(&socket, remote_path, local_path, algo) {
+                    eprintln!("Verify failed: {}", e);
+                }
+            }
+            "loss" => {
+                let Some(count) = parts
+                    .get(1)
+                    .and_then(|c| c.parse::<u32>().ok())
+                    .filter(|&c| c > 0)
+                else {
+                    eprintln!("Usage: loss <count>");
+                    continue;
+                };
+
+                if let Err(e) = measure_loss(&socket, count) {
+                    eprintln!("Loss test failed: {}", e);
+                }
+            }
             "help" => {
                 println!("Available commands:");
                 println!("  dir / ls                          - List current directory");
+                println!("         [--sort name|type|size|mtime]");
                 println!("  cd <path>                         - Change directory");
                 println!("  cd.. / cdup                       - Go to parent directory");
                 println!("  mkdir <name>                      - Create directory");
+                println!("  df                                - Show server disk capacity");
+                println!("  stat <path>                       - Show size, type and mtime");
+                println!("  mktemp [prefix] [suffix]          - Create a unique empty file");
+                println!("  touch [-t <unix_secs>] <file>     - Set file mtime (default: now)");
                 println!("  copy <src> <dst>                  - Copy file");
+                println!("  ln <target> <link>                - Create a hard link");
+                println!("  swap <a> <b>                      - Exchange two remote files");
+                println!("  del / rm <file>                   - Delete a remote file");
+                println!("  move / mv <src> <dst>             - Rename or move a remote file");
                 println!("  upload <local_file> [remote_dir]  - Upload file to server");
+                println!("         [--atomic]                   (publish only once complete)");
+                println!("         [--force]                    (replace an existing file)");
+                println!("         [--append]                   (add to the end of the file)");
+                println!("         [--compress]                 (deflate each chunk)");
                 println!("  download <remote_file> [local_dir] - Download file from server");
+                println!("         [--compress]                 (deflate each chunk)");
+                println!("  verify <remote_file> <local_file> - Compare remote and local hashes");
+                println!("         [--algo sha256|sha512]");
+                println!("  loss <count>                      - Measure echo packet loss and RTT");
                 println!("  help                              - Show this help");
                 println!("  exit / quit                       - Exit client");
             }
@@ -415,5 +1482,6 @@ fn main() -> io::Result<()> {
         }
     }
 
+    println!("Goodbye.");
     Ok(())
 }
@@ -0,0 +1,134 @@
+use crate::{DfEntry, Response, ShellError};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Space an upload must leave free on its filesystem, so that finishing it does not starve
+/// everything else writing there.
+pub const UPLOAD_HEADROOM: u64 = 64 * 1024 * 1024;
+
+/// Returns `(free, total)` bytes of the filesystem holding `path`.
+pub fn capacity(path: &Path) -> io::Result<(u64, u64)> {
+    Ok((fs2::available_space(path)?, fs2::total_space(path)?))
+}
+
+/// Builds the `Df` reply: the served root (reported as `/`) followed by each extra path.
+pub fn df_report(root: &Path, extra_paths: &[PathBuf]) -> Response {
+    let labelled = std::iter::once(("/".to_string(), root)).chain(
+        extra_paths
+            .iter()
+            .map(|p| (p.display().to_string(), p.as_path())),
+    );
+
+    let mut entries = Vec::new();
+    for (label, path) in labelled {
+        match capacity(path) {
+            Ok((free, total)) => entries.push(DfEntry {
+                path: label,
+                free,
+                total,
+            }),
+            Err(e) => {
+                return Response::Error(ShellError::io(&format!("df failed for {}", label), &e));
+            }
+        }
+    }
+    Response::Df { entries }
+}
+
+/// Refuses an upload of `size` bytes into `dir` with `TooLarge` unless the filesystem has room
+/// for it plus [`UPLOAD_HEADROOM`], so a full disk is caught before any junk is written. A
+/// filesystem that cannot report its free space lets the upload through.
+pub fn check_upload_space(dir: &Path, size: u64) -> Result<(), ShellError> {
+    match fs2::available_space(dir) {
+        Ok(free) if !upload_fits(free, size) => Err(ShellError::TooLarge(format!(
+            "Not enough free space for {} bytes: {} available, of which {} are kept free",
+            size, free, UPLOAD_HEADROOM
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Creates the directory `dir` an upload of `size` bytes is going into, once
+/// [`check_upload_space`] has passed on the deepest part of it that already exists, so a
+/// refused upload leaves no empty directories behind.
+pub fn prepare_upload_dir(dir: &Path, size: u64) -> Result<(), ShellError> {
+    let existing = dir.ancestors().find(|p| p.exists()).unwrap_or(dir);
+    check_upload_space(existing, size)?;
+    fs::create_dir_all(dir).map_err(|e| ShellError::io("Cannot create the upload directory", &e))
+}
+
+fn upload_fits(free: u64, size: u64) -> bool {
+    // subtracting keeps a size near u64::MAX from saturating into a fit
+    free.checked_sub(UPLOAD_HEADROOM)
+        .is_some_and(|room| size <= room)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upload_with_room_to_spare_fits() {
+        assert!(upload_fits(UPLOAD_HEADROOM + 1000, 10));
+    }
+
+    #[test]
+    fn upload_leaving_exactly_the_headroom_fits() {
+        assert!(upload_fits(UPLOAD_HEADROOM + 1000, 1000));
+        assert!(upload_fits(UPLOAD_HEADROOM, 0));
+    }
+
+    #[test]
+    fn upload_cutting_into_the_headroom_is_refused() {
+        assert!(!upload_fits(UPLOAD_HEADROOM + 1000, 1001));
+    }
+
+    #[test]
+    fn upload_larger_than_the_free_space_is_refused() {
+        assert!(!upload_fits(100, 1000));
+        assert!(!upload_fits(0, u64::MAX));
+        assert!(!upload_fits(u64::MAX, u64::MAX - UPLOAD_HEADROOM + 1));
+    }
+
+    #[test]
+    fn upload_directories_are_created_once_there_is_room() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("a/b");
+
+        prepare_upload_dir(&dir, 10).unwrap();
+        assert!(dir.is_dir());
+    }
+
+    #[test]
+    fn refused_upload_creates_no_directories() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("a/b");
+
+        let e = prepare_upload_dir(&dir, u64::MAX).unwrap_err();
+        assert!(matches!(e, ShellError::TooLarge(_)), "{:?}", e);
+        assert!(!root.path().join("a").exists());
+    }
+
+    #[test]
+    fn upload_directory_blocked_by_a_file_is_an_error() {
+        let root = tempfile::tempdir().unwrap();
+        fs::write(root.path().join("a"), "").unwrap();
+
+        let e = prepare_upload_dir(&root.path().join("a/b"), 10).unwrap_err();
+        assert!(!matches!(e, ShellError::TooLarge(_)), "{:?}", e);
+    }
+
+    #[test]
+    fn df_lists_the_root_first() {
+        let root = tempfile::tempdir().unwrap();
+        match df_report(root.path(), &[root.path().to_path_buf()]) {
+            Response::Df { entries } => {
+                assert_eq!(entries.len(), 2);
+                assert_eq!(entries[0].path, "/");
+                assert!(entries[0].free <= entries[0].total);
+            }
+            other => panic!("unexpected reply: {:?}", other),
+        }
+    }
+}
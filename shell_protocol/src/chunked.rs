@@ -0,0 +1,86 @@
+use std::io::{self, Read, Write};
+
+/// Carries data whose size is not known up front (`Upload` or `FileMetadata` with `size:
+/// None`): every chunk is prefixed with its big-endian `u32` length, and an empty chunk ends
+/// the data. Call [`ChunkedWriter::finish`] to send that terminator.
+pub struct ChunkedWriter<W> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Ends the data with an empty chunk and hands back the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&0u32.to_be_bytes())?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // an empty chunk would end the data early
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(u32::MAX as usize);
+        self.inner.write_all(&(len as u32).to_be_bytes())?;
+        self.inner.write_all(&buf[..len])?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the data a [`ChunkedWriter`] sent, reporting end of file at the terminating empty
+/// chunk and never reading past it, so the next message starts cleanly. A connection that
+/// closes before the terminator fails with `UnexpectedEof` rather than passing for the end.
+pub struct ChunkedReader<R> {
+    inner: R,
+    /// Bytes left in the current chunk
+    remaining: u32,
+    done: bool,
+}
+
+impl<R: Read> ChunkedReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            let mut header = [0u8; 4];
+            self.inner.read_exact(&mut header)?;
+            self.remaining = u32::from_be_bytes(header);
+            if self.remaining == 0 {
+                self.done = true;
+                return Ok(0);
+            }
+        }
+
+        let len = buf.len().min(self.remaining as usize);
+        let n = self.inner.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed inside streamed data",
+            ));
+        }
+        self.remaining -= n as u32;
+        Ok(n)
+    }
+}
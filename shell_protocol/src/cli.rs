@@ -0,0 +1,197 @@
+use crate::{DfEntry, DirEntry};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Prints capacity entries as a table with the path column sized to the longest path.
+pub fn print_df_table(entries: &[DfEntry]) {
+    let width = entries
+        .iter()
+        .map(|e| e.path.len())
+        .chain(std::iter::once("Path".len()))
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{:<width$}  {:>15}  {:>15}  {:>15}  {:>5}",
+        "Path", "Total", "Used", "Free", "Use%"
+    );
+    for e in entries {
+        let used = e.total.saturating_sub(e.free);
+        let percent = if e.total == 0 {
+            0.0
+        } else {
+            used as f64 / e.total as f64 * 100.0
+        };
+        println!(
+            "{:<width$}  {:>15}  {:>15}  {:>15}  {:>4.0}%",
+            e.path, e.total, used, e.free, percent
+        );
+    }
+}
+
+/// Applies a successful `cd <path>` to the client-side copy of the remote path.
+pub fn apply_cd(remote_dirs: &mut Vec<String>, path: &str) {
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                remote_dirs.pop();
+            }
+            dir => remote_dirs.push(dir.to_string()),
+        }
+    }
+}
+
+/// Prompt showing the remote path, reconstructed from the successful `cd`s, e.g. `remote:/logs/2024 > `.
+pub fn prompt(remote_dirs: &[String]) -> String {
+    format!("remote:/{} > ", remote_dirs.join("/"))
+}
+
+/// Keys accepted by `dir --sort`.
+pub const DIR_SORT_KEYS: [&str; 4] = ["name", "type", "size", "mtime"];
+
+/// Orders a listing for display by `name` or by `type` (directories first, then by name).
+/// Listings carry no size or mtime, so `size` and `mtime` fall back to name order.
+pub fn sort_dir_list(list: &mut [DirEntry], key: &str) {
+    match key {
+        "type" => list.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name))),
+        "size" | "mtime" => {
+            eprintln!("Warning: listings do not include {}, sorting by name", key);
+            list.sort_by(|a, b| a.name.cmp(&b.name));
+        }
+        _ => list.sort_by(|a, b| a.name.cmp(&b.name)),
+    }
+}
+
+/// Parses `touch [-t <unix_secs>] <remote_file>`; without `-t` the time is now.
+pub fn parse_touch_args(args: &[&str]) -> Option<(String, u64)> {
+    match args {
+        [path] => {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+            Some((path.to_string(), now.as_secs()))
+        }
+        ["-t", secs, path] => Some((path.to_string(), secs.parse().ok()?)),
+        _ => None,
+    }
+}
+
+/// Whether the server advertised `feature`, printing why not if it did not. Servers that
+/// predate capability reporting are given the benefit of the doubt.
+pub fn check_feature(features: Option<&[String]>, feature: &str) -> bool {
+    match features {
+        Some(features) if !features.iter().any(|f| f == feature) => {
+            println!("The server does not support `{}`", feature);
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Prints a `StatResult` one field per line.
+pub fn print_stat(name: &str, size: u64, is_dir: bool, modified_unix: u64, readonly: bool) {
+    println!("Name:     {}", name);
+    println!("Type:     {}", if is_dir { "directory" } else { "file" });
+    println!("Size:     {} bytes", size);
+    println!("Modified: {} (Unix seconds)", modified_unix);
+    println!("Readonly: {}", if readonly { "yes" } else { "no" });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dirs(path: &str) -> Vec<String> {
+        let mut remote_dirs = Vec::new();
+        apply_cd(&mut remote_dirs, path);
+        remote_dirs
+    }
+
+    #[test]
+    fn cd_descends_one_component_at_a_time() {
+        assert_eq!(dirs("logs/2024"), ["logs", "2024"]);
+        assert_eq!(dirs("./logs//2024/"), ["logs", "2024"]);
+    }
+
+    #[test]
+    fn cd_dot_dot_climbs_and_stops_at_the_root() {
+        let mut remote_dirs = dirs("logs/2024");
+        apply_cd(&mut remote_dirs, "../archive");
+        assert_eq!(remote_dirs, ["logs", "archive"]);
+        apply_cd(&mut remote_dirs, "../../..");
+        assert!(remote_dirs.is_empty());
+    }
+
+    #[test]
+    fn prompt_shows_the_remote_path() {
+        assert_eq!(prompt(&[]), "remote:/ > ");
+        assert_eq!(prompt(&dirs("logs/2024")), "remote:/logs/2024 > ");
+    }
+
+    fn entry(name: &str, is_dir: bool) -> DirEntry {
+        DirEntry {
+            name: name.to_string(),
+            is_dir,
+        }
+    }
+
+    fn names(list: &[DirEntry]) -> Vec<&str> {
+        list.iter().map(|e| e.name.as_str()).collect()
+    }
+
+    #[test]
+    fn sort_by_type_lists_directories_first() {
+        let mut list = [
+            entry("b.txt", false),
+            entry("z", true),
+            entry("a.txt", false),
+            entry("c", true),
+        ];
+        sort_dir_list(&mut list, "type");
+        assert_eq!(names(&list), ["c", "z", "a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn sort_by_keys_listings_lack_falls_back_to_name() {
+        for key in ["name", "size", "mtime"] {
+            let mut list = [entry("b", true), entry("c", false), entry("a", false)];
+            sort_dir_list(&mut list, key);
+            assert_eq!(names(&list), ["a", "b", "c"]);
+        }
+    }
+
+    #[test]
+    fn touch_with_an_explicit_time() {
+        assert_eq!(
+            parse_touch_args(&["-t", "1700000000", "a.txt"]),
+            Some(("a.txt".to_string(), 1_700_000_000))
+        );
+    }
+
+    #[test]
+    fn touch_without_a_time_uses_now() {
+        let before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let (path, secs) = parse_touch_args(&["a.txt"]).unwrap();
+        assert_eq!(path, "a.txt");
+        assert!(secs >= before);
+    }
+
+    #[test]
+    fn touch_with_malformed_arguments_is_refused() {
+        assert_eq!(parse_touch_args(&[]), None);
+        assert_eq!(parse_touch_args(&["-t", "yesterday", "a.txt"]), None);
+        assert_eq!(parse_touch_args(&["-t", "1700000000"]), None);
+    }
+
+    #[test]
+    fn feature_check_follows_the_advertised_list() {
+        let features = ["dir".to_string(), "stat".to_string()];
+        assert!(check_feature(Some(&features), "stat"));
+        assert!(!check_feature(Some(&features), "tar"));
+    }
+
+    #[test]
+    fn feature_check_trusts_servers_without_capabilities() {
+        assert!(check_feature(None, "tar"));
+    }
+}
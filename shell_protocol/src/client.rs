@@ -0,0 +1,761 @@
+use crate::chunked::{ChunkedReader, ChunkedWriter};
+use crate::cli::apply_cd;
+use crate::compress::Inflater;
+use crate::framing::{read_frame, write_frame};
+use crate::hash::{sha256_file, to_hex};
+use crate::tls::Transport;
+use crate::{DirEntry, MAX_MESSAGE_SIZE, Request, Response, wire_config};
+use bincode::error::DecodeError;
+use bincode::serde::{decode_from_std_read, encode_into_std_write};
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use rustls::ClientConfig;
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const MAX_TRANSIENT_RETRIES: u32 = 5;
+const TRANSIENT_RETRY_DELAY_MS: u64 = 100;
+/// Fresh connections the requests wrapped in [`ShellClient::with_retry`] may use up.
+const MAX_RECONNECTS: u32 = 2;
+
+/// A connection to the TCP server with one method per request. Errors the server reports
+/// come back as `io::Error`s wrapping the `ShellError`, so callers can still match on it.
+pub struct ShellClient {
+    stream: Transport,
+    /// Messages carry a length prefix, for the async server
+    framed: bool,
+    /// The address and TLS config the connection was made with, so it can be replaced
+    remote: Option<(String, Option<Arc<ClientConfig>>)>,
+    /// The login and remote cwd a replacement connection has to restore
+    login: Option<(String, String)>,
+    cwd: Vec<String>,
+}
+
+/// A file or directory as `Stat` describes it.
+#[derive(Debug, Clone)]
+pub struct FileStat {
+    pub name: String,
+    pub size: u64,
+    pub is_dir: bool,
+    /// Seconds since the epoch
+    pub modified_unix: u64,
+    pub readonly: bool,
+}
+
+/// Where the server stands on an upload once it has accepted the request.
+#[derive(Debug, Clone, Copy)]
+pub struct UploadSession {
+    /// Identifies the upload to `UploadResume` if the connection drops.
+    pub id: u64,
+    /// Bytes the server already holds; the file is sent from here on.
+    pub received: u64,
+}
+
+/// How an upload treats its destination and the data on the wire. The default writes the
+/// file directly and refuses to replace an existing one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UploadOptions {
+    /// Stage the file and publish it only once complete
+    pub atomic: bool,
+    /// Replace a file already at the destination
+    pub overwrite: bool,
+    /// Add the data to the end of the destination
+    pub append: bool,
+    /// Deflate the data on the wire
+    pub compressed: bool,
+}
+
+impl ShellClient {
+    pub fn new(stream: Transport) -> Self {
+        Self {
+            stream,
+            framed: false,
+            remote: None,
+            login: None,
+            cwd: Vec::new(),
+        }
+    }
+
+    /// Switches to length-prefixed messages (see [`crate::framing`]), which the async
+    /// server expects; raw file data is unaffected.
+    pub fn framed(mut self) -> Self {
+        self.framed = true;
+        self
+    }
+
+    /// Connects to `addr` (`host:port`). With `tls`, the server's certificate must be valid
+    /// for the host part of `addr`. Only a client made this way can reconnect in
+    /// [`ShellClient::with_retry`].
+    pub fn connect(addr: &str, tls: Option<&Arc<ClientConfig>>) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        let mut client = Self::new(Transport::connect(stream, host_of(addr), tls)?);
+        client.remote = Some((addr.to_string(), tls.cloned()));
+        Ok(client)
+    }
+
+    /// Runs `op`, and whenever the connection drops under it, reconnects and runs it again,
+    /// up to `retries` times. The server starts every connection afresh, so the new one is
+    /// logged in and moved to the remote cwd first. Only for requests that are safe to
+    /// repeat: one the server carried out before the connection dropped is carried out again.
+    pub fn with_retry<T>(
+        &mut self,
+        op: impl Fn(&mut Self) -> io::Result<T>,
+        retries: u32,
+    ) -> io::Result<T> {
+        let mut attempt = 0;
+        loop {
+            match op(self) {
+                Err(e) if attempt < retries && is_dropped_connection(&e) => {
+                    let Some((addr, tls)) = self.remote.clone() else {
+                        return Err(e);
+                    };
+                    attempt += 1;
+                    self.reconnect(&addr, tls.as_ref())?;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Replaces the connection with a new one to `addr` in the same session state.
+    fn reconnect(&mut self, addr: &str, tls: Option<&Arc<ClientConfig>>) -> io::Result<()> {
+        let stream = TcpStream::connect(addr)?;
+        self.stream = Transport::connect(stream, host_of(addr), tls)?;
+        if let Some((user, password)) = self.login.clone() {
+            self.log_in(&user, &password)?;
+        }
+        if !self.cwd.is_empty() {
+            let req = Request::Cd {
+                path: self.cwd.join("/"),
+            };
+            expect_ok(self.request(&req)?)?;
+        }
+        Ok(())
+    }
+
+    /// The underlying connection, for requests answered with a stream of frames or raw bytes.
+    pub fn stream(&mut self) -> &mut Transport {
+        &mut self.stream
+    }
+
+    pub fn send(&mut self, req: &Request) -> io::Result<()> {
+        if self.framed {
+            return write_frame(&mut self.stream, req);
+        }
+        encode_into_std_write(req, &mut self.stream, wire_config())
+            .map_err(|e| io::Error::other(format!("encode error: {e}")))?;
+        Ok(())
+    }
+
+    pub fn receive(&mut self) -> io::Result<Response> {
+        if self.framed {
+            return read_frame(&mut self.stream, MAX_MESSAGE_SIZE as u64);
+        }
+        decode_from_std_read(&mut self.stream, wire_config()).map_err(|e| match e {
+            // the connection failed rather than the server sending something unreadable
+            DecodeError::Io { inner, .. } => inner,
+            e => io::Error::new(io::ErrorKind::InvalidData, format!("decode error: {e}")),
+        })
+    }
+
+    /// Sends `req` and returns the server's reply as is, error replies included.
+    pub fn request(&mut self, req: &Request) -> io::Result<Response> {
+        self.send(req)?;
+        self.receive()
+    }
+
+    /// The server's protocol version and optional features.
+    pub fn capabilities(&mut self) -> io::Result<(u32, Vec<String>)> {
+        match self.request(&Request::Capabilities)? {
+            Response::Capabilities {
+                proto_version,
+                features,
+            } => Ok((proto_version, features)),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    pub fn log_in(&mut self, user: &str, password: &str) -> io::Result<()> {
+        let req = Request::Auth {
+            user: user.to_string(),
+            password: password.to_string(),
+        };
+        expect_ok(self.request(&req)?)?;
+        self.login = Some((user.to_string(), password.to_string()));
+        Ok(())
+    }
+
+    pub fn dir(&mut self) -> io::Result<Vec<DirEntry>> {
+        self.with_retry(
+            |client| match client.request(&Request::Dir)? {
+                Response::DirList(list) => Ok(list),
+                other => Err(unexpected(other)),
+            },
+            MAX_RECONNECTS,
+        )
+    }
+
+    pub fn cd(&mut self, path: &str) -> io::Result<()> {
+        let req = Request::Cd {
+            path: path.to_string(),
+        };
+        self.with_retry(|client| expect_ok(client.request(&req)?), MAX_RECONNECTS)?;
+        apply_cd(&mut self.cwd, path);
+        Ok(())
+    }
+
+    pub fn cd_up(&mut self) -> io::Result<()> {
+        expect_ok(self.request(&Request::CdUp)?)?;
+        self.cwd.pop();
+        Ok(())
+    }
+
+    /// Creates the directory `name` in the remote cwd. If the connection dropped after the
+    /// server made it, the retried request fails with `AlreadyExists`.
+    pub fn mkdir(&mut self, name: &str) -> io::Result<()> {
+        let req = Request::Mkdir {
+            name: name.to_string(),
+        };
+        self.with_retry(|client| expect_ok(client.request(&req)?), MAX_RECONNECTS)
+    }
+
+    pub fn stat(&mut self, path: &str) -> io::Result<FileStat> {
+        let req = Request::Stat {
+            path: path.to_string(),
+        };
+        self.with_retry(
+            |client| match client.request(&req)? {
+                Response::StatResult {
+                    name,
+                    size,
+                    is_dir,
+                    modified_unix,
+                    readonly,
+                } => Ok(FileStat {
+                    name,
+                    size,
+                    is_dir,
+                    modified_unix,
+                    readonly,
+                }),
+                other => Err(unexpected(other)),
+            },
+            MAX_RECONNECTS,
+        )
+    }
+
+    /// Uploads `local` into the remote directory `remote_dir`, returning the bytes sent. A
+    /// file already there is not replaced; the server refuses the upload instead.
+    pub fn upload(&mut self, local: &Path, remote_dir: &str) -> io::Result<u64> {
+        let req = upload_request(local, remote_dir, UploadOptions::default())?;
+        let mut f = File::open(local)?;
+        let session = self.start_upload(&req)?;
+        match self.stream_upload(&mut f, session.received, false)? {
+            (bytes_written, Response::UploadComplete { .. }) => Ok(bytes_written),
+            (_, other) => Err(unexpected(other)),
+        }
+    }
+
+    /// Sends an `Upload` or `UploadResume` request and returns the session the server opened.
+    pub fn start_upload(&mut self, req: &Request) -> io::Result<UploadSession> {
+        match self.request(req)? {
+            Response::UploadSession { id, received } => Ok(UploadSession { id, received }),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    /// Streams `f` from `offset` to the end and returns the byte count with the server's
+    /// verdict, which comes once it has stored and checked the data.
+    pub fn stream_upload(
+        &mut self,
+        f: &mut File,
+        offset: u64,
+        compressed: bool,
+    ) -> io::Result<(u64, Response)> {
+        f.seek(SeekFrom::Start(offset))?;
+        let bytes_written = if compressed {
+            let mut encoder = DeflateEncoder::new(&mut self.stream, Compression::default());
+            let bytes_written = io::copy(f, &mut encoder)?;
+            encoder.finish()?;
+            bytes_written
+        } else {
+            io::copy(f, &mut self.stream)?
+        };
+        self.stream.flush()?;
+        Ok((bytes_written, self.receive()?))
+    }
+
+    /// Sends everything `reader` yields as the data of an `Upload` of unknown size, in
+    /// chunks ended by an empty one, and returns the byte count with the server's verdict.
+    pub fn stream_upload_chunked(&mut self, reader: &mut impl Read) -> io::Result<(u64, Response)> {
+        let mut chunks = ChunkedWriter::new(&mut self.stream);
+        let bytes_written = io::copy(reader, &mut chunks)?;
+        chunks.finish()?;
+        Ok((bytes_written, self.receive()?))
+    }
+
+    /// Downloads the remote file `remote` into `local_dir`, returning where it was saved.
+    pub fn download(&mut self, remote: &str, local_dir: &Path) -> io::Result<PathBuf> {
+        let req = download_request(remote, local_dir, false);
+        self.fetch(&req, local_dir, false, |_, _, _| {})
+    }
+
+    /// Sends a request answered with `FileMetadata` followed by the file bytes, and saves
+    /// the file into `local_dir` under the name the server gave it. `progress` is called
+    /// with the name, bytes received so far and total size, if known, after every read.
+    ///
+    /// A failed transfer never leaves a file that looks complete: it is removed, or with
+    /// `keep_partial` renamed to `<name>.partial`, which a later `Download` resumes from.
+    pub fn fetch(
+        &mut self,
+        req: &Request,
+        local_dir: &Path,
+        keep_partial: bool,
+        mut progress: impl FnMut(&str, u64, Option<u64>),
+    ) -> io::Result<PathBuf> {
+        // create the local folder (and any intermediate dirs) before asking for the file,
+        // so a bad target fails before the server starts sending data
+        fs::create_dir_all(local_dir).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("cannot create local folder {}: {}", local_dir.display(), e),
+            )
+        })?;
+
+        // the data only arrives deflated if the request asked for it
+        let compressed = matches!(
+            req,
+            Request::Download {
+                compressed: true,
+                ..
+            }
+        );
+
+        let (name, size, offset, sha256) = match self.request(req)? {
+            Response::FileMetadata {
+                name,
+                size,
+                offset,
+                sha256,
+            } => (name, size, offset, sha256),
+            other => return Err(unexpected(other)),
+        };
+        let local_path = local_dir.join(&name);
+
+        let mut hasher = Sha256::new();
+        let mut f = if offset > 0 {
+            // move the partial file back in place and hash what it already holds,
+            // so the digest check still covers the whole file
+            fs::rename(partial_path(&local_path), &local_path)?;
+            let mut f = OpenOptions::new()
+                .read(true)
+                .append(true)
+                .open(&local_path)?;
+            io::copy(&mut f, &mut hasher)?;
+            f
+        } else {
+            File::create(&local_path)?
+        };
+        let mut on_read = |received| progress(&name, received, size);
+        let received = self.receive_body(&mut f, offset, size, compressed, hasher, &mut on_read);
+        let result = received.and_then(|digest| match sha256 {
+            Some(expected) if expected != digest => Err(io::Error::other(format!(
+                "Checksum mismatch: expected sha256 {}, received {}",
+                to_hex(&expected),
+                to_hex(&digest)
+            ))),
+            _ => Ok(()),
+        });
+        drop(f);
+        match result {
+            Ok(()) => Ok(local_path),
+            Err(e) => Err(discard_partial(&local_path, keep_partial, e)),
+        }
+    }
+
+    /// Like [`Self::fetch`], but writes the file to `out` instead of saving it, and returns
+    /// the name the server gave it with its size. The data is checked against the server's digest only
+    /// once it has all been written, so `out` may already hold it when that check fails.
+    pub fn fetch_to(
+        &mut self,
+        req: &Request,
+        out: &mut impl Write,
+        mut progress: impl FnMut(&str, u64, Option<u64>),
+    ) -> io::Result<(String, u64)> {
+        let compressed = matches!(
+            req,
+            Request::Download {
+                compressed: true,
+                ..
+            }
+        );
+
+        let (name, size, sha256) = match self.request(req)? {
+            Response::FileMetadata {
+                name,
+                size,
+                offset: 0,
+                sha256,
+            } => (name, size, sha256),
+            Response::FileMetadata { offset, .. } => {
+                return Err(io::Error::other(format!(
+                    "Server resumed at byte {}, but the file is written from the start",
+                    offset
+                )));
+            }
+            other => return Err(unexpected(other)),
+        };
+
+        let mut received = 0;
+        let mut on_read = |n| {
+            received = n;
+            progress(&name, n, size)
+        };
+        let digest = self.receive_body(out, 0, size, compressed, Sha256::new(), &mut on_read)?;
+        match sha256 {
+            Some(expected) if expected != digest => Err(io::Error::other(format!(
+                "Checksum mismatch: expected sha256 {}, received {}",
+                to_hex(&expected),
+                to_hex(&digest)
+            ))),
+            _ => Ok((name, received)),
+        }
+    }
+
+    /// Reads the bytes that follow `FileMetadata` into `out` and returns the SHA-256 of
+    /// everything `hasher` has seen. Without a `size` they arrive in chunks, which are never
+    /// deflated; otherwise the bytes from `offset` up to `size` arrive raw, or as one deflate
+    /// stream with `compressed`.
+    fn receive_body(
+        &mut self,
+        out: &mut impl Write,
+        offset: u64,
+        size: Option<u64>,
+        compressed: bool,
+        hasher: Sha256,
+        progress: &mut impl FnMut(u64),
+    ) -> io::Result<[u8; 32]> {
+        if size.is_none() {
+            let mut chunks = ChunkedReader::new(&mut self.stream);
+            receive_file(&mut chunks, out, offset, None, hasher, progress)
+        } else if compressed {
+            let mut decoder = Inflater::new(&mut self.stream);
+            receive_file(&mut decoder, out, offset, size, hasher, progress)
+                .and_then(|digest| decoder.finish().map(|_| digest))
+        } else {
+            receive_file(&mut self.stream, out, offset, size, hasher, progress)
+        }
+    }
+
+    /// Copies `size` raw bytes of a batch download into `out`, calling `progress` with the
+    /// bytes received so far after every read.
+    pub fn receive_into(
+        &mut self,
+        out: &mut impl Write,
+        size: u64,
+        mut progress: impl FnMut(u64),
+    ) -> io::Result<()> {
+        receive_file(
+            &mut self.stream,
+            out,
+            0,
+            Some(size),
+            Sha256::new(),
+            &mut progress,
+        )
+        .map(|_| ())
+    }
+
+    /// Closes the connection, telling a TLS server that no truncation took place.
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        self.stream.shutdown()
+    }
+}
+
+/// Builds an `Upload` of `local` into `remote_dir`. The file is hashed here, since the
+/// digest travels in the request ahead of the data.
+pub fn upload_request(
+    local: &Path,
+    remote_dir: &str,
+    options: UploadOptions,
+) -> io::Result<Request> {
+    let file_name = local
+        .file_name()
+        .and_then(|os| os.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Invalid filename"))?
+        .to_string();
+    Ok(Request::Upload {
+        dst_path: remote_dir.to_string(),
+        file_name,
+        size: Some(fs::metadata(local)?.len()),
+        atomic: options.atomic,
+        overwrite: options.overwrite,
+        append: options.append,
+        sha256: Some(sha256_file(local)?),
+        compressed: options.compressed,
+    })
+}
+
+/// Builds a `Download` of `remote` into `local_dir`, resuming from the `.partial` file an
+/// earlier failed download left there, if any.
+pub fn download_request(remote: &str, local_dir: &Path, compressed: bool) -> Request {
+    let offset = Path::new(remote)
+        .file_name()
+        .map(|name| partial_path(&local_dir.join(name)))
+        .and_then(|partial| fs::metadata(partial).ok())
+        .map_or(0, |metadata| metadata.len());
+    Request::Download {
+        src_path: remote.to_string(),
+        offset,
+        compressed,
+    }
+}
+
+/// Where a failed download is kept with `keep_partial`, and resumed from by the next one.
+pub fn partial_path(local_path: &Path) -> PathBuf {
+    let mut partial = local_path.as_os_str().to_os_string();
+    partial.push(".partial");
+    partial.into()
+}
+
+/// The host part of `host:port`, without the brackets of an IPv6 literal.
+pub fn host_of(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Errors after which the connection is gone, though the server may accept a new one.
+fn is_dropped_connection(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+fn expect_ok(resp: Response) -> io::Result<()> {
+    match resp {
+        Response::Ok => Ok(()),
+        other => Err(unexpected(other)),
+    }
+}
+
+/// An error reply becomes an error carrying the `ShellError`; anything else was not a
+/// valid answer to the request.
+fn unexpected(resp: Response) -> io::Error {
+    match resp {
+        Response::Error(e) => io::Error::other(e),
+        other => io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unexpected response: {:?}", other),
+        ),
+    }
+}
+
+/// Copies the raw bytes of a file from `offset` up to `size`, or without one up to the end
+/// of `reader`, into `out`, returning the SHA-256 of `hasher` (already fed the first
+/// `offset` bytes) over them.
+fn receive_file(
+    reader: &mut impl Read,
+    out: &mut impl Write,
+    offset: u64,
+    size: Option<u64>,
+    mut hasher: Sha256,
+    progress: &mut impl FnMut(u64),
+) -> io::Result<[u8; 32]> {
+    let mut remaining = size.map_or(u64::MAX, |size| size.saturating_sub(offset));
+    let mut buf = [0u8; 8192];
+    let mut total_read = offset;
+
+    while remaining > 0 {
+        let to_read = std::cmp::min(buf.len() as u64, remaining) as usize;
+        let n = read_retrying(reader, &mut buf[..to_read])?;
+        if n == 0 {
+            let Some(size) = size else { break };
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Connection closed after {} of {} bytes", total_read, size),
+            ));
+        }
+        out.write_all(&buf[..n])?;
+        hasher.update(&buf[..n]);
+        remaining -= n as u64;
+        total_read += n as u64;
+        progress(total_read);
+    }
+    out.flush()?;
+    Ok(hasher.finalize().into())
+}
+
+/// Reads into `buf`, retrying reads that were interrupted by a signal and, up to
+/// `MAX_TRANSIENT_RETRIES` times, reads that hit a socket timeout.
+fn read_retrying<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut transient_retries = 0;
+    loop {
+        match reader.read(buf) {
+            Ok(n) => return Ok(n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) && transient_retries < MAX_TRANSIENT_RETRIES =>
+            {
+                transient_retries += 1;
+                thread::sleep(Duration::from_millis(TRANSIENT_RETRY_DELAY_MS));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Deals with the local file of a failed download, returning the transfer error `e` with
+/// what became of the file added to it.
+fn discard_partial(local_path: &Path, keep_partial: bool, e: io::Error) -> io::Error {
+    let note = if keep_partial {
+        let partial = partial_path(local_path);
+        match fs::rename(local_path, &partial) {
+            Ok(()) => format!("partial download kept as {}", partial.display()),
+            Err(err) => format!("cannot keep partial download: {}", err),
+        }
+    } else {
+        match fs::remove_file(local_path) {
+            Ok(()) => return e,
+            Err(err) => format!("cannot remove {}: {}", local_path.display(), err),
+        }
+    };
+    io::Error::new(e.kind(), format!("{}; {}", e, note))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    /// A reader that plays back a fixed sequence of reads and errors, then reports EOF.
+    struct Scripted {
+        steps: VecDeque<io::Result<&'static [u8]>>,
+        reads: u32,
+    }
+
+    impl Scripted {
+        fn new(steps: impl IntoIterator<Item = io::Result<&'static [u8]>>) -> Self {
+            Self {
+                steps: steps.into_iter().collect(),
+                reads: 0,
+            }
+        }
+    }
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            match self.steps.pop_front() {
+                Some(Ok(bytes)) => {
+                    buf[..bytes.len()].copy_from_slice(bytes);
+                    Ok(bytes.len())
+                }
+                Some(Err(e)) => Err(e),
+                None => Ok(0),
+            }
+        }
+    }
+
+    fn err(kind: io::ErrorKind) -> io::Result<&'static [u8]> {
+        Err(io::Error::from(kind))
+    }
+
+    #[test]
+    fn interrupted_read_is_retried_and_returns_the_short_read() {
+        let mut reader = Scripted::new([err(io::ErrorKind::Interrupted), Ok(&b"abc"[..])]);
+        let mut buf = [0u8; 16];
+
+        assert_eq!(read_retrying(&mut reader, &mut buf).unwrap(), 3);
+        assert_eq!(&buf[..3], b"abc");
+        assert_eq!(reader.reads, 2);
+    }
+
+    #[test]
+    fn short_reads_are_assembled_into_the_whole_file() {
+        let mut reader = Scripted::new([
+            Ok(&b"hel"[..]),
+            err(io::ErrorKind::Interrupted),
+            err(io::ErrorKind::WouldBlock),
+            Ok(&b"lo"[..]),
+        ]);
+        let mut out = Vec::new();
+
+        let digest = receive_file(
+            &mut reader,
+            &mut out,
+            0,
+            Some(5),
+            Sha256::new(),
+            &mut |_| {},
+        )
+        .unwrap();
+        assert_eq!(out, b"hello");
+        assert_eq!(digest, <[u8; 32]>::from(Sha256::digest(b"hello")));
+    }
+
+    #[test]
+    fn timeouts_past_the_retry_limit_are_an_error() {
+        let retries = MAX_TRANSIENT_RETRIES as usize;
+        let mut reader = Scripted::new(
+            std::iter::repeat_with(|| err(io::ErrorKind::WouldBlock)).take(retries + 1),
+        );
+        let mut buf = [0u8; 16];
+
+        let e = read_retrying(&mut reader, &mut buf).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+        assert_eq!(reader.reads, MAX_TRANSIENT_RETRIES + 1);
+    }
+
+    #[test]
+    fn dropped_connection_is_replaced_with_the_session_restored() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        // answers everything with canned replies, but closes the first connection instead
+        // of answering its Dir; returns every request it saw
+        let server = thread::spawn(move || {
+            let mut seen = Vec::new();
+            for drop_on_dir in [true, false] {
+                let (mut stream, _) = listener.accept().unwrap();
+                while let Ok(req) =
+                    decode_from_std_read::<Request, _, _>(&mut stream, wire_config())
+                {
+                    seen.push(format!("{:?}", req));
+                    let resp = match req {
+                        Request::Dir if drop_on_dir => break,
+                        Request::Dir => Response::DirList(vec![DirEntry {
+                            name: "a.txt".to_string(),
+                            is_dir: false,
+                        }]),
+                        _ => Response::Ok,
+                    };
+                    encode_into_std_write(&resp, &mut stream, wire_config()).unwrap();
+                }
+            }
+            seen
+        });
+
+        let mut client = ShellClient::connect(&addr, None).unwrap();
+        client.log_in("ann", "secret").unwrap();
+        client.cd("docs").unwrap();
+        let names: Vec<String> = client.dir().unwrap().into_iter().map(|e| e.name).collect();
+        assert_eq!(names, ["a.txt"]);
+        drop(client);
+
+        let login = r#"Auth { user: "ann", password: "secret" }"#;
+        let cd = r#"Cd { path: "docs" }"#;
+        assert_eq!(server.join().unwrap(), [login, cd, "Dir", login, cd, "Dir"]);
+    }
+}
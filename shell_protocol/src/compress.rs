@@ -0,0 +1,160 @@
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use std::io::{self, Read, Write};
+
+/// Bytes of each UDP chunk left unused when its data is deflated, since deflating
+/// incompressible data adds a few bytes of block headers.
+pub const COMPRESSION_SLACK: usize = 64;
+
+/// Deflates one UDP chunk on its own, so chunks can still be decoded in any order.
+pub fn compress_chunk(data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Inflates one UDP chunk, refusing output larger than `max_len` so a small datagram cannot
+/// expand into an arbitrarily large buffer.
+pub fn decompress_chunk(data: &[u8], max_len: usize) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    DeflateDecoder::new(data)
+        .take(max_len as u64 + 1)
+        .read_to_end(&mut out)?;
+    if out.len() > max_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("chunk inflates to more than {} bytes", max_len),
+        ));
+    }
+    Ok(out)
+}
+
+/// Reads one deflate stream off a TCP connection. Unlike `flate2::read::DeflateDecoder`,
+/// which asks the connection for more bytes before noticing it has already reached the end,
+/// this stops at the end marker, so reading past the announced size cannot block.
+pub struct Inflater<R> {
+    reader: R,
+    decompress: Decompress,
+    buf: Box<[u8]>,
+    pos: usize,
+    len: usize,
+    done: bool,
+}
+
+impl<R: Read> Inflater<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decompress: Decompress::new(false),
+            buf: vec![0; 8192].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            done: false,
+        }
+    }
+
+    /// Consumes the rest of the stream once the announced number of bytes has been read from
+    /// it, so the next message starts cleanly. Data beyond the announced size is an error.
+    pub fn finish(&mut self) -> io::Result<()> {
+        let mut probe = [0u8; 1];
+        match self.read(&mut probe)? {
+            0 => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "compressed stream holds more data than announced",
+            )),
+        }
+    }
+}
+
+impl<R: Read> Read for Inflater<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.done {
+                return Ok(0);
+            }
+
+            // inflate from what is buffered first: it may hold output or the end marker
+            let (in_before, out_before) = (self.decompress.total_in(), self.decompress.total_out());
+            let status = self
+                .decompress
+                .decompress(&self.buf[self.pos..self.len], out, FlushDecompress::None)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.pos += (self.decompress.total_in() - in_before) as usize;
+            let produced = (self.decompress.total_out() - out_before) as usize;
+            if status == Status::StreamEnd {
+                self.done = true;
+            }
+            if produced > 0 || self.done || out.is_empty() {
+                return Ok(produced);
+            }
+
+            if self.pos == self.len {
+                self.len = self.reader.read(&mut self.buf)?;
+                self.pos = 0;
+                if self.len == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed inside a compressed stream",
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<u8> {
+        b"abcabcabc".repeat(1000)
+    }
+
+    #[test]
+    fn chunk_round_trip() {
+        let data = sample();
+        let packed = compress_chunk(&data).unwrap();
+        assert!(packed.len() < data.len());
+        assert_eq!(decompress_chunk(&packed, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn chunk_inflating_past_the_limit_is_refused() {
+        let packed = compress_chunk(&sample()).unwrap();
+        let err = decompress_chunk(&packed, 100).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    /// A connection with nothing more to read yet.
+    struct Idle;
+
+    impl Read for Idle {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            panic!("read past the end of the compressed stream");
+        }
+    }
+
+    #[test]
+    fn inflater_finishes_without_reading_past_the_stream() {
+        let data = sample();
+        let packed = compress_chunk(&data).unwrap();
+
+        let mut inflater = Inflater::new((&packed[..]).chain(Idle));
+        let mut out = vec![0; data.len()];
+        inflater.read_exact(&mut out).unwrap();
+        inflater.finish().unwrap();
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn inflater_reports_a_truncated_stream() {
+        let packed = compress_chunk(&sample()).unwrap();
+        let mut out = Vec::new();
+        let err = Inflater::new(&packed[..packed.len() / 2])
+            .read_to_end(&mut out)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,104 @@
+use serde::Deserialize;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Seconds a UDP session may stay idle before it is dropped.
+pub const DEFAULT_SESSION_TIMEOUT_SECS: u64 = 300;
+/// Bytes of file data per UDP download chunk, and the most a server may be configured with,
+/// since clients size their receive buffers for it.
+pub const DEFAULT_CHUNK_SIZE: usize = 8192;
+
+/// Server settings from `--config <file.yaml>`. Every key is optional; `addr` and `root` can
+/// instead come from the positional arguments, which take precedence.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub addr: Option<String>,
+    pub root: Option<PathBuf>,
+    /// UDP only: idle time after which a session and its transfers are dropped
+    pub session_timeout_secs: u64,
+    /// UDP only: bytes of file data per download chunk
+    pub chunk_size: usize,
+    /// Largest upload accepted, by the size the client declares
+    pub max_file_size: Option<u64>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: None,
+            root: None,
+            session_timeout_secs: DEFAULT_SESSION_TIMEOUT_SECS,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_file_size: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let file = File::open(path)
+            .map_err(|e| format!("cannot read config {}: {}", path.display(), e))?;
+        let config: ServerConfig = serde_yaml::from_reader(file)
+            .map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+        if config.session_timeout_secs == 0 {
+            return Err(format!(
+                "{}: session_timeout_secs must be positive",
+                path.display()
+            ));
+        }
+        if !(1..=DEFAULT_CHUNK_SIZE).contains(&config.chunk_size) {
+            return Err(format!(
+                "{}: chunk_size must be between 1 and {}",
+                path.display(),
+                DEFAULT_CHUNK_SIZE
+            ));
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn load_str(yaml: &str) -> Result<ServerConfig, String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server.yaml");
+        fs::write(&path, yaml).unwrap();
+        ServerConfig::load(&path)
+    }
+
+    #[test]
+    fn sample_config_loads() {
+        let sample = Path::new(env!("CARGO_MANIFEST_DIR")).join("config/server.yaml");
+        let config = ServerConfig::load(&sample).unwrap();
+        assert_eq!(config.addr.as_deref(), Some("127.0.0.1:8888"));
+        assert_eq!(config.root, Some(PathBuf::from("./test_root")));
+        assert_eq!(config.session_timeout_secs, 300);
+        assert_eq!(config.chunk_size, 8192);
+        assert_eq!(config.max_file_size, Some(1 << 30));
+    }
+
+    #[test]
+    fn missing_keys_take_their_defaults() {
+        let config = load_str("max_file_size: 10\n").unwrap();
+        assert_eq!(config.addr, None);
+        assert_eq!(config.session_timeout_secs, DEFAULT_SESSION_TIMEOUT_SECS);
+        assert_eq!(config.chunk_size, DEFAULT_CHUNK_SIZE);
+    }
+
+    #[test]
+    fn out_of_range_values_are_refused() {
+        assert!(load_str("session_timeout_secs: 0\n").is_err());
+        assert!(load_str("chunk_size: 0\n").is_err());
+        assert!(load_str("chunk_size: 9000\n").is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_refused() {
+        let err = load_str("max_filesize: 10\n").unwrap_err();
+        assert!(err.contains("max_filesize"), "{err}");
+    }
+}
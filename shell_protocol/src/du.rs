@@ -0,0 +1,43 @@
+use crate::Response;
+use crate::paths::resolve_dir;
+use std::fs;
+use std::path::Path;
+
+/// Sums the sizes of every regular file under the directory `path` (relative to `cwd`),
+/// refusing directories that resolve outside `root`, which must be canonical. Symlinks are
+/// neither followed nor counted, so the walk cannot leave the tree, and entries that cannot
+/// be read are skipped rather than failing the whole request.
+pub fn disk_usage(root: &Path, cwd: &Path, path: &str) -> Response {
+    let dir = match resolve_dir(root, cwd, path) {
+        Ok(dir) => dir,
+        Err(e) => return Response::Error(e),
+    };
+
+    let (mut total_bytes, mut file_count) = (0, 0);
+    walk(&dir, &mut total_bytes, &mut file_count);
+    Response::DiskUsageResult {
+        total_bytes,
+        file_count,
+    }
+}
+
+fn walk(dir: &Path, total_bytes: &mut u64, file_count: &mut u64) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        // file_type and metadata do not follow symlinks
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            walk(&entry.path(), total_bytes, file_count);
+        } else if file_type.is_file()
+            && let Ok(meta) = entry.metadata()
+        {
+            *total_bytes += meta.len();
+            *file_count += 1;
+        }
+    }
+}
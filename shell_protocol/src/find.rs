@@ -0,0 +1,50 @@
+use crate::glob::glob_match;
+use crate::{Response, ShellError};
+use std::fs;
+use std::path::Path;
+
+/// Most paths one `Find` returns, keeping the reply well under the message size limit.
+pub const MAX_FIND_RESULTS: usize = 10_000;
+
+/// Walks `cwd` and lists every file or directory whose name matches the glob `pattern`,
+/// as sorted `/`-separated paths relative to `cwd`. `max_depth` counts levels below `cwd`,
+/// so `Some(1)` only looks at its direct entries. Symlinks are listed but never followed,
+/// so the walk cannot leave the root, and directories that cannot be read are skipped.
+pub fn find_paths(root: &Path, cwd: &Path, pattern: &str, max_depth: Option<u32>) -> Response {
+    if !cwd.starts_with(root) {
+        return Response::Error(ShellError::PathEscapesRoot);
+    }
+
+    let mut found = Vec::new();
+    walk(cwd, "", pattern, max_depth, &mut found);
+    found.sort();
+    found.truncate(MAX_FIND_RESULTS);
+    Response::FindResult(found)
+}
+
+fn walk(dir: &Path, prefix: &str, pattern: &str, depth_left: Option<u32>, found: &mut Vec<String>) {
+    if depth_left == Some(0) || found.len() >= MAX_FIND_RESULTS {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let rel = format!("{}{}", prefix, name);
+        if glob_match(pattern, &name) {
+            found.push(rel.clone());
+        }
+        // file_type does not follow symlinks
+        if entry.file_type().is_ok_and(|t| t.is_dir()) {
+            walk(
+                &entry.path(),
+                &format!("{}/", rel),
+                pattern,
+                depth_left.map(|d| d - 1),
+                found,
+            );
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::wire_config;
+use bincode::serde::{decode_from_slice, encode_to_vec};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::io::{self, Read, Write};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the big-endian `u32` length prefix in front of every framed message.
+pub const FRAME_HEADER_LEN: usize = 4;
+
+fn encode_frame(msg: &impl Serialize) -> io::Result<Vec<u8>> {
+    let body = encode_to_vec(msg, wire_config())
+        .map_err(|e| io::Error::other(format!("encode error: {e}")))?;
+    let len = u32::try_from(body.len())
+        .map_err(|_| io::Error::other(format!("message of {} bytes", body.len())))?;
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + body.len());
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&body);
+    Ok(frame)
+}
+
+/// Checks a frame's announced length against `max_size` before anything is allocated.
+fn frame_len(header: [u8; FRAME_HEADER_LEN], max_size: u64) -> io::Result<usize> {
+    let len = u32::from_be_bytes(header);
+    if u64::from(len) > max_size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds the {} byte limit", len, max_size),
+        ));
+    }
+    Ok(len as usize)
+}
+
+fn decode_frame<T: DeserializeOwned>(body: &[u8]) -> io::Result<T> {
+    decode_from_slice(body, wire_config())
+        .map(|(msg, _)| msg)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("decode error: {e}")))
+}
+
+/// Writes `msg` prefixed with its encoded size, the framing the async TCP server speaks, so
+/// a reader can take in the whole message with one `read_exact` before decoding it. Raw
+/// file bytes after a transfer message are sent as they are, without a prefix.
+pub fn write_frame(stream: &mut impl Write, msg: &impl Serialize) -> io::Result<()> {
+    stream.write_all(&encode_frame(msg)?)
+}
+
+/// Reads one frame; one announcing more than `max_size` bytes fails with `InvalidData`.
+pub fn read_frame<T: DeserializeOwned>(stream: &mut impl Read, max_size: u64) -> io::Result<T> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    stream.read_exact(&mut header)?;
+    let mut body = vec![0u8; frame_len(header, max_size)?];
+    stream.read_exact(&mut body)?;
+    decode_frame(&body)
+}
+
+/// [`write_frame`] for async streams.
+pub async fn write_frame_async(
+    stream: &mut (impl AsyncWrite + Unpin),
+    msg: &impl Serialize,
+) -> io::Result<()> {
+    stream.write_all(&encode_frame(msg)?).await
+}
+
+/// [`read_frame`] for async streams.
+pub async fn read_frame_async<T: DeserializeOwned>(
+    stream: &mut (impl AsyncRead + Unpin),
+    max_size: u64,
+) -> io::Result<T> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    stream.read_exact(&mut header).await?;
+    let mut body = vec![0u8; frame_len(header, max_size)?];
+    stream.read_exact(&mut body).await?;
+    decode_frame(&body)
+}
@@ -0,0 +1,71 @@
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum HashAlgo {
+    Sha256,
+    Sha512,
+}
+
+impl HashAlgo {
+    /// Parses the name used on the command line, e.g. `sha256`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "sha256" => Some(HashAlgo::Sha256),
+            "sha512" => Some(HashAlgo::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgo::Sha256 => "sha256",
+            HashAlgo::Sha512 => "sha512",
+        }
+    }
+}
+
+/// Hashes a file in fixed-size chunks, returning its size and lowercase hex digest.
+pub fn hash_file(path: &Path, algo: HashAlgo) -> io::Result<(u64, String)> {
+    match algo {
+        HashAlgo::Sha256 => digest_file::<Sha256>(path),
+        HashAlgo::Sha512 => digest_file::<Sha512>(path),
+    }
+}
+
+/// Raw SHA-256 of a file, as carried by the `sha256` field of transfer messages.
+pub fn sha256_file(path: &Path) -> io::Result<[u8; 32]> {
+    sha256_reader(&mut File::open(path)?)
+}
+
+/// Raw SHA-256 of everything left in `reader`.
+pub fn sha256_reader(reader: &mut impl Read) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    io::copy(reader, &mut hasher)?;
+    Ok(hasher.finalize().into())
+}
+
+/// Lowercase hex rendering of a digest.
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn digest_file<D: Digest>(path: &Path) -> io::Result<(u64, String)> {
+    let mut f = File::open(path)?;
+    let mut hasher = D::new();
+    let mut buf = [0u8; 8192];
+    let mut size = 0u64;
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        size += n as u64;
+    }
+    Ok((size, to_hex(&hasher.finalize())))
+}
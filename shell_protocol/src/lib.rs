@@ -1,8 +1,135 @@
+pub mod access_log;
+pub mod auth;
+pub mod capacity;
+pub mod chunked;
+pub mod cli;
+pub mod client;
+pub mod compress;
+pub mod config;
+pub mod du;
+pub mod find;
+pub mod framing;
+pub mod glob;
+pub mod hash;
+pub mod link;
+pub mod metrics;
+pub mod mktemp;
+pub mod paths;
+pub mod rate_limit;
+pub mod remove;
+pub mod rename;
+pub mod settime;
+pub mod stat;
+pub mod storage;
+pub mod swap;
+pub mod tcp_server;
+pub mod tls;
+pub mod tokio_server;
+pub mod udp_server;
+pub mod upload_session;
+
+use bincode::config::{Configuration, Limit, LittleEndian, Varint};
 use bincode::{Decode, Encode};
+use hash::HashAlgo;
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+/// Version of the request/response encoding; bumped when existing messages change shape.
+pub const PROTOCOL_VERSION: u32 = 12;
+
+/// Upper bound on the encoded size of a single message. A length field claiming more than this
+/// fails to decode with `DecodeError::LimitExceeded` instead of being allocated; raise it here if
+/// legitimate messages (e.g. very large directory listings) ever outgrow it.
+pub const MAX_MESSAGE_SIZE: usize = 8 * 1024 * 1024;
+
+/// Upper bound on the bytes an encoded `UploadChunk` or `FileChunk` adds around its data
+/// (variant tag, varint chunk id, length and CRC-32, and the `is_last` flag).
+pub const CHUNK_OVERHEAD: usize = 24;
+
+/// The bincode configuration every peer encodes and decodes messages with.
+pub fn wire_config() -> Configuration<LittleEndian, Varint, Limit<MAX_MESSAGE_SIZE>> {
+    bincode::config::standard().with_limit::<MAX_MESSAGE_SIZE>()
+}
+
+/// Checks the server root at startup, so a mistyped path fails with a clear message instead
+/// of every later `Dir` or `Cd` failing on its own.
+pub fn validate_root(root: &Path) -> Result<(), String> {
+    match fs::metadata(root) {
+        Ok(meta) if meta.is_dir() => Ok(()),
+        Ok(_) => Err(format!(
+            "root must be a directory, got a file: {}",
+            root.display()
+        )),
+        Err(e) => Err(format!("cannot use root {}: {}", root.display(), e)),
+    }
+}
+
+/// Creates the file an upload writes to: `dest` itself, or the staging file at `write_path`.
+/// Unless `overwrite` is set, an existing `dest` is refused with `AlreadyExists`; a direct
+/// upload then also opens `dest` with `create_new`, so a file that appears after the check is
+/// not clobbered either. With `append` the data goes on the end of `dest` instead, and the
+/// length it had before is returned for [`discard_upload`].
+pub fn create_upload_file(
+    dest: &Path,
+    write_path: &Path,
+    overwrite: bool,
+    append: bool,
+) -> Result<(File, Option<u64>), ShellError> {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    let context = format!("Cannot create {}", name);
+    if append {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(dest)
+            .map_err(|e| ShellError::io(&context, &e))?;
+        let len = file
+            .metadata()
+            .map_err(|e| ShellError::io(&context, &e))?
+            .len();
+        return Ok((file, Some(len)));
+    }
+    if !overwrite && fs::symlink_metadata(dest).is_ok() {
+        return Err(ShellError::AlreadyExists(format!(
+            "{} already exists",
+            name
+        )));
+    }
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if overwrite || write_path != dest {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    let file = options
+        .open(write_path)
+        .map_err(|e| ShellError::io(&context, &e))?;
+    Ok((file, None))
+}
+
+/// Undoes a failed upload to `path`. An append is cut back to `appended_to`, the length the
+/// file had before it, so earlier contents survive; any other upload is removed.
+pub fn discard_upload(path: &Path, appended_to: Option<u64>) {
+    match appended_to {
+        Some(len) => {
+            if let Ok(file) = OpenOptions::new().write(true).open(path) {
+                let _ = file.set_len(len);
+            }
+        }
+        None => {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Encode, Decode)]
 pub enum Request {
+    /// Capabilities: server replies with its protocol version and the optional features it supports
+    Capabilities,
     Dir,
     CdUp,
     Mkdir {
@@ -16,53 +143,412 @@ pub enum Request {
         dst: String,
     },
     /// Upload (client → server): after sending metadata, client will stream raw bytes.
+    /// With `atomic` set, the server stages the data under a `.part` name and only renames
+    /// it to the real destination once the whole file has been received. When `sha256` is
+    /// given, the server checks the received bytes against it and discards the file on a
+    /// mismatch. Over TCP the server first replies `UploadSession` (UDP: `Ok`) and answers
+    /// the finished stream with `UploadComplete` or `Error`.
+    /// With `compressed` the data is deflated on the wire (one stream over TCP, each chunk on
+    /// its own over UDP); `size` is always the uncompressed size.
+    /// Without `overwrite` an existing destination is refused with `AlreadyExists`, while
+    /// `append` adds the data to the end of it (or creates it); `size` and `sha256` then
+    /// describe only the appended bytes. An upload cannot both append and be atomic.
+    /// With `size` unknown (`None`, TCP only) the data is sent in length-prefixed chunks up
+    /// to an empty one (see [`chunked`]); such an upload cannot be compressed or resumed.
     Upload {
         dst_path: String,
         file_name: String,
-        size: u64,
+        size: Option<u64>,
+        atomic: bool,
+        overwrite: bool,
+        append: bool,
+        sha256: Option<[u8; 32]>,
+        compressed: bool,
     },
-    /// Download (server → client): server responds with metadata, then streams raw file bytes.
+    /// Download (server → client): server responds with metadata, then streams raw file bytes
+    /// starting at `offset`, so an interrupted TCP download can be resumed. UDP only accepts 0.
+    /// `compressed` deflates the data on the wire, as for `Upload`.
     Download {
         src_path: String,
+        offset: u64,
+        compressed: bool,
     },
-    /// UDP Upload: client sends file chunk
+    /// UDP Upload: client sends file chunk, with the CRC-32 of `data` as sent
     UploadChunk {
         chunk_id: u32,
         data: Vec<u8>,
+        crc32: u32,
         is_last: bool,
     },
     /// UDP Download: request next chunk
     DownloadChunk {
         chunk_id: u32,
     },
+    /// Watch (TCP only): server replies `Ok`, then streams `FsEvent` frames for changes
+    /// under the directory until the client disconnects.
+    Watch {
+        path: String,
+    },
+    /// Follow (TCP only): like `tail -f`, server replies `Ok`, then streams a `Line` frame for
+    /// every line appended to the file until the client disconnects.
+    Follow {
+        path: String,
+    },
+    /// Manifest (TCP only): server streams one `ManifestEntry` frame per file under the
+    /// directory, followed by a single `ManifestEnd` frame.
+    Manifest {
+        path: String,
+    },
+    /// Search (TCP only): server walks the directory and streams a `SearchHit` frame for each
+    /// file whose name matches `name_glob` and, if given, whose content matches `content_regex`,
+    /// followed by a single `SearchEnd` frame. Either filter may be omitted.
+    Search {
+        path: String,
+        name_glob: Option<String>,
+        content_regex: Option<String>,
+    },
+    /// DownloadZip (TCP only): server packs every regular file under the directory into a ZIP
+    /// archive, replies with `FileMetadata` for `<dir>.zip`, then streams the archive bytes
+    /// like `Download`. Symlinks are skipped.
+    DownloadZip {
+        src_path: String,
+    },
+    /// DownloadMany (TCP only): server replies with `BatchMetadata` listing every file's size,
+    /// then streams the files' raw bytes back-to-back in the order given
+    DownloadMany {
+        paths: Vec<String>,
+    },
+    /// Df: free/total capacity of the server root and any extra paths it was started with
+    Df,
+    /// Echo: server replies with the same payload; used by the UDP client to probe datagram sizes
+    Echo {
+        payload: Vec<u8>,
+    },
+    /// MkTemp: create a uniquely named empty file `<prefix><random><suffix>` in the cwd
+    MkTemp {
+        prefix: String,
+        suffix: String,
+    },
+    /// SetTime: set the modification time of a file under the root, in seconds since the epoch
+    SetTime {
+        path: String,
+        mtime_secs: u64,
+    },
+    /// UDP: a request tagged with a client-chosen id so it can be retried safely. The server
+    /// caches the response for a short window and replays it for a repeated id instead of
+    /// executing the request again.
+    Idempotent {
+        request_id: u64,
+        request: Box<Request>,
+    },
+    /// Hash: digest of a file relative to the cwd, computed with `algo`
+    Hash {
+        path: String,
+        algo: HashAlgo,
+    },
+    /// Link: create a hard link `link` to the existing file `target`; both must stay under
+    /// the root. Only supported by servers running on Unix.
+    Link {
+        target: String,
+        link: String,
+    },
+    /// Swap: exchange the two existing paths `a` and `b`; both must stay under the root.
+    /// Atomic on Linux servers, a three-step rename elsewhere.
+    Swap {
+        a: String,
+        b: String,
+    },
+    /// Delete: remove the file at `path`, relative to the cwd; it must stay under the root.
+    /// Directories are refused.
+    Delete {
+        path: String,
+    },
+    /// Move: rename `src` to `dst`, or move it into `dst` if that is a directory; both must
+    /// stay under the root. Falls back to copy-then-delete across mount points.
+    Move {
+        src: String,
+        dst: String,
+    },
+    /// Find (TCP only): every path under the cwd whose file name matches the glob `pattern`,
+    /// at most `max_depth` levels deep, answered with a single `FindResult`.
+    Find {
+        pattern: String,
+        max_depth: Option<u32>,
+    },
+    /// UploadResume (TCP only): continue an interrupted upload on a new connection. The server
+    /// replies with `UploadSession` carrying how many bytes it already holds, then the client
+    /// streams the rest of the file from there. `size` must match the original upload;
+    /// `compressed` applies to the remaining bytes only.
+    UploadResume {
+        id: u64,
+        size: u64,
+        compressed: bool,
+    },
+    /// Auth: log in as `user`. A server started with a credentials file advertises the `auth`
+    /// feature and answers every other request except `Capabilities` with
+    /// `ShellError::Unauthorized` until this has succeeded.
+    Auth {
+        user: String,
+        password: String,
+    },
+    /// Stat: size, type, modification time and read-only flag of the file or directory at
+    /// `path`, relative to the cwd; answered with `StatResult`
+    Stat {
+        path: String,
+    },
+    /// DiskUsage (TCP only): total size and number of the regular files anywhere under the
+    /// directory `path`, answered with `DiskUsageResult`. Symlinks are not followed.
+    DiskUsage {
+        path: String,
+    },
+    /// Hello (UDP only): asks for the chunk size the rest of the session uses for the data of
+    /// `UploadChunk` and `FileChunk`. The server answers with `Hello`, carrying the size it
+    /// settled on, which is never larger than what fits in one datagram.
+    Hello {
+        preferred_chunk_size: u32,
+    },
+    /// DownloadArchive (TCP only): server packs every regular file under the directory `path`
+    /// into a tar archive as it streams it, so `FileMetadata` for `<dir>.tar` carries no size
+    /// or digest and the archive follows in chunks like an upload of unknown size. Symlinks
+    /// are skipped.
+    DownloadArchive {
+        path: String,
+    },
 }
 
-#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+impl Request {
+    /// The variant name, e.g. for logs; never includes the request's fields.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Request::Capabilities => "Capabilities",
+            Request::Dir => "Dir",
+            Request::CdUp => "CdUp",
+            Request::Mkdir { .. } => "Mkdir",
+            Request::Cd { .. } => "Cd",
+            Request::Copy { .. } => "Copy",
+            Request::Upload { .. } => "Upload",
+            Request::Download { .. } => "Download",
+            Request::UploadChunk { .. } => "UploadChunk",
+            Request::DownloadChunk { .. } => "DownloadChunk",
+            Request::Watch { .. } => "Watch",
+            Request::Follow { .. } => "Follow",
+            Request::Manifest { .. } => "Manifest",
+            Request::Search { .. } => "Search",
+            Request::DownloadZip { .. } => "DownloadZip",
+            Request::DownloadMany { .. } => "DownloadMany",
+            Request::Df => "Df",
+            Request::Echo { .. } => "Echo",
+            Request::MkTemp { .. } => "MkTemp",
+            Request::SetTime { .. } => "SetTime",
+            Request::Idempotent { .. } => "Idempotent",
+            Request::Hash { .. } => "Hash",
+            Request::Link { .. } => "Link",
+            Request::Swap { .. } => "Swap",
+            Request::Delete { .. } => "Delete",
+            Request::Move { .. } => "Move",
+            Request::Find { .. } => "Find",
+            Request::UploadResume { .. } => "UploadResume",
+            Request::Auth { .. } => "Auth",
+            Request::Stat { .. } => "Stat",
+            Request::DiskUsage { .. } => "DiskUsage",
+            Request::Hello { .. } => "Hello",
+            Request::DownloadArchive { .. } => "DownloadArchive",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
 pub struct DirEntry {
     pub name: String,
     pub is_dir: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Encode, Decode)]
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
+pub struct BatchEntry {
+    pub name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
+pub struct DfEntry {
+    pub path: String,
+    pub free: u64,
+    pub total: u64,
+}
+
+/// Why a request failed. The variant lets a client react to the kind of failure without
+/// parsing the message; the message itself is meant for the user.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Encode, Decode)]
+pub enum ShellError {
+    NotFound(String),
+    PermissionDenied(String),
+    /// The destination exists and the request may not replace it
+    AlreadyExists(String),
+    /// The path resolves to somewhere outside the server root
+    PathEscapesRoot,
+    /// A size or count the server is not willing to handle
+    TooLarge(String),
+    /// The request itself is malformed or not valid in this state
+    InvalidRequest(String),
+    /// Transferred data did not match its announced checksum
+    ChecksumMismatch(String),
+    Io(String),
+    /// The connection has not logged in, or the credentials were wrong
+    Unauthorized,
+    /// The server is serving as many clients as it can; trying again later may succeed
+    Busy(String),
+}
+
+impl ShellError {
+    /// Classifies an I/O error by its kind, prefixing the message with `context`
+    /// (e.g. "mkdir failed").
+    pub fn io(context: &str, e: &io::Error) -> Self {
+        let msg = format!("{}: {}", context, e);
+        match e.kind() {
+            io::ErrorKind::NotFound => ShellError::NotFound(msg),
+            io::ErrorKind::PermissionDenied => ShellError::PermissionDenied(msg),
+            io::ErrorKind::AlreadyExists => ShellError::AlreadyExists(msg),
+            _ => ShellError::Io(msg),
+        }
+    }
+
+    pub fn invalid(msg: impl Into<String>) -> Self {
+        ShellError::InvalidRequest(msg.into())
+    }
+}
+
+impl fmt::Display for ShellError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShellError::PathEscapesRoot => f.write_str("Path is outside the server root"),
+            ShellError::Unauthorized => f.write_str("Not authenticated"),
+            ShellError::NotFound(msg)
+            | ShellError::PermissionDenied(msg)
+            | ShellError::AlreadyExists(msg)
+            | ShellError::TooLarge(msg)
+            | ShellError::InvalidRequest(msg)
+            | ShellError::ChecksumMismatch(msg)
+            | ShellError::Io(msg)
+            | ShellError::Busy(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for ShellError {}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Encode, Decode)]
 pub enum Response {
     Ok,
+    Capabilities {
+        proto_version: u32,
+        features: Vec<String>,
+    },
     DirList(Vec<DirEntry>),
     CopyResult {
         bytes_copied: u64,
     },
+    /// Precedes a downloaded file. `size` and `sha256` cover the whole file, while only the
+    /// bytes from the honored resume `offset` onwards follow. Without a `size` (TCP only) the
+    /// file is generated as it is sent and arrives in chunks up to an empty one (see
+    /// [`chunked`]).
     FileMetadata {
         name: String,
-        size: u64,
+        size: Option<u64>,
+        offset: u64,
+        sha256: Option<[u8; 32]>,
     },
-    Error(String),
+    Error(ShellError),
     /// UDP: Acknowledge chunk received
     ChunkAck {
         chunk_id: u32,
     },
-    /// UDP: Send file chunk
+    /// UDP: The upload chunk arrived with a CRC-32 that does not match its data, so it was
+    /// dropped; the client should send it again
+    ChunkNack {
+        chunk_id: u32,
+    },
+    /// UDP: Acknowledge the last upload chunk with the total bytes the server stored
+    UploadComplete {
+        total_bytes: u64,
+    },
+    /// UDP: Send file chunk, with the CRC-32 of `data` as sent
     FileChunk {
         chunk_id: u32,
         data: Vec<u8>,
+        crc32: u32,
         is_last: bool,
     },
+    /// Watch: a filesystem change, with `path` relative to the watched directory
+    FsEvent {
+        kind: String,
+        path: String,
+    },
+    /// Follow: a complete line appended to the followed file, without its trailing newline
+    Line {
+        text: String,
+    },
+    /// Manifest: one file, with `path` relative to the requested directory
+    ManifestEntry {
+        path: String,
+        size: u64,
+        sha256: String,
+    },
+    /// Manifest: terminates the entry stream
+    ManifestEnd {
+        files: u64,
+    },
+    /// Search: one matching file, with `path` relative to the searched directory and the
+    /// first matching line (1-based `line_number`) when a content filter was given
+    SearchHit {
+        path: String,
+        line_number: Option<u64>,
+        line: Option<String>,
+    },
+    /// Search: terminates the hit stream; `truncated` is set if the result limit was reached
+    SearchEnd {
+        hits: u64,
+        truncated: bool,
+    },
+    Df {
+        entries: Vec<DfEntry>,
+    },
+    Echo {
+        payload: Vec<u8>,
+    },
+    /// A path relative to the client's cwd
+    Path(String),
+    /// Lowercase hex digest of a file
+    Hash {
+        digest: String,
+    },
+    /// DownloadMany: the files about to be streamed, in order
+    BatchMetadata {
+        files: Vec<BatchEntry>,
+    },
+    /// Find: matching paths relative to the cwd, sorted and capped at `MAX_FIND_RESULTS`
+    FindResult(Vec<String>),
+    /// TCP: the server is ready for upload data from offset `received` onwards; quote `id`
+    /// in an `UploadResume` to continue the upload if the connection drops
+    UploadSession {
+        id: u64,
+        received: u64,
+    },
+    /// Stat: `modified_unix` is in seconds since the epoch
+    StatResult {
+        name: String,
+        size: u64,
+        is_dir: bool,
+        modified_unix: u64,
+        readonly: bool,
+    },
+    /// DiskUsage: entries that could not be read are left out of both totals
+    DiskUsageResult {
+        total_bytes: u64,
+        file_count: u64,
+    },
+    /// Hello: the chunk size both sides use from now on; transfers already under way keep
+    /// the size they started with
+    Hello {
+        chunk_size: u32,
+    },
 }
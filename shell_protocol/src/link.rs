@@ -0,0 +1,37 @@
+#[cfg(unix)]
+use crate::paths::{resolve_existing, resolve_in_parent};
+use crate::{Response, ShellError};
+#[cfg(unix)]
+use std::fs;
+use std::path::Path;
+
+/// Creates a hard link at `link` to the existing file `target`, both relative to `cwd`,
+/// refusing either path if it resolves outside `root`. The link must not exist yet.
+#[cfg(unix)]
+pub fn hard_link(root: &Path, cwd: &Path, target: &str, link: &str) -> Response {
+    let target = match resolve_existing(root, cwd, target) {
+        Ok(target) => target,
+        Err(e) => return Response::Error(e),
+    };
+    if target.is_dir() {
+        return Response::Error(ShellError::invalid(
+            "ln failed: cannot hard link a directory",
+        ));
+    }
+    let link = match resolve_in_parent(root, cwd, link) {
+        Ok(link) => link,
+        Err(e) => return Response::Error(e),
+    };
+
+    match fs::hard_link(&target, &link) {
+        Ok(()) => Response::Ok,
+        Err(e) => Response::Error(ShellError::io("ln failed", &e)),
+    }
+}
+
+/// `fs::hard_link` also works on Windows (NTFS), but the server is only run and
+/// checked on Unix, so other platforms report the request as unsupported.
+#[cfg(not(unix))]
+pub fn hard_link(_root: &Path, _cwd: &Path, _target: &str, _link: &str) -> Response {
+    Response::Error(ShellError::invalid("ln is only supported on Unix servers"))
+}
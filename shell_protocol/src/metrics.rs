@@ -0,0 +1,122 @@
+use std::fmt::Write as _;
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::{self, JoinHandle};
+
+/// Counters shared between a server's request loop and its HTTP metrics endpoint.
+#[derive(Debug, Default)]
+pub struct ServerMetrics {
+    pub requests_total: AtomicU64,
+    pub errors_total: AtomicU64,
+    pub bytes_uploaded: AtomicU64,
+    pub bytes_downloaded: AtomicU64,
+    pub active_sessions: AtomicU64,
+    pub largest_request_bytes: AtomicU64,
+}
+
+impl ServerMetrics {
+    pub fn record_request(&self, is_error: bool) {
+        self.requests_total.fetch_add(1, Ordering::Relaxed);
+        if is_error {
+            self.errors_total.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn add_uploaded(&self, bytes: u64) {
+        self.bytes_uploaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn add_downloaded(&self, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Records the encoded size of an accepted request, keeping the largest seen.
+    pub fn record_request_size(&self, bytes: u64) {
+        self.largest_request_bytes
+            .fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    pub fn set_active_sessions(&self, sessions: u64) {
+        self.active_sessions.store(sessions, Ordering::Relaxed);
+    }
+
+    /// For servers that track sessions one connection at a time rather than as a count.
+    pub fn session_opened(&self) {
+        self.active_sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn session_closed(&self) {
+        self.active_sessions.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Renders the counters in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let metrics = [
+            (
+                "shell_requests_total",
+                "counter",
+                "Requests handled",
+                &self.requests_total,
+            ),
+            (
+                "shell_errors_total",
+                "counter",
+                "Requests answered with an error",
+                &self.errors_total,
+            ),
+            (
+                "shell_bytes_uploaded_total",
+                "counter",
+                "File bytes received from clients",
+                &self.bytes_uploaded,
+            ),
+            (
+                "shell_bytes_downloaded_total",
+                "counter",
+                "File bytes sent to clients",
+                &self.bytes_downloaded,
+            ),
+            (
+                "shell_active_sessions",
+                "gauge",
+                "Currently active client sessions",
+                &self.active_sessions,
+            ),
+            (
+                "shell_largest_request_bytes",
+                "gauge",
+                "Largest encoded request accepted",
+                &self.largest_request_bytes,
+            ),
+        ];
+
+        let mut out = String::new();
+        for (name, kind, help, value) in metrics {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+        out
+    }
+}
+
+/// Serves `/healthz` and `/metrics` over plain HTTP from a background thread.
+pub fn spawn_http_endpoint(addr: &str, metrics: Arc<ServerMetrics>) -> io::Result<JoinHandle<()>> {
+    let server = tiny_http::Server::http(addr).map_err(io::Error::other)?;
+
+    Ok(thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let response = match request.url() {
+                "/healthz" => tiny_http::Response::from_string("ok\n"),
+                "/metrics" => tiny_http::Response::from_string(metrics.render_prometheus())
+                    .with_header(
+                        tiny_http::Header::from_bytes("Content-Type", "text/plain; version=0.0.4")
+                            .unwrap(),
+                    ),
+                _ => tiny_http::Response::from_string("not found\n").with_status_code(404),
+            };
+            let _ = request.respond(response);
+        }
+    }))
+}
@@ -0,0 +1,42 @@
+use crate::{Response, ShellError};
+use std::fs::OpenOptions;
+use std::hash::{BuildHasher, RandomState};
+use std::io;
+use std::path::{Component, Path};
+
+const MKTEMP_ATTEMPTS: u32 = 16;
+
+/// Creates a uniquely named empty file `<prefix><random><suffix>` under `cwd` and replies
+/// with its name relative to `cwd`. The name may reach into subdirectories of `cwd`, but
+/// never above it.
+pub fn mktemp(cwd: &Path, prefix: &str, suffix: &str) -> Response {
+    for _ in 0..MKTEMP_ATTEMPTS {
+        let name = format!("{}{:016x}{}", prefix, random_u64(), suffix);
+        if !Path::new(&name)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Response::Error(ShellError::invalid("Invalid prefix or suffix"));
+        }
+
+        // `create_new` fails instead of reusing a file that already has this name
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(cwd.join(&name))
+        {
+            Ok(_) => return Response::Path(name),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Response::Error(ShellError::io("mktemp failed", &e)),
+        }
+    }
+    // every name tried was taken
+    Response::Error(ShellError::AlreadyExists(
+        "mktemp failed: no unused name found".into(),
+    ))
+}
+
+// every `RandomState` is seeded with fresh random keys, so hashing nothing yields a random value
+pub(crate) fn random_u64() -> u64 {
+    RandomState::new().hash_one(())
+}
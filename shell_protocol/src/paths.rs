@@ -0,0 +1,72 @@
+use crate::ShellError;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Resolves `path`, relative to `cwd`, to the canonical path of an existing file or
+/// directory, refusing anything outside `root` (which must itself be canonical). Symlinks
+/// and `..` segments are resolved before the check, so neither can step out of the root.
+pub fn resolve_existing(root: &Path, cwd: &Path, path: &str) -> Result<PathBuf, ShellError> {
+    match fs::canonicalize(cwd.join(path)) {
+        Ok(full) if full.starts_with(root) => Ok(full),
+        Ok(_) => Err(ShellError::PathEscapesRoot),
+        Err(e) => Err(ShellError::io(path, &e)),
+    }
+}
+
+/// Resolves a path that may not exist yet, such as an upload destination. The deepest part
+/// of it that does exist is canonicalized and must lie inside `root`; the rest may only name
+/// plain directories and files below that, never `..`.
+pub fn resolve_new(root: &Path, cwd: &Path, path: &Path) -> Result<PathBuf, ShellError> {
+    let full = cwd.join(path);
+    let mut existing = full.as_path();
+    let mut missing = Vec::new();
+    // symlink_metadata, so a dangling symlink counts as existing and canonicalize refuses it
+    while fs::symlink_metadata(existing).is_err() {
+        let (Some(parent), Some(name)) = (existing.parent(), existing.file_name()) else {
+            return Err(ShellError::invalid("Invalid path"));
+        };
+        missing.push(name);
+        existing = parent;
+    }
+
+    let base = match fs::canonicalize(existing) {
+        Ok(base) if base.starts_with(root) => base,
+        Ok(_) => return Err(ShellError::PathEscapesRoot),
+        Err(e) => return Err(ShellError::io(&path.display().to_string(), &e)),
+    };
+    Ok(missing.iter().rev().fold(base, |p, name| p.join(name)))
+}
+
+/// Resolves `path`, relative to `cwd`, through its parent directory alone: the parent is
+/// canonicalized and must lie inside `root`, while the last component is kept as named. A
+/// symlink there is therefore acted on itself rather than the file it points to. The path
+/// itself need not exist.
+pub fn resolve_in_parent(root: &Path, cwd: &Path, path: &str) -> Result<PathBuf, ShellError> {
+    let full = cwd.join(path);
+    let (Some(parent), Some(name)) = (full.parent(), full.file_name()) else {
+        return Err(ShellError::invalid("Invalid path"));
+    };
+    match fs::canonicalize(parent) {
+        Ok(parent) if parent.starts_with(root) => Ok(parent.join(name)),
+        Ok(_) => Err(ShellError::PathEscapesRoot),
+        Err(e) => Err(ShellError::io(path, &e)),
+    }
+}
+
+/// [`resolve_existing`] for requests that need a directory.
+pub fn resolve_dir(root: &Path, cwd: &Path, path: &str) -> Result<PathBuf, ShellError> {
+    let full = resolve_existing(root, cwd, path)?;
+    if !full.is_dir() {
+        return Err(ShellError::invalid(format!("{}: not a directory", path)));
+    }
+    Ok(full)
+}
+
+/// [`resolve_existing`] for requests that need a regular file.
+pub fn resolve_file(root: &Path, cwd: &Path, path: &str) -> Result<PathBuf, ShellError> {
+    let full = resolve_existing(root, cwd, path)?;
+    if !full.is_file() {
+        return Err(ShellError::invalid(format!("{}: not a file", path)));
+    }
+    Ok(full)
+}
@@ -0,0 +1,189 @@
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Token bucket for throttling: holds up to `capacity` tokens, refilled continuously at
+/// `refill_per_sec`. Callers decide what a token is (a byte, a request, a datagram).
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    refill_per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket, so an initial burst of up to `capacity` tokens is allowed.
+    ///
+    /// Panics if `capacity` or `refill_per_sec` is zero.
+    pub fn new(capacity: u64, refill_per_sec: u64) -> Self {
+        assert!(capacity > 0, "token bucket capacity must be positive");
+        assert!(
+            refill_per_sec > 0,
+            "token bucket refill rate must be positive"
+        );
+        Self {
+            capacity: capacity as f64,
+            refill_per_sec: refill_per_sec as f64,
+            tokens: capacity as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes `n` tokens if they are available right now; otherwise takes nothing.
+    pub fn try_take(&mut self, n: u64) -> bool {
+        self.try_take_at(n, Instant::now())
+    }
+
+    /// Takes `n` tokens, sleeping until they have been refilled. Requests larger than the
+    /// capacity are taken in capacity-sized pieces, so they are paced rather than rejected.
+    pub fn take_blocking(&mut self, n: u64) {
+        self.take_blocking_with(n, Instant::now, thread::sleep);
+    }
+
+    /// Tokens currently available, rounded down.
+    pub fn available(&mut self) -> u64 {
+        self.refill(Instant::now());
+        self.tokens as u64
+    }
+
+    fn try_take_at(&mut self, n: u64, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= n as f64 {
+            self.tokens -= n as f64;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// `take_blocking` on the given clock and sleep, so tests can run it in simulated time.
+    fn take_blocking_with(
+        &mut self,
+        n: u64,
+        mut now: impl FnMut() -> Instant,
+        mut sleep: impl FnMut(Duration),
+    ) {
+        let mut remaining = n as f64;
+        while remaining > 0.0 {
+            self.refill(now());
+            let piece = remaining.min(self.capacity);
+            if self.tokens >= piece {
+                self.tokens -= piece;
+                remaining -= piece;
+            } else {
+                let missing = piece - self.tokens;
+                sleep(Duration::from_secs_f64(missing / self.refill_per_sec));
+            }
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+/// A bytes-per-second cap shared by every transfer that consults it, so concurrent downloads
+/// together stay under it. Bursts are limited to a tenth of a second's worth of bytes.
+#[derive(Debug)]
+pub struct Throttle {
+    bucket: Mutex<TokenBucket>,
+}
+
+impl Throttle {
+    /// Panics if `bytes_per_sec` is zero.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bucket: Mutex::new(TokenBucket::new((bytes_per_sec / 10).max(1), bytes_per_sec)),
+        }
+    }
+
+    /// Sleeps until `bytes` may be sent. Callers queue behind each other on the shared bucket.
+    pub fn wait_for(&self, bytes: u64) {
+        self.bucket
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take_blocking(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    /// A bucket created at `start`, emptied so refills can be measured from zero.
+    fn empty_bucket(capacity: u64, refill_per_sec: u64, start: Instant) -> TokenBucket {
+        let mut bucket = TokenBucket::new(capacity, refill_per_sec);
+        bucket.last_refill = start;
+        assert!(bucket.try_take_at(capacity, start));
+        bucket
+    }
+
+    #[test]
+    fn refills_at_the_configured_rate() {
+        let start = Instant::now();
+        let mut bucket = empty_bucket(1000, 100, start);
+
+        assert!(!bucket.try_take_at(1, start));
+        assert!(bucket.try_take_at(50, start + Duration::from_millis(500)));
+        assert!(!bucket.try_take_at(1, start + Duration::from_millis(500)));
+        assert!(bucket.try_take_at(100, start + Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn refill_stops_at_the_burst_cap() {
+        let start = Instant::now();
+        let mut bucket = empty_bucket(10, 100, start);
+        let later = start + Duration::from_secs(60);
+
+        assert!(!bucket.try_take_at(11, later));
+        assert!(bucket.try_take_at(10, later));
+        assert!(!bucket.try_take_at(1, later));
+    }
+
+    #[test]
+    fn try_take_on_an_empty_bucket_takes_nothing() {
+        let start = Instant::now();
+        let mut bucket = empty_bucket(10, 10, start);
+
+        assert!(!bucket.try_take_at(5, start + Duration::from_millis(400)));
+        // the failed take left the 4 refilled tokens in place
+        assert!(bucket.try_take_at(4, start + Duration::from_millis(400)));
+    }
+
+    #[test]
+    fn take_blocking_sleeps_for_the_missing_tokens() {
+        let start = Instant::now();
+        let mut bucket = empty_bucket(100, 50, start);
+        let clock = Cell::new(start);
+        let slept = Cell::new(Duration::ZERO);
+
+        // 250 tokens at 50 a second, in pieces of at most the 100-token capacity
+        bucket.take_blocking_with(
+            250,
+            || clock.get(),
+            |d| {
+                slept.set(slept.get() + d);
+                clock.set(clock.get() + d);
+            },
+        );
+
+        let slept = slept.get().as_secs_f64();
+        assert!((slept - 5.0).abs() < 1e-6, "slept {}s", slept);
+        assert_eq!(bucket.tokens as u64, 0);
+    }
+
+    #[test]
+    fn throttle_paces_beyond_its_burst() {
+        // a 1000 byte burst, then 10 bytes a millisecond
+        let throttle = Throttle::new(10_000);
+        let start = Instant::now();
+        throttle.wait_for(2000);
+        assert!(start.elapsed() >= Duration::from_millis(95));
+    }
+}
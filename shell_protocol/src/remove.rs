@@ -0,0 +1,19 @@
+use crate::paths::resolve_in_parent;
+use crate::{Response, ShellError};
+use std::fs;
+use std::path::Path;
+
+/// Removes the file at `path` (relative to `cwd`), refusing paths that resolve outside
+/// `root`. Only the parent directory is resolved, so a symlink is removed itself rather than
+/// the file it points to. Directories are refused by `fs::remove_file`.
+pub fn remove_file(root: &Path, cwd: &Path, path: &str) -> Response {
+    let target = match resolve_in_parent(root, cwd, path) {
+        Ok(target) => target,
+        Err(e) => return Response::Error(e),
+    };
+
+    match fs::remove_file(&target) {
+        Ok(()) => Response::Ok,
+        Err(e) => Response::Error(ShellError::io("delete failed", &e)),
+    }
+}
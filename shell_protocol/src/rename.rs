@@ -0,0 +1,59 @@
+use crate::paths::{resolve_existing, resolve_in_parent};
+use crate::{Response, ShellError};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Moves or renames `src` to `dst`, both relative to `cwd`, refusing either path if it
+/// resolves outside `root`. If `dst` is an existing directory the file keeps its name inside
+/// it. An existing file at `dst` is replaced, as with `fs::rename`. Across mount points the
+/// file is copied and the original removed, which is not atomic.
+pub fn move_path(root: &Path, cwd: &Path, src: &str, dst: &str) -> Response {
+    // through the parent, so a symlink is moved itself rather than the file it points to
+    let src = match resolve_in_parent(root, cwd, src) {
+        Ok(src) => src,
+        Err(e) => return Response::Error(e),
+    };
+    if let Err(e) = fs::symlink_metadata(&src) {
+        return Response::Error(ShellError::io("move failed", &e));
+    }
+    let dst = match resolve_in_parent(root, cwd, dst) {
+        Ok(resolved) if resolved.is_dir() => {
+            // the directory may itself be a symlink, so it is resolved in full
+            match resolve_existing(root, cwd, dst) {
+                Ok(dir) => dir.join(src.file_name().unwrap_or_default()),
+                Err(e) => return Response::Error(e),
+            }
+        }
+        Ok(resolved) => resolved,
+        Err(e) => return Response::Error(e),
+    };
+    if src == dst {
+        return Response::Error(ShellError::invalid(
+            "move failed: source and destination are the same",
+        ));
+    }
+
+    match rename_or_copy(&src, &dst) {
+        Ok(()) => Response::Ok,
+        Err(e) => Response::Error(ShellError::io("move failed", &e)),
+    }
+}
+
+fn rename_or_copy(src: &Path, dst: &Path) -> io::Result<()> {
+    match fs::rename(src, dst) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            if fs::symlink_metadata(src)?.is_dir() {
+                return Err(e);
+            }
+            fs::copy(src, dst)?;
+            if let Err(e) = fs::remove_file(src) {
+                // leave only the original behind rather than two copies
+                let _ = fs::remove_file(dst);
+                return Err(e);
+            }
+            Ok(())
+        }
+        result => result,
+    }
+}
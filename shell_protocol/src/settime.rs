@@ -0,0 +1,19 @@
+use crate::paths::resolve_existing;
+use crate::{Response, ShellError};
+use filetime::FileTime;
+use std::path::Path;
+
+/// Sets the modification time of `path` (relative to `cwd`) to `mtime_secs` since the Unix
+/// epoch, refusing files that resolve outside `root`. The access time is left untouched.
+pub fn set_mtime(root: &Path, cwd: &Path, path: &str, mtime_secs: u64) -> Response {
+    let target = match resolve_existing(root, cwd, path) {
+        Ok(target) => target,
+        Err(e) => return Response::Error(e),
+    };
+
+    let mtime = FileTime::from_unix_time(mtime_secs as i64, 0);
+    match filetime::set_file_mtime(&target, mtime) {
+        Ok(()) => Response::Ok,
+        Err(e) => Response::Error(ShellError::io("settime failed", &e)),
+    }
+}
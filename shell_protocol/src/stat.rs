@@ -0,0 +1,37 @@
+use crate::paths::resolve_existing;
+use crate::{Response, ShellError};
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// Describes the file or directory at `path` (relative to `cwd`) without reading it,
+/// refusing paths that resolve outside `root`, which must be canonical. Symlinks are
+/// followed, so the result describes their target.
+pub fn stat_path(root: &Path, cwd: &Path, path: &str) -> Response {
+    let full = match resolve_existing(root, cwd, path) {
+        Ok(full) => full,
+        Err(e) => return Response::Error(e),
+    };
+    let meta = match fs::metadata(&full) {
+        Ok(meta) => meta,
+        Err(e) => return Response::Error(ShellError::io("stat failed", &e)),
+    };
+
+    // the root itself has no file name of its own to report
+    let name = full
+        .file_name()
+        .map_or_else(|| "/".to_string(), |n| n.to_string_lossy().to_string());
+    // a time before the epoch, or a platform without mtimes, reads as 0
+    let modified_unix = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs());
+    Response::StatResult {
+        name,
+        size: meta.len(),
+        is_dir: meta.is_dir(),
+        modified_unix,
+        readonly: meta.permissions().readonly(),
+    }
+}
@@ -0,0 +1,317 @@
+use crate::paths::{resolve_dir, resolve_existing, resolve_file, resolve_new};
+use crate::{DirEntry, ShellError};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A file opened for reading. Seekable, so a download can be resumed at an offset and its
+/// size found without a separate lookup.
+pub trait ReadSeek: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> ReadSeek for T {}
+
+/// Where a session's files live. Each backend tracks the session's working directory, which
+/// relative paths start from, and refuses any path that leads outside its root.
+pub trait StorageBackend: Send {
+    fn read_dir(&self) -> Result<Vec<DirEntry>, ShellError>;
+
+    /// Moves the working directory; `..` steps up, but never above the root.
+    fn change_dir(&mut self, path: &str) -> Result<(), ShellError>;
+
+    fn make_dir(&mut self, name: &str) -> Result<(), ShellError>;
+
+    /// Copies the file `src` to `dst`, returning the number of bytes copied.
+    fn copy(&mut self, src: &str, dst: &str) -> Result<u64, ShellError>;
+
+    fn open_read(&self, path: &str) -> Result<Box<dyn ReadSeek>, ShellError>;
+
+    /// Creates `path`, or truncates it if it is already a file.
+    fn create_write(&mut self, path: &str) -> Result<Box<dyn Write + Send>, ShellError>;
+
+    /// Removes the file `path`; a failed upload uses this so it is not left looking complete.
+    fn remove_file(&mut self, path: &str) -> Result<(), ShellError>;
+
+    /// The canonical root and working directory, for backends that are a local directory
+    /// tree. Requests beyond the ones above (links, hashing, archives, watches, and uploads
+    /// that append, stage or resume) work on these paths and are refused by backends that
+    /// return `None`.
+    fn local_paths(&self) -> Option<(&Path, &Path)> {
+        None
+    }
+}
+
+/// The root and working directory of a backend that is a local directory tree, or the error
+/// that refuses a request needing one.
+pub fn require_local_paths(storage: &dyn StorageBackend) -> Result<(&Path, &Path), ShellError> {
+    storage
+        .local_paths()
+        .ok_or_else(|| ShellError::invalid("Not supported by this storage backend"))
+}
+
+/// The server's own directory tree on disk.
+#[derive(Debug, Clone)]
+pub struct LocalFs {
+    root: PathBuf,
+    cwd: PathBuf,
+}
+
+impl LocalFs {
+    /// Starts at `root`, which must already be canonical.
+    pub fn new(root: PathBuf) -> Self {
+        Self {
+            cwd: root.clone(),
+            root,
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn cwd(&self) -> &Path {
+        &self.cwd
+    }
+}
+
+impl StorageBackend for LocalFs {
+    fn read_dir(&self) -> Result<Vec<DirEntry>, ShellError> {
+        let entries = fs::read_dir(&self.cwd).map_err(|e| ShellError::io("read_dir failed", &e))?;
+        let mut list = Vec::new();
+        for e in entries.flatten() {
+            let name = e.file_name().to_string_lossy().to_string();
+            let is_dir = e.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            list.push(DirEntry { name, is_dir });
+        }
+        Ok(list)
+    }
+
+    fn change_dir(&mut self, path: &str) -> Result<(), ShellError> {
+        self.cwd = resolve_dir(&self.root, &self.cwd, path)?;
+        Ok(())
+    }
+
+    fn make_dir(&mut self, name: &str) -> Result<(), ShellError> {
+        let new = resolve_new(&self.root, &self.cwd, Path::new(name))?;
+        fs::create_dir(&new).map_err(|e| ShellError::io("mkdir failed", &e))
+    }
+
+    fn copy(&mut self, src: &str, dst: &str) -> Result<u64, ShellError> {
+        let src = resolve_existing(&self.root, &self.cwd, src)?;
+        let dst = resolve_new(&self.root, &self.cwd, Path::new(dst))?;
+        fs::copy(&src, &dst).map_err(|e| ShellError::io("copy failed", &e))
+    }
+
+    fn open_read(&self, path: &str) -> Result<Box<dyn ReadSeek>, ShellError> {
+        let full = resolve_file(&self.root, &self.cwd, path)?;
+        let f = File::open(&full).map_err(|e| ShellError::io(path, &e))?;
+        Ok(Box::new(f))
+    }
+
+    fn create_write(&mut self, path: &str) -> Result<Box<dyn Write + Send>, ShellError> {
+        let full = resolve_new(&self.root, &self.cwd, Path::new(path))?;
+        let f = File::create(&full).map_err(|e| ShellError::io(path, &e))?;
+        Ok(Box::new(f))
+    }
+
+    fn remove_file(&mut self, path: &str) -> Result<(), ShellError> {
+        let full = resolve_file(&self.root, &self.cwd, path)?;
+        fs::remove_file(&full).map_err(|e| ShellError::io(path, &e))
+    }
+
+    fn local_paths(&self) -> Option<(&Path, &Path)> {
+        Some((&self.root, &self.cwd))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Dir,
+    File(Vec<u8>),
+}
+
+/// A directory tree held in memory. Clones share the tree but each keeps its own working
+/// directory, like sessions of one server.
+#[derive(Debug, Clone, Default)]
+pub struct InMemoryFs {
+    /// Keyed by `/`-separated path from the root, which itself has no entry
+    nodes: Arc<Mutex<BTreeMap<String, Node>>>,
+    cwd: String,
+}
+
+impl InMemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The working directory, as a path from the root without a leading `/`.
+    pub fn cwd(&self) -> &str {
+        &self.cwd
+    }
+
+    /// The key `path` names: relative to the working directory, or to the root when it
+    /// starts with `/`.
+    fn resolve(&self, path: &str) -> Result<String, ShellError> {
+        let mut parts: Vec<&str> = if path.starts_with('/') {
+            Vec::new()
+        } else {
+            self.cwd.split('/').filter(|p| !p.is_empty()).collect()
+        };
+        for part in path.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => {
+                    parts.pop().ok_or(ShellError::PathEscapesRoot)?;
+                }
+                name => parts.push(name),
+            }
+        }
+        Ok(parts.join("/"))
+    }
+
+    fn is_dir(nodes: &BTreeMap<String, Node>, key: &str) -> bool {
+        key.is_empty() || matches!(nodes.get(key), Some(Node::Dir))
+    }
+
+    /// Checks that `key` can be created: its parent is a directory and it is not one itself.
+    fn check_new(nodes: &BTreeMap<String, Node>, key: &str, path: &str) -> Result<(), ShellError> {
+        let parent = key.rsplit_once('/').map_or("", |(parent, _)| parent);
+        if key.is_empty() || Self::is_dir(nodes, key) {
+            return Err(ShellError::invalid(format!("{}: is a directory", path)));
+        }
+        if !Self::is_dir(nodes, parent) {
+            return Err(ShellError::NotFound(format!("{}: no such directory", path)));
+        }
+        Ok(())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Node>> {
+        // a panic mid-update leaves the map itself intact, so keep using it
+        self.nodes.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl StorageBackend for InMemoryFs {
+    fn read_dir(&self) -> Result<Vec<DirEntry>, ShellError> {
+        let prefix = if self.cwd.is_empty() {
+            String::new()
+        } else {
+            format!("{}/", self.cwd)
+        };
+        let nodes = self.lock();
+        let list = nodes
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .filter(|(key, _)| !key[prefix.len()..].contains('/'))
+            .map(|(key, node)| DirEntry {
+                name: key[prefix.len()..].to_string(),
+                is_dir: matches!(node, Node::Dir),
+            })
+            .collect();
+        Ok(list)
+    }
+
+    fn change_dir(&mut self, path: &str) -> Result<(), ShellError> {
+        let key = self.resolve(path)?;
+        let nodes = self.lock();
+        match nodes.get(&key) {
+            _ if key.is_empty() => {}
+            Some(Node::Dir) => {}
+            Some(Node::File(_)) => {
+                return Err(ShellError::invalid(format!("{}: not a directory", path)));
+            }
+            None => return Err(ShellError::NotFound(path.to_string())),
+        }
+        drop(nodes);
+        self.cwd = key;
+        Ok(())
+    }
+
+    fn make_dir(&mut self, name: &str) -> Result<(), ShellError> {
+        let key = self.resolve(name)?;
+        let mut nodes = self.lock();
+        if key.is_empty() || nodes.contains_key(&key) {
+            return Err(ShellError::AlreadyExists(format!(
+                "mkdir failed: {} already exists",
+                name
+            )));
+        }
+        Self::check_new(&nodes, &key, name)?;
+        nodes.insert(key, Node::Dir);
+        Ok(())
+    }
+
+    fn copy(&mut self, src: &str, dst: &str) -> Result<u64, ShellError> {
+        let (src_key, dst_key) = (self.resolve(src)?, self.resolve(dst)?);
+        let mut nodes = self.lock();
+        let data = match nodes.get(&src_key) {
+            Some(Node::File(data)) => data.clone(),
+            Some(Node::Dir) => return Err(ShellError::invalid(format!("{}: not a file", src))),
+            None => return Err(ShellError::NotFound(src.to_string())),
+        };
+        Self::check_new(&nodes, &dst_key, dst)?;
+        let len = data.len() as u64;
+        nodes.insert(dst_key, Node::File(data));
+        Ok(len)
+    }
+
+    fn open_read(&self, path: &str) -> Result<Box<dyn ReadSeek>, ShellError> {
+        let key = self.resolve(path)?;
+        match self.lock().get(&key) {
+            Some(Node::File(data)) => Ok(Box::new(Cursor::new(data.clone()))),
+            Some(Node::Dir) => Err(ShellError::invalid(format!("{}: not a file", path))),
+            None => Err(ShellError::NotFound(path.to_string())),
+        }
+    }
+
+    fn create_write(&mut self, path: &str) -> Result<Box<dyn Write + Send>, ShellError> {
+        let key = self.resolve(path)?;
+        let mut nodes = self.lock();
+        Self::check_new(&nodes, &key, path)?;
+        nodes.insert(key.clone(), Node::File(Vec::new()));
+        Ok(Box::new(MemWriter {
+            nodes: Arc::clone(&self.nodes),
+            key,
+        }))
+    }
+
+    fn remove_file(&mut self, path: &str) -> Result<(), ShellError> {
+        let key = self.resolve(path)?;
+        let mut nodes = self.lock();
+        match nodes.get(&key) {
+            Some(Node::File(_)) => {
+                nodes.remove(&key);
+                Ok(())
+            }
+            Some(Node::Dir) => Err(ShellError::invalid(format!("{}: not a file", path))),
+            None => Err(ShellError::NotFound(path.to_string())),
+        }
+    }
+}
+
+/// Appends to a file of an [`InMemoryFs`]; the data is visible to readers as it is written.
+struct MemWriter {
+    nodes: Arc<Mutex<BTreeMap<String, Node>>>,
+    key: String,
+}
+
+impl Write for MemWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut nodes = self.nodes.lock().unwrap_or_else(|e| e.into_inner());
+        match nodes.get_mut(&self.key) {
+            Some(Node::File(data)) => {
+                data.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} was removed while being written", self.key),
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
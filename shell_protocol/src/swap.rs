@@ -0,0 +1,90 @@
+use crate::paths::resolve_in_parent;
+use crate::{Response, ShellError};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Exchanges the files (or directories) at `a` and `b`, both relative to `cwd`, refusing
+/// either path if it resolves outside `root`. On Linux this is a single atomic
+/// `renameat2(RENAME_EXCHANGE)`; elsewhere, or on filesystems that do not support it, it
+/// falls back to three renames through a temporary name, which another client can observe
+/// half-done.
+pub fn swap_paths(root: &Path, cwd: &Path, a: &str, b: &str) -> Response {
+    let a = match resolve_present(root, cwd, a) {
+        Ok(a) => a,
+        Err(e) => return Response::Error(e),
+    };
+    let b = match resolve_present(root, cwd, b) {
+        Ok(b) => b,
+        Err(e) => return Response::Error(e),
+    };
+    if a == b {
+        return Response::Error(ShellError::invalid(
+            "swap failed: both paths name the same file",
+        ));
+    }
+
+    match exchange(&a, &b) {
+        Ok(()) => Response::Ok,
+        Err(e) => Response::Error(ShellError::io("swap failed", &e)),
+    }
+}
+
+/// [`resolve_in_parent`] for a path that must exist, so a symlink is swapped itself rather
+/// than the file it points to.
+fn resolve_present(root: &Path, cwd: &Path, path: &str) -> Result<PathBuf, ShellError> {
+    let resolved = resolve_in_parent(root, cwd, path)?;
+    match fs::symlink_metadata(&resolved) {
+        Ok(_) => Ok(resolved),
+        Err(e) => Err(ShellError::io(&format!("swap failed: {}", path), &e)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_a = CString::new(a.as_os_str().as_bytes())?;
+    let c_b = CString::new(b.as_os_str().as_bytes())?;
+    // SAFETY: both pointers come from CStrings that outlive the call
+    let ret = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            c_a.as_ptr(),
+            libc::AT_FDCWD,
+            c_b.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if ret == 0 {
+        return Ok(());
+    }
+
+    let e = io::Error::last_os_error();
+    // EINVAL: the filesystem does not support the flag; ENOSYS: the kernel predates it
+    match e.raw_os_error() {
+        Some(libc::EINVAL) | Some(libc::ENOSYS) => exchange_by_renames(a, b),
+        _ => Err(e),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn exchange(a: &Path, b: &Path) -> io::Result<()> {
+    exchange_by_renames(a, b)
+}
+
+/// Non-atomic fallback: `a` is parked under a temporary name next to it while `b` takes
+/// its place. A failed second step moves `a` back, so at worst nothing changes.
+fn exchange_by_renames(a: &Path, b: &Path) -> io::Result<()> {
+    let mut parked = a.as_os_str().to_os_string();
+    parked.push(format!(".swap-{}", std::process::id()));
+    let parked = PathBuf::from(parked);
+
+    fs::rename(a, &parked)?;
+    if let Err(e) = fs::rename(b, a) {
+        let _ = fs::rename(&parked, a);
+        return Err(e);
+    }
+    fs::rename(&parked, b)
+}
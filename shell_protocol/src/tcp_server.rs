@@ -0,0 +1,2012 @@
+use crate::access_log::AccessLogger;
+use crate::auth::Credentials;
+use crate::capacity::{df_report, prepare_upload_dir};
+use crate::chunked::{ChunkedReader, ChunkedWriter};
+use crate::compress::Inflater;
+use crate::config::ServerConfig;
+use crate::du::disk_usage;
+use crate::find::find_paths;
+use crate::glob::glob_match;
+use crate::hash::{HashAlgo, hash_file, sha256_file, sha256_reader, to_hex};
+use crate::link::hard_link;
+use crate::metrics::{ServerMetrics, spawn_http_endpoint};
+use crate::mktemp::mktemp;
+use crate::paths::{resolve_dir, resolve_existing, resolve_file, resolve_new};
+use crate::rate_limit::Throttle;
+use crate::remove::remove_file;
+use crate::rename::move_path;
+use crate::settime::set_mtime;
+use crate::stat::stat_path;
+use crate::storage::{LocalFs, StorageBackend, require_local_paths};
+use crate::swap::swap_paths;
+use crate::tls::{Transport, server_config};
+use crate::upload_session::{UploadSession, UploadSessions};
+use crate::{
+    BatchEntry, MAX_MESSAGE_SIZE, PROTOCOL_VERSION, Request, Response, ShellError,
+    create_upload_file, discard_upload, validate_root, wire_config,
+};
+use bincode::error::DecodeError;
+use bincode::serde::{decode_from_std_read, encode_into_std_write};
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use notify::{EventKind, RecursiveMode, Watcher};
+use rustls::ServerConfig as TlsConfig;
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::ops::Deref;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How often idle connections and the accept loop look for a Ctrl-C
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(200);
+const MAX_SEARCH_HITS: u64 = 1000;
+const DEFAULT_MAX_CLIENTS: usize = 8;
+/// How long an interrupted upload can wait for its `UploadResume`.
+const UPLOAD_SESSION_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+static NEXT_ARCHIVE_ID: AtomicU64 = AtomicU64::new(0);
+/// Optional features reported in reply to `Request::Capabilities`.
+const FEATURES: &[&str] = &[
+    "watch",
+    "follow",
+    "manifest",
+    "search",
+    "find",
+    "du",
+    "zip",
+    "tar",
+    "getmany",
+    "compress",
+    "hash",
+    "settime",
+    "stat",
+    "link",
+    "swap",
+    "delete",
+    "move",
+    "df",
+    "mktemp",
+    "echo",
+    "resume-upload",
+];
+
+/// Reply to `Request::Capabilities`; `auth` is added when the server requires a login.
+fn capabilities(auth: bool) -> Response {
+    let auth = auth.then_some("auth");
+    Response::Capabilities {
+        proto_version: PROTOCOL_VERSION,
+        features: FEATURES
+            .iter()
+            .chain(&auth)
+            .map(|f| f.to_string())
+            .collect(),
+    }
+}
+
+/// Sends the response to a request, counting it (and whether it failed) in the metrics.
+fn send_response(
+    stream: &mut impl Write,
+    metrics: &ConnMetrics,
+    resp: &Response,
+) -> std::io::Result<()> {
+    metrics.record_response(resp);
+    write_frame(stream, resp)
+}
+
+/// Writes a single encoded response frame; streaming replies use this for follow-up frames.
+fn write_frame(stream: &mut impl Write, resp: &Response) -> std::io::Result<()> {
+    encode_into_std_write(resp, stream, wire_config())
+        .map_err(|e| std::io::Error::other(format!("encode error: {e}")))?;
+    Ok(())
+}
+
+/// Re-issues a blocking call that was interrupted by a signal before transferring anything,
+/// instead of treating `ErrorKind::Interrupted` as a dropped client. `write_all` and
+/// `read_exact` already do this internally; bare `read`s need it.
+fn retry_interrupted<T>(mut op: impl FnMut() -> std::io::Result<T>) -> std::io::Result<T> {
+    loop {
+        match op() {
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Counts the bytes a request is decoded from, failing the read once they pass `max`.
+struct SizedReader<'a, R> {
+    inner: &'a mut R,
+    read: u64,
+    max: u64,
+}
+
+impl<R: Read> Read for SizedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.read > self.max {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "request too large",
+            ));
+        }
+        // never read past the byte that proves the limit was exceeded
+        let allowed = (self.max + 1 - self.read).min(buf.len() as u64) as usize;
+        let n = retry_interrupted(|| self.inner.read(&mut buf[..allowed]))?;
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+/// Reads one request and its encoded size; a request longer than `max_size` bytes fails
+/// with `DecodeError::LimitExceeded`, the same as one over the bincode limit.
+fn read_request(stream: &mut impl Read, max_size: u64) -> Result<(Request, u64), DecodeError> {
+    let mut reader = SizedReader {
+        inner: stream,
+        read: 0,
+        max: max_size,
+    };
+    let result = decode_from_std_read(&mut reader, wire_config());
+    if reader.read > max_size {
+        return Err(DecodeError::LimitExceeded);
+    }
+    result.map(|req| (req, reader.read))
+}
+
+/// Staging path used for atomic uploads: the destination with a `.part` suffix appended.
+fn staging_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_os_string();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Streams exactly `size` raw bytes from the client into `f`, or with `None` everything up
+/// to the end of `stream`, returning their SHA-256 and how many there were.
+fn receive_file(
+    stream: &mut impl Read,
+    f: &mut impl Write,
+    size: Option<u64>,
+    mut hasher: Sha256,
+    metrics: &ConnMetrics,
+) -> std::io::Result<([u8; 32], u64)> {
+    let mut remaining = size.unwrap_or(u64::MAX);
+    let mut received = 0;
+    let mut buf = [0u8; 8192];
+    while remaining > 0 {
+        let to_read = std::cmp::min(buf.len() as u64, remaining) as usize;
+        let n = retry_interrupted(|| stream.read(&mut buf[..to_read]))?;
+        if n == 0 {
+            if size.is_none() {
+                break;
+            }
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "EOF during file upload",
+            ));
+        }
+        f.write_all(&buf[..n])?;
+        hasher.update(&buf[..n]);
+        remaining -= n as u64;
+        received += n as u64;
+        metrics.add_uploaded(n as u64);
+    }
+    f.flush()?;
+    Ok((hasher.finalize().into(), received))
+}
+
+/// Streams the rest of `f` to the client as raw bytes, returning how many were sent. With a
+/// throttle, every buffer waits for its share of the server's `--max-bps` first.
+fn send_file(
+    stream: &mut impl Write,
+    f: &mut impl Read,
+    throttle: Option<&Throttle>,
+) -> std::io::Result<u64> {
+    let mut buf = [0u8; 8192];
+    let mut sent = 0u64;
+    loop {
+        let n = retry_interrupted(|| f.read(&mut buf))?;
+        if n == 0 {
+            break;
+        }
+        if let Some(throttle) = throttle {
+            throttle.wait_for(n as u64);
+        }
+        stream.write_all(&buf[..n])?;
+        sent += n as u64;
+    }
+    stream.flush()?;
+    Ok(sent)
+}
+
+/// Packs every regular file under `dir` into a deflated ZIP archive at `archive_path`, with
+/// entry names relative to `dir`. Returns the number of files added.
+fn write_zip(dir: &Path, archive_path: &Path) -> zip::result::ZipResult<u64> {
+    let mut files = Vec::new();
+    walk_files(dir, &mut files)?;
+
+    let mut archive = zip::ZipWriter::new(File::create(archive_path)?);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .large_file(true);
+    for path in &files {
+        let rel = path.strip_prefix(dir).unwrap_or(path);
+        // ZIP entry names always use `/`, whatever the server's separator
+        let name = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        archive.start_file(name, options)?;
+        std::io::copy(&mut File::open(path)?, &mut archive)?;
+    }
+    archive.finish()?;
+    Ok(files.len() as u64)
+}
+
+/// Zips `dir` into a temporary file and streams it to the client as `<dir name>.zip`.
+fn send_zip(
+    stream: &mut Transport,
+    metrics: &ConnMetrics,
+    dir: &Path,
+    throttle: Option<&Throttle>,
+) -> std::io::Result<()> {
+    let dir_name = dir
+        .file_name()
+        .and_then(|os| os.to_str())
+        .unwrap_or("archive");
+    // the archive size must be known up front for `FileMetadata`, so it is built on disk first;
+    // the counter keeps concurrent clients from sharing a file
+    let archive_path = std::env::temp_dir().join(format!(
+        "shell_protocol-{}-{}.zip",
+        std::process::id(),
+        NEXT_ARCHIVE_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = match write_zip(dir, &archive_path) {
+        Ok(count) => File::open(&archive_path).and_then(|mut f| {
+            let size = f.metadata()?.len();
+            let name = format!("{}.zip", dir_name);
+            let sha256 = sha256_file(&archive_path).ok();
+            send_response(
+                stream,
+                metrics,
+                &Response::FileMetadata {
+                    name: name.clone(),
+                    size: Some(size),
+                    offset: 0,
+                    sha256,
+                },
+            )?;
+            let bytes_sent = send_file(stream, &mut f, throttle)?;
+            metrics.add_downloaded(bytes_sent);
+            println!("Sent {} ({} files, {} bytes)", name, count, bytes_sent);
+            Ok(())
+        }),
+        Err(e) => send_response(
+            stream,
+            metrics,
+            &Response::Error(ShellError::io("zip failed", &e.into())),
+        ),
+    };
+    let _ = fs::remove_file(&archive_path);
+    result
+}
+
+/// Counts the bytes written through it and, with a throttle, paces them like `send_file`.
+struct ThrottledWriter<'a, W> {
+    inner: W,
+    throttle: Option<&'a Throttle>,
+    written: u64,
+}
+
+impl<W: Write> Write for ThrottledWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(throttle) = self.throttle {
+            throttle.wait_for(buf.len() as u64);
+        }
+        self.inner.write_all(buf)?;
+        self.written += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Streams every regular file under `dir` to the client as a tar archive named
+/// `<dir name>.tar`, with entry names relative to `dir`. The archive is sent while it is
+/// built, so `FileMetadata` carries no size and the bytes go out in chunks. Once that has
+/// started, a failure can only be reported by dropping the connection, which the client
+/// sees as a truncated stream.
+fn send_tar(
+    stream: &mut Transport,
+    metrics: &ConnMetrics,
+    dir: &Path,
+    throttle: Option<&Throttle>,
+) -> std::io::Result<()> {
+    let mut files = Vec::new();
+    if let Err(e) = walk_files(dir, &mut files) {
+        let resp = Response::Error(ShellError::io("tar failed", &e));
+        return send_response(stream, metrics, &resp);
+    }
+    let dir_name = dir
+        .file_name()
+        .and_then(|os| os.to_str())
+        .unwrap_or("archive");
+    let name = format!("{}.tar", dir_name);
+    send_response(
+        stream,
+        metrics,
+        &Response::FileMetadata {
+            name: name.clone(),
+            size: None,
+            offset: 0,
+            sha256: None,
+        },
+    )?;
+
+    let out = ThrottledWriter {
+        inner: ChunkedWriter::new(&mut *stream),
+        throttle,
+        written: 0,
+    };
+    let mut archive = tar::Builder::new(out);
+    let mut count = 0;
+    for path in &files {
+        let rel = path.strip_prefix(dir).unwrap_or(path);
+        match archive.append_path_with_name(path, rel) {
+            Ok(()) => count += 1,
+            // nothing is written for a file that is gone before it could be opened
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                eprintln!("{} vanished before it could be archived", path.display());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let out = archive.into_inner()?;
+    let bytes_sent = out.written;
+    out.inner.finish()?;
+    metrics.add_downloaded(bytes_sent);
+    println!("Sent {} ({} files, {} bytes)", name, count, bytes_sent);
+    Ok(())
+}
+
+/// Opens every path (relative to `cwd`, refusing any that resolves outside `root`), then sends
+/// `BatchMetadata` and the files' bytes back-to-back. Nothing is streamed unless every file
+/// could be opened.
+fn send_many(
+    stream: &mut Transport,
+    metrics: &ConnMetrics,
+    root: &Path,
+    cwd: &Path,
+    paths: &[String],
+    throttle: Option<&Throttle>,
+) -> std::io::Result<()> {
+    let opened = paths
+        .iter()
+        .map(|path| {
+            let full = resolve_file(root, cwd, path)?;
+            let f = File::open(&full).map_err(|e| ShellError::io(path, &e))?;
+            let size = f.metadata().map_err(|e| ShellError::io(path, &e))?.len();
+            let name = full
+                .file_name()
+                .and_then(|os| os.to_str())
+                .unwrap_or("file")
+                .to_string();
+            Ok((f, BatchEntry { name, size }))
+        })
+        .collect::<Result<Vec<_>, ShellError>>();
+    let opened = match opened {
+        Ok(opened) => opened,
+        Err(e) => return send_response(stream, metrics, &Response::Error(e)),
+    };
+
+    let (files, entries): (Vec<_>, Vec<_>) = opened.into_iter().unzip();
+    send_response(
+        stream,
+        metrics,
+        &Response::BatchMetadata {
+            files: entries.clone(),
+        },
+    )?;
+    for (f, entry) in files.into_iter().zip(&entries) {
+        // the client splits the stream by the announced sizes, so a file that shrank since
+        // it was opened leaves it out of step; drop the connection rather than send garbage
+        let bytes_sent = send_file(stream, &mut f.take(entry.size), throttle)?;
+        metrics.add_downloaded(bytes_sent);
+        if bytes_sent != entry.size {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("{} shrank while being sent", entry.name),
+            ));
+        }
+    }
+    println!("Sent {} files back-to-back", entries.len());
+    Ok(())
+}
+
+/// Returns whether the peer is still connected, without consuming any of its pending data.
+fn peer_connected(stream: &TcpStream) -> std::io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut probe = [0u8; 1];
+    let result = retry_interrupted(|| stream.peek(&mut probe));
+    stream.set_nonblocking(false)?;
+
+    match result {
+        Ok(0) => Ok(false),
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(true),
+        Err(_) => Ok(false),
+    }
+}
+
+/// Streams `FsEvent` frames for changes under `dir` until the client disconnects.
+fn watch_dir(
+    stream: &mut Transport,
+    metrics: &ConnMetrics,
+    dir: &Path,
+    shutdown: &AtomicBool,
+) -> std::io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let watcher = notify::recommended_watcher(tx).and_then(|mut watcher| {
+        watcher.watch(dir, RecursiveMode::Recursive)?;
+        Ok(watcher)
+    });
+    // keep the watcher alive for as long as we are streaming events
+    let _watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(e) => {
+            return send_response(
+                stream,
+                metrics,
+                &Response::Error(match &e.kind {
+                    notify::ErrorKind::Io(io_err) => ShellError::io("watch failed", io_err),
+                    _ => ShellError::Io(format!("watch failed: {}", e)),
+                }),
+            );
+        }
+    };
+    send_response(stream, metrics, &Response::Ok)?;
+    println!("Watching {}", dir.display());
+
+    loop {
+        match rx.recv_timeout(WATCH_POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                let kind = match event.kind {
+                    EventKind::Create(_) => "create",
+                    EventKind::Modify(_) => "modify",
+                    EventKind::Remove(_) => "remove",
+                    EventKind::Access(_) => continue,
+                    _ => "other",
+                };
+                // paths outside the watched directory are never reported
+                for path in event.paths.iter().filter_map(|p| p.strip_prefix(dir).ok()) {
+                    let frame = Response::FsEvent {
+                        kind: kind.to_string(),
+                        path: path.to_string_lossy().to_string(),
+                    };
+                    if write_frame(stream, &frame).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            Ok(Err(e)) => eprintln!("Watch error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {
+                if shutdown.load(Ordering::Relaxed) || !peer_connected(stream.tcp())? {
+                    return Ok(());
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+/// Streams a `Line` frame for each line appended to `path` until the client disconnects.
+/// Starts at the current end of the file and reopens it from the start if it shrinks,
+/// which is how truncation and most log rotations show up.
+fn follow_file(
+    stream: &mut Transport,
+    metrics: &ConnMetrics,
+    path: &Path,
+    shutdown: &AtomicBool,
+) -> std::io::Result<()> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            return send_response(
+                stream,
+                metrics,
+                &Response::Error(ShellError::io("Open failed", &e)),
+            );
+        }
+    };
+    let mut pos = file.seek(SeekFrom::End(0))?;
+    send_response(stream, metrics, &Response::Ok)?;
+    println!("Following {}", path.display());
+
+    // bytes after the last newline, held back until the line is complete
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let len = match fs::metadata(path) {
+            Ok(metadata) => metadata.len(),
+            // mid-rotation the path may briefly not exist; try again on the next poll
+            Err(_) => pos,
+        };
+        if len < pos {
+            println!("{} shrank, reopening", path.display());
+            if let Ok(reopened) = File::open(path) {
+                file = reopened;
+                pos = 0;
+                pending.clear();
+            }
+        }
+
+        let n = retry_interrupted(|| file.read(&mut buf))?;
+        if n == 0 {
+            if shutdown.load(Ordering::Relaxed) || !peer_connected(stream.tcp())? {
+                return Ok(());
+            }
+            std::thread::sleep(FOLLOW_POLL_INTERVAL);
+            continue;
+        }
+        pos += n as u64;
+        pending.extend_from_slice(&buf[..n]);
+
+        while let Some(newline) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=newline).collect();
+            let text = String::from_utf8_lossy(&line[..newline])
+                .trim_end_matches('\r')
+                .to_string();
+            if write_frame(stream, &Response::Line { text }).is_err() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Streams the rest of an upload into its file, starting from the `received` bytes already
+/// in `partial`, then checks the digest and publishes the file. If the connection drops
+/// first, the session is parked under `id` so the client can resume it.
+fn receive_upload(
+    stream: &mut Transport,
+    metrics: &ConnMetrics,
+    uploads: &UploadSessions,
+    id: u64,
+    session: UploadSession,
+    partial: (File, Sha256, u64),
+    compressed: bool,
+) -> std::io::Result<()> {
+    let (mut f, hasher, received) = partial;
+    if let Err(e) = send_response(stream, metrics, &Response::UploadSession { id, received }) {
+        uploads.park(id, session);
+        return Err(e);
+    }
+
+    // only the declared size is ever read into the file; what a plain stream sends beyond it
+    // is taken for the next request, while a deflate stream is checked for leftover data.
+    // Uploads of unknown size go through `receive_streamed_upload` instead
+    let size = session.size.unwrap_or(received);
+    let remaining = Some(size - received);
+    let result = if compressed {
+        let mut decoder = Inflater::new(&mut *stream);
+        receive_file(&mut decoder, &mut f, remaining, hasher, metrics)
+            .map(|(digest, _)| (digest, decoder.finish()))
+    } else {
+        receive_file(stream, &mut f, remaining, hasher, metrics).map(|(digest, _)| (digest, Ok(())))
+    };
+    let digest = match result {
+        Ok((digest, Ok(()))) => digest,
+        Ok((_, Err(e))) if e.kind() == std::io::ErrorKind::InvalidData => {
+            drop(f);
+            discard_upload(&session.write_path, session.appended_to);
+            eprintln!(
+                "Upload of {} sent more than its declared {} bytes",
+                session.file_name, size
+            );
+            // the rest of the stream cannot be skipped reliably, so the connection ends here
+            let _ = send_response(
+                stream,
+                metrics,
+                &Response::Error(ShellError::TooLarge(format!(
+                    "Upload exceeds its declared size of {} bytes",
+                    size
+                ))),
+            );
+            return Err(e);
+        }
+        Ok((_, Err(e))) | Err(e) => {
+            let _ = f.flush();
+            eprintln!(
+                "Upload {:016x} of {} interrupted; kept for resuming",
+                id, session.file_name
+            );
+            uploads.park(id, session);
+            return Err(e);
+        }
+    };
+    drop(f);
+    finish_upload(stream, metrics, &session, digest, size)
+}
+
+/// Receives an upload of unknown size, sent in chunks up to an empty one. It cannot be
+/// resumed, so it gets no session id and an interrupted one is discarded rather than
+/// parked; `max_file_size` is enforced as the data arrives instead of up front.
+fn receive_streamed_upload(
+    stream: &mut Transport,
+    metrics: &ConnMetrics,
+    session: UploadSession,
+    mut f: File,
+    max_file_size: Option<u64>,
+) -> std::io::Result<()> {
+    let resp = Response::UploadSession { id: 0, received: 0 };
+    if let Err(e) = send_response(stream, metrics, &resp) {
+        drop(f);
+        discard_upload(&session.write_path, session.appended_to);
+        return Err(e);
+    }
+
+    // one byte past the limit is enough to tell that the upload is too large
+    let limit = max_file_size.unwrap_or(u64::MAX);
+    let result = {
+        let mut chunks = ChunkedReader::new(&mut *stream).take(limit.saturating_add(1));
+        receive_file(&mut chunks, &mut f, None, Sha256::new(), metrics)
+    };
+    drop(f);
+    match result {
+        Ok((_, received)) if received > limit => {
+            discard_upload(&session.write_path, session.appended_to);
+            eprintln!(
+                "Upload of {} went over the {} byte upload limit",
+                session.file_name, limit
+            );
+            // the rest of the data is still unread, so the connection ends here
+            let _ = send_response(
+                stream,
+                metrics,
+                &Response::Error(ShellError::TooLarge(format!(
+                    "{} is over the {} byte upload limit",
+                    session.file_name, limit
+                ))),
+            );
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "streamed upload over the size limit",
+            ))
+        }
+        Ok((digest, received)) => finish_upload(stream, metrics, &session, digest, received),
+        Err(e) => {
+            discard_upload(&session.write_path, session.appended_to);
+            eprintln!("Upload of {} interrupted; discarded", session.file_name);
+            Err(e)
+        }
+    }
+}
+
+/// Checks a fully received upload against its digest, publishes it, and tells the client
+/// how many bytes were stored.
+fn finish_upload(
+    stream: &mut Transport,
+    metrics: &ConnMetrics,
+    session: &UploadSession,
+    digest: [u8; 32],
+    total_bytes: u64,
+) -> std::io::Result<()> {
+    if session.sha256.is_some_and(|expected| expected != digest) {
+        // corrupted data must not be left in place looking complete
+        discard_upload(&session.write_path, session.appended_to);
+        eprintln!("Upload of {} failed its checksum", session.file_name);
+        return send_response(
+            stream,
+            metrics,
+            &Response::Error(ShellError::ChecksumMismatch(format!(
+                "Checksum mismatch: received data hashes to {}",
+                to_hex(&digest)
+            ))),
+        );
+    }
+
+    if session.atomic
+        && let Err(e) = fs::rename(&session.write_path, &session.dest)
+    {
+        // the staged data is of no use once it cannot be published
+        discard_upload(&session.write_path, session.appended_to);
+        eprintln!("Cannot publish upload of {}: {}", session.file_name, e);
+        return send_response(
+            stream,
+            metrics,
+            &Response::Error(ShellError::io(
+                &format!("Cannot publish {}", session.file_name),
+                &e,
+            )),
+        );
+    }
+    println!(
+        "Uploaded file {} to {}",
+        session.file_name,
+        session.dest.display()
+    );
+    send_response(stream, metrics, &Response::UploadComplete { total_bytes })
+}
+
+/// Receives an upload of `size` bytes into a backend that is not a local directory tree,
+/// through `create_write`. Such an upload cannot be resumed, so it gets no session id, and
+/// one that is cut short or fails its checksum is removed.
+fn receive_backend_upload(
+    stream: &mut (impl Read + Write),
+    metrics: &ConnMetrics,
+    storage: &mut dyn StorageBackend,
+    path: &str,
+    size: u64,
+    sha256: Option<[u8; 32]>,
+    compressed: bool,
+) -> std::io::Result<()> {
+    let mut f = match storage.create_write(path) {
+        Ok(f) => f,
+        Err(e) => return send_response(stream, metrics, &Response::Error(e)),
+    };
+    if let Err(e) = send_response(
+        stream,
+        metrics,
+        &Response::UploadSession { id: 0, received: 0 },
+    ) {
+        drop(f);
+        let _ = storage.remove_file(path);
+        return Err(e);
+    }
+
+    let result = if compressed {
+        let mut decoder = Inflater::new(&mut *stream);
+        receive_file(&mut decoder, &mut f, Some(size), Sha256::new(), metrics)
+            .and_then(|received| decoder.finish().map(|()| received))
+    } else {
+        receive_file(stream, &mut f, Some(size), Sha256::new(), metrics)
+    };
+    drop(f);
+    let digest = match result {
+        Ok((digest, _)) => digest,
+        Err(e) => {
+            let _ = storage.remove_file(path);
+            eprintln!("Upload of {} interrupted; discarded", path);
+            if e.kind() == std::io::ErrorKind::InvalidData {
+                let _ = send_response(
+                    stream,
+                    metrics,
+                    &Response::Error(ShellError::TooLarge(format!(
+                        "Upload exceeds its declared size of {} bytes",
+                        size
+                    ))),
+                );
+            }
+            return Err(e);
+        }
+    };
+    if sha256.is_some_and(|expected| expected != digest) {
+        let _ = storage.remove_file(path);
+        eprintln!("Upload of {} failed its checksum", path);
+        return send_response(
+            stream,
+            metrics,
+            &Response::Error(ShellError::ChecksumMismatch(format!(
+                "Checksum mismatch: received data hashes to {}",
+                to_hex(&digest)
+            ))),
+        );
+    }
+    println!("Uploaded file {}", path);
+    send_response(
+        stream,
+        metrics,
+        &Response::UploadComplete { total_bytes: size },
+    )
+}
+
+/// Answers a `Download` from the session's storage: the file's metadata, then its bytes from
+/// `offset` on, deflated if `compressed`.
+fn send_download(
+    stream: &mut impl Write,
+    metrics: &ConnMetrics,
+    storage: &dyn StorageBackend,
+    src_path: &str,
+    offset: u64,
+    compressed: bool,
+    throttle: Option<&Throttle>,
+) -> std::io::Result<()> {
+    let mut f = match storage.open_read(src_path) {
+        Ok(f) => f,
+        Err(e) => return send_response(stream, metrics, &Response::Error(e)),
+    };
+    // named as requested, so a symlink keeps its own name
+    let name = Path::new(src_path)
+        .file_name()
+        .and_then(|os| os.to_str())
+        .unwrap_or("file")
+        .to_string();
+    let size = f.seek(SeekFrom::End(0))?;
+    if offset > size {
+        return send_response(
+            stream,
+            metrics,
+            &Response::Error(ShellError::invalid(format!(
+                "Offset {} is past the end of {} ({} bytes)",
+                offset, name, size
+            ))),
+        );
+    }
+
+    // hashed in a pass of its own, since the digest has to lead the data
+    f.seek(SeekFrom::Start(0))?;
+    let sha256 = sha256_reader(&mut f).ok();
+    f.seek(SeekFrom::Start(offset))?;
+    send_response(
+        stream,
+        metrics,
+        &Response::FileMetadata {
+            name: name.clone(),
+            size: Some(size),
+            offset,
+            sha256,
+        },
+    )?;
+    let bytes_sent = if compressed {
+        let mut encoder = DeflateEncoder::new(&mut *stream, Compression::fast());
+        let bytes_sent = send_file(&mut encoder, &mut f, throttle)?;
+        encoder.finish()?.flush()?;
+        bytes_sent
+    } else {
+        send_file(stream, &mut f, throttle)?
+    };
+    metrics.add_downloaded(bytes_sent);
+    println!("Sent file {} ({} bytes)", name, bytes_sent);
+    Ok(())
+}
+
+/// Opens the partial file of a parked upload for appending. Anything past the announced
+/// size is cut off, and the bytes already there are hashed so the final digest still
+/// covers the whole file. An append upload starts where the file ended before it, so only
+/// the bytes from there on count as received.
+fn reopen_upload(session: &UploadSession) -> std::io::Result<(File, Sha256, u64)> {
+    let mut f = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&session.write_path)?;
+    let start = session.appended_to.unwrap_or(0);
+    // only uploads of known size are ever parked
+    let size = session.size.unwrap_or_default();
+    let received = f.metadata()?.len().saturating_sub(start).min(size);
+    f.set_len(start + received)?;
+
+    let mut hasher = Sha256::new();
+    f.seek(SeekFrom::Start(start))?;
+    std::io::copy(&mut (&mut f).take(received), &mut hasher)?;
+    f.seek(SeekFrom::Start(start + received))?;
+    Ok((f, hasher, received))
+}
+
+/// Collects every regular file under `dir`, recursing into subdirectories, in sorted order.
+fn walk_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+
+    for path in entries {
+        // symlinks are not followed, so a link cycle cannot loop the walk
+        let file_type = fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_dir() {
+            walk_files(&path, files)?;
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Streams a `ManifestEntry` frame per file under `dir`, then a closing `ManifestEnd`.
+/// Entries are hashed and sent one at a time, so large trees are never held in memory.
+fn send_manifest(stream: &mut Transport, metrics: &ConnMetrics, dir: &Path) -> std::io::Result<()> {
+    let mut files = Vec::new();
+    if let Err(e) = walk_files(dir, &mut files) {
+        return send_response(
+            stream,
+            metrics,
+            &Response::Error(ShellError::io("walk failed", &e)),
+        );
+    }
+
+    let mut count = 0u64;
+    for path in &files {
+        let (size, sha256) = match hash_file(path, HashAlgo::Sha256) {
+            Ok(digest) => digest,
+            Err(e) => {
+                // files removed or unreadable mid-walk are skipped rather than aborting the manifest
+                eprintln!("Skipping {}: {}", path.display(), e);
+                continue;
+            }
+        };
+        let rel = path.strip_prefix(dir).unwrap_or(path);
+        let entry = Response::ManifestEntry {
+            path: rel.to_string_lossy().to_string(),
+            size,
+            sha256,
+        };
+        write_frame(stream, &entry)?;
+        count += 1;
+    }
+
+    send_response(stream, metrics, &Response::ManifestEnd { files: count })?;
+    println!("Sent manifest of {} ({} files)", dir.display(), count);
+    Ok(())
+}
+
+/// Returns the first line of `path` matching `regex` with its 1-based number. Invalid UTF-8
+/// is replaced rather than skipping the file, so binary files can still match.
+fn first_matching_line(
+    path: &Path,
+    regex: &regex::Regex,
+) -> std::io::Result<Option<(u64, String)>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut buf = Vec::new();
+    let mut line_number = 0u64;
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(None);
+        }
+        line_number += 1;
+        let line = String::from_utf8_lossy(&buf);
+        let line = line.trim_end_matches(['\n', '\r']);
+        if regex.is_match(line) {
+            return Ok(Some((line_number, line.to_string())));
+        }
+    }
+}
+
+/// Streams a `SearchHit` frame per file under `dir` passing both filters, then a closing
+/// `SearchEnd`. At most `MAX_SEARCH_HITS` hits are sent.
+fn send_search(
+    stream: &mut Transport,
+    metrics: &ConnMetrics,
+    dir: &Path,
+    name_glob: Option<&str>,
+    content_regex: Option<&str>,
+) -> std::io::Result<()> {
+    let regex = match content_regex.map(regex::Regex::new).transpose() {
+        Ok(regex) => regex,
+        Err(e) => {
+            return send_response(
+                stream,
+                metrics,
+                &Response::Error(ShellError::invalid(format!("invalid regex: {}", e))),
+            );
+        }
+    };
+    let mut files = Vec::new();
+    if let Err(e) = walk_files(dir, &mut files) {
+        return send_response(
+            stream,
+            metrics,
+            &Response::Error(ShellError::io("walk failed", &e)),
+        );
+    }
+
+    let mut hits = 0u64;
+    let mut truncated = false;
+    for path in &files {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name_glob.is_some_and(|glob| !glob_match(glob, &name)) {
+            continue;
+        }
+
+        let (line_number, line) = match &regex {
+            Some(regex) => match first_matching_line(path, regex) {
+                Ok(Some((number, line))) => (Some(number), Some(line)),
+                Ok(None) => continue,
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            },
+            None => (None, None),
+        };
+
+        if hits == MAX_SEARCH_HITS {
+            truncated = true;
+            break;
+        }
+        let rel = path.strip_prefix(dir).unwrap_or(path);
+        let hit = Response::SearchHit {
+            path: rel.to_string_lossy().to_string(),
+            line_number,
+            line,
+        };
+        write_frame(stream, &hit)?;
+        hits += 1;
+    }
+
+    send_response(stream, metrics, &Response::SearchEnd { hits, truncated })?;
+    println!("Searched {} ({} hits)", dir.display(), hits);
+    Ok(())
+}
+
+fn handle_fs_request(storage: &mut dyn StorageBackend, req: Request) -> Response {
+    match req {
+        Request::Dir => match storage.read_dir() {
+            Ok(list) => Response::DirList(list),
+            Err(e) => Response::Error(e),
+        },
+        Request::CdUp => match storage.change_dir("..") {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e),
+        },
+        Request::Cd { path } => match storage.change_dir(&path) {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e),
+        },
+        Request::Mkdir { name } => match storage.make_dir(&name) {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e),
+        },
+        Request::Copy { src, dst } => match storage.copy(&src, &dst) {
+            Ok(bytes) => Response::CopyResult {
+                bytes_copied: bytes,
+            },
+            Err(e) => Response::Error(e),
+        },
+        Request::Echo { payload } => Response::Echo { payload },
+        req => match require_local_paths(storage) {
+            Ok((root, cwd)) => handle_local_request(root, cwd, req),
+            Err(e) => Response::Error(e),
+        },
+    }
+}
+
+/// Requests that work on paths of a local directory tree.
+fn handle_local_request(root: &Path, cwd: &Path, req: Request) -> Response {
+    match req {
+        Request::MkTemp { prefix, suffix } => mktemp(cwd, &prefix, &suffix),
+        Request::SetTime { path, mtime_secs } => set_mtime(root, cwd, &path, mtime_secs),
+        Request::Stat { path } => stat_path(root, cwd, &path),
+        Request::Link { target, link } => hard_link(root, cwd, &target, &link),
+        Request::Swap { a, b } => swap_paths(root, cwd, &a, &b),
+        Request::Delete { path } => remove_file(root, cwd, &path),
+        Request::Move { src, dst } => move_path(root, cwd, &src, &dst),
+        Request::Find { pattern, max_depth } => find_paths(root, cwd, &pattern, max_depth),
+        Request::DiskUsage { path } => disk_usage(root, cwd, &path),
+        Request::Hash { path, algo } => match resolve_existing(root, cwd, &path) {
+            Ok(full) => match hash_file(&full, algo) {
+                Ok((_, digest)) => Response::Hash { digest },
+                Err(e) => Response::Error(ShellError::io("hash failed", &e)),
+            },
+            Err(e) => Response::Error(e),
+        },
+        _ => Response::Error(ShellError::invalid("Unexpected request in FS handler")),
+    }
+}
+
+/// One connection's view of the server metrics, which also writes the access log: every
+/// request is logged with the first response sent for it.
+struct ConnMetrics<'a> {
+    metrics: &'a ServerMetrics,
+    access_log: Option<&'a AccessLogger>,
+    peer_addr: SocketAddr,
+    /// Name of the request being served, until its first response has been logged
+    pending: Cell<Option<&'static str>>,
+}
+
+impl ConnMetrics<'_> {
+    fn begin_request(&self, name: &'static str) {
+        self.pending.set(Some(name));
+    }
+
+    fn record_response(&self, resp: &Response) {
+        self.metrics
+            .record_request(matches!(resp, Response::Error(_)));
+        if let (Some(log), Some(name)) = (self.access_log, self.pending.take()) {
+            log.record(self.peer_addr, name, resp);
+        }
+    }
+}
+
+impl Deref for ConnMetrics<'_> {
+    type Target = ServerMetrics;
+
+    fn deref(&self) -> &ServerMetrics {
+        self.metrics
+    }
+}
+
+/// Server-wide state shared by every connection.
+struct Server {
+    root: PathBuf,
+    metrics: Arc<ServerMetrics>,
+    /// Shared by all workers, since a resumed upload usually arrives on another connection
+    uploads: UploadSessions,
+    credentials: Option<Credentials>,
+    /// Set when the server was started with a certificate; every connection is then TLS
+    tls: Option<Arc<TlsConfig>>,
+    df_paths: Vec<PathBuf>,
+    max_request_size: u64,
+    /// Cap on the combined rate of all downloads, from `--max-bps`
+    throttle: Option<Throttle>,
+    /// Largest upload accepted, from the config file
+    max_file_size: Option<u64>,
+    access_log: Option<AccessLogger>,
+    /// Set by Ctrl-C; connections finish their current request and close
+    shutdown: Arc<AtomicBool>,
+}
+
+fn handle_client(
+    mut stream: Transport,
+    peer_addr: SocketAddr,
+    server: &Server,
+    storage: &mut dyn StorageBackend,
+) -> std::io::Result<()> {
+    let Server {
+        root,
+        metrics,
+        uploads,
+        credentials,
+        df_paths,
+        max_request_size,
+        throttle,
+        max_file_size,
+        access_log,
+        shutdown,
+        ..
+    } = server;
+    let metrics = &ConnMetrics {
+        metrics,
+        access_log: access_log.as_ref(),
+        peer_addr,
+        pending: Cell::new(None),
+    };
+    let max_request_size = *max_request_size;
+    let mut authenticated = credentials.is_none();
+
+    loop {
+        // wait for the next request in slices, so an idle connection notices a shutdown
+        while !stream.wait_readable(SHUTDOWN_POLL_INTERVAL)? {
+            if shutdown.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+        }
+
+        let req = match read_request(&mut stream, max_request_size) {
+            Ok((req, size)) => {
+                metrics.record_request_size(size);
+                metrics.begin_request(req.name());
+                req
+            }
+            // the rest of the oversized frame is still unread, so the stream cannot be
+            // resynchronised: report the error and drop the connection
+            Err(DecodeError::LimitExceeded) => {
+                eprintln!("Request exceeds {} bytes, disconnecting", max_request_size);
+                metrics.begin_request("-");
+                send_response(
+                    &mut stream,
+                    metrics,
+                    &Response::Error(ShellError::TooLarge(format!(
+                        "Request exceeds the {} byte limit",
+                        max_request_size
+                    ))),
+                )?;
+                break;
+            }
+            // assume connection closed or bad data → exit
+            Err(_) => break,
+        };
+
+        // until the client has logged in, only the handshake is answered
+        if !authenticated && !matches!(req, Request::Capabilities | Request::Auth { .. }) {
+            send_response(
+                &mut stream,
+                metrics,
+                &Response::Error(ShellError::Unauthorized),
+            )?;
+            continue;
+        }
+
+        match req {
+            Request::Capabilities => {
+                send_response(&mut stream, metrics, &capabilities(credentials.is_some()))?;
+            }
+
+            Request::Auth { user, password } => {
+                let resp = match credentials {
+                    Some(credentials) if credentials.verify(&user, &password) => {
+                        println!("User {} logged in", user);
+                        authenticated = true;
+                        Response::Ok
+                    }
+                    Some(_) => {
+                        eprintln!("Failed login as {}", user);
+                        Response::Error(ShellError::Unauthorized)
+                    }
+                    // nothing to log in to; accept so clients need not know in advance
+                    None => Response::Ok,
+                };
+                send_response(&mut stream, metrics, &resp)?;
+            }
+
+            Request::Upload {
+                dst_path,
+                file_name,
+                size,
+                atomic,
+                overwrite,
+                append,
+                sha256,
+                compressed,
+            } => {
+                if atomic && append {
+                    let resp = Response::Error(ShellError::invalid(
+                        "An upload cannot both append and be atomic",
+                    ));
+                    send_response(&mut stream, metrics, &resp)?;
+                    continue;
+                }
+                if compressed && size.is_none() {
+                    let resp = Response::Error(ShellError::invalid(
+                        "An upload of unknown size cannot be compressed",
+                    ));
+                    send_response(&mut stream, metrics, &resp)?;
+                    continue;
+                }
+                if let Some(size) = size
+                    && let Some(limit) = max_file_size.filter(|&limit| size > limit)
+                {
+                    let resp = Response::Error(ShellError::TooLarge(format!(
+                        "{} is {} bytes, over the {} byte upload limit",
+                        file_name, size, limit
+                    )));
+                    send_response(&mut stream, metrics, &resp)?;
+                    continue;
+                }
+
+                // Build destination path properly
+                let rel = if dst_path == "." || dst_path.is_empty() {
+                    PathBuf::from(&file_name)
+                } else {
+                    Path::new(&dst_path).join(&file_name)
+                };
+                let Some((root, cwd)) = storage.local_paths() else {
+                    // other backends only take whole files, written through the backend
+                    let resp = if atomic || append || size.is_none() {
+                        Some(ShellError::invalid(
+                            "Atomic, appending and streamed uploads are not supported by this storage backend",
+                        ))
+                    } else if !overwrite && storage.open_read(&rel.to_string_lossy()).is_ok() {
+                        Some(ShellError::AlreadyExists(format!(
+                            "{} already exists",
+                            rel.display()
+                        )))
+                    } else {
+                        None
+                    };
+                    match resp {
+                        Some(e) => send_response(&mut stream, metrics, &Response::Error(e))?,
+                        None => receive_backend_upload(
+                            &mut stream,
+                            metrics,
+                            storage,
+                            &rel.to_string_lossy(),
+                            size.unwrap_or_default(),
+                            sha256,
+                            compressed,
+                        )?,
+                    }
+                    continue;
+                };
+                let dest = match resolve_new(root, cwd, &rel) {
+                    Ok(dest) => dest,
+                    Err(e) => {
+                        send_response(&mut stream, metrics, &Response::Error(e))?;
+                        continue;
+                    }
+                };
+
+                if let Some(parent) = dest.parent() {
+                    // an upload of unknown size only gets the headroom checked
+                    if let Err(e) = prepare_upload_dir(parent, size.unwrap_or(0)) {
+                        send_response(&mut stream, metrics, &Response::Error(e))?;
+                        continue;
+                    }
+                }
+
+                // atomic uploads are written to a staging file and renamed once complete
+                let write_path = if atomic {
+                    staging_path(&dest)
+                } else {
+                    dest.clone()
+                };
+
+                match create_upload_file(&dest, &write_path, overwrite, append) {
+                    Ok((f, appended_to)) => {
+                        uploads.expire(UPLOAD_SESSION_TIMEOUT);
+                        let session = UploadSession {
+                            dest,
+                            write_path,
+                            file_name,
+                            size,
+                            atomic,
+                            appended_to,
+                            sha256,
+                        };
+                        if session.size.is_none() {
+                            receive_streamed_upload(
+                                &mut stream,
+                                metrics,
+                                session,
+                                f,
+                                *max_file_size,
+                            )?;
+                            continue;
+                        }
+                        let id = uploads.new_id();
+                        receive_upload(
+                            &mut stream,
+                            metrics,
+                            uploads,
+                            id,
+                            session,
+                            (f, Sha256::new(), 0),
+                            compressed,
+                        )?;
+                    }
+                    Err(e) => {
+                        send_response(&mut stream, metrics, &Response::Error(e))?;
+                    }
+                }
+            }
+
+            Request::UploadResume {
+                id,
+                size,
+                compressed,
+            } => {
+                uploads.expire(UPLOAD_SESSION_TIMEOUT);
+                let Some(session) = uploads.take(id) else {
+                    send_response(
+                        &mut stream,
+                        metrics,
+                        &Response::Error(ShellError::NotFound(format!(
+                            "Unknown or expired upload id {:016x}",
+                            id
+                        ))),
+                    )?;
+                    continue;
+                };
+                if session.size != Some(size) {
+                    let err = ShellError::invalid(format!(
+                        "Upload {:016x} expects {} bytes, not {}",
+                        id,
+                        session.size.unwrap_or_default(),
+                        size
+                    ));
+                    uploads.park(id, session);
+                    send_response(&mut stream, metrics, &Response::Error(err))?;
+                    continue;
+                }
+
+                match reopen_upload(&session) {
+                    Ok(partial) => {
+                        receive_upload(
+                            &mut stream,
+                            metrics,
+                            uploads,
+                            id,
+                            session,
+                            partial,
+                            compressed,
+                        )?;
+                    }
+                    // the partial file is gone, so there is nothing left to resume
+                    Err(e) => {
+                        send_response(
+                            &mut stream,
+                            metrics,
+                            &Response::Error(ShellError::io("Cannot resume upload", &e)),
+                        )?;
+                    }
+                }
+            }
+
+            Request::Download {
+                src_path,
+                offset,
+                compressed,
+            } => {
+                send_download(
+                    &mut stream,
+                    metrics,
+                    storage,
+                    &src_path,
+                    offset,
+                    compressed,
+                    throttle.as_ref(),
+                )?;
+            }
+
+            Request::Watch { path } => {
+                let dir = match require_local_paths(storage)
+                    .and_then(|(root, cwd)| resolve_dir(root, cwd, &path))
+                {
+                    Ok(dir) => dir,
+                    Err(e) => {
+                        send_response(&mut stream, metrics, &Response::Error(e))?;
+                        continue;
+                    }
+                };
+
+                // the connection is dedicated to the watch until the client goes away
+                return watch_dir(&mut stream, metrics, &dir, shutdown);
+            }
+
+            Request::Follow { path } => {
+                let full = match require_local_paths(storage)
+                    .and_then(|(root, cwd)| resolve_file(root, cwd, &path))
+                {
+                    Ok(full) => full,
+                    Err(e) => {
+                        send_response(&mut stream, metrics, &Response::Error(e))?;
+                        continue;
+                    }
+                };
+
+                // like a watch, the connection now belongs to the follow
+                return follow_file(&mut stream, metrics, &full, shutdown);
+            }
+
+            Request::Manifest { path } => {
+                let dir = match require_local_paths(storage)
+                    .and_then(|(root, cwd)| resolve_dir(root, cwd, &path))
+                {
+                    Ok(dir) => dir,
+                    Err(e) => {
+                        send_response(&mut stream, metrics, &Response::Error(e))?;
+                        continue;
+                    }
+                };
+
+                send_manifest(&mut stream, metrics, &dir)?;
+            }
+
+            Request::DownloadZip { src_path } => {
+                let dir = match require_local_paths(storage)
+                    .and_then(|(root, cwd)| resolve_dir(root, cwd, &src_path))
+                {
+                    Ok(dir) => dir,
+                    Err(e) => {
+                        send_response(&mut stream, metrics, &Response::Error(e))?;
+                        continue;
+                    }
+                };
+
+                send_zip(&mut stream, metrics, &dir, throttle.as_ref())?;
+            }
+
+            Request::DownloadArchive { path } => {
+                let dir = match require_local_paths(storage)
+                    .and_then(|(root, cwd)| resolve_dir(root, cwd, &path))
+                {
+                    Ok(dir) => dir,
+                    Err(e) => {
+                        send_response(&mut stream, metrics, &Response::Error(e))?;
+                        continue;
+                    }
+                };
+
+                send_tar(&mut stream, metrics, &dir, throttle.as_ref())?;
+            }
+
+            Request::DownloadMany { paths } => match require_local_paths(storage) {
+                Ok((root, cwd)) => {
+                    send_many(&mut stream, metrics, root, cwd, &paths, throttle.as_ref())?;
+                }
+                Err(e) => send_response(&mut stream, metrics, &Response::Error(e))?,
+            },
+
+            Request::Search {
+                path,
+                name_glob,
+                content_regex,
+            } => {
+                let dir = match require_local_paths(storage)
+                    .and_then(|(root, cwd)| resolve_dir(root, cwd, &path))
+                {
+                    Ok(dir) => dir,
+                    Err(e) => {
+                        send_response(&mut stream, metrics, &Response::Error(e))?;
+                        continue;
+                    }
+                };
+
+                send_search(
+                    &mut stream,
+                    metrics,
+                    &dir,
+                    name_glob.as_deref(),
+                    content_regex.as_deref(),
+                )?;
+            }
+
+            Request::Df => {
+                send_response(&mut stream, metrics, &df_report(root, df_paths))?;
+            }
+
+            other => {
+                let resp = handle_fs_request(storage, other);
+                send_response(&mut stream, metrics, &resp)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Everything the TCP server is started with, from its command line and config file.
+pub struct TcpServerOptions {
+    pub addr: String,
+    /// Canonical, so every path a client names can be compared against it
+    pub root: PathBuf,
+    pub http_addr: Option<String>,
+    pub df_paths: Vec<PathBuf>,
+    pub max_request_size: u64,
+    pub max_clients: usize,
+    pub max_bps: Option<u64>,
+    /// Largest upload accepted, from the config file
+    pub max_file_size: Option<u64>,
+    pub users_file: Option<PathBuf>,
+    pub credentials: Option<Credentials>,
+    /// Set when the server was started with a certificate; every connection is then TLS
+    pub tls: Option<Arc<TlsConfig>>,
+    pub access_log_file: Option<PathBuf>,
+    pub access_log: Option<AccessLogger>,
+}
+
+impl TcpServerOptions {
+    /// Parses `args` (without the program name), reads the config file they name and loads
+    /// the files the options point to. The error is a message for the user.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut http_addr = None;
+        let mut df_paths = Vec::new();
+        let mut max_request_size = MAX_MESSAGE_SIZE as u64;
+        let mut max_clients = DEFAULT_MAX_CLIENTS;
+        let mut users_file = None;
+        let mut tls_cert = None;
+        let mut tls_key = None;
+        let mut max_bps = None;
+        let mut config_file = None;
+        let mut access_log_file = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => config_file = args.next().map(PathBuf::from),
+                "--http-addr" => http_addr = args.next(),
+                "--df-path" => df_paths.extend(args.next().map(PathBuf::from)),
+                "--users" => users_file = args.next().map(PathBuf::from),
+                "--access-log" => access_log_file = args.next().map(PathBuf::from),
+                "--tls-cert" => tls_cert = args.next().map(PathBuf::from),
+                "--tls-key" => tls_key = args.next().map(PathBuf::from),
+                "--max-request-size" => match args.next().and_then(|v| v.parse::<u64>().ok()) {
+                    // the bincode limit still applies on top of this knob
+                    Some(bytes) if bytes > 0 => {
+                        max_request_size = bytes.min(MAX_MESSAGE_SIZE as u64)
+                    }
+                    _ => return Err("--max-request-size requires a positive byte count".into()),
+                },
+                "--max-bps" => match args.next().and_then(|v| v.parse::<u64>().ok()) {
+                    Some(bps) if bps > 0 => max_bps = Some(bps),
+                    _ => return Err("--max-bps requires a positive byte rate".into()),
+                },
+                "--max-clients" => match args.next().and_then(|v| v.parse::<usize>().ok()) {
+                    Some(n) if n > 0 => max_clients = n,
+                    _ => return Err("--max-clients requires a positive number".into()),
+                },
+                _ => positional.push(arg),
+            }
+        }
+        let config = config_file
+            .as_deref()
+            .map(ServerConfig::load)
+            .transpose()?
+            .unwrap_or_default();
+        // positional arguments override the config file
+        let (addr, root) = match (positional.as_slice(), config.addr, config.root) {
+            ([addr, root], _, _) => (addr.clone(), PathBuf::from(root)),
+            ([], Some(addr), Some(root)) => (addr, root),
+            _ => {
+                return Err(
+                    "Usage: server [<addr:port> <root_dir>] [--config <file.yaml>] [--http-addr <addr:port>] [--df-path <dir>]... [--max-request-size <bytes>] [--max-clients <n>] [--max-bps <bytes>] [--users <file>] [--tls-cert <pem> --tls-key <pem>] [--access-log <file>]".into(),
+                );
+            }
+        };
+        validate_root(&root)?;
+        // every path a client names is canonicalized and compared against this canonical root
+        let root = fs::canonicalize(&root)
+            .map_err(|e| format!("cannot use root {}: {}", root.display(), e))?;
+        let credentials = users_file.as_deref().map(Credentials::load).transpose()?;
+        let tls = match (&tls_cert, &tls_key) {
+            (Some(cert), Some(key)) => Some(server_config(cert, key)?),
+            (None, None) => None,
+            _ => return Err("--tls-cert and --tls-key must be given together".into()),
+        };
+        let access_log = access_log_file
+            .as_deref()
+            .map(AccessLogger::open)
+            .transpose()?;
+        Ok(Self {
+            addr,
+            root,
+            http_addr,
+            df_paths,
+            max_request_size,
+            max_clients,
+            max_bps,
+            max_file_size: config.max_file_size,
+            users_file,
+            credentials,
+            tls,
+            access_log_file,
+            access_log,
+        })
+    }
+}
+
+/// Serves clients on `listener`, which is switched to non-blocking so the accept loop can
+/// poll `shutdown`. Once that is set, clients finish their current request, the workers
+/// exit and this returns.
+pub fn run_tcp_server(
+    listener: TcpListener,
+    options: TcpServerOptions,
+    shutdown: Arc<AtomicBool>,
+) -> std::io::Result<()> {
+    let metrics = Arc::new(ServerMetrics::default());
+    if let Some(http_addr) = &options.http_addr {
+        spawn_http_endpoint(http_addr, Arc::clone(&metrics))?;
+        println!("Health/metrics endpoint listening on http://{}", http_addr);
+    }
+    listener.set_nonblocking(true)?;
+
+    // a fixed pool of workers serves clients concurrently; once every worker is busy,
+    // accepted connections queue here, and once the queue is full they are turned away
+    let max_clients = options.max_clients;
+    let (conn_tx, conn_rx) = mpsc::sync_channel::<(TcpStream, SocketAddr)>(max_clients);
+    let server = Server {
+        root: options.root,
+        metrics,
+        uploads: UploadSessions::default(),
+        credentials: options.credentials,
+        tls: options.tls,
+        df_paths: options.df_paths,
+        max_request_size: options.max_request_size,
+        throttle: options.max_bps.map(Throttle::new),
+        max_file_size: options.max_file_size,
+        access_log: options.access_log,
+        shutdown,
+    };
+    let conn_rx = Mutex::new(conn_rx);
+    std::thread::scope(|s| {
+        for _ in 0..max_clients {
+            s.spawn(|| {
+                loop {
+                    // the lock is only held while waiting, never while serving a client
+                    let next = conn_rx.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                    let Ok((stream, peer_addr)) = next else {
+                        break;
+                    };
+                    serve_client(stream, peer_addr, &server);
+                }
+            });
+        }
+
+        while !server.shutdown.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, peer_addr)) => {
+                    // workers are winding down; a connection that raced the Ctrl-C is dropped
+                    // rather than queued behind them
+                    if server.shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    // accepted sockets may inherit the listener's non-blocking mode
+                    if let Err(e) = stream.set_nonblocking(false) {
+                        eprintln!("Cannot serve {}: {}", peer_addr, e);
+                        continue;
+                    }
+                    match conn_tx.try_send((stream, peer_addr)) {
+                        Ok(()) => {}
+                        Err(TrySendError::Full((stream, peer_addr))) => {
+                            reject_busy(stream, peer_addr, &server)
+                        }
+                        Err(TrySendError::Disconnected(_)) => break,
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(SHUTDOWN_POLL_INTERVAL);
+                }
+                Err(e) => {
+                    eprintln!("Accept error: {:?}", e);
+                }
+            }
+        }
+        // workers drain the queue, then find the channel closed and exit
+        drop(conn_tx);
+    });
+
+    // interrupted uploads can only be resumed by this process, so their partial files go
+    server.uploads.discard_all();
+    println!("Shutdown completed");
+    Ok(())
+}
+
+/// Turns away a connection that arrived while every worker is busy and the queue is full.
+/// Over TLS the error cannot be sent before a handshake, so the connection is just closed.
+fn reject_busy(mut stream: TcpStream, peer_addr: SocketAddr, server: &Server) {
+    println!(
+        "Connection attempt from {} rejected (server busy)",
+        peer_addr
+    );
+    if server.tls.is_none() {
+        let _ = write_frame(
+            &mut stream,
+            &Response::Error(ShellError::Busy(
+                "Server busy: too many clients are connected".to_string(),
+            )),
+        );
+    }
+}
+
+/// Runs `handle_client` for one connection on a pool worker. A panicking handler only drops
+/// its own client; the worker survives to serve the next one.
+fn serve_client(stream: TcpStream, peer_addr: SocketAddr, server: &Server) {
+    let metrics = &server.metrics;
+    println!("Client connected: {}", peer_addr);
+    metrics.session_opened();
+
+    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        handle_client(
+            Transport::accept(stream, server.tls.as_ref())?,
+            peer_addr,
+            server,
+            &mut LocalFs::new(server.root.clone()),
+        )
+    }));
+    match result {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("Client handler error for {}: {:?}", peer_addr, e),
+        Err(_) => eprintln!("Client handler for {} panicked", peer_addr),
+    }
+
+    println!("Client disconnected: {}", peer_addr);
+    metrics.session_closed();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::InMemoryFs;
+    use std::io::Cursor;
+
+    /// A connection whose client side has already sent `input`.
+    struct FakeStream {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl FakeStream {
+        fn sending(input: &[u8]) -> Self {
+            Self {
+                input: Cursor::new(input.to_vec()),
+                output: Vec::new(),
+            }
+        }
+    }
+
+    impl Read for FakeStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for FakeStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn conn_metrics(metrics: &ServerMetrics) -> ConnMetrics<'_> {
+        ConnMetrics {
+            metrics,
+            access_log: None,
+            peer_addr: SocketAddr::from(([127, 0, 0, 1], 40000)),
+            pending: Cell::new(None),
+        }
+    }
+
+    fn memory_fs_with(path: &str, data: &[u8]) -> InMemoryFs {
+        let mut fs = InMemoryFs::new();
+        fs.create_write(path).unwrap().write_all(data).unwrap();
+        fs
+    }
+
+    fn read_back(fs: &InMemoryFs, path: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        fs.open_read(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    /// Splits what the server wrote into the replies it sent and the raw bytes after them.
+    fn replies(output: &[u8], count: usize) -> (Vec<Response>, &[u8]) {
+        let mut reader = output;
+        let replies = (0..count)
+            .map(|_| decode_from_std_read(&mut reader, wire_config()).unwrap())
+            .collect();
+        (replies, reader)
+    }
+
+    #[test]
+    fn download_reads_through_the_backend() {
+        let data = b"in-memory contents";
+        let fs = memory_fs_with("notes.txt", data);
+        let metrics = ServerMetrics::default();
+        let mut out = Vec::new();
+
+        send_download(
+            &mut out,
+            &conn_metrics(&metrics),
+            &fs,
+            "notes.txt",
+            3,
+            false,
+            None,
+        )
+        .unwrap();
+
+        let (replies, rest) = replies(&out, 1);
+        let expected_sha256: [u8; 32] = Sha256::digest(data).into();
+        assert!(matches!(
+            &replies[0],
+            Response::FileMetadata { name, size: Some(18), offset: 3, sha256: Some(sha256) }
+                if name == "notes.txt" && *sha256 == expected_sha256
+        ));
+        assert_eq!(rest, &data[3..]);
+    }
+
+    #[test]
+    fn download_of_a_missing_file_is_an_error_reply() {
+        let fs = InMemoryFs::new();
+        let metrics = ServerMetrics::default();
+        let mut out = Vec::new();
+
+        send_download(
+            &mut out,
+            &conn_metrics(&metrics),
+            &fs,
+            "gone.txt",
+            0,
+            false,
+            None,
+        )
+        .unwrap();
+
+        let (replies, rest) = replies(&out, 1);
+        assert!(matches!(
+            replies[0],
+            Response::Error(ShellError::NotFound(_))
+        ));
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn upload_writes_through_the_backend() {
+        let data = b"uploaded bytes";
+        let mut fs = InMemoryFs::new();
+        let metrics = ServerMetrics::default();
+        let mut stream = FakeStream::sending(data);
+        let sha256 = Some(Sha256::digest(data).into());
+
+        receive_backend_upload(
+            &mut stream,
+            &conn_metrics(&metrics),
+            &mut fs,
+            "up.bin",
+            data.len() as u64,
+            sha256,
+            false,
+        )
+        .unwrap();
+
+        let (replies, _) = replies(&stream.output, 2);
+        assert!(matches!(
+            replies[0],
+            Response::UploadSession { id: 0, received: 0 }
+        ));
+        assert!(matches!(
+            replies[1],
+            Response::UploadComplete { total_bytes: 14 }
+        ));
+        assert_eq!(read_back(&fs, "up.bin"), data);
+    }
+
+    #[test]
+    fn upload_failing_its_checksum_is_removed() {
+        let mut fs = InMemoryFs::new();
+        let metrics = ServerMetrics::default();
+        let mut stream = FakeStream::sending(b"tampered");
+
+        receive_backend_upload(
+            &mut stream,
+            &conn_metrics(&metrics),
+            &mut fs,
+            "up.bin",
+            8,
+            Some([0; 32]),
+            false,
+        )
+        .unwrap();
+
+        let (replies, _) = replies(&stream.output, 2);
+        assert!(matches!(
+            replies[1],
+            Response::Error(ShellError::ChecksumMismatch(_))
+        ));
+        assert!(fs.open_read("up.bin").is_err());
+    }
+
+    #[test]
+    fn upload_cut_short_is_removed() {
+        let mut fs = InMemoryFs::new();
+        let metrics = ServerMetrics::default();
+        let mut stream = FakeStream::sending(b"only part");
+
+        let result = receive_backend_upload(
+            &mut stream,
+            &conn_metrics(&metrics),
+            &mut fs,
+            "up.bin",
+            100,
+            None,
+            false,
+        );
+
+        assert!(result.is_err());
+        assert!(fs.open_read("up.bin").is_err());
+    }
+
+    #[test]
+    fn requests_needing_a_local_tree_are_refused_by_other_backends() {
+        let mut fs = InMemoryFs::new();
+        fs.make_dir("docs").unwrap();
+
+        assert!(matches!(
+            handle_fs_request(
+                &mut fs,
+                Request::Cd {
+                    path: "docs".to_string()
+                }
+            ),
+            Response::Ok
+        ));
+        assert!(matches!(
+            handle_fs_request(
+                &mut fs,
+                Request::Stat {
+                    path: ".".to_string()
+                }
+            ),
+            Response::Error(ShellError::InvalidRequest(_))
+        ));
+    }
+}
@@ -0,0 +1,241 @@
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, ServerConfig,
+    ServerConnection, SignatureScheme, StreamOwned,
+};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A TCP connection, either plain or wrapped in TLS. Reads and writes go through TLS when it
+/// is enabled; socket options are set on the underlying stream from `tcp()`.
+pub enum Transport {
+    Plain(TcpStream),
+    Server(Box<StreamOwned<ServerConnection, TcpStream>>),
+    Client(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Transport {
+    /// Wraps an accepted connection; the handshake runs on the first read or write.
+    pub fn accept(stream: TcpStream, config: Option<&Arc<ServerConfig>>) -> io::Result<Self> {
+        match config {
+            Some(config) => {
+                let conn = ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+                Ok(Transport::Server(Box::new(StreamOwned::new(conn, stream))))
+            }
+            None => Ok(Transport::Plain(stream)),
+        }
+    }
+
+    /// Wraps an outgoing connection to `host`, the name the server's certificate must carry.
+    /// The handshake is completed here, so a rejected certificate fails the connect rather
+    /// than the first request.
+    pub fn connect(
+        stream: TcpStream,
+        host: &str,
+        config: Option<&Arc<ClientConfig>>,
+    ) -> io::Result<Self> {
+        match config {
+            Some(config) => {
+                let name = ServerName::try_from(host.to_string())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                let conn =
+                    ClientConnection::new(Arc::clone(config), name).map_err(io::Error::other)?;
+                let mut tls = StreamOwned::new(conn, stream);
+                while tls.conn.is_handshaking() {
+                    tls.conn.complete_io(&mut tls.sock)?;
+                }
+                Ok(Transport::Client(Box::new(tls)))
+            }
+            None => Ok(Transport::Plain(stream)),
+        }
+    }
+
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            Transport::Plain(stream) => stream,
+            Transport::Server(tls) => tls.get_ref(),
+            Transport::Client(tls) => tls.get_ref(),
+        }
+    }
+
+    /// Waits up to `timeout` for the peer to send something, returning whether a read would
+    /// now make progress. A closed connection counts as readable, since the read returns at
+    /// once. Data TLS has already decrypted is checked first, as the socket may be empty.
+    pub fn wait_readable(&mut self, timeout: Duration) -> io::Result<bool> {
+        let buffered = match self {
+            Transport::Plain(_) => Ok(0),
+            Transport::Server(tls) => tls
+                .conn
+                .process_new_packets()
+                .map(|state| state.plaintext_bytes_to_read()),
+            Transport::Client(tls) => tls
+                .conn
+                .process_new_packets()
+                .map(|state| state.plaintext_bytes_to_read()),
+        };
+        // a TLS error is left for the next read to report
+        if buffered.is_err() || buffered.is_ok_and(|n| n > 0) {
+            return Ok(true);
+        }
+
+        let tcp = self.tcp();
+        let previous = tcp.read_timeout()?;
+        tcp.set_read_timeout(Some(timeout))?;
+        let peeked = tcp.peek(&mut [0u8; 1]);
+        tcp.set_read_timeout(previous)?;
+        match peeked {
+            Ok(_) => Ok(true),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Closes the connection, first telling a TLS peer that no truncation took place.
+    pub fn shutdown(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Plain(_) => {}
+            Transport::Server(tls) => {
+                tls.conn.send_close_notify();
+                tls.flush()?;
+            }
+            Transport::Client(tls) => {
+                tls.conn.send_close_notify();
+                tls.flush()?;
+            }
+        }
+        self.tcp().shutdown(Shutdown::Both)
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.read(buf),
+            Transport::Server(tls) => tls.read(buf),
+            Transport::Client(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Transport::Plain(stream) => stream.write(buf),
+            Transport::Server(tls) => tls.write(buf),
+            Transport::Client(tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Transport::Plain(stream) => stream.flush(),
+            Transport::Server(tls) => tls.flush(),
+            Transport::Client(tls) => tls.flush(),
+        }
+    }
+}
+
+/// Server side: the PEM certificate chain and private key the server presents.
+pub fn server_config(cert: &Path, key: &Path) -> Result<Arc<ServerConfig>, String> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("cannot read certificate {}: {}", cert.display(), e))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("cannot read private key {}: {}", key.display(), e))?;
+    let config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("invalid certificate or key: {}", e))?;
+    Ok(Arc::new(config))
+}
+
+/// Client side: trusts the certificates in the PEM file `ca`, or, with `insecure`, any
+/// certificate at all, which keeps the traffic private but not the server's identity.
+pub fn client_config(ca: Option<&Path>, insecure: bool) -> Result<Arc<ClientConfig>, String> {
+    let builder = ClientConfig::builder();
+    let config = if insecure {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCert(provider)))
+            .with_no_client_auth()
+    } else {
+        let ca = ca.ok_or("TLS needs a CA certificate or --insecure")?;
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(ca)
+            .map_err(|e| format!("cannot read CA {}: {}", ca.display(), e))?
+        {
+            let cert = cert.map_err(|e| format!("cannot read CA {}: {}", ca.display(), e))?;
+            roots
+                .add(cert)
+                .map_err(|e| format!("invalid CA certificate {}: {}", ca.display(), e))?;
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+    Ok(Arc::new(config))
+}
+
+/// Certificate verifier for `--insecure`: any certificate is accepted, but handshake
+/// signatures are still checked so the session keys are genuine.
+#[derive(Debug)]
+struct AcceptAnyCert(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
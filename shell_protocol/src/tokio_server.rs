@@ -0,0 +1,391 @@
+use crate::capacity::{df_report, prepare_upload_dir};
+use crate::du::disk_usage;
+use crate::find::find_paths;
+use crate::framing::{read_frame_async, write_frame_async};
+use crate::hash::{hash_file, sha256_file, to_hex};
+use crate::link::hard_link;
+use crate::mktemp::mktemp;
+use crate::paths::{resolve_dir, resolve_existing, resolve_new};
+use crate::remove::remove_file;
+use crate::rename::move_path;
+use crate::settime::set_mtime;
+use crate::stat::stat_path;
+use crate::swap::swap_paths;
+use crate::upload_session::UploadSession;
+use crate::{
+    DirEntry, MAX_MESSAGE_SIZE, PROTOCOL_VERSION, Request, Response, ShellError,
+    create_upload_file, discard_upload,
+};
+use sha2::{Digest, Sha256};
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Optional features reported in reply to `Request::Capabilities`.
+const FEATURES: &[&str] = &[
+    "find", "du", "hash", "settime", "stat", "link", "swap", "delete", "move", "df", "mktemp",
+    "echo",
+];
+
+fn capabilities() -> Response {
+    Response::Capabilities {
+        proto_version: PROTOCOL_VERSION,
+        features: FEATURES.iter().map(|f| f.to_string()).collect(),
+    }
+}
+
+/// Staging path used for atomic uploads: the destination with a `.part` suffix appended.
+fn staging_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_os_string();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+async fn read_dir(cwd: &Path) -> Response {
+    let mut entries = match fs::read_dir(cwd).await {
+        Ok(entries) => entries,
+        Err(e) => return Response::Error(ShellError::io("read_dir failed", &e)),
+    };
+    let mut list = Vec::new();
+    while let Ok(Some(e)) = entries.next_entry().await {
+        let name = e.file_name().to_string_lossy().to_string();
+        let is_dir = e.file_type().await.map(|t| t.is_dir()).unwrap_or(false);
+        list.push(DirEntry { name, is_dir });
+    }
+    Response::DirList(list)
+}
+
+/// Requests served by the same blocking helpers as the threaded server. They run on tokio's
+/// blocking pool, so a slow walk or hash does not hold up other connections.
+async fn run_blocking(root: &Path, cwd: &Path, req: Request) -> Response {
+    let (root, cwd) = (root.to_path_buf(), cwd.to_path_buf());
+    tokio::task::spawn_blocking(move || handle_local_request(&root, &cwd, req))
+        .await
+        .unwrap_or_else(|e| Response::Error(ShellError::Io(format!("request failed: {}", e))))
+}
+
+fn handle_local_request(root: &Path, cwd: &Path, req: Request) -> Response {
+    match req {
+        Request::MkTemp { prefix, suffix } => mktemp(cwd, &prefix, &suffix),
+        Request::SetTime { path, mtime_secs } => set_mtime(root, cwd, &path, mtime_secs),
+        Request::Stat { path } => stat_path(root, cwd, &path),
+        Request::Link { target, link } => hard_link(root, cwd, &target, &link),
+        Request::Swap { a, b } => swap_paths(root, cwd, &a, &b),
+        Request::Delete { path } => remove_file(root, cwd, &path),
+        Request::Move { src, dst } => move_path(root, cwd, &src, &dst),
+        Request::Find { pattern, max_depth } => find_paths(root, cwd, &pattern, max_depth),
+        Request::DiskUsage { path } => disk_usage(root, cwd, &path),
+        Request::Hash { path, algo } => match resolve_existing(root, cwd, &path) {
+            Ok(full) => match hash_file(&full, algo) {
+                Ok((_, digest)) => Response::Hash { digest },
+                Err(e) => Response::Error(ShellError::io("hash failed", &e)),
+            },
+            Err(e) => Response::Error(e),
+        },
+        Request::Df => df_report(root, &[]),
+        other => Response::Error(ShellError::invalid(format!(
+            "{} is not supported by the async server",
+            other.name()
+        ))),
+    }
+}
+
+/// Streams exactly `size` raw bytes from the client into `f`, returning their SHA-256.
+async fn receive_file(stream: &mut TcpStream, f: &mut File, size: u64) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let mut remaining = size;
+    let mut buf = [0u8; 8192];
+    while remaining > 0 {
+        let to_read = std::cmp::min(buf.len() as u64, remaining) as usize;
+        let n = stream.read(&mut buf[..to_read]).await?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "EOF during file upload",
+            ));
+        }
+        f.write_all(&buf[..n]).await?;
+        hasher.update(&buf[..n]);
+        remaining -= n as u64;
+    }
+    f.flush().await?;
+    Ok(hasher.finalize().into())
+}
+
+/// Receives the data of an accepted upload, checks its digest and publishes the file. There
+/// is no resuming here, so an upload cut off midway is discarded.
+async fn receive_upload(
+    stream: &mut TcpStream,
+    session: UploadSession,
+    mut f: File,
+) -> io::Result<Response> {
+    write_frame_async(stream, &Response::UploadSession { id: 0, received: 0 }).await?;
+
+    // uploads of unknown size are refused before they get here
+    let size = session.size.unwrap_or_default();
+    let digest = match receive_file(stream, &mut f, size).await {
+        Ok(digest) => digest,
+        Err(e) => {
+            drop(f);
+            discard_upload(&session.write_path, session.appended_to);
+            eprintln!("Upload of {} interrupted; discarded", session.file_name);
+            return Err(e);
+        }
+    };
+    drop(f);
+
+    if session.sha256.is_some_and(|expected| expected != digest) {
+        // corrupted data must not be left in place looking complete
+        discard_upload(&session.write_path, session.appended_to);
+        eprintln!("Upload of {} failed its checksum", session.file_name);
+        return Ok(Response::Error(ShellError::ChecksumMismatch(format!(
+            "Checksum mismatch: received data hashes to {}",
+            to_hex(&digest)
+        ))));
+    }
+
+    if session.atomic {
+        fs::rename(&session.write_path, &session.dest).await?;
+    }
+    println!(
+        "Uploaded file {} to {}",
+        session.file_name,
+        session.dest.display()
+    );
+    Ok(Response::UploadComplete { total_bytes: size })
+}
+
+/// Sends `FileMetadata` and then the file from `offset` on as raw bytes, or the error that
+/// stopped it before anything was sent.
+async fn send_download(
+    stream: &mut TcpStream,
+    root: &Path,
+    cwd: &Path,
+    src_path: &str,
+    offset: u64,
+) -> io::Result<Option<Response>> {
+    let full = match resolve_existing(root, cwd, src_path) {
+        Ok(full) if full.is_dir() => {
+            return Ok(Some(Response::Error(ShellError::invalid(
+                "path is a directory; use download-zip over the threaded server",
+            ))));
+        }
+        Ok(full) => full,
+        Err(e) => return Ok(Some(Response::Error(e))),
+    };
+    let mut f = match File::open(&full).await {
+        Ok(f) => f,
+        Err(e) => return Ok(Some(Response::Error(ShellError::io("Open failed", &e)))),
+    };
+    let size = f.metadata().await?.len();
+    // named as requested, so a symlink keeps its own name
+    let name = Path::new(src_path)
+        .file_name()
+        .or(full.file_name())
+        .and_then(|os| os.to_str())
+        .unwrap_or("file")
+        .to_string();
+    if offset > size {
+        return Ok(Some(Response::Error(ShellError::invalid(format!(
+            "Offset {} is past the end of {} ({} bytes)",
+            offset, name, size
+        )))));
+    }
+
+    // hashed in a pass of its own, since the digest has to lead the data
+    let sha256 = tokio::task::spawn_blocking(move || sha256_file(&full).ok())
+        .await
+        .ok()
+        .flatten();
+    f.seek(SeekFrom::Start(offset)).await?;
+    let metadata = Response::FileMetadata {
+        name: name.clone(),
+        size: Some(size),
+        offset,
+        sha256,
+    };
+    write_frame_async(stream, &metadata).await?;
+    let bytes_sent = tokio::io::copy(&mut f, stream).await?;
+    println!("Sent file {} ({} bytes)", name, bytes_sent);
+    Ok(None)
+}
+
+async fn handle_client(mut stream: TcpStream, root: Arc<PathBuf>) -> io::Result<()> {
+    let mut cwd = root.to_path_buf();
+
+    loop {
+        let req: Request = match read_frame_async(&mut stream, MAX_MESSAGE_SIZE as u64).await {
+            Ok(req) => req,
+            // an oversized or undecodable frame: report it and drop the connection
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                eprintln!("Bad request frame: {}", e);
+                let resp = Response::Error(ShellError::invalid(e.to_string()));
+                write_frame_async(&mut stream, &resp).await?;
+                break;
+            }
+            // assume connection closed → exit
+            Err(_) => break,
+        };
+
+        let resp = match req {
+            Request::Capabilities => capabilities(),
+
+            // nothing to log in to; accept so clients need not know in advance
+            Request::Auth { .. } => Response::Ok,
+
+            Request::Dir => read_dir(&cwd).await,
+
+            Request::CdUp => match resolve_dir(&root, &cwd, "..") {
+                Ok(dir) => {
+                    cwd = dir;
+                    Response::Ok
+                }
+                Err(e) => Response::Error(e),
+            },
+
+            Request::Cd { path } => match resolve_dir(&root, &cwd, &path) {
+                Ok(dir) => {
+                    cwd = dir;
+                    Response::Ok
+                }
+                Err(e) => Response::Error(e),
+            },
+
+            Request::Mkdir { name } => match resolve_new(&root, &cwd, Path::new(&name)) {
+                Ok(new) => match fs::create_dir(&new).await {
+                    Ok(()) => Response::Ok,
+                    Err(e) => Response::Error(ShellError::io("mkdir failed", &e)),
+                },
+                Err(e) => Response::Error(e),
+            },
+
+            Request::Copy { src, dst } => {
+                let paths = resolve_existing(&root, &cwd, &src)
+                    .and_then(|src| Ok((src, resolve_new(&root, &cwd, Path::new(&dst))?)));
+                match paths {
+                    Ok((src, dst)) => match fs::copy(&src, &dst).await {
+                        Ok(bytes_copied) => Response::CopyResult { bytes_copied },
+                        Err(e) => Response::Error(ShellError::io("copy failed", &e)),
+                    },
+                    Err(e) => Response::Error(e),
+                }
+            }
+
+            Request::Echo { payload } => Response::Echo { payload },
+
+            Request::Upload { compressed, .. } | Request::Download { compressed, .. }
+                if compressed =>
+            {
+                Response::Error(ShellError::invalid(
+                    "Compressed transfers are not supported by the async server",
+                ))
+            }
+
+            Request::Upload { size: None, .. } => Response::Error(ShellError::invalid(
+                "Uploads of unknown size are not supported by the async server",
+            )),
+
+            Request::Upload { atomic, append, .. } if atomic && append => Response::Error(
+                ShellError::invalid("An upload cannot both append and be atomic"),
+            ),
+
+            Request::Upload {
+                dst_path,
+                file_name,
+                size: Some(size),
+                atomic,
+                overwrite,
+                append,
+                sha256,
+                ..
+            } => {
+                let rel = if dst_path == "." || dst_path.is_empty() {
+                    PathBuf::from(&file_name)
+                } else {
+                    Path::new(&dst_path).join(&file_name)
+                };
+                let dest = resolve_new(&root, &cwd, &rel).and_then(|dest| {
+                    if let Some(parent) = dest.parent() {
+                        prepare_upload_dir(parent, size)?;
+                    }
+                    Ok(dest)
+                });
+                // atomic uploads are written to a staging file and renamed once complete
+                let opened = dest.and_then(|dest| {
+                    let write_path = if atomic {
+                        staging_path(&dest)
+                    } else {
+                        dest.clone()
+                    };
+                    let (f, appended_to) =
+                        create_upload_file(&dest, &write_path, overwrite, append)?;
+                    let session = UploadSession {
+                        dest,
+                        write_path,
+                        file_name,
+                        size: Some(size),
+                        atomic,
+                        appended_to,
+                        sha256,
+                    };
+                    Ok((session, File::from_std(f)))
+                });
+                match opened {
+                    Ok((session, f)) => receive_upload(&mut stream, session, f).await?,
+                    Err(e) => Response::Error(e),
+                }
+            }
+
+            Request::Download {
+                src_path, offset, ..
+            } => match send_download(&mut stream, &root, &cwd, &src_path, offset).await? {
+                Some(resp) => resp,
+                None => continue,
+            },
+
+            other => run_blocking(&root, &cwd, other).await,
+        };
+        write_frame_async(&mut stream, &resp).await?;
+    }
+
+    Ok(())
+}
+
+/// Serves every client on `listener` until `shutdown` completes, each connection as a task
+/// of its own however many clients there are. `root` must already be canonical.
+pub async fn run_tokio_server(
+    listener: TcpListener,
+    root: PathBuf,
+    shutdown: impl Future<Output = ()>,
+) {
+    let root = Arc::new(root);
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer_addr) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        eprintln!("Accept error: {:?}", e);
+                        continue;
+                    }
+                };
+                println!("Client connected: {}", peer_addr);
+                let root = Arc::clone(&root);
+                tokio::spawn(async move {
+                    if let Err(e) = handle_client(stream, root).await {
+                        eprintln!("Client handler error for {}: {:?}", peer_addr, e);
+                    }
+                    println!("Client disconnected: {}", peer_addr);
+                });
+            }
+            _ = &mut shutdown => {
+                println!("Shutting down");
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,1374 @@
+use crate::access_log::AccessLogger;
+use crate::auth::Credentials;
+use crate::capacity::{df_report, prepare_upload_dir};
+use crate::compress::{COMPRESSION_SLACK, compress_chunk, decompress_chunk};
+use crate::config::ServerConfig;
+use crate::hash::{HashAlgo, hash_file, sha256_reader, to_hex};
+use crate::link::hard_link;
+use crate::metrics::{ServerMetrics, spawn_http_endpoint};
+use crate::mktemp::mktemp;
+use crate::paths::{resolve_existing, resolve_new};
+use crate::rate_limit::Throttle;
+use crate::remove::remove_file;
+use crate::rename::move_path;
+use crate::settime::set_mtime;
+use crate::stat::stat_path;
+use crate::storage::{LocalFs, ReadSeek, StorageBackend, require_local_paths};
+use crate::swap::swap_paths;
+use crate::{
+    CHUNK_OVERHEAD, MAX_MESSAGE_SIZE, PROTOCOL_VERSION, Request, Response, ShellError,
+    create_upload_file, discard_upload, validate_root, wire_config,
+};
+use bincode::{decode_from_slice, encode_to_vec};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const MAX_PACKET_SIZE: usize = 65507; // Maximum UDP packet size
+const MAX_PAYLOAD_SIZE: usize = 65000; // Leave room for headers
+const UPLOAD_STALE_SECS: u64 = 30; // Idle time after which an unfinished upload may be replaced
+const MAX_PENDING_CHUNKS: usize = 256; // Out-of-order upload chunks held per session
+/// Optional features reported in reply to `Request::Capabilities`.
+const FEATURES: &[&str] = &[
+    "compress",
+    "hash",
+    "settime",
+    "stat",
+    "link",
+    "swap",
+    "delete",
+    "move",
+    "df",
+    "mktemp",
+    "echo",
+    "idempotent",
+    "hello",
+];
+const REPLAY_WINDOW_SECS: u64 = 30; // How long responses to tagged requests are kept for replay
+/// Longest the receive loop waits for a datagram, so idle sessions expire without traffic
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Per-client state. A session runs at most one upload and one download at a time; the
+/// recv loop is single-threaded, so transfer state is only ever touched by one request.
+struct ClientSession {
+    storage: Box<dyn StorageBackend>,
+    last_activity: u64,
+    upload_file: Option<UploadState>,
+    download_file: Option<DownloadState>,
+    /// Responses to `Idempotent` requests by request id, with the time they were produced
+    replies: HashMap<u64, (Response, u64)>,
+    /// Last chunk id and reply of the most recently finished upload, repeated if the client
+    /// resends that chunk because the reply was lost
+    finished_upload: Option<(u32, Response)>,
+    /// Set once the client has logged in, or from the start if no credentials are configured
+    authenticated: bool,
+    /// Data bytes per chunk for new transfers: the configured `chunk_size` until the client
+    /// negotiates its own with `Hello`
+    chunk_size: usize,
+}
+
+#[derive(Debug)]
+struct UploadState {
+    file: File,
+    file_path: PathBuf,
+    /// Final destination for atomic uploads; `file_path` is then the `.part` staging file.
+    publish_path: Option<PathBuf>,
+    /// For an append, the length `file_path` had before the upload
+    appended_to: Option<u64>,
+    expected_size: u64,
+    received_bytes: u64,
+    /// Id of the next chunk to write; lower ids are retransmissions and only re-acked
+    next_chunk: u32,
+    /// Chunks that arrived ahead of `next_chunk`, held until the gap before them is filled
+    pending: BTreeMap<u32, Vec<u8>>,
+    /// Id of the chunk marked `is_last`, once it has arrived
+    last_chunk: Option<u32>,
+    /// Whether each chunk's data arrives deflated
+    compressed: bool,
+    /// Digest the client declared, checked once the last chunk is in
+    expected_sha256: Option<[u8; 32]>,
+    /// Running SHA-256 of the chunks received so far
+    hasher: Sha256,
+    /// When the upload was started or last received a chunk
+    last_chunk_at: u64,
+}
+
+struct DownloadState {
+    file: Box<dyn ReadSeek>,
+    file_name: String,
+    file_size: u64,
+    /// The session's chunk size when the download started, which a later `Hello` leaves alone
+    chunk_size: usize,
+    sent_chunks: u32,
+    /// Whether each chunk's data is deflated before it is sent
+    compressed: bool,
+}
+
+/// Staging path used for atomic uploads: the destination with a `.part` suffix appended.
+fn staging_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_os_string();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+fn get_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+/// Forgets replies older than the replay window. Replies stamped after `now`, left by a
+/// wall clock stepped backwards, are kept.
+fn expire_replies(replies: &mut HashMap<u64, (Response, u64)>, now: u64) {
+    replies.retain(|_, (_, at)| now.saturating_sub(*at) < REPLAY_WINDOW_SECS);
+}
+
+/// The chunk size granted to a `Hello` asking for `preferred`: at least one byte, and small
+/// enough that a deflated chunk still fits in its `UploadChunk` under `max_request_size` and
+/// in its `FileChunk` under `MAX_PAYLOAD_SIZE`.
+fn negotiate_chunk_size(preferred: u32, max_request_size: u64) -> usize {
+    let datagram = max_request_size.min(MAX_PAYLOAD_SIZE as u64) as usize;
+    let largest = datagram
+        .saturating_sub(CHUNK_OVERHEAD + COMPRESSION_SLACK)
+        .max(1);
+    (preferred as usize).clamp(1, largest)
+}
+
+/// Drops the sessions idle for `timeout` seconds or more. An unfinished upload of an expired
+/// session is discarded like a failed one, since its client can no longer complete it, and
+/// each expiry is logged with the transfers it cut short.
+fn cleanup_sessions(sessions: &mut HashMap<String, ClientSession>, now: u64, timeout: u64) {
+    sessions.retain(|addr, session| {
+        if now.saturating_sub(session.last_activity) < timeout {
+            return true;
+        }
+
+        println!("Session {} expired after {}s idle", addr, timeout);
+        if let Some(upload) = session.upload_file.take() {
+            println!(
+                "Discarding unfinished upload to {} ({} of {} bytes)",
+                upload.file_path.display(),
+                upload.received_bytes,
+                upload.expected_size
+            );
+            drop(upload.file);
+            discard_upload(&upload.file_path, upload.appended_to);
+        }
+        if let Some(download) = session.download_file.take() {
+            println!(
+                "Abandoning download of {} ({} chunks sent)",
+                download.file_name, download.sent_chunks
+            );
+        }
+        false
+    });
+}
+
+/// Stores one chunk of the session's upload. Chunks may arrive in any order: one ahead of
+/// the next expected id waits in the reorder buffer, and each run that becomes contiguous
+/// with the file is written out. The upload finishes once every chunk up to the one marked
+/// `is_last` is written, and the chunk that completed it gets the final reply.
+fn handle_upload_chunk(
+    session: &mut ClientSession,
+    chunk_id: u32,
+    data: Vec<u8>,
+    crc32: u32,
+    is_last: bool,
+    now: u64,
+    metrics: &ServerMetrics,
+) -> Response {
+    let Some(upload) = session.upload_file.as_mut() else {
+        return match &session.finished_upload {
+            // the reply to the chunk that completed the upload was lost; repeat it
+            Some((last_chunk, resp)) if *last_chunk == chunk_id => {
+                println!("Repeating reply to final chunk {}", chunk_id);
+                resp.clone()
+            }
+            _ => Response::Error(ShellError::invalid("No active upload session")),
+        };
+    };
+    upload.last_chunk_at = now;
+
+    // a chunk damaged on the way is dropped rather than written; the nack has it sent again
+    if crc32fast::hash(&data) != crc32 {
+        eprintln!("Chunk {} failed its CRC-32, asking for it again", chunk_id);
+        return Response::ChunkNack { chunk_id };
+    }
+
+    if chunk_id < upload.next_chunk || upload.pending.contains_key(&chunk_id) {
+        // the ack for this chunk was lost, so it came again: ack without storing it twice
+        println!("Re-acking duplicate chunk {}", chunk_id);
+        return Response::ChunkAck { chunk_id };
+    }
+    if chunk_id > upload.next_chunk && upload.pending.len() >= MAX_PENDING_CHUNKS {
+        return Response::Error(ShellError::TooLarge(format!(
+            "Too many chunks buffered while waiting for chunk {}",
+            upload.next_chunk
+        )));
+    }
+    let data = if upload.compressed {
+        match decompress_chunk(&data, MAX_PACKET_SIZE) {
+            Ok(data) => data,
+            Err(e) => return Response::Error(ShellError::io(&format!("Chunk {}", chunk_id), &e)),
+        }
+    } else {
+        data
+    };
+    if is_last {
+        upload.last_chunk = Some(chunk_id);
+    }
+    upload.pending.insert(chunk_id, data);
+
+    while let Some(data) = upload.pending.remove(&upload.next_chunk) {
+        // a client that keeps sending past its declared size is cut off, so it cannot get
+        // around the server's upload limit by understating the size
+        if upload.received_bytes + data.len() as u64 > upload.expected_size {
+            let expected = upload.expected_size;
+            let upload = session.upload_file.take().unwrap();
+            eprintln!(
+                "Upload to {} sent more than its declared {} bytes",
+                upload.file_path.display(),
+                expected
+            );
+            drop(upload.file);
+            discard_upload(&upload.file_path, upload.appended_to);
+            return Response::Error(ShellError::TooLarge(format!(
+                "Upload exceeds its declared size of {} bytes",
+                expected
+            )));
+        }
+        if let Err(e) = upload.file.write_all(&data) {
+            eprintln!("Write error: {}", e);
+            session.upload_file = None;
+            return Response::Error(ShellError::io("Write error", &e));
+        }
+        upload.hasher.update(&data);
+        upload.received_bytes += data.len() as u64;
+        metrics.add_uploaded(data.len() as u64);
+        println!(
+            "Received chunk {} ({} bytes, total: {}/{})",
+            upload.next_chunk,
+            data.len(),
+            upload.received_bytes,
+            upload.expected_size
+        );
+        upload.next_chunk += 1;
+    }
+
+    if upload
+        .last_chunk
+        .is_some_and(|last_chunk| upload.next_chunk > last_chunk)
+    {
+        let upload = session.upload_file.take().unwrap();
+        let resp = finish_upload(upload);
+        session.finished_upload = Some((chunk_id, resp.clone()));
+        resp
+    } else {
+        Response::ChunkAck { chunk_id }
+    }
+}
+
+/// Opens `src_path` for a chunked download from the session's storage, returning the
+/// download's state and the `FileMetadata` reply announcing it.
+fn start_download(
+    storage: &dyn StorageBackend,
+    src_path: &str,
+    offset: u64,
+    compressed: bool,
+    chunk_size: usize,
+) -> Result<(DownloadState, Response), ShellError> {
+    // chunk ids are already absolute, so a lost chunk is simply asked for again
+    if offset != 0 {
+        return Err(ShellError::invalid(
+            "Resuming at an offset is only supported over TCP",
+        ));
+    }
+    let mut file = storage.open_read(src_path)?;
+    let read_error = |e: std::io::Error| ShellError::io("Read error", &e);
+    let size = file.seek(SeekFrom::End(0)).map_err(read_error)?;
+    file.seek(SeekFrom::Start(0)).map_err(read_error)?;
+    let sha256 = sha256_reader(&mut file).ok();
+    // named as requested, so a symlink keeps its own name
+    let name = Path::new(src_path)
+        .file_name()
+        .and_then(|os| os.to_str())
+        .unwrap_or("file")
+        .to_string();
+
+    println!("Starting download: {} ({} bytes)", name, size);
+    let download = DownloadState {
+        file,
+        file_name: name.clone(),
+        file_size: size,
+        chunk_size,
+        sent_chunks: 0,
+        compressed,
+    };
+    let resp = Response::FileMetadata {
+        name,
+        size: Some(size),
+        offset: 0,
+        sha256,
+    };
+    Ok((download, resp))
+}
+
+/// Reads chunk `chunk_id` of a download into its `FileChunk` reply. Chunks are served by id
+/// rather than in sequence, so a client can re-request a lost chunk or receive them out of
+/// order. Only a failed read is an `Err`, after which the download is dropped.
+fn download_chunk(
+    download: &mut DownloadState,
+    chunk_id: u32,
+    throttle: Option<&Throttle>,
+    metrics: &ServerMetrics,
+) -> std::io::Result<Response> {
+    let chunk_size = download.chunk_size;
+    let offset = chunk_id as u64 * chunk_size as u64;
+    if offset > download.file_size {
+        return Ok(Response::Error(ShellError::invalid(format!(
+            "Chunk {} is past the end of {}",
+            chunk_id, download.file_name
+        ))));
+    }
+    let mut buf = Vec::with_capacity(chunk_size);
+    download.file.seek(SeekFrom::Start(offset))?;
+    let n = (&mut download.file)
+        .take(chunk_size as u64)
+        .read_to_end(&mut buf)?;
+
+    // pacing the replies paces the client's requests too
+    if let Some(throttle) = throttle {
+        throttle.wait_for(n as u64);
+    }
+    metrics.add_downloaded(n as u64);
+    let is_last = offset + n as u64 >= download.file_size;
+    println!(
+        "Sending chunk {} ({} bytes, last: {})",
+        chunk_id, n, is_last
+    );
+    download.sent_chunks += 1;
+
+    // the session stays open after the last chunk so earlier ones can still be
+    // re-requested; the next Download replaces it
+    if is_last {
+        println!(
+            "Download complete: {} ({} chunks, {} bytes)",
+            download.file_name, download.sent_chunks, download.file_size
+        );
+    }
+
+    let data = if download.compressed {
+        compress_chunk(&buf)
+    } else {
+        Ok(buf)
+    };
+    Ok(match data {
+        Ok(data) => Response::FileChunk {
+            chunk_id,
+            crc32: crc32fast::hash(&data),
+            data,
+            is_last,
+        },
+        Err(e) => Response::Error(ShellError::io("Compress error", &e)),
+    })
+}
+
+/// Checks a fully written upload against its declared size and digest, then publishes it;
+/// data that fails either check is discarded so it cannot be mistaken for a good upload.
+fn finish_upload(mut upload: UploadState) -> Response {
+    let _ = upload.file.flush();
+    let received = upload.received_bytes;
+    let expected = upload.expected_size;
+    let digest: [u8; 32] = std::mem::take(&mut upload.hasher).finalize().into();
+    if received != expected {
+        discard_upload(&upload.file_path, upload.appended_to);
+        eprintln!(
+            "Upload size mismatch: received {} of {} bytes",
+            received, expected
+        );
+        Response::Error(ShellError::ChecksumMismatch(format!(
+            "Size mismatch: received {} of {} bytes",
+            received, expected
+        )))
+    } else if upload
+        .expected_sha256
+        .is_some_and(|sha256| sha256 != digest)
+    {
+        discard_upload(&upload.file_path, upload.appended_to);
+        eprintln!("Upload failed its checksum");
+        Response::Error(ShellError::ChecksumMismatch(format!(
+            "Checksum mismatch: received data hashes to {}",
+            to_hex(&digest)
+        )))
+    } else {
+        match publish_upload(upload) {
+            Ok(path) => {
+                println!("Upload complete: {} ({} bytes)", path.display(), received);
+                Response::UploadComplete {
+                    total_bytes: received,
+                }
+            }
+            Err(e) => {
+                eprintln!("Publish error: {}", e);
+                Response::Error(ShellError::io("Publish error", &e))
+            }
+        }
+    }
+}
+
+/// Finalizes a completed upload, renaming atomic uploads from their staging file into place.
+/// Returns the path the data is now visible under.
+fn publish_upload(upload: UploadState) -> std::io::Result<PathBuf> {
+    let UploadState {
+        file,
+        file_path,
+        publish_path,
+        ..
+    } = upload;
+    drop(file);
+
+    match publish_path {
+        Some(dest) => {
+            if let Err(e) = fs::rename(&file_path, &dest) {
+                let _ = fs::remove_file(&file_path);
+                return Err(e);
+            }
+            Ok(dest)
+        }
+        None => Ok(file_path),
+    }
+}
+
+/// Requests answered from the session's cwd alone, without touching its transfer state.
+enum FsRequest {
+    Dir,
+    CdUp,
+    Cd { path: String },
+    Mkdir { name: String },
+    Copy { src: String, dst: String },
+    Echo { payload: Vec<u8> },
+    Local(LocalRequest),
+}
+
+/// Filesystem requests that work on paths of a local directory tree rather than through
+/// the storage backend.
+enum LocalRequest {
+    MkTemp { prefix: String, suffix: String },
+    SetTime { path: String, mtime_secs: u64 },
+    Hash { path: String, algo: HashAlgo },
+    Stat { path: String },
+    Link { target: String, link: String },
+    Swap { a: String, b: String },
+    Delete { path: String },
+    Move { src: String, dst: String },
+    Df,
+}
+
+/// Chunked transfers, which start or advance the session's upload/download state.
+enum TransferRequest {
+    Upload {
+        dst_path: String,
+        file_name: String,
+        size: u64,
+        atomic: bool,
+        overwrite: bool,
+        append: bool,
+        sha256: Option<[u8; 32]>,
+        compressed: bool,
+    },
+    UploadChunk {
+        chunk_id: u32,
+        data: Vec<u8>,
+        crc32: u32,
+        is_last: bool,
+    },
+    Download {
+        src_path: String,
+        offset: u64,
+        compressed: bool,
+    },
+    DownloadChunk {
+        chunk_id: u32,
+    },
+}
+
+/// A `Request` sorted by how the UDP server handles it, so transfer and TCP-only
+/// requests can never reach `handle_fs_request`.
+enum UdpRequest {
+    Capabilities,
+    Auth {
+        user: String,
+        password: String,
+    },
+    Hello {
+        preferred_chunk_size: u32,
+    },
+    Fs(FsRequest),
+    Transfer(TransferRequest),
+    /// A filesystem request the client may retry; executed at most once per id
+    Idempotent {
+        request_id: u64,
+        request: FsRequest,
+    },
+    /// Streaming requests that need a connection; carries the request name
+    TcpOnly(&'static str),
+    /// Requests that cannot be handled in this form; carries the reason
+    Rejected(&'static str),
+}
+
+impl From<Request> for UdpRequest {
+    fn from(req: Request) -> Self {
+        match req {
+            Request::Capabilities => UdpRequest::Capabilities,
+            Request::Auth { user, password } => UdpRequest::Auth { user, password },
+            Request::Hello {
+                preferred_chunk_size,
+            } => UdpRequest::Hello {
+                preferred_chunk_size,
+            },
+            Request::Dir => UdpRequest::Fs(FsRequest::Dir),
+            Request::CdUp => UdpRequest::Fs(FsRequest::CdUp),
+            Request::Cd { path } => UdpRequest::Fs(FsRequest::Cd { path }),
+            Request::Mkdir { name } => UdpRequest::Fs(FsRequest::Mkdir { name }),
+            Request::Copy { src, dst } => UdpRequest::Fs(FsRequest::Copy { src, dst }),
+            Request::MkTemp { prefix, suffix } => {
+                UdpRequest::local(LocalRequest::MkTemp { prefix, suffix })
+            }
+            Request::SetTime { path, mtime_secs } => {
+                UdpRequest::local(LocalRequest::SetTime { path, mtime_secs })
+            }
+            Request::Hash { path, algo } => UdpRequest::local(LocalRequest::Hash { path, algo }),
+            Request::Stat { path } => UdpRequest::local(LocalRequest::Stat { path }),
+            Request::Link { target, link } => {
+                UdpRequest::local(LocalRequest::Link { target, link })
+            }
+            Request::Swap { a, b } => UdpRequest::local(LocalRequest::Swap { a, b }),
+            Request::Delete { path } => UdpRequest::local(LocalRequest::Delete { path }),
+            Request::Move { src, dst } => UdpRequest::local(LocalRequest::Move { src, dst }),
+            Request::Echo { payload } => UdpRequest::Fs(FsRequest::Echo { payload }),
+            Request::Df => UdpRequest::local(LocalRequest::Df),
+            Request::Upload { size: None, .. } => {
+                UdpRequest::Rejected("Uploads of unknown size are only supported over TCP")
+            }
+            Request::Upload {
+                dst_path,
+                file_name,
+                size: Some(size),
+                atomic,
+                overwrite,
+                append,
+                sha256,
+                compressed,
+            } => UdpRequest::Transfer(TransferRequest::Upload {
+                dst_path,
+                file_name,
+                size,
+                atomic,
+                overwrite,
+                append,
+                sha256,
+                compressed,
+            }),
+            Request::UploadChunk {
+                chunk_id,
+                data,
+                crc32,
+                is_last,
+            } => UdpRequest::Transfer(TransferRequest::UploadChunk {
+                chunk_id,
+                data,
+                crc32,
+                is_last,
+            }),
+            Request::Download {
+                src_path,
+                offset,
+                compressed,
+            } => UdpRequest::Transfer(TransferRequest::Download {
+                src_path,
+                offset,
+                compressed,
+            }),
+            Request::DownloadChunk { chunk_id } => {
+                UdpRequest::Transfer(TransferRequest::DownloadChunk { chunk_id })
+            }
+            Request::Idempotent {
+                request_id,
+                request,
+            } => match UdpRequest::from(*request) {
+                UdpRequest::Fs(request) => UdpRequest::Idempotent {
+                    request_id,
+                    request,
+                },
+                _ => UdpRequest::Rejected("Only filesystem requests can be retried by id"),
+            },
+            Request::Watch { .. } => UdpRequest::TcpOnly("Watch"),
+            Request::Follow { .. } => UdpRequest::TcpOnly("Follow"),
+            Request::Manifest { .. } => UdpRequest::TcpOnly("Manifest"),
+            Request::Search { .. } => UdpRequest::TcpOnly("Search"),
+            Request::DownloadZip { .. } => UdpRequest::TcpOnly("DownloadZip"),
+            Request::DownloadArchive { .. } => UdpRequest::TcpOnly("DownloadArchive"),
+            Request::DownloadMany { .. } => UdpRequest::TcpOnly("DownloadMany"),
+            Request::Find { .. } => UdpRequest::TcpOnly("Find"),
+            Request::DiskUsage { .. } => UdpRequest::TcpOnly("DiskUsage"),
+            Request::UploadResume { .. } => UdpRequest::TcpOnly("UploadResume"),
+        }
+    }
+}
+
+impl UdpRequest {
+    fn local(req: LocalRequest) -> Self {
+        UdpRequest::Fs(FsRequest::Local(req))
+    }
+}
+
+/// Reply to `Request::Capabilities`; `auth` is added when the server requires a login.
+fn capabilities(auth: bool) -> Response {
+    let auth = auth.then_some("auth");
+    Response::Capabilities {
+        proto_version: PROTOCOL_VERSION,
+        features: FEATURES
+            .iter()
+            .chain(&auth)
+            .map(|f| f.to_string())
+            .collect(),
+    }
+}
+
+fn handle_fs_request(
+    storage: &mut dyn StorageBackend,
+    df_paths: &[PathBuf],
+    req: FsRequest,
+) -> Response {
+    match req {
+        FsRequest::Dir => match storage.read_dir() {
+            Ok(list) => Response::DirList(list),
+            Err(e) => Response::Error(e),
+        },
+        FsRequest::CdUp => match storage.change_dir("..") {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e),
+        },
+        FsRequest::Cd { path } => match storage.change_dir(&path) {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e),
+        },
+        FsRequest::Mkdir { name } => match storage.make_dir(&name) {
+            Ok(()) => Response::Ok,
+            Err(e) => Response::Error(e),
+        },
+        FsRequest::Copy { src, dst } => match storage.copy(&src, &dst) {
+            Ok(bytes) => Response::CopyResult {
+                bytes_copied: bytes,
+            },
+            Err(e) => Response::Error(e),
+        },
+        FsRequest::Echo { payload } => Response::Echo { payload },
+        FsRequest::Local(req) => match require_local_paths(storage) {
+            Ok((root, cwd)) => handle_local_request(root, cwd, df_paths, req),
+            Err(e) => Response::Error(e),
+        },
+    }
+}
+
+/// Requests that work on paths of a local directory tree.
+fn handle_local_request(
+    root: &Path,
+    cwd: &Path,
+    df_paths: &[PathBuf],
+    req: LocalRequest,
+) -> Response {
+    match req {
+        LocalRequest::MkTemp { prefix, suffix } => mktemp(cwd, &prefix, &suffix),
+        LocalRequest::SetTime { path, mtime_secs } => set_mtime(root, cwd, &path, mtime_secs),
+        LocalRequest::Link { target, link } => hard_link(root, cwd, &target, &link),
+        LocalRequest::Swap { a, b } => swap_paths(root, cwd, &a, &b),
+        LocalRequest::Delete { path } => remove_file(root, cwd, &path),
+        LocalRequest::Move { src, dst } => move_path(root, cwd, &src, &dst),
+        LocalRequest::Hash { path, algo } => match resolve_existing(root, cwd, &path) {
+            Ok(full) => match hash_file(&full, algo) {
+                Ok((_, digest)) => Response::Hash { digest },
+                Err(e) => Response::Error(ShellError::io("hash failed", &e)),
+            },
+            Err(e) => Response::Error(e),
+        },
+        LocalRequest::Stat { path } => stat_path(root, cwd, &path),
+        LocalRequest::Df => df_report(root, df_paths),
+    }
+}
+
+/// Everything the UDP server is started with, from its command line and config file.
+pub struct UdpServerOptions {
+    pub addr: String,
+    /// Canonical, so every path a client names can be compared against it
+    pub root: PathBuf,
+    pub http_addr: Option<String>,
+    pub df_paths: Vec<PathBuf>,
+    pub max_request_size: u64,
+    pub max_bps: Option<u64>,
+    pub session_timeout_secs: u64,
+    pub chunk_size: usize,
+    pub max_file_size: Option<u64>,
+    pub users_file: Option<PathBuf>,
+    pub credentials: Option<Credentials>,
+    pub access_log_file: Option<PathBuf>,
+    pub access_log: Option<AccessLogger>,
+}
+
+impl UdpServerOptions {
+    /// Parses `args` (without the program name), reads the config file they name and loads
+    /// the files the options point to. The error is a message for the user.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut positional = Vec::new();
+        let mut http_addr = None;
+        let mut df_paths = Vec::new();
+        let mut max_request_size = MAX_MESSAGE_SIZE as u64;
+        let mut users_file = None;
+        let mut max_bps = None;
+        let mut config_file = None;
+        let mut access_log_file = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => config_file = args.next().map(PathBuf::from),
+                "--http-addr" => http_addr = args.next(),
+                "--df-path" => df_paths.extend(args.next().map(PathBuf::from)),
+                "--users" => users_file = args.next().map(PathBuf::from),
+                "--access-log" => access_log_file = args.next().map(PathBuf::from),
+                "--max-bps" => match args.next().and_then(|v| v.parse::<u64>().ok()) {
+                    Some(bps) if bps > 0 => max_bps = Some(bps),
+                    _ => return Err("--max-bps requires a positive byte rate".into()),
+                },
+                "--max-request-size" => match args.next().and_then(|v| v.parse::<u64>().ok()) {
+                    // the bincode limit still applies on top of this knob
+                    Some(bytes) if bytes > 0 => {
+                        max_request_size = bytes.min(MAX_MESSAGE_SIZE as u64)
+                    }
+                    _ => return Err("--max-request-size requires a positive byte count".into()),
+                },
+                _ => positional.push(arg),
+            }
+        }
+        let config = config_file
+            .as_deref()
+            .map(ServerConfig::load)
+            .transpose()?
+            .unwrap_or_default();
+        // positional arguments override the config file
+        let (addr, root) = match (positional.as_slice(), config.addr, config.root) {
+            ([addr, root], _, _) => (addr.clone(), PathBuf::from(root)),
+            ([], Some(addr), Some(root)) => (addr, root),
+            _ => {
+                return Err(
+                    "Usage: udp_server [<addr:port> <root_dir>] [--config <file.yaml>] [--http-addr <addr:port>] [--df-path <dir>]... [--max-request-size <bytes>] [--max-bps <bytes>] [--users <file>] [--access-log <file>]".into(),
+                );
+            }
+        };
+        validate_root(&root)?;
+        // every path a client names is canonicalized and compared against this canonical root
+        let root = fs::canonicalize(&root)
+            .map_err(|e| format!("cannot use root {}: {}", root.display(), e))?;
+        let credentials = users_file.as_deref().map(Credentials::load).transpose()?;
+        let access_log = access_log_file
+            .as_deref()
+            .map(AccessLogger::open)
+            .transpose()?;
+        Ok(Self {
+            addr,
+            root,
+            http_addr,
+            df_paths,
+            max_request_size,
+            max_bps,
+            session_timeout_secs: config.session_timeout_secs,
+            chunk_size: config.chunk_size,
+            max_file_size: config.max_file_size,
+            users_file,
+            credentials,
+            access_log_file,
+            access_log,
+        })
+    }
+}
+
+/// Answers datagrams on `socket` until `shutdown` is set. The socket's read timeout is set
+/// to the session cleanup interval, which is also how soon a shutdown is noticed; uploads
+/// still in progress then are discarded.
+pub fn run_udp_server(
+    socket: UdpSocket,
+    options: UdpServerOptions,
+    shutdown: Arc<AtomicBool>,
+) -> std::io::Result<()> {
+    let UdpServerOptions {
+        root,
+        http_addr,
+        df_paths,
+        max_request_size,
+        max_bps,
+        session_timeout_secs,
+        chunk_size,
+        max_file_size,
+        credentials,
+        access_log,
+        ..
+    } = options;
+    let throttle = max_bps.map(Throttle::new);
+
+    let metrics = Arc::new(ServerMetrics::default());
+    if let Some(http_addr) = &http_addr {
+        spawn_http_endpoint(http_addr, Arc::clone(&metrics))?;
+        println!("Health/metrics endpoint listening on http://{}", http_addr);
+    }
+
+    socket.set_read_timeout(Some(CLEANUP_INTERVAL))?;
+
+    // Session management: client_addr -> session
+    let mut sessions: HashMap<String, ClientSession> = HashMap::new();
+    let mut buf = vec![0u8; MAX_PACKET_SIZE];
+
+    while !shutdown.load(Ordering::SeqCst) {
+        // Clean up sessions idle for longer than the configured timeout
+        let now = get_timestamp();
+        cleanup_sessions(&mut sessions, now, session_timeout_secs);
+
+        match socket.recv_from(&mut buf) {
+            Ok((size, src_addr)) => {
+                println!("Received {} bytes from {}", size, src_addr);
+
+                let client_key = src_addr.to_string();
+
+                if size as u64 > max_request_size {
+                    eprintln!("Request of {} bytes exceeds the limit", size);
+                    metrics.record_request(true);
+                    let resp = Response::Error(ShellError::TooLarge(format!(
+                        "Request exceeds the {} byte limit",
+                        max_request_size
+                    )));
+                    if let Some(log) = &access_log {
+                        log.record(src_addr, "-", &resp);
+                    }
+                    if let Ok(data) = encode_to_vec(&resp, wire_config()) {
+                        let _ = socket.send_to(&data, src_addr);
+                    }
+                    continue;
+                }
+
+                // Decode request
+                let req: Request = match decode_from_slice(&buf[..size], wire_config()) {
+                    Ok((req, _)) => {
+                        metrics.record_request_size(size as u64);
+                        req
+                    }
+                    Err(e) => {
+                        eprintln!("Decode error: {}", e);
+                        let resp =
+                            Response::Error(ShellError::invalid(format!("Invalid request: {}", e)));
+                        if let Some(log) = &access_log {
+                            log.record(src_addr, "-", &resp);
+                        }
+                        if let Ok(data) = encode_to_vec(&resp, wire_config()) {
+                            let _ = socket.send_to(&data, src_addr);
+                        }
+                        continue;
+                    }
+                };
+
+                // Get or create session
+                let session = sessions.entry(client_key.clone()).or_insert_with(|| {
+                    println!("New session from {}", src_addr);
+                    ClientSession {
+                        storage: Box::new(LocalFs::new(root.clone())),
+                        last_activity: now,
+                        upload_file: None,
+                        download_file: None,
+                        replies: HashMap::new(),
+                        finished_upload: None,
+                        authenticated: credentials.is_none(),
+                        chunk_size,
+                    }
+                });
+
+                session.last_activity = now;
+
+                let request_name = req.name();
+                let req = UdpRequest::from(req);
+                // until the client has logged in, only the handshake is answered
+                let handshake = matches!(
+                    req,
+                    UdpRequest::Capabilities | UdpRequest::Auth { .. } | UdpRequest::Hello { .. }
+                );
+
+                // Handle request
+                let resp = match req {
+                    _ if !session.authenticated && !handshake => {
+                        Response::Error(ShellError::Unauthorized)
+                    }
+
+                    UdpRequest::Capabilities => capabilities(credentials.is_some()),
+
+                    UdpRequest::Auth { user, password } => match &credentials {
+                        Some(credentials) if credentials.verify(&user, &password) => {
+                            println!("User {} logged in from {}", user, src_addr);
+                            session.authenticated = true;
+                            Response::Ok
+                        }
+                        Some(_) => {
+                            eprintln!("Failed login as {} from {}", user, src_addr);
+                            Response::Error(ShellError::Unauthorized)
+                        }
+                        // nothing to log in to; accept so clients need not know in advance
+                        None => Response::Ok,
+                    },
+
+                    UdpRequest::Hello {
+                        preferred_chunk_size,
+                    } => {
+                        session.chunk_size =
+                            negotiate_chunk_size(preferred_chunk_size, max_request_size);
+                        println!(
+                            "Session {} uses {} byte chunks (asked for {})",
+                            src_addr, session.chunk_size, preferred_chunk_size
+                        );
+                        Response::Hello {
+                            chunk_size: session.chunk_size as u32,
+                        }
+                    }
+
+                    UdpRequest::Transfer(TransferRequest::Upload {
+                        file_name, size, ..
+                    }) if max_file_size.is_some_and(|limit| size > limit) => {
+                        Response::Error(ShellError::TooLarge(format!(
+                            "{} is {} bytes, over the {} byte upload limit",
+                            file_name,
+                            size,
+                            max_file_size.unwrap_or_default()
+                        )))
+                    }
+
+                    UdpRequest::Transfer(TransferRequest::Upload {
+                        dst_path,
+                        file_name,
+                        size,
+                        atomic,
+                        overwrite,
+                        append,
+                        sha256,
+                        compressed,
+                    }) => {
+                        // the client resends an Upload whose Ok was lost; as long as no chunk has
+                        // arrived, the upload it started is still the one it is asking for
+                        let repeated = session.upload_file.as_ref().is_some_and(|active| {
+                            active.next_chunk == 0
+                                && active.expected_size == size
+                                && active.expected_sha256 == sha256
+                        });
+                        // a live upload is never replaced; one that has gone quiet is abandoned
+                        // and its partial file removed, so a client that gave up can start over
+                        let busy = match session.upload_file.take() {
+                            Some(active)
+                                if now.saturating_sub(active.last_chunk_at) < UPLOAD_STALE_SECS =>
+                            {
+                                session.upload_file = Some(active);
+                                true
+                            }
+                            Some(stale) => {
+                                eprintln!(
+                                    "Abandoning stalled upload to {}",
+                                    stale.file_path.display()
+                                );
+                                drop(stale.file);
+                                discard_upload(&stale.file_path, stale.appended_to);
+                                false
+                            }
+                            None => false,
+                        };
+
+                        if repeated {
+                            Response::Ok
+                        } else if busy {
+                            Response::Error(ShellError::invalid(
+                                "An upload is already in progress in this session",
+                            ))
+                        } else if atomic && append {
+                            Response::Error(ShellError::invalid(
+                                "An upload cannot both append and be atomic",
+                            ))
+                        } else {
+                            // Initialize upload
+                            let rel = if dst_path == "." || dst_path.is_empty() {
+                                PathBuf::from(&file_name)
+                            } else {
+                                Path::new(&dst_path).join(&file_name)
+                            };
+                            let dest = require_local_paths(&*session.storage)
+                                .and_then(|(root, cwd)| resolve_new(root, cwd, &rel));
+                            let dest = dest.and_then(|dest| match dest.parent() {
+                                Some(parent) => prepare_upload_dir(parent, size).map(|()| dest),
+                                None => Ok(dest),
+                            });
+
+                            // atomic uploads are written to a staging file and renamed once complete
+                            let paths = dest.map(|dest| {
+                                if atomic {
+                                    (staging_path(&dest), dest)
+                                } else {
+                                    (dest.clone(), dest)
+                                }
+                            });
+
+                            match paths.and_then(|(write_path, dest)| {
+                                create_upload_file(&dest, &write_path, overwrite, append).map(
+                                    |(file, appended_to)| {
+                                        let publish_path = atomic.then_some(dest);
+                                        (file, write_path, publish_path, appended_to)
+                                    },
+                                )
+                            }) {
+                                Ok((file, write_path, publish_path, appended_to)) => {
+                                    println!("Starting upload: {} ({} bytes)", file_name, size);
+                                    session.finished_upload = None;
+                                    session.upload_file = Some(UploadState {
+                                        file,
+                                        file_path: write_path,
+                                        publish_path,
+                                        appended_to,
+                                        expected_size: size,
+                                        received_bytes: 0,
+                                        next_chunk: 0,
+                                        pending: BTreeMap::new(),
+                                        last_chunk: None,
+                                        compressed,
+                                        expected_sha256: sha256,
+                                        hasher: Sha256::new(),
+                                        last_chunk_at: now,
+                                    });
+                                    Response::Ok
+                                }
+                                Err(e) => Response::Error(e),
+                            }
+                        }
+                    }
+
+                    UdpRequest::Transfer(TransferRequest::UploadChunk {
+                        chunk_id,
+                        data,
+                        crc32,
+                        is_last,
+                    }) => {
+                        handle_upload_chunk(session, chunk_id, data, crc32, is_last, now, &metrics)
+                    }
+
+                    UdpRequest::Transfer(TransferRequest::Download {
+                        src_path,
+                        offset,
+                        compressed,
+                    }) => {
+                        match start_download(
+                            &*session.storage,
+                            &src_path,
+                            offset,
+                            compressed,
+                            session.chunk_size,
+                        ) {
+                            Ok((download, resp)) => {
+                                session.download_file = Some(download);
+                                resp
+                            }
+                            Err(e) => Response::Error(e),
+                        }
+                    }
+
+                    UdpRequest::Transfer(TransferRequest::DownloadChunk { chunk_id }) => {
+                        match session.download_file.as_mut() {
+                            Some(download) => {
+                                match download_chunk(
+                                    download,
+                                    chunk_id,
+                                    throttle.as_ref(),
+                                    &metrics,
+                                ) {
+                                    Ok(resp) => resp,
+                                    Err(e) => {
+                                        eprintln!("Read error: {}", e);
+                                        session.download_file = None;
+                                        Response::Error(ShellError::io("Read error", &e))
+                                    }
+                                }
+                            }
+                            None => {
+                                Response::Error(ShellError::invalid("No active download session"))
+                            }
+                        }
+                    }
+
+                    UdpRequest::Fs(fs_req) => {
+                        handle_fs_request(&mut *session.storage, &df_paths, fs_req)
+                    }
+
+                    UdpRequest::Idempotent {
+                        request_id,
+                        request,
+                    } => {
+                        expire_replies(&mut session.replies, now);
+                        if let Some((resp, _)) = session.replies.get(&request_id) {
+                            println!(
+                                "Replaying response to request {} from {}",
+                                request_id, src_addr
+                            );
+                            resp.clone()
+                        } else {
+                            let resp = handle_fs_request(&mut *session.storage, &df_paths, request);
+                            session.replies.insert(request_id, (resp.clone(), now));
+                            resp
+                        }
+                    }
+
+                    UdpRequest::TcpOnly(name) => Response::Error(ShellError::invalid(format!(
+                        "{} is only supported over TCP",
+                        name
+                    ))),
+
+                    UdpRequest::Rejected(reason) => Response::Error(ShellError::invalid(reason)),
+                };
+
+                metrics.record_request(matches!(resp, Response::Error(_)));
+                if let Some(log) = &access_log {
+                    log.record(src_addr, request_name, &resp);
+                }
+                metrics.set_active_sessions(sessions.len() as u64);
+
+                // Encode and send response
+                match encode_to_vec(&resp, wire_config()) {
+                    Ok(data) => {
+                        if data.len() > MAX_PAYLOAD_SIZE {
+                            let err_resp = Response::Error(ShellError::TooLarge(
+                                "Response too large for UDP".into(),
+                            ));
+                            if let Ok(err_data) = encode_to_vec(&err_resp, wire_config()) {
+                                let _ = socket.send_to(&err_data, src_addr);
+                            }
+                        } else {
+                            match socket.send_to(&data, src_addr) {
+                                Ok(sent) => println!("Sent {} bytes to {}", sent, src_addr),
+                                Err(e) => eprintln!("Send error: {}", e),
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Encode error: {}", e);
+                        let err_resp =
+                            Response::Error(ShellError::Io(format!("Encode error: {}", e)));
+                        if let Ok(err_data) = encode_to_vec(&err_resp, wire_config()) {
+                            let _ = socket.send_to(&err_data, src_addr);
+                        }
+                    }
+                }
+            }
+            // nothing arrived in time; loop round to expire idle sessions
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(e) => {
+                eprintln!("Receive error: {}", e);
+            }
+        }
+    }
+
+    // a partial upload cannot be continued once the server is gone
+    for session in sessions.into_values() {
+        if let Some(upload) = session.upload_file {
+            drop(upload.file);
+            discard_upload(&upload.file_path, upload.appended_to);
+        }
+    }
+    println!("Shutdown completed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::InMemoryFs;
+
+    /// A session uploading `size` bytes to `path`, as `Upload` leaves it.
+    fn uploading_session(root: &Path, path: PathBuf, size: u64) -> ClientSession {
+        ClientSession {
+            storage: Box::new(LocalFs::new(root.to_path_buf())),
+            last_activity: 0,
+            upload_file: Some(UploadState {
+                file: File::create(&path).unwrap(),
+                file_path: path,
+                publish_path: None,
+                appended_to: None,
+                expected_size: size,
+                received_bytes: 0,
+                next_chunk: 0,
+                pending: BTreeMap::new(),
+                last_chunk: None,
+                compressed: false,
+                expected_sha256: None,
+                hasher: Sha256::new(),
+                last_chunk_at: 0,
+            }),
+            download_file: None,
+            replies: HashMap::new(),
+            finished_upload: None,
+            authenticated: true,
+            chunk_size: 4,
+        }
+    }
+
+    fn send_chunk(session: &mut ClientSession, chunks: &[&[u8]], chunk_id: u32) -> Response {
+        let data = chunks[chunk_id as usize].to_vec();
+        let crc32 = crc32fast::hash(&data);
+        let is_last = chunk_id as usize == chunks.len() - 1;
+        let metrics = ServerMetrics::default();
+        handle_upload_chunk(session, chunk_id, data, crc32, is_last, 0, &metrics)
+    }
+
+    #[test]
+    fn chunks_out_of_order_are_written_in_order() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("upload.bin");
+        let chunks: [&[u8]; 4] = [b"aaaa", b"bbbb", b"cccc", b"dd"];
+        let mut session = uploading_session(root.path(), path.clone(), 14);
+
+        for chunk_id in [0, 2, 1] {
+            assert!(matches!(
+                send_chunk(&mut session, &chunks, chunk_id),
+                Response::ChunkAck { chunk_id: acked } if acked == chunk_id
+            ));
+        }
+        assert!(matches!(
+            send_chunk(&mut session, &chunks, 3),
+            Response::UploadComplete { total_bytes: 14 }
+        ));
+        assert_eq!(fs::read(&path).unwrap(), chunks.concat());
+    }
+
+    #[test]
+    fn last_chunk_arriving_early_waits_for_the_gap() {
+        let root = tempfile::tempdir().unwrap();
+        let path = root.path().join("upload.bin");
+        let chunks: [&[u8]; 3] = [b"aaaa", b"bbbb", b"cc"];
+        let mut session = uploading_session(root.path(), path.clone(), 10);
+
+        send_chunk(&mut session, &chunks, 2);
+        send_chunk(&mut session, &chunks, 0);
+        assert!(session.upload_file.is_some());
+        assert!(matches!(
+            send_chunk(&mut session, &chunks, 1),
+            Response::UploadComplete { total_bytes: 10 }
+        ));
+        assert_eq!(fs::read(&path).unwrap(), chunks.concat());
+    }
+
+    #[test]
+    fn idle_sessions_expire_and_drop_their_partial_uploads() {
+        let root = tempfile::tempdir().unwrap();
+        let dest = root.path().join("report.pdf");
+        let part = staging_path(&dest);
+        let mut stale = uploading_session(root.path(), part.clone(), 100);
+        stale.upload_file.as_mut().unwrap().publish_path = Some(dest.clone());
+        stale.last_activity = 1000;
+        let mut live = uploading_session(root.path(), root.path().join("live.bin"), 100);
+        live.last_activity = 1050;
+        let mut sessions = HashMap::from([
+            ("127.0.0.1:1000".to_string(), stale),
+            ("127.0.0.1:2000".to_string(), live),
+        ]);
+
+        cleanup_sessions(&mut sessions, 1060, 60);
+
+        assert_eq!(sessions.keys().collect::<Vec<_>>(), ["127.0.0.1:2000"]);
+        assert!(!part.exists());
+        assert!(!dest.exists());
+        assert!(root.path().join("live.bin").exists());
+    }
+
+    #[test]
+    fn sessions_just_under_the_timeout_are_kept() {
+        let root = tempfile::tempdir().unwrap();
+        let mut session = uploading_session(root.path(), root.path().join("a.bin"), 1);
+        session.last_activity = 1000;
+        let mut sessions = HashMap::from([("127.0.0.1:1000".to_string(), session)]);
+
+        cleanup_sessions(&mut sessions, 1059, 60);
+        assert_eq!(sessions.len(), 1);
+        // a clock stepped backwards never expires a session
+        cleanup_sessions(&mut sessions, 10, 60);
+        assert_eq!(sessions.len(), 1);
+        cleanup_sessions(&mut sessions, 1060, 60);
+        assert!(sessions.is_empty());
+    }
+
+    #[test]
+    fn replies_expire_after_the_replay_window() {
+        let mut replies = HashMap::from([(1, (Response::Ok, 100)), (2, (Response::Ok, 120))]);
+        expire_replies(&mut replies, 100 + REPLAY_WINDOW_SECS);
+        assert_eq!(replies.keys().collect::<Vec<_>>(), [&2]);
+    }
+
+    #[test]
+    fn replies_from_the_future_survive_a_clock_step_back() {
+        let mut replies = HashMap::from([(1, (Response::Ok, 500))]);
+        expire_replies(&mut replies, 10);
+        assert!(replies.contains_key(&1));
+    }
+
+    #[test]
+    fn downloads_are_served_from_the_backend_by_chunk_id() {
+        let mut fs = InMemoryFs::new();
+        fs.create_write("data.txt")
+            .unwrap()
+            .write_all(b"abcdefghij")
+            .unwrap();
+        let metrics = ServerMetrics::default();
+
+        let (mut download, resp) = start_download(&fs, "data.txt", 0, false, 4).unwrap();
+        assert!(matches!(
+            resp,
+            Response::FileMetadata {
+                size: Some(10),
+                offset: 0,
+                sha256: Some(_),
+                ..
+            }
+        ));
+
+        let mut chunks = BTreeMap::new();
+        for chunk_id in [2, 0, 1] {
+            match download_chunk(&mut download, chunk_id, None, &metrics).unwrap() {
+                Response::FileChunk {
+                    data,
+                    crc32,
+                    is_last,
+                    ..
+                } => {
+                    assert_eq!(crc32, crc32fast::hash(&data));
+                    assert_eq!(is_last, chunk_id == 2);
+                    chunks.insert(chunk_id, data);
+                }
+                other => panic!("unexpected reply: {:?}", other),
+            }
+        }
+        assert_eq!(
+            chunks.into_values().flatten().collect::<Vec<_>>(),
+            b"abcdefghij"
+        );
+    }
+
+    #[test]
+    fn download_of_a_missing_file_is_refused() {
+        let fs = InMemoryFs::new();
+        assert!(matches!(
+            start_download(&fs, "gone.txt", 0, false, 4),
+            Err(ShellError::NotFound(_))
+        ));
+    }
+}
@@ -0,0 +1,86 @@
+use crate::discard_upload;
+use crate::mktemp::random_u64;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// What the server needs to carry on with an interrupted TCP upload on a new connection.
+#[derive(Debug)]
+pub struct UploadSession {
+    /// Where the finished file ends up
+    pub dest: PathBuf,
+    /// Where the bytes are written: `dest` itself, or its `.part` staging file if atomic
+    pub write_path: PathBuf,
+    pub file_name: String,
+    /// `None` for an upload of unknown size, which is never parked
+    pub size: Option<u64>,
+    pub atomic: bool,
+    /// For an append, the length `write_path` had before the upload; its data follows that
+    pub appended_to: Option<u64>,
+    pub sha256: Option<[u8; 32]>,
+}
+
+/// Interrupted uploads waiting to be resumed, keyed by their upload id. An upload in
+/// progress is held by its connection and only parked here once that connection drops, so
+/// two connections can never write to the same upload.
+#[derive(Debug, Default)]
+pub struct UploadSessions {
+    parked: Mutex<HashMap<u64, (UploadSession, Instant)>>,
+}
+
+impl UploadSessions {
+    /// A fresh, hard to guess id; a resumed upload can only be claimed by whoever holds it.
+    pub fn new_id(&self) -> u64 {
+        let parked = self.lock();
+        loop {
+            let id = random_u64();
+            if !parked.contains_key(&id) {
+                return id;
+            }
+        }
+    }
+
+    /// Keeps an interrupted upload so a later `UploadResume` can claim it.
+    pub fn park(&self, id: u64, session: UploadSession) {
+        self.lock().insert(id, (session, Instant::now()));
+    }
+
+    /// Claims a parked upload for the calling connection.
+    pub fn take(&self, id: u64) -> Option<UploadSession> {
+        self.lock().remove(&id).map(|(session, _)| session)
+    }
+
+    /// Forgets uploads parked for longer than `timeout`. Staging files of atomic uploads are
+    /// deleted; a non-atomic upload leaves its partial file in place, as an interrupted one
+    /// always has.
+    pub fn expire(&self, timeout: Duration) {
+        self.lock().retain(|id, (session, parked_at)| {
+            if parked_at.elapsed() < timeout {
+                return true;
+            }
+            eprintln!("Upload {:016x} of {} expired", id, session.file_name);
+            if session.atomic {
+                let _ = fs::remove_file(&session.write_path);
+            }
+            false
+        });
+    }
+
+    /// Drops every parked upload and deletes its partial data, for a server shutting down:
+    /// the sessions live in memory, so none of them could be resumed afterwards.
+    pub fn discard_all(&self) {
+        for (id, (session, _)) in self.lock().drain() {
+            eprintln!(
+                "Removing partial upload {:016x} of {}",
+                id, session.file_name
+            );
+            discard_upload(&session.write_path, session.appended_to);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, (UploadSession, Instant)>> {
+        self.parked.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use common::{run_tcp_server, run_udp_server, udp_request};
+use shell_protocol::Request;
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LOG_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The log's lines once it holds `count` of them; a line may be written just after its reply.
+fn wait_for_lines(path: &Path, count: usize) -> Vec<String> {
+    let deadline = Instant::now() + LOG_TIMEOUT;
+    loop {
+        let lines: Vec<String> = fs::read_to_string(path)
+            .unwrap_or_default()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        if lines.len() >= count || Instant::now() >= deadline {
+            return lines;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn requests() -> Vec<Request> {
+    vec![
+        Request::Dir,
+        Request::Mkdir {
+            name: "logs".to_string(),
+        },
+        Request::Cd {
+            path: "logs".to_string(),
+        },
+        Request::Stat {
+            path: "missing.txt".to_string(),
+        },
+        Request::Dir,
+    ]
+}
+
+fn check_log(lines: &[String]) {
+    let names: Vec<&str> = lines
+        .iter()
+        .map(|line| line.split("request=").nth(1).unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "Dir outcome=Ok",
+            "Mkdir outcome=Ok",
+            "Cd outcome=Ok",
+            "Stat outcome=Error",
+            "Dir outcome=Ok",
+        ]
+    );
+    assert!(
+        lines
+            .iter()
+            .all(|line| line.starts_with("ts=") && line.contains(" client=127.0.0.1:"))
+    );
+}
+
+#[test]
+fn tcp_server_logs_one_line_per_request() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("access.log");
+    let server = run_tcp_server(&["--access-log", log.to_str().unwrap()]);
+
+    let mut client = server.connect();
+    for req in requests() {
+        client.request(&req).unwrap();
+    }
+
+    let lines = wait_for_lines(&log, requests().len());
+    check_log(&lines);
+}
+
+#[test]
+fn udp_server_logs_one_line_per_request() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("access.log");
+    let server = run_udp_server(&["--access-log", log.to_str().unwrap()]);
+
+    let socket = server.udp_socket();
+    for req in requests() {
+        udp_request(&socket, &req);
+    }
+
+    let lines = wait_for_lines(&log, requests().len());
+    check_log(&lines);
+}
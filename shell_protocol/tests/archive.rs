@@ -0,0 +1,56 @@
+mod common;
+
+use common::run_tcp_server;
+use shell_protocol::Request;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::Path;
+
+/// Every regular file under `dir`, by path relative to it, with its contents.
+fn tree(dir: &Path) -> BTreeMap<String, Vec<u8>> {
+    let mut files = BTreeMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(next) = pending.pop() {
+        for entry in fs::read_dir(next).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                let rel = path
+                    .strip_prefix(dir)
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned();
+                files.insert(rel, fs::read(&path).unwrap());
+            }
+        }
+    }
+    files
+}
+
+#[test]
+fn tar_of_a_directory_unpacks_to_the_same_tree() {
+    let server = run_tcp_server(&[]);
+    fs::create_dir(server.path("project")).unwrap();
+    fs::write(server.path("project/README.md"), "# project\n").unwrap();
+    fs::write(server.path("project/data.bin"), vec![0xab; 20_000]).unwrap();
+
+    let local = tempfile::tempdir().unwrap();
+    let req = Request::DownloadArchive {
+        path: "project".to_string(),
+    };
+    let saved = server
+        .connect()
+        .fetch(&req, local.path(), false, |_, _, _| {})
+        .unwrap();
+    assert_eq!(saved, local.path().join("project.tar"));
+
+    let unpacked = local.path().join("unpacked");
+    tar::Archive::new(File::open(saved).unwrap())
+        .unpack(&unpacked)
+        .unwrap();
+    let remote = tree(&server.path("project"));
+    assert_eq!(remote.len(), 2);
+    // entries are named relative to the archived directory
+    assert_eq!(tree(&unpacked), remote);
+}
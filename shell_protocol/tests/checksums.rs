@@ -0,0 +1,66 @@
+mod common;
+
+use bincode::serde::{decode_from_std_read, encode_into_std_write};
+use common::run_tcp_server;
+use shell_protocol::client::{ShellClient, UploadOptions, upload_request};
+use shell_protocol::hash::sha256_reader;
+use shell_protocol::{Request, Response, ShellError, wire_config};
+use std::fs::{self, File};
+use std::io::Write;
+use std::net::TcpListener;
+use std::thread;
+
+const DATA: &[u8] = b"the quick brown fox jumps over the lazy dog";
+
+#[test]
+fn upload_corrupted_after_hashing_is_a_checksum_mismatch() {
+    let server = run_tcp_server(&[]);
+    let local = tempfile::tempdir().unwrap();
+    let path = local.path().join("fox.txt");
+    fs::write(&path, DATA).unwrap();
+
+    // the request carries the digest of the original, then one byte changes in transit
+    let req = upload_request(&path, ".", UploadOptions::default()).unwrap();
+    let mut corrupted = DATA.to_vec();
+    corrupted[10] ^= 0x01;
+    fs::write(&path, &corrupted).unwrap();
+
+    let mut client = server.connect();
+    client.start_upload(&req).unwrap();
+    let (_, reply) = client
+        .stream_upload(&mut File::open(&path).unwrap(), 0, false)
+        .unwrap();
+    match reply {
+        Response::Error(ShellError::ChecksumMismatch(_)) => {}
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    assert!(!server.path("fox.txt").exists());
+}
+
+#[test]
+fn download_corrupted_in_transit_is_a_checksum_mismatch() {
+    // a server that announces the digest of DATA and sends it with one byte changed
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fake = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _: Request = decode_from_std_read(&mut stream, wire_config()).unwrap();
+        let metadata = Response::FileMetadata {
+            name: "fox.txt".to_string(),
+            size: Some(DATA.len() as u64),
+            offset: 0,
+            sha256: Some(sha256_reader(&mut &DATA[..]).unwrap()),
+        };
+        encode_into_std_write(metadata, &mut stream, wire_config()).unwrap();
+        let mut corrupted = DATA.to_vec();
+        corrupted[10] ^= 0x01;
+        stream.write_all(&corrupted).unwrap();
+    });
+
+    let local = tempfile::tempdir().unwrap();
+    let mut client = ShellClient::connect(&addr.to_string(), None).unwrap();
+    let err = client.download("fox.txt", local.path()).unwrap_err();
+    assert!(err.to_string().contains("Checksum mismatch"), "{err}");
+    assert!(!local.path().join("fox.txt").exists());
+    fake.join().unwrap();
+}
@@ -0,0 +1,243 @@
+//! Fixtures shared by the integration tests: a server running on a thread of the test
+//! process, on an ephemeral port over a temporary root, and shut down again when its
+//! [`ServerGuard`] is dropped.
+
+// each test binary compiles its own copy and uses only part of it
+#![allow(dead_code)]
+
+use bincode::{decode_from_slice, encode_to_vec};
+use shell_protocol::client::ShellClient;
+use shell_protocol::tcp_server::{self, TcpServerOptions};
+use shell_protocol::tokio_server;
+use shell_protocol::udp_server::{self, UdpServerOptions};
+use shell_protocol::{Request, Response, wire_config};
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const UDP_REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A running server. Dropping it shuts the server down and removes its root.
+pub struct ServerGuard {
+    pub addr: SocketAddr,
+    pub root: TempDir,
+    stop: Option<Box<dyn FnOnce() + Send>>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl ServerGuard {
+    /// `addr` as the `host:port` string the client library expects.
+    pub fn addr(&self) -> String {
+        self.addr.to_string()
+    }
+
+    /// `rel` under the server's root.
+    pub fn path(&self, rel: &str) -> PathBuf {
+        self.root.path().join(rel)
+    }
+
+    /// A TCP client connected to the server.
+    pub fn connect(&self) -> ShellClient {
+        ShellClient::connect(&self.addr(), None).expect("connect to server")
+    }
+
+    /// A UDP socket connected to the server, with a receive timeout so a lost reply fails
+    /// the test instead of hanging it.
+    pub fn udp_socket(&self) -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").expect("bind client socket");
+        socket
+            .set_read_timeout(Some(UDP_REPLY_TIMEOUT))
+            .expect("set read timeout");
+        socket.connect(self.addr).expect("connect client socket");
+        socket
+    }
+
+    /// Asks the server to shut down, as Ctrl-C does.
+    pub fn interrupt(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            stop();
+        }
+    }
+
+    /// Waits up to `timeout` for the server to return, giving its result if it did.
+    pub fn wait_for_exit(&mut self, timeout: Duration) -> Option<io::Result<()>> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if self.thread.as_ref().is_none_or(|t| t.is_finished()) {
+                let thread = self.thread.take()?;
+                return Some(thread.join().expect("server panicked"));
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        None
+    }
+}
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        self.interrupt();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The server's command line: a placeholder address (the fixture binds the socket itself),
+/// `root`, then `args`.
+fn server_args(root: &TempDir, args: &[&str]) -> Vec<String> {
+    ["127.0.0.1:0", root.path().to_str().expect("root path")]
+        .iter()
+        .chain(args)
+        .map(|arg| arg.to_string())
+        .collect()
+}
+
+/// Starts the threaded TCP server over an empty temporary root, with `args` after the
+/// address and root.
+pub fn run_tcp_server(args: &[&str]) -> ServerGuard {
+    let root = tempfile::tempdir().expect("create server root");
+    let options = TcpServerOptions::from_args(server_args(&root, args)).expect("server options");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind server socket");
+    let addr = listener.local_addr().expect("server address");
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&shutdown);
+    ServerGuard {
+        addr,
+        root,
+        stop: Some(Box::new(move || shutdown.store(true, Ordering::SeqCst))),
+        thread: Some(thread::spawn(move || {
+            tcp_server::run_tcp_server(listener, options, flag)
+        })),
+    }
+}
+
+/// Starts the UDP server over an empty temporary root, with `args` after the address and root.
+pub fn run_udp_server(args: &[&str]) -> ServerGuard {
+    let root = tempfile::tempdir().expect("create server root");
+    let options = UdpServerOptions::from_args(server_args(&root, args)).expect("server options");
+    let socket = UdpSocket::bind("127.0.0.1:0").expect("bind server socket");
+    let addr = socket.local_addr().expect("server address");
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let flag = Arc::clone(&shutdown);
+    ServerGuard {
+        addr,
+        root,
+        stop: Some(Box::new(move || shutdown.store(true, Ordering::SeqCst))),
+        thread: Some(thread::spawn(move || {
+            udp_server::run_udp_server(socket, options, flag)
+        })),
+    }
+}
+
+/// Starts the async TCP server over an empty temporary root, on a runtime of its own.
+pub fn run_tokio_server() -> ServerGuard {
+    let root = tempfile::tempdir().expect("create server root");
+    let canonical = root.path().canonicalize().expect("canonical root");
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind server socket");
+    listener.set_nonblocking(true).expect("set non-blocking");
+    let addr = listener.local_addr().expect("server address");
+    let (stop_tx, stop_rx) = tokio::sync::oneshot::channel::<()>();
+    ServerGuard {
+        addr,
+        root,
+        stop: Some(Box::new(move || {
+            let _ = stop_tx.send(());
+        })),
+        thread: Some(thread::spawn(move || {
+            let runtime = tokio::runtime::Runtime::new()?;
+            runtime.block_on(async {
+                let listener = tokio::net::TcpListener::from_std(listener)?;
+                tokio_server::run_tokio_server(listener, canonical, async {
+                    let _ = stop_rx.await;
+                })
+                .await;
+                Ok(())
+            })
+        })),
+    }
+}
+
+/// Sends `req` as one datagram and decodes the reply.
+pub fn udp_request(socket: &UdpSocket, req: &Request) -> Response {
+    let data = encode_to_vec(req, wire_config()).expect("encode request");
+    socket.send(&data).expect("send request");
+    udp_receive(socket)
+}
+
+/// Decodes the next datagram from the server.
+pub fn udp_receive(socket: &UdpSocket) -> Response {
+    let mut buf = vec![0u8; 65536];
+    let n = socket.recv(&mut buf).expect("server reply");
+    decode_from_slice(&buf[..n], wire_config())
+        .expect("decode reply")
+        .0
+}
+
+/// Relays datagrams between one client and `server`. `to_server` and `to_client` see each
+/// datagram on its way and may change it, or return false to drop it. Returns the address
+/// the client should use in place of the server's.
+pub fn udp_proxy(
+    server: SocketAddr,
+    mut to_server: impl FnMut(&mut Vec<u8>) -> bool + Send + 'static,
+    mut to_client: impl FnMut(&mut Vec<u8>) -> bool + Send + 'static,
+) -> SocketAddr {
+    let front = UdpSocket::bind("127.0.0.1:0").expect("bind proxy socket");
+    let back = UdpSocket::bind("127.0.0.1:0").expect("bind proxy socket");
+    back.connect(server).expect("connect proxy socket");
+    let addr = front.local_addr().expect("proxy address");
+    let client = Arc::new(Mutex::new(None::<SocketAddr>));
+
+    let (front_in, back_out, seen) = (
+        front.try_clone().expect("clone proxy socket"),
+        back.try_clone().expect("clone proxy socket"),
+        Arc::clone(&client),
+    );
+    thread::spawn(move || {
+        let mut buf = vec![0u8; 65536];
+        while let Ok((n, from)) = front_in.recv_from(&mut buf) {
+            *seen.lock().unwrap() = Some(from);
+            let mut datagram = buf[..n].to_vec();
+            if to_server(&mut datagram) {
+                let _ = back_out.send(&datagram);
+            }
+        }
+    });
+    thread::spawn(move || {
+        let mut buf = vec![0u8; 65536];
+        while let Ok(n) = back.recv(&mut buf) {
+            let mut datagram = buf[..n].to_vec();
+            let client = *client.lock().unwrap();
+            if let Some(client) = client
+                && to_client(&mut datagram)
+            {
+                let _ = front.send_to(&datagram, client);
+            }
+        }
+    });
+    addr
+}
+
+/// Runs the UDP client against `addr` with `args`, feeding it `commands` one per line after
+/// the server address, and returns once it has exited.
+pub fn run_udp_client(addr: SocketAddr, args: &[&str], commands: &[&str]) -> Output {
+    let mut client = Command::new(env!("CARGO_BIN_EXE_shell_protocol_udp_client"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("start client");
+    let mut stdin = client.stdin.take().expect("client stdin");
+    writeln!(stdin, "{}", addr).expect("write to client");
+    for command in commands {
+        writeln!(stdin, "{}", command).expect("write to client");
+    }
+    drop(stdin);
+    client.wait_with_output().expect("wait for client")
+}
@@ -0,0 +1,36 @@
+mod common;
+
+use common::run_tcp_server;
+use shell_protocol::Response;
+use shell_protocol::client::{UploadOptions, download_request, upload_request};
+use std::fs::{self, File};
+
+#[test]
+fn compressed_upload_and_download_round_trip() {
+    let server = run_tcp_server(&[]);
+    let local = tempfile::tempdir().unwrap();
+    let path = local.path().join("log.txt");
+    let data = b"GET /index.html 200\n".repeat(5000);
+    fs::write(&path, &data).unwrap();
+
+    let options = UploadOptions {
+        compressed: true,
+        ..UploadOptions::default()
+    };
+    let req = upload_request(&path, ".", options).unwrap();
+    let mut client = server.connect();
+    client.start_upload(&req).unwrap();
+    match client
+        .stream_upload(&mut File::open(&path).unwrap(), 0, true)
+        .unwrap()
+    {
+        (sent, Response::UploadComplete { .. }) => assert_eq!(sent, data.len() as u64),
+        (_, other) => panic!("unexpected reply: {:?}", other),
+    }
+    assert_eq!(fs::read(server.path("log.txt")).unwrap(), data);
+
+    let back = local.path().join("back");
+    let req = download_request("log.txt", &back, true);
+    let saved = client.fetch(&req, &back, false, |_, _, _| {}).unwrap();
+    assert_eq!(fs::read(saved).unwrap(), data);
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use common::{run_tcp_server, run_udp_server, udp_request};
+use shell_protocol::udp_server::UdpServerOptions;
+use shell_protocol::{Request, Response, ShellError};
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// Writes `yaml` to a config file that lives as long as the returned directory.
+fn config_file(yaml: &str) -> (TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("server.yaml");
+    fs::write(&path, yaml).unwrap();
+    (dir, path)
+}
+
+#[test]
+fn tcp_server_enforces_the_configured_max_file_size() {
+    let (_dir, config) = config_file("max_file_size: 100\n");
+    let server = run_tcp_server(&["--config", config.to_str().unwrap()]);
+
+    let upload = Request::Upload {
+        dst_path: ".".to_string(),
+        file_name: "big.bin".to_string(),
+        size: Some(200),
+        atomic: false,
+        overwrite: false,
+        append: false,
+        sha256: None,
+        compressed: false,
+    };
+    match server.connect().request(&upload).unwrap() {
+        Response::Error(ShellError::TooLarge(_)) => {}
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    assert!(!server.path("big.bin").exists());
+}
+
+#[test]
+fn udp_server_uses_the_configured_chunk_size() {
+    let (_dir, config) = config_file("chunk_size: 1000\nsession_timeout_secs: 60\n");
+    let args = ["127.0.0.1:0", ".", "--config", config.to_str().unwrap()];
+    let options = UdpServerOptions::from_args(args.map(String::from)).unwrap();
+    assert_eq!(
+        (options.session_timeout_secs, options.chunk_size),
+        (60, 1000)
+    );
+
+    let server = run_udp_server(&["--config", config.to_str().unwrap()]);
+    fs::write(server.path("data.bin"), vec![1u8; 3000]).unwrap();
+
+    let socket = server.udp_socket();
+    let download = Request::Download {
+        src_path: "data.bin".to_string(),
+        offset: 0,
+        compressed: false,
+    };
+    match udp_request(&socket, &download) {
+        Response::FileMetadata { size, .. } => assert_eq!(size, Some(3000)),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    match udp_request(&socket, &Request::DownloadChunk { chunk_id: 0 }) {
+        Response::FileChunk { data, .. } => assert_eq!(data.len(), 1000),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
@@ -0,0 +1,146 @@
+mod common;
+
+use bincode::error::DecodeError;
+use bincode::serde::{decode_from_std_read, encode_into_std_write, encode_to_vec};
+use common::run_tcp_server;
+use shell_protocol::client::ShellClient;
+use shell_protocol::{Request, Response, ShellError, wire_config};
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::Duration;
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn two_clients_are_served_at_the_same_time() {
+    let server = run_tcp_server(&["--max-clients", "2"]);
+    std::fs::write(server.path("shared.txt"), "hi").unwrap();
+
+    // both connections stay open until both clients are done, so a server serving one
+    // connection at a time would leave the other client waiting for its replies
+    let barrier = Arc::new(Barrier::new(2));
+    let clients: Vec<_> = (0..2)
+        .map(|_| {
+            let mut client = server.connect();
+            client
+                .stream()
+                .tcp()
+                .set_read_timeout(Some(REPLY_TIMEOUT))
+                .unwrap();
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                barrier.wait();
+                for _ in 0..20 {
+                    let names: Vec<String> =
+                        client.dir().unwrap().into_iter().map(|e| e.name).collect();
+                    assert_eq!(names, ["shared.txt"]);
+                }
+                barrier.wait();
+            })
+        })
+        .collect();
+    for client in clients {
+        client.join().unwrap();
+    }
+}
+
+#[test]
+fn connections_beyond_the_queue_are_told_the_server_is_busy() {
+    let server = run_tcp_server(&["--max-clients", "1"]);
+
+    // the only worker is serving `active`, and `queued` fills the one queue slot
+    let mut active = server.connect();
+    active.dir().unwrap();
+    let _queued = TcpStream::connect(server.addr).unwrap();
+
+    let mut rejected = server.connect();
+    rejected
+        .stream()
+        .tcp()
+        .set_read_timeout(Some(REPLY_TIMEOUT))
+        .unwrap();
+    match rejected.receive().unwrap() {
+        Response::Error(ShellError::Busy(msg)) => assert!(msg.starts_with("Server busy"), "{msg}"),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+
+    // the worker is still serving its client
+    active.dir().unwrap();
+}
+
+#[test]
+fn oversized_request_is_refused_and_the_server_keeps_serving() {
+    let server = run_tcp_server(&["--max-request-size", "1024"]);
+
+    // the server hangs up with the rest of the request unread, which may reset the
+    // connection before its error reply is read
+    let frame = encode_to_vec(
+        Request::Echo {
+            payload: vec![0; 4096],
+        },
+        wire_config(),
+    )
+    .unwrap();
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.set_read_timeout(Some(REPLY_TIMEOUT)).unwrap();
+    let _ = stream.write_all(&frame);
+    match decode_from_std_read::<Response, _, _>(&mut stream, wire_config()) {
+        Ok(Response::Error(ShellError::TooLarge(_))) => {}
+        Err(DecodeError::Io { inner, .. }) if inner.kind() == ErrorKind::ConnectionReset => {}
+        other => panic!("unexpected reply: {:?}", other),
+    }
+
+    assert!(server.connect().dir().unwrap().is_empty());
+}
+
+#[test]
+fn truncated_request_drops_only_that_connection() {
+    let server = run_tcp_server(&[]);
+
+    let frame = encode_to_vec(
+        Request::Mkdir {
+            name: "never-created".to_string(),
+        },
+        wire_config(),
+    )
+    .unwrap();
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.set_read_timeout(Some(REPLY_TIMEOUT)).unwrap();
+    stream.write_all(&frame[..frame.len() / 2]).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+
+    // the server closes the connection without replying
+    let mut rest = Vec::new();
+    assert_eq!(stream.read_to_end(&mut rest).unwrap(), 0);
+
+    assert!(server.connect().dir().unwrap().is_empty());
+    assert!(!server.path("never-created").exists());
+}
+
+#[test]
+fn truncated_download_is_an_error() {
+    // a server that announces 100 bytes, sends 10 and hangs up
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let fake = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let _: Request = decode_from_std_read(&mut stream, wire_config()).unwrap();
+        let metadata = Response::FileMetadata {
+            name: "data.bin".to_string(),
+            size: Some(100),
+            offset: 0,
+            sha256: None,
+        };
+        encode_into_std_write(metadata, &mut stream, wire_config()).unwrap();
+        stream.write_all(&[7; 10]).unwrap();
+    });
+
+    let local = tempfile::tempdir().unwrap();
+    let mut client = ShellClient::connect(&addr.to_string(), None).unwrap();
+    let err = client.download("data.bin", local.path()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    assert!(!local.path().join("data.bin").exists());
+    fake.join().unwrap();
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::{ServerGuard, run_tcp_server};
+use shell_protocol::{Request, Response};
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::symlink;
+
+/// Builds a tree of 4 files and 1,110 bytes under `data/`, plus (on Unix) a symlink to a
+/// file outside it.
+fn fixture_tree(server: &ServerGuard) {
+    fs::create_dir_all(server.path("data/logs/2024")).unwrap();
+    fs::create_dir_all(server.path("data/empty")).unwrap();
+    fs::write(server.path("data/readme.txt"), vec![b'r'; 10]).unwrap();
+    fs::write(server.path("data/logs/a.log"), vec![b'a'; 100]).unwrap();
+    fs::write(server.path("data/logs/2024/b.log"), vec![b'b'; 1000]).unwrap();
+    fs::write(server.path("data/logs/2024/empty.log"), "").unwrap();
+    fs::write(server.path("outside.bin"), vec![0; 5000]).unwrap();
+    #[cfg(unix)]
+    symlink(server.path("outside.bin"), server.path("data/link.bin")).unwrap();
+}
+
+fn du(server: &ServerGuard, path: &str) -> Response {
+    let req = Request::DiskUsage {
+        path: path.to_string(),
+    };
+    server.connect().request(&req).unwrap()
+}
+
+#[test]
+fn du_totals_every_file_under_the_directory() {
+    let server = run_tcp_server(&[]);
+    fixture_tree(&server);
+
+    match du(&server, "data") {
+        Response::DiskUsageResult {
+            total_bytes,
+            file_count,
+        } => {
+            // the symlink is not followed, so outside.bin is not counted
+            assert_eq!(total_bytes, 1110);
+            assert_eq!(file_count, 4);
+        }
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
+
+#[test]
+fn du_of_a_subdirectory_counts_only_that_subtree() {
+    let server = run_tcp_server(&[]);
+    fixture_tree(&server);
+
+    match du(&server, "data/logs/2024") {
+        Response::DiskUsageResult {
+            total_bytes,
+            file_count,
+        } => assert_eq!((total_bytes, file_count), (1000, 2)),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    match du(&server, "data/empty") {
+        Response::DiskUsageResult {
+            total_bytes,
+            file_count,
+        } => assert_eq!((total_bytes, file_count), (0, 0)),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
+
+#[test]
+fn du_of_a_file_is_an_error() {
+    let server = run_tcp_server(&[]);
+    fixture_tree(&server);
+
+    assert!(matches!(du(&server, "data/readme.txt"), Response::Error(_)));
+}
@@ -0,0 +1,31 @@
+mod common;
+
+use common::{run_tcp_server, run_udp_server, udp_request};
+use shell_protocol::{PROTOCOL_VERSION, Request, Response};
+use std::fs;
+
+#[test]
+fn tcp_server_serves_its_temporary_root() {
+    let server = run_tcp_server(&[]);
+    fs::write(server.path("hello.txt"), "hi").unwrap();
+
+    let names: Vec<String> = server
+        .connect()
+        .dir()
+        .unwrap()
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    assert_eq!(names, ["hello.txt"]);
+}
+
+#[test]
+fn udp_server_answers_on_its_ephemeral_port() {
+    let server = run_udp_server(&[]);
+    assert_ne!(server.addr.port(), 0);
+
+    match udp_request(&server.udp_socket(), &Request::Capabilities) {
+        Response::Capabilities { proto_version, .. } => assert_eq!(proto_version, PROTOCOL_VERSION),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use common::{run_tcp_server, run_udp_server, udp_request};
+use shell_protocol::{Request, Response, ShellError};
+use std::fs;
+
+fn move_req(src: &str, dst: &str) -> Request {
+    Request::Move {
+        src: src.to_string(),
+        dst: dst.to_string(),
+    }
+}
+
+#[test]
+fn move_renames_within_a_directory() {
+    let server = run_tcp_server(&[]);
+    fs::write(server.path("old.txt"), "contents").unwrap();
+
+    let reply = server
+        .connect()
+        .request(&move_req("old.txt", "new.txt"))
+        .unwrap();
+    assert!(matches!(reply, Response::Ok), "{:?}", reply);
+    assert!(!server.path("old.txt").exists());
+    assert_eq!(
+        fs::read_to_string(server.path("new.txt")).unwrap(),
+        "contents"
+    );
+}
+
+#[test]
+fn move_into_a_subdirectory_keeps_the_name() {
+    let server = run_tcp_server(&[]);
+    fs::write(server.path("report.txt"), "contents").unwrap();
+    fs::create_dir(server.path("archive")).unwrap();
+
+    let reply = server
+        .connect()
+        .request(&move_req("report.txt", "archive"))
+        .unwrap();
+    assert!(matches!(reply, Response::Ok), "{:?}", reply);
+    assert!(!server.path("report.txt").exists());
+    assert_eq!(
+        fs::read_to_string(server.path("archive/report.txt")).unwrap(),
+        "contents"
+    );
+}
+
+#[test]
+fn move_out_of_the_root_is_refused() {
+    let server = run_tcp_server(&[]);
+    fs::write(server.path("keep.txt"), "contents").unwrap();
+
+    let reply = server
+        .connect()
+        .request(&move_req("keep.txt", "../escaped.txt"))
+        .unwrap();
+    assert!(
+        matches!(reply, Response::Error(ShellError::PathEscapesRoot)),
+        "{:?}",
+        reply
+    );
+    assert!(server.path("keep.txt").exists());
+}
+
+#[test]
+fn udp_move_into_a_subdirectory() {
+    let server = run_udp_server(&[]);
+    fs::write(server.path("report.txt"), "contents").unwrap();
+    fs::create_dir(server.path("archive")).unwrap();
+
+    let reply = udp_request(
+        &server.udp_socket(),
+        &move_req("report.txt", "archive/renamed.txt"),
+    );
+    assert!(matches!(reply, Response::Ok), "{:?}", reply);
+    assert!(server.path("archive/renamed.txt").exists());
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::run_tcp_server;
+use std::net::TcpStream;
+use std::time::Duration;
+
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[test]
+fn shutdown_with_a_full_queue_completes() {
+    let mut server = run_tcp_server(&["--max-clients", "1"]);
+
+    // one client on the only worker, one in the queue, and the rest waiting to be accepted
+    let mut active = server.connect();
+    active.dir().unwrap();
+    let waiting: Vec<_> = (0..4)
+        .map(|_| TcpStream::connect(server.addr).unwrap())
+        .collect();
+
+    server.interrupt();
+    server
+        .wait_for_exit(SHUTDOWN_TIMEOUT)
+        .expect("server still running after the shutdown")
+        .expect("server failed");
+    drop(waiting);
+}
@@ -0,0 +1,46 @@
+use std::fs;
+use std::process::{Command, Output};
+
+const SERVERS: [&str; 3] = [
+    env!("CARGO_BIN_EXE_shell_protocol_tcp_server"),
+    env!("CARGO_BIN_EXE_shell_protocol_udp_server"),
+    env!("CARGO_BIN_EXE_shell_protocol_tokio_server"),
+];
+
+fn start(bin: &str, root: &std::path::Path) -> Output {
+    Command::new(bin)
+        .arg("127.0.0.1:0")
+        .arg(root)
+        .output()
+        .expect("run server")
+}
+
+#[test]
+fn file_as_root_is_a_startup_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("root.txt");
+    fs::write(&file, "not a directory").unwrap();
+
+    for bin in SERVERS {
+        let output = start(bin, &file);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{bin} started");
+        assert!(
+            stderr.contains("root must be a directory, got a file"),
+            "{bin}: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn missing_root_is_a_startup_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let missing = dir.path().join("missing");
+
+    for bin in SERVERS {
+        let output = start(bin, &missing);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(!output.status.success(), "{bin} started");
+        assert!(stderr.contains("cannot use root"), "{bin}: {stderr}");
+    }
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{run_tcp_server, run_udp_server, udp_request};
+use shell_protocol::{Request, Response};
+use std::fs;
+
+fn stat(path: &str) -> Request {
+    Request::Stat {
+        path: path.to_string(),
+    }
+}
+
+#[test]
+fn stat_reports_a_file_size() {
+    let server = run_tcp_server(&[]);
+    fs::write(server.path("notes.txt"), "twelve bytes").unwrap();
+
+    match server.connect().request(&stat("notes.txt")).unwrap() {
+        Response::StatResult {
+            name, size, is_dir, ..
+        } => {
+            assert_eq!(name, "notes.txt");
+            assert_eq!(size, 12);
+            assert!(!is_dir);
+        }
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
+
+#[test]
+fn stat_reports_a_directory() {
+    let server = run_tcp_server(&[]);
+    fs::create_dir(server.path("logs")).unwrap();
+
+    match server.connect().request(&stat("logs")).unwrap() {
+        Response::StatResult { is_dir, .. } => assert!(is_dir),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
+
+#[test]
+fn stat_of_a_missing_path_is_an_error() {
+    let server = run_tcp_server(&[]);
+
+    match server.connect().request(&stat("missing.txt")).unwrap() {
+        Response::Error(_) => {}
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
+
+#[test]
+fn udp_stat_matches_tcp() {
+    let server = run_udp_server(&[]);
+    fs::write(server.path("notes.txt"), "twelve bytes").unwrap();
+
+    match udp_request(&server.udp_socket(), &stat("notes.txt")) {
+        Response::StatResult { size, is_dir, .. } => {
+            assert_eq!(size, 12);
+            assert!(!is_dir);
+        }
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
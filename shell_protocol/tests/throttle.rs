@@ -0,0 +1,30 @@
+mod common;
+
+use common::run_tcp_server;
+use std::fs;
+use std::time::{Duration, Instant};
+
+const LIMIT: u64 = 50_000;
+const SIZE: u64 = 100_000;
+
+#[test]
+fn throttled_download_takes_at_least_size_over_limit() {
+    let server = run_tcp_server(&["--max-bps", &LIMIT.to_string()]);
+    fs::write(server.path("big.bin"), vec![7u8; SIZE as usize]).unwrap();
+    let local = tempfile::tempdir().unwrap();
+
+    let started = Instant::now();
+    let saved = server.connect().download("big.bin", local.path()).unwrap();
+    let elapsed = started.elapsed();
+
+    assert_eq!(fs::metadata(saved).unwrap().len(), SIZE);
+    // the bucket starts full and holds a tenth of a second's worth of bytes
+    let burst = LIMIT / 10;
+    let minimum = Duration::from_secs_f64((SIZE - burst) as f64 / LIMIT as f64);
+    assert!(
+        elapsed >= minimum,
+        "took {:?}, expected at least {:?}",
+        elapsed,
+        minimum
+    );
+}
@@ -0,0 +1,66 @@
+mod common;
+
+use common::run_tcp_server;
+use shell_protocol::client::ShellClient;
+use shell_protocol::tls::client_config;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+/// A self-signed certificate for 127.0.0.1, written as PEM files.
+struct SelfSigned {
+    dir: TempDir,
+}
+
+impl SelfSigned {
+    fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+        fs::write(dir.path().join("cert.pem"), cert.cert.pem()).unwrap();
+        fs::write(dir.path().join("key.pem"), cert.signing_key.serialize_pem()).unwrap();
+        Self { dir }
+    }
+
+    fn cert(&self) -> PathBuf {
+        self.dir.path().join("cert.pem")
+    }
+
+    fn key(&self) -> PathBuf {
+        self.dir.path().join("key.pem")
+    }
+}
+
+#[test]
+fn dir_round_trips_over_tls() {
+    let tls = SelfSigned::new();
+    let (cert, key) = (tls.cert(), tls.key());
+    let server = run_tcp_server(&[
+        "--tls-cert",
+        cert.to_str().unwrap(),
+        "--tls-key",
+        key.to_str().unwrap(),
+    ]);
+    fs::write(server.path("secret.txt"), "hi").unwrap();
+
+    let config = client_config(Some(&cert), false).unwrap();
+    let mut client = ShellClient::connect(&server.addr(), Some(&config)).unwrap();
+    let names: Vec<String> = client.dir().unwrap().into_iter().map(|e| e.name).collect();
+    assert_eq!(names, ["secret.txt"]);
+}
+
+#[test]
+fn certificate_from_another_ca_is_refused() {
+    let tls = SelfSigned::new();
+    let server = run_tcp_server(&[
+        "--tls-cert",
+        tls.cert().to_str().unwrap(),
+        "--tls-key",
+        tls.key().to_str().unwrap(),
+    ]);
+
+    let other = SelfSigned::new();
+    let config = client_config(Some(&other.cert()), false).unwrap();
+    let refused =
+        ShellClient::connect(&server.addr(), Some(&config)).and_then(|mut client| client.dir());
+    assert!(refused.is_err());
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::{ServerGuard, run_tcp_server, run_tokio_server};
+use shell_protocol::client::ShellClient;
+use shell_protocol::{Request, Response};
+use std::fs;
+use std::thread;
+
+const CLIENTS: usize = 50;
+
+fn populate(server: &ServerGuard) {
+    fs::create_dir(server.path("logs")).unwrap();
+    fs::write(server.path("readme.txt"), "hello").unwrap();
+    fs::write(server.path("logs/a.log"), "a").unwrap();
+}
+
+/// The async server reads length-prefixed frames.
+fn connect_framed(server: &ServerGuard) -> ShellClient {
+    ShellClient::connect(&server.addr(), None)
+        .expect("connect to server")
+        .framed()
+}
+
+fn sorted_names(client: &mut ShellClient) -> Vec<(String, bool)> {
+    let mut names: Vec<(String, bool)> = client
+        .dir()
+        .unwrap()
+        .into_iter()
+        .map(|e| (e.name, e.is_dir))
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn tokio_server_answers_like_the_threaded_server() {
+    let threaded = run_tcp_server(&[]);
+    let tokio = run_tokio_server();
+    populate(&threaded);
+    populate(&tokio);
+    let mut expected = threaded.connect();
+    let mut actual = connect_framed(&tokio);
+
+    assert_eq!(sorted_names(&mut actual), sorted_names(&mut expected));
+    for client in [&mut expected, &mut actual] {
+        client.cd("logs").unwrap();
+    }
+    assert_eq!(sorted_names(&mut actual), sorted_names(&mut expected));
+
+    let stat = Request::Stat {
+        path: "a.log".to_string(),
+    };
+    match (
+        expected.request(&stat).unwrap(),
+        actual.request(&stat).unwrap(),
+    ) {
+        (Response::StatResult { size: a, .. }, Response::StatResult { size: b, .. }) => {
+            assert_eq!(a, b)
+        }
+        other => panic!("unexpected replies: {:?}", other),
+    }
+}
+
+#[test]
+fn tokio_server_serves_many_clients_at_once() {
+    let server = run_tokio_server();
+    populate(&server);
+
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|_| {
+            let mut client = connect_framed(&server);
+            thread::spawn(move || sorted_names(&mut client))
+        })
+        .collect();
+    for client in clients {
+        assert_eq!(
+            client.join().unwrap(),
+            [
+                ("logs".to_string(), true),
+                ("readme.txt".to_string(), false)
+            ]
+        );
+    }
+}
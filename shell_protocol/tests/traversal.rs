@@ -0,0 +1,86 @@
+mod common;
+
+use common::run_tcp_server;
+use shell_protocol::{Request, Response, ShellError};
+use std::fs;
+#[cfg(unix)]
+use std::os::unix::fs::symlink;
+
+fn cd(path: &str) -> Request {
+    Request::Cd {
+        path: path.to_string(),
+    }
+}
+
+fn assert_escapes(reply: Response) {
+    assert!(
+        matches!(reply, Response::Error(ShellError::PathEscapesRoot)),
+        "{:?}",
+        reply
+    );
+}
+
+#[test]
+fn cd_above_the_root_is_refused() {
+    let server = run_tcp_server(&[]);
+    let mut client = server.connect();
+
+    assert_escapes(client.request(&cd("../../etc")).unwrap());
+    assert_escapes(client.request(&cd("..")).unwrap());
+    // the connection stays at the root
+    fs::write(server.path("here.txt"), "").unwrap();
+    assert_eq!(client.dir().unwrap()[0].name, "here.txt");
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_out_of_the_root_is_refused() {
+    let server = run_tcp_server(&[]);
+    let outside = tempfile::tempdir().unwrap();
+    fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+    symlink(outside.path(), server.path("escape")).unwrap();
+    symlink(
+        outside.path().join("secret.txt"),
+        server.path("secret-link.txt"),
+    )
+    .unwrap();
+    let mut client = server.connect();
+
+    assert_escapes(client.request(&cd("escape")).unwrap());
+    let stat = Request::Stat {
+        path: "secret-link.txt".to_string(),
+    };
+    assert_escapes(client.request(&stat).unwrap());
+}
+
+#[test]
+fn nested_cd_inside_the_root_succeeds() {
+    let server = run_tcp_server(&[]);
+    fs::create_dir_all(server.path("a/b/c")).unwrap();
+    fs::write(server.path("a/b/c/deep.txt"), "").unwrap();
+    let mut client = server.connect();
+
+    client.cd("a/b").unwrap();
+    client.cd("c").unwrap();
+    assert_eq!(client.dir().unwrap()[0].name, "deep.txt");
+    // `..` is fine while it stays inside the root
+    client.cd("../../..").unwrap();
+    assert_eq!(client.dir().unwrap()[0].name, "a");
+}
+
+#[test]
+fn download_many_reports_each_refusal_as_its_shell_error() {
+    let server = run_tcp_server(&[]);
+    fs::create_dir(server.path("dir")).unwrap();
+    fs::write(server.path("ok.txt"), "").unwrap();
+    let mut client = server.connect();
+    let download_many = |paths: &[&str]| Request::DownloadMany {
+        paths: paths.iter().map(|p| p.to_string()).collect(),
+    };
+
+    assert_escapes(client.request(&download_many(&["ok.txt", ".."])).unwrap());
+    match client.request(&download_many(&["dir"])).unwrap() {
+        Response::Error(ShellError::InvalidRequest(msg)) => assert!(msg.contains("not a file")),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
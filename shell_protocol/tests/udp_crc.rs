@@ -0,0 +1,104 @@
+mod common;
+
+use bincode::serde::{decode_from_slice, encode_to_vec};
+use common::{run_udp_client, run_udp_server, udp_proxy};
+use shell_protocol::{Request, Response, wire_config};
+use std::fs;
+
+fn sample() -> Vec<u8> {
+    (0..30_000u32).map(|i| (i % 251) as u8).collect()
+}
+
+#[test]
+fn corrupted_upload_chunk_is_rejected_and_resent() {
+    let server = run_udp_server(&[]);
+    let mut corrupted = false;
+    let proxy = udp_proxy(
+        server.addr,
+        move |datagram| {
+            // flip a data byte but keep the checksum the client computed
+            if !corrupted
+                && let Ok((
+                    Request::UploadChunk {
+                        chunk_id,
+                        mut data,
+                        crc32,
+                        is_last,
+                    },
+                    _,
+                )) = decode_from_slice::<Request, _>(datagram, wire_config())
+            {
+                data[0] ^= 0xff;
+                let req = Request::UploadChunk {
+                    chunk_id,
+                    data,
+                    crc32,
+                    is_last,
+                };
+                *datagram = encode_to_vec(req, wire_config()).unwrap();
+                corrupted = true;
+            }
+            true
+        },
+        |_| true,
+    );
+    let local = tempfile::tempdir().unwrap();
+    let path = local.path().join("data.bin");
+    fs::write(&path, sample()).unwrap();
+
+    let upload = format!("upload {} .", path.display());
+    let output = run_udp_client(proxy, &[], &[&upload, "exit"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(
+        stderr.contains("Chunk 0 arrived corrupted, resending"),
+        "{stderr}"
+    );
+    assert_eq!(fs::read(server.path("data.bin")).unwrap(), sample());
+}
+
+#[test]
+fn corrupted_download_chunk_is_rejected_and_requested_again() {
+    let server = run_udp_server(&[]);
+    fs::write(server.path("data.bin"), sample()).unwrap();
+    let mut corrupted = false;
+    let proxy = udp_proxy(
+        server.addr,
+        |_| true,
+        move |datagram| {
+            if !corrupted
+                && let Ok((
+                    Response::FileChunk {
+                        chunk_id,
+                        mut data,
+                        crc32,
+                        is_last,
+                    },
+                    _,
+                )) = decode_from_slice::<Response, _>(datagram, wire_config())
+            {
+                data[0] ^= 0xff;
+                let resp = Response::FileChunk {
+                    chunk_id,
+                    data,
+                    crc32,
+                    is_last,
+                };
+                *datagram = encode_to_vec(resp, wire_config()).unwrap();
+                corrupted = true;
+            }
+            true
+        },
+    );
+    let local = tempfile::tempdir().unwrap();
+
+    let download = format!("download data.bin {}", local.path().display());
+    let output = run_udp_client(proxy, &[], &[&download, "exit"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(
+        stderr.contains("arrived corrupted, requesting it again"),
+        "{stderr}"
+    );
+    assert_eq!(fs::read(local.path().join("data.bin")).unwrap(), sample());
+}
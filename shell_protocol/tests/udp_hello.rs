@@ -0,0 +1,79 @@
+mod common;
+
+use common::{run_udp_server, udp_request};
+use shell_protocol::compress::COMPRESSION_SLACK;
+use shell_protocol::{CHUNK_OVERHEAD, Request, Response};
+use std::fs;
+use std::net::UdpSocket;
+
+/// Largest datagram UDP can carry over IPv4.
+const MAX_DATAGRAM: usize = 65507;
+
+fn hello(socket: &UdpSocket, preferred_chunk_size: u32) -> usize {
+    match udp_request(
+        socket,
+        &Request::Hello {
+            preferred_chunk_size,
+        },
+    ) {
+        Response::Hello { chunk_size } => chunk_size as usize,
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
+
+fn download(path: &str) -> Request {
+    Request::Download {
+        src_path: path.to_string(),
+        offset: 0,
+        compressed: false,
+    }
+}
+
+#[test]
+fn smaller_preference_is_granted_and_used() {
+    let server = run_udp_server(&[]);
+    fs::write(server.path("data.bin"), vec![1u8; 3000]).unwrap();
+    let socket = server.udp_socket();
+
+    assert_eq!(hello(&socket, 1000), 1000);
+
+    assert!(matches!(
+        udp_request(&socket, &download("data.bin")),
+        Response::FileMetadata { .. }
+    ));
+    match udp_request(&socket, &Request::DownloadChunk { chunk_id: 0 }) {
+        Response::FileChunk { data, .. } => assert_eq!(data.len(), 1000),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+}
+
+#[test]
+fn oversized_preference_is_clamped_to_a_datagram() {
+    let server = run_udp_server(&[]);
+    let socket = server.udp_socket();
+
+    let granted = hello(&socket, 1_000_000);
+    assert!(
+        granted + CHUNK_OVERHEAD + COMPRESSION_SLACK <= MAX_DATAGRAM,
+        "{granted}"
+    );
+    assert!(granted > 8192, "{granted}");
+}
+
+#[test]
+fn preference_is_clamped_to_the_request_size_limit() {
+    let server = run_udp_server(&["--max-request-size", "4096"]);
+    let socket = server.udp_socket();
+
+    let granted = hello(&socket, 60_000);
+    assert!(
+        granted + CHUNK_OVERHEAD + COMPRESSION_SLACK <= 4096,
+        "{granted}"
+    );
+}
+
+#[test]
+fn zero_preference_is_raised_to_one_byte() {
+    let server = run_udp_server(&[]);
+    assert_eq!(hello(&server.udp_socket(), 0), 1);
+}
@@ -0,0 +1,48 @@
+mod common;
+
+use common::{run_udp_server, udp_request};
+use shell_protocol::{Request, Response};
+use std::fs;
+
+fn mkdir_once(request_id: u64, name: &str) -> Request {
+    Request::Idempotent {
+        request_id,
+        request: Box::new(Request::Mkdir {
+            name: name.to_string(),
+        }),
+    }
+}
+
+#[test]
+fn retried_request_replays_the_first_response_without_running_again() {
+    let server = run_udp_server(&[]);
+    let socket = server.udp_socket();
+
+    assert!(matches!(
+        udp_request(&socket, &mkdir_once(7, "photos")),
+        Response::Ok
+    ));
+    // were the retry run again, it would recreate the directory
+    fs::remove_dir(server.path("photos")).unwrap();
+
+    assert!(matches!(
+        udp_request(&socket, &mkdir_once(7, "photos")),
+        Response::Ok
+    ));
+    assert!(!server.path("photos").exists());
+}
+
+#[test]
+fn a_new_request_id_runs_again() {
+    let server = run_udp_server(&[]);
+    let socket = server.udp_socket();
+
+    assert!(matches!(
+        udp_request(&socket, &mkdir_once(1, "photos")),
+        Response::Ok
+    ));
+    assert!(matches!(
+        udp_request(&socket, &mkdir_once(2, "photos")),
+        Response::Error(_)
+    ));
+}
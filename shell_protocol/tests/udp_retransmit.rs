@@ -0,0 +1,40 @@
+mod common;
+
+use bincode::serde::decode_from_slice;
+use common::{run_udp_client, run_udp_server, udp_proxy};
+use shell_protocol::{Response, wire_config};
+use std::fs;
+
+#[test]
+fn upload_survives_a_lost_first_ack() {
+    let server = run_udp_server(&[]);
+    let mut dropped = false;
+    let proxy = udp_proxy(
+        server.addr,
+        |_| true,
+        move |datagram| {
+            let reply = decode_from_slice::<Response, _>(datagram, wire_config());
+            if !dropped && matches!(reply, Ok((Response::ChunkAck { .. }, _))) {
+                dropped = true;
+                return false;
+            }
+            true
+        },
+    );
+    let local = tempfile::tempdir().unwrap();
+    let path = local.path().join("data.bin");
+    let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(&path, &data).unwrap();
+
+    // with a window of one chunk, nothing else is sent until the first chunk is resent
+    let upload = format!("upload {} .", path.display());
+    let output = run_udp_client(
+        proxy,
+        &["--retries", "3", "--window", "1"],
+        &[&upload, "exit"],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(stderr.contains("No ack for chunk 0, resending"), "{stderr}");
+    assert_eq!(fs::read(server.path("data.bin")).unwrap(), data);
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use bincode::serde::decode_from_slice;
+use common::{run_udp_client, run_udp_server, udp_proxy};
+use shell_protocol::{Request, Response, wire_config};
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+const WINDOW: u32 = 4;
+
+/// Upload chunks seen by the proxy: those not yet acked, the highest id so far, and the
+/// widest span of ids in flight at any moment.
+#[derive(Default)]
+struct Flight {
+    unacked: BTreeSet<u32>,
+    highest: u32,
+    widest: u32,
+}
+
+#[test]
+fn upload_window_never_exceeds_its_configured_size() {
+    let server = run_udp_server(&[]);
+    let flight = Arc::new(Mutex::new(Flight::default()));
+    let (sent, acked) = (Arc::clone(&flight), Arc::clone(&flight));
+    let proxy = udp_proxy(
+        server.addr,
+        move |datagram| {
+            if let Ok((Request::UploadChunk { chunk_id, .. }, _)) =
+                decode_from_slice::<Request, _>(datagram, wire_config())
+            {
+                let mut flight = sent.lock().unwrap();
+                flight.unacked.insert(chunk_id);
+                flight.highest = flight.highest.max(chunk_id);
+                // the window runs from the oldest chunk still unacked
+                let oldest = *flight.unacked.first().unwrap();
+                flight.widest = flight.widest.max(flight.highest - oldest + 1);
+            }
+            true
+        },
+        move |datagram| {
+            if let Ok((Response::ChunkAck { chunk_id }, _)) =
+                decode_from_slice::<Response, _>(datagram, wire_config())
+            {
+                acked.lock().unwrap().unacked.remove(&chunk_id);
+            }
+            true
+        },
+    );
+    let local = tempfile::tempdir().unwrap();
+    let path = local.path().join("data.bin");
+    let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(&path, &data).unwrap();
+
+    let upload = format!("upload {} .", path.display());
+    let window = WINDOW.to_string();
+    let output = run_udp_client(proxy, &["--window", &window], &[&upload, "exit"]);
+    assert!(output.status.success());
+    assert_eq!(fs::read(server.path("data.bin")).unwrap(), data);
+
+    let flight = flight.lock().unwrap();
+    assert!(
+        flight.highest >= WINDOW,
+        "too few chunks to fill the window"
+    );
+    assert!(
+        flight.widest <= WINDOW,
+        "{} chunks in flight",
+        flight.widest
+    );
+    assert_eq!(flight.widest, WINDOW, "the window was never filled");
+}
@@ -0,0 +1,223 @@
+mod common;
+
+use common::{ServerGuard, run_tcp_server};
+use flate2::Compression;
+use flate2::write::DeflateEncoder;
+use shell_protocol::client::{UploadOptions, upload_request};
+use shell_protocol::{Request, Response, ShellError};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+/// Uploads `local` into the server's root and returns the server's verdict.
+fn upload(server: &ServerGuard, local: &Path, options: UploadOptions) -> Response {
+    let req = upload_request(local, ".", options).unwrap();
+    let mut client = server.connect();
+    match client.request(&req).unwrap() {
+        Response::UploadSession { .. } => {}
+        refused => return refused,
+    }
+    let (_, reply) = client
+        .stream_upload(&mut File::open(local).unwrap(), 0, options.compressed)
+        .unwrap();
+    reply
+}
+
+fn assert_complete(reply: Response) {
+    assert!(
+        matches!(reply, Response::UploadComplete { .. }),
+        "{:?}",
+        reply
+    );
+}
+
+#[test]
+fn upload_over_an_existing_file_is_refused() {
+    let server = run_tcp_server(&[]);
+    fs::write(server.path("notes.txt"), "original").unwrap();
+    let local = tempfile::tempdir().unwrap();
+    let path = local.path().join("notes.txt");
+    fs::write(&path, "replacement").unwrap();
+
+    match upload(&server, &path, UploadOptions::default()) {
+        Response::Error(ShellError::AlreadyExists(_)) => {}
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    assert_eq!(
+        fs::read_to_string(server.path("notes.txt")).unwrap(),
+        "original"
+    );
+}
+
+#[test]
+fn forced_upload_replaces_an_existing_file() {
+    let server = run_tcp_server(&[]);
+    fs::write(server.path("notes.txt"), "original").unwrap();
+    let local = tempfile::tempdir().unwrap();
+    let path = local.path().join("notes.txt");
+    fs::write(&path, "replacement").unwrap();
+
+    let options = UploadOptions {
+        overwrite: true,
+        ..UploadOptions::default()
+    };
+    assert_complete(upload(&server, &path, options));
+    assert_eq!(
+        fs::read_to_string(server.path("notes.txt")).unwrap(),
+        "replacement"
+    );
+}
+
+#[test]
+fn append_upload_adds_to_the_end_of_the_file() {
+    let server = run_tcp_server(&[]);
+    let local = tempfile::tempdir().unwrap();
+    let path = local.path().join("log.txt");
+    let append = UploadOptions {
+        append: true,
+        ..UploadOptions::default()
+    };
+
+    // the first append creates the file
+    fs::write(&path, "AAA").unwrap();
+    assert_complete(upload(&server, &path, append));
+    fs::write(&path, "BBB").unwrap();
+    assert_complete(upload(&server, &path, append));
+
+    assert_eq!(
+        fs::read_to_string(server.path("log.txt")).unwrap(),
+        "AAABBB"
+    );
+}
+
+#[test]
+fn atomic_append_is_refused() {
+    let server = run_tcp_server(&[]);
+    let local = tempfile::tempdir().unwrap();
+    let path = local.path().join("log.txt");
+    fs::write(&path, "AAA").unwrap();
+
+    let options = UploadOptions {
+        append: true,
+        atomic: true,
+        ..UploadOptions::default()
+    };
+    match upload(&server, &path, options) {
+        Response::Error(_) => {}
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    assert!(!server.path("log.txt").exists());
+}
+
+#[test]
+fn atomic_upload_that_cannot_be_published_is_discarded() {
+    let server = run_tcp_server(&[]);
+    // a directory in the way makes the final rename fail once the data is staged
+    fs::create_dir(server.path("notes.txt")).unwrap();
+    fs::write(server.path("notes.txt/keep.txt"), "").unwrap();
+    let local = tempfile::tempdir().unwrap();
+    let path = local.path().join("notes.txt");
+    fs::write(&path, "replacement").unwrap();
+
+    let options = UploadOptions {
+        atomic: true,
+        overwrite: true,
+        ..UploadOptions::default()
+    };
+    match upload(&server, &path, options) {
+        Response::Error(e) => assert!(e.to_string().starts_with("Cannot publish"), "{}", e),
+        other => panic!("unexpected reply: {:?}", other),
+    }
+    let names: Vec<_> = fs::read_dir(server.root.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["notes.txt"]);
+    assert!(server.path("notes.txt").is_dir());
+}
+
+/// A TCP server whose config sets `max_file_size` to `limit`.
+fn run_with_limit(limit: u64) -> ServerGuard {
+    let dir = tempfile::tempdir().unwrap();
+    let config = dir.path().join("server.yaml");
+    fs::write(&config, format!("max_file_size: {}\n", limit)).unwrap();
+    // the config is read at startup, so it may go once the server is listening
+    run_tcp_server(&["--config", config.to_str().unwrap()])
+}
+
+/// An `Upload` of `size` bytes (`None`: streamed in chunks) into the server's root.
+fn upload_of(file_name: &str, size: Option<u64>, compressed: bool) -> Request {
+    Request::Upload {
+        dst_path: ".".to_string(),
+        file_name: file_name.to_string(),
+        size,
+        atomic: false,
+        overwrite: false,
+        append: false,
+        sha256: None,
+        compressed,
+    }
+}
+
+fn assert_too_large(reply: Response) {
+    assert!(
+        matches!(reply, Response::Error(ShellError::TooLarge(_))),
+        "{:?}",
+        reply
+    );
+}
+
+/// The server hangs up on an upload with the rest of its data unread, which may reset the
+/// connection before its error reply is read.
+fn assert_cut_off_as_too_large(reply: io::Result<Response>) {
+    match reply {
+        Ok(reply) => assert_too_large(reply),
+        Err(e) => assert!(e.to_string().contains("Connection reset"), "{e}"),
+    }
+}
+
+#[test]
+fn upload_declared_over_the_limit_is_refused() {
+    let server = run_with_limit(100);
+    let mut client = server.connect();
+
+    assert_too_large(
+        client
+            .request(&upload_of("big.bin", Some(101), false))
+            .unwrap(),
+    );
+    assert!(!server.path("big.bin").exists());
+    // refused before any data was sent, so the connection is still usable
+    assert!(client.dir().unwrap().is_empty());
+}
+
+#[test]
+fn streamed_upload_going_over_the_limit_is_refused() {
+    let server = run_with_limit(100);
+    let mut client = server.connect();
+
+    client
+        .start_upload(&upload_of("big.bin", None, false))
+        .unwrap();
+    let reply = client
+        .stream_upload_chunked(&mut &[7u8; 500][..])
+        .map(|(_, reply)| reply);
+    assert_cut_off_as_too_large(reply);
+    assert!(!server.path("big.bin").exists());
+}
+
+#[test]
+fn upload_sending_more_than_it_declared_is_refused() {
+    let server = run_with_limit(100);
+    let mut client = server.connect();
+
+    // the size claims to be within the limit, but the deflated stream holds far more
+    client
+        .start_upload(&upload_of("big.bin", Some(50), true))
+        .unwrap();
+    let mut encoder = DeflateEncoder::new(client.stream(), Compression::default());
+    encoder.write_all(&[7u8; 500]).unwrap();
+    encoder.finish().unwrap().flush().unwrap();
+    assert_cut_off_as_too_large(client.receive());
+    assert!(!server.path("big.bin").exists());
+}